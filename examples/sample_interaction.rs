@@ -12,13 +12,16 @@
 
 use molasses::{
     application::{decrypt_application_message, encrypt_application_message, ApplicationMessage},
+    clock::{Clock, SystemClock},
     credential::{BasicCredential, Credential, Identity},
     crypto::{
         ciphersuite::{CipherSuite, X25519_SHA256_AES128GCM},
         sig::{SigPublicKey, SigSecretKey, SignatureScheme, ED25519_IMPL},
     },
     group_state::{GroupState, Welcome},
-    handshake::{Handshake, ProtocolVersion, UserInitKey, MLS_DUMMY_VERSION},
+    handshake::{
+        Handshake, Lifetime, ProtocolVersion, UserInitKey, UserInitKeyBuilder, MLS_DUMMY_VERSION,
+    },
     tls_de::TlsDeserializer,
     tls_ser::TlsSerializer,
     upcast::{CryptoCtx, CryptoUpcast},
@@ -35,6 +38,7 @@ use serde::ser::Serialize;
 const COMMON_CIPHER_SUITE: &'static CipherSuite = &X25519_SHA256_AES128GCM;
 const COMMON_SIG_SCHEME: &'static SignatureScheme = &ED25519_IMPL;
 const COMMON_PROTOCOL_VERSION: ProtocolVersion = MLS_DUMMY_VERSION;
+const UIK_LIFETIME_SECS: u64 = 7 * 24 * 60 * 60;
 
 // Pauses the main thread until the user presses Enter
 fn pause_for_effect() {
@@ -46,7 +50,7 @@ fn pause_for_effect() {
 pub fn deserialize<'de, T: Deserialize<'de> + CryptoUpcast>(bytes: &[u8]) -> T {
     // Deserialize
     let mut cursor = bytes;
-    let mut deserializer = TlsDeserializer::from_reader(&mut cursor);
+    let mut deserializer = TlsDeserializer::from_bytes(&mut cursor);
     let mut val = <T as Deserialize>::deserialize(&mut deserializer).unwrap();
 
     // Punt on negotiating ciphersuites and signature schemes. I don't wanna deal with that
@@ -270,15 +274,14 @@ fn bob(tx: channel::Sender<Vec<u8>>, rx: channel::Receiver<Vec<u8>>) {
     let user_init_key_id = b"bob_user_init_key".to_vec();
     let cipher_suites = vec![COMMON_CIPHER_SUITE];
     let supported_versions = vec![COMMON_PROTOCOL_VERSION];
-    let user_init_key = UserInitKey::new_from_random(
-        &identity_secret_key,
-        user_init_key_id,
-        credential,
-        cipher_suites,
-        supported_versions,
-        &mut rng,
-    )
-    .unwrap();
+    // It's good for a week
+    let now = SystemClock.now();
+    let lifetime = Lifetime::new(now, now + UIK_LIFETIME_SECS);
+    let user_init_key =
+        UserInitKeyBuilder::new(user_init_key_id, credential, cipher_suites, supported_versions)
+            .with_lifetime(lifetime)
+            .build(&identity_secret_key, &mut rng)
+            .unwrap();
 
     // Send the UserInitKey
     tx.send(serialize(&user_init_key)).unwrap();
@@ -356,15 +359,14 @@ fn carol(tx: channel::Sender<Vec<u8>>, rx: channel::Receiver<Vec<u8>>) {
     let user_init_key_id = b"carol_user_init_key".to_vec();
     let cipher_suites = vec![COMMON_CIPHER_SUITE];
     let supported_versions = vec![COMMON_PROTOCOL_VERSION];
-    let user_init_key = UserInitKey::new_from_random(
-        &identity_secret_key,
-        user_init_key_id,
-        credential,
-        cipher_suites,
-        supported_versions,
-        &mut rng,
-    )
-    .unwrap();
+    // It's good for a week
+    let now = SystemClock.now();
+    let lifetime = Lifetime::new(now, now + UIK_LIFETIME_SECS);
+    let user_init_key =
+        UserInitKeyBuilder::new(user_init_key_id, credential, cipher_suites, supported_versions)
+            .with_lifetime(lifetime)
+            .build(&identity_secret_key, &mut rng)
+            .unwrap();
 
     // Send the UserInitKey
     tx.send(serialize(&user_init_key)).unwrap();
@@ -2,6 +2,7 @@
 //! messages
 
 use crate::{
+    credential::Roster,
     crypto::{
         aead::{AeadKey, AeadNonce},
         ciphersuite::CipherSuite,
@@ -21,7 +22,7 @@ use serde::de::Deserialize;
 
 /// Contains a secret that is unique to a member of the group. This is part of the application key
 /// schedule defined in the "Encryption Keys" section of the spec.
-#[derive(Clone)]
+#[derive(Clone, Deserialize, Serialize)]
 pub(crate) struct WriteSecret(HmacKey);
 
 // WriteSecret --> HmacKey trivially
@@ -31,25 +32,90 @@ impl From<WriteSecret> for HmacKey {
     }
 }
 
+/// By default, how many generations ahead of its sender's current generation an application message
+/// may be. Decrypting such a message means ratcheting forward that many times.
+pub const DEFAULT_MAX_FORWARD_SKIP: u32 = 1000;
+
+/// By default, how many skipped keys an `ApplicationKeyChain` holds on to. Past this, the oldest
+/// ones are deleted, and the messages they belong to can no longer be decrypted.
+pub const DEFAULT_MAX_SKIPPED_KEYS: u32 = 1000;
+
+/// The key and nonce of a message that hasn't been received yet, even though a later message from
+/// the same sender has. These are kept so that the message can still be decrypted if it arrives.
+#[derive(Clone, Deserialize, Serialize)]
+pub(crate) struct SkippedKey {
+    /// The roster index of the message's sender
+    sender: u32,
+
+    /// The generation of the sender's `WriteSecret` that the key and nonce came from
+    generation: u32,
+
+    // opaque key<0..255>;
+    /// The bytes of `write_key_[sender]_[generation]`
+    #[serde(rename = "key__bound_u8")]
+    key: Vec<u8>,
+
+    // opaque nonce<0..255>;
+    /// The bytes of `write_nonce_[sender]_[generation]`
+    #[serde(rename = "nonce__bound_u8")]
+    nonce: Vec<u8>,
+}
+
+/// What to do to an `ApplicationKeyChain` once a message it found the key for has been
+/// authenticated. Until then, the key chain is left alone, so that a forged message can't make it
+/// throw away keys.
+enum KeyChainAdvance {
+    /// The message was decrypted with the skipped key at this index of `skipped_keys`. That key
+    /// gets deleted.
+    UseSkipped(usize),
+
+    /// The message was at or ahead of its sender's current generation. The sender's `WriteSecret`
+    /// becomes `write_secret`, at generation `generation`, and the keys for the generations that
+    /// were passed over go in the cache.
+    Ratchet {
+        skipped: Vec<SkippedKey>,
+        write_secret: WriteSecret,
+        generation: u32,
+    },
+}
+
 /// Contains the secrets for every member of the group. These are called "application_secrets" in
 /// the spec, but that's kinda confusing since "application_secret" is also something that the
 /// `GroupState` creates and uses to seed this struct.
 ///
 /// This is intended to be used with the `encrypt_application_message` and
 /// `decrypt_application_message` functions.
+// This deliberately doesn't implement Serialize, so that its secrets can't be serialized by
+// accident. The only way to serialize it is ApplicationKeyChain::to_snapshot.
+#[derive(Clone)]
 pub struct ApplicationKeyChain {
     /// Contains write secrets and their respective generations, starting at 0
-    write_secrets_and_gens: Vec<(WriteSecret, u32)>,
+    pub(crate) write_secrets_and_gens: Vec<(WriteSecret, u32)>,
 
     /// The creating group's ciphersuite
-    group_cs: &'static CipherSuite,
+    pub(crate) group_cs: &'static CipherSuite,
 
     /// The creating group's ID
-    group_id: Vec<u8>,
+    pub(crate) group_id: Vec<u8>,
 
     /// The creating group's epoch at the time of creation. This is important for making the
     /// `ApplicationKeyChain` work independently from the creating `GroupState`.
-    group_epoch_at_creation: u32,
+    pub(crate) group_epoch_at_creation: u32,
+
+    /// The creating group's sender data secret at the time of creation. This is used to hide the
+    /// senders of messages from everyone outside the group. It's `None` only for key chains that
+    /// weren't made by the key schedule.
+    pub(crate) sender_data_secret: Option<HmacKey>,
+
+    /// The keys and nonces of messages that were passed over when ratcheting a `WriteSecret`
+    /// forward, oldest first. Each one is deleted once it's used.
+    pub(crate) skipped_keys: Vec<SkippedKey>,
+
+    /// The most generations an incoming message may be ahead of its sender's current generation
+    pub(crate) max_forward_skip: u32,
+
+    /// The most entries `skipped_keys` may have. When there are more, the oldest are deleted.
+    pub(crate) max_skipped_keys: u32,
 }
 
 impl ApplicationKeyChain {
@@ -94,9 +160,43 @@ impl ApplicationKeyChain {
             group_cs: group_state.cs,
             group_id: group_state.group_id.clone(),
             group_epoch_at_creation: group_state.epoch,
+            sender_data_secret: group_state.sender_data_secret.clone(),
+            skipped_keys: Vec::new(),
+            max_forward_skip: DEFAULT_MAX_FORWARD_SKIP,
+            max_skipped_keys: DEFAULT_MAX_SKIPPED_KEYS,
+        }
+    }
+
+    /// Creates an `ApplicationKeyChain` with no write secrets for a member who has left the group.
+    /// It can't encrypt or decrypt anything.
+    pub(crate) fn for_left_group(group_state: &GroupState) -> ApplicationKeyChain {
+        ApplicationKeyChain {
+            write_secrets_and_gens: Vec::new(),
+            group_cs: group_state.cs,
+            group_id: group_state.group_id.clone(),
+            group_epoch_at_creation: group_state.epoch,
+            sender_data_secret: None,
+            skipped_keys: Vec::new(),
+            max_forward_skip: DEFAULT_MAX_FORWARD_SKIP,
+            max_skipped_keys: DEFAULT_MAX_SKIPPED_KEYS,
         }
     }
 
+    /// Sets how far ahead of its sender's current generation an incoming message may be, and how
+    /// many skipped keys this key chain holds on to. If this key chain already holds more than
+    /// `max_skipped_keys` skipped keys, the oldest are deleted. Setting both to 0 means that
+    /// messages must arrive in order.
+    pub fn set_skip_limits(&mut self, max_forward_skip: u32, max_skipped_keys: u32) {
+        self.max_forward_skip = max_forward_skip;
+        self.max_skipped_keys = max_skipped_keys;
+        self.evict_skipped_keys();
+    }
+
+    /// Returns the number of skipped keys this key chain is holding on to
+    pub fn num_skipped_keys(&self) -> usize {
+        self.skipped_keys.len()
+    }
+
     /// Retrieves `write_secrets_[roster_idx]` and derives a key and nonce from it, as per section
     /// 9.1 of the MLS spec
     ///
@@ -113,28 +213,117 @@ impl ApplicationKeyChain {
             .ok_or(Error::ValidationError("Roster index out of bounds of application key chain"))?;
 
         // Derive the key and nonce
-        let mut key_buf = vec![0u8; self.group_cs.aead_impl.key_size()];
-        let mut nonce_buf = vec![0u8; self.group_cs.aead_impl.nonce_size()];
-        hkdf::expand_label(
-            self.group_cs.hash_impl,
-            &write_secret.0,
-            b"key",
-            b"",
-            key_buf.as_mut_slice(),
-        );
-        hkdf::expand_label(
-            self.group_cs.hash_impl,
-            &write_secret.0,
-            b"nonce",
-            b"",
-            nonce_buf.as_mut_slice(),
-        );
-
+        let (key_buf, nonce_buf) = derive_key_nonce_bytes(self.group_cs, write_secret);
         let key = AeadKey::new_from_bytes(self.group_cs.aead_impl, &key_buf)?;
         let nonce = AeadNonce::new_from_bytes(self.group_cs.aead_impl, &nonce_buf)?;
         Ok((key, nonce, *generation))
     }
 
+    /// Finds the key and nonce for the message from the member indexed by `roster_idx` with the
+    /// given generation. If the message is ahead of the member's current generation, this works
+    /// out the keys that will be skipped over, but doesn't change this key chain. That's up to
+    /// `advance`, which should only be called once the message has been authenticated.
+    ///
+    /// Returns: `Ok((write_key, write_nonce, advance))` on success. Returns an
+    /// `Error::ValidationError` if `roster_idx` is out of bounds, if the message is too far ahead,
+    /// or if it's behind and its key isn't in the cache, e.g., because it was already used.
+    fn get_key_nonce_for_gen(
+        &self,
+        roster_idx: usize,
+        message_generation: u32,
+    ) -> Result<(AeadKey, AeadNonce, KeyChainAdvance), Error> {
+        let cs = self.group_cs;
+        let (write_secret, current_generation) = self
+            .write_secrets_and_gens
+            .get(roster_idx)
+            .ok_or(Error::ValidationError("Roster index out of bounds of application key chain"))?;
+        let sender = u32::try_from(roster_idx)
+            .map_err(|_| Error::ValidationError("Roster index exceeds u32::MAX"))?;
+
+        // If the message is from the past, its key had better be in the cache
+        if message_generation < *current_generation {
+            let pos = self
+                .skipped_keys
+                .iter()
+                .position(|k| k.sender == sender && k.generation == message_generation)
+                .ok_or(Error::ValidationError(
+                    "Application message's generation is old and its key is not in the cache",
+                ))?;
+            let skipped_key = &self.skipped_keys[pos];
+            let key = AeadKey::new_from_bytes(cs.aead_impl, &skipped_key.key)?;
+            let nonce = AeadNonce::new_from_bytes(cs.aead_impl, &skipped_key.nonce)?;
+            return Ok((key, nonce, KeyChainAdvance::UseSkipped(pos)));
+        }
+
+        if message_generation - current_generation > self.max_forward_skip {
+            return Err(Error::ValidationError(
+                "Application message's generation is too far ahead of the write secret's",
+            ));
+        }
+
+        // Ratchet a copy of the write secret up to the message's generation, saving the keys we
+        // pass over along the way
+        let mut write_secret = write_secret.clone();
+        let mut generation = *current_generation;
+        let mut skipped = Vec::new();
+        while generation < message_generation {
+            let (key, nonce) = derive_key_nonce_bytes(cs, &write_secret);
+            skipped.push(SkippedKey {
+                sender,
+                generation,
+                key,
+                nonce,
+            });
+            ratchet_write_secret(cs, sender, &mut write_secret, &mut generation)?;
+        }
+
+        // This is the message's key. Ratchet once more so that it's only used once.
+        let (key_buf, nonce_buf) = derive_key_nonce_bytes(cs, &write_secret);
+        let key = AeadKey::new_from_bytes(cs.aead_impl, &key_buf)?;
+        let nonce = AeadNonce::new_from_bytes(cs.aead_impl, &nonce_buf)?;
+        ratchet_write_secret(cs, sender, &mut write_secret, &mut generation)?;
+
+        let advance = KeyChainAdvance::Ratchet {
+            skipped,
+            write_secret,
+            generation,
+        };
+        Ok((key, nonce, advance))
+    }
+
+    /// Updates this key chain after the member indexed by `roster_idx` sent a message that was
+    /// decrypted with a key from `get_key_nonce_for_gen`
+    ///
+    /// Requires: `advance` came from calling `get_key_nonce_for_gen` with `roster_idx` on this key
+    /// chain, and this key chain hasn't changed since
+    fn advance(&mut self, roster_idx: usize, advance: KeyChainAdvance) {
+        match advance {
+            KeyChainAdvance::UseSkipped(pos) => {
+                // TODO: Make this erasure secure
+                self.skipped_keys.remove(pos);
+            }
+            KeyChainAdvance::Ratchet {
+                skipped,
+                write_secret,
+                generation,
+            } => {
+                self.write_secrets_and_gens[roster_idx] = (write_secret, generation);
+                self.skipped_keys.extend(skipped);
+                self.evict_skipped_keys();
+            }
+        }
+    }
+
+    /// Deletes the oldest skipped keys until there are at most `max_skipped_keys` of them
+    fn evict_skipped_keys(&mut self) {
+        let max_skipped_keys = self.max_skipped_keys as usize;
+        if self.skipped_keys.len() > max_skipped_keys {
+            let num_to_evict = self.skipped_keys.len() - max_skipped_keys;
+            // TODO: Make this erasure secure
+            self.skipped_keys.drain(..num_to_evict);
+        }
+    }
+
     /// Ratchets `write_secrets_[roster_idx]` forward, as per section 9.1 of the MLS spec
     ///
     /// Returns: `Ok(())` on success. If the write secret is out of bounds, returns an
@@ -161,35 +350,64 @@ impl ApplicationKeyChain {
             .write_secrets_and_gens
             .get_mut(roster_idx)
             .ok_or(Error::ValidationError("Roster index out of bounds of application key chain"))?;
-        let current_secret = write_secret.clone();
-
-        // Ratchet the write secret, using its current value as a key
-        // write_secret_[sender]_[n] =
-        //     HKDF-Expand-Label(write_secret_[sender]_[n-1], "app sender", sender, Hash.length)
         let roster_idx = u32::try_from(roster_idx)
             .map_err(|_| Error::ValidationError("Roster index exceeds u32::MAX"))?;
-        let serialized_roster_idx = tls_ser::serialize_to_bytes(&roster_idx).unwrap();
-        let prk: HmacKey = current_secret.into();
-        hkdf::expand_label(
-            self.group_cs.hash_impl,
-            &prk,
-            b"app sender",
-            &serialized_roster_idx,
-            (write_secret.0).0.as_mut_slice(), // Overwrite the undelrying HmacKey
-        );
 
-        // Increment the generation
-        *generation = generation
-            .checked_add(1)
-            .ok_or(Error::KdfError("Write secret's generation has hit its max"))?;
+        ratchet_write_secret(self.group_cs, roster_idx, write_secret, generation)
+    }
 
-        Ok(())
+    /// Returns the epoch of the creating group at the time this key chain was created
+    pub(crate) fn get_epoch(&self) -> u32 {
+        self.group_epoch_at_creation
+    }
+
+    /// Returns the ID of the group that created this key chain
+    pub(crate) fn get_group_id(&self) -> &[u8] {
+        &self.group_id
+    }
+
+    /// Derives the key and nonce that protect the sender data of the message with the given
+    /// encrypted content. This is our own addition, modeled after later drafts:
+    ///
+    /// ```text
+    /// sample = encrypted_content[0..Hash.length]
+    /// sender_data_key = HKDF-Expand-Label(sender_data_secret, "sd key", sample, key_length)
+    /// sender_data_nonce = HKDF-Expand-Label(sender_data_secret, "sd nonce", sample, nonce_length)
+    /// ```
+    ///
+    /// where `sample` is all of `encrypted_content` if it's shorter than `Hash.length`.
+    ///
+    /// Returns: `Ok((sender_data_key, sender_data_nonce))` on success. Returns an
+    /// `Error::ValidationError` if this key chain has no sender data secret.
+    fn get_sender_data_key_nonce(
+        &self,
+        encrypted_content: &[u8],
+    ) -> Result<(AeadKey, AeadNonce), Error> {
+        let cs = self.group_cs;
+        let sender_data_secret = self
+            .sender_data_secret
+            .as_ref()
+            .ok_or(Error::ValidationError("Key chain has no sender data secret"))?;
+        let sample_len = core::cmp::min(cs.hash_impl.digest_size(), encrypted_content.len());
+        let sample = &encrypted_content[..sample_len];
+
+        let mut key_buf = vec![0u8; cs.aead_impl.key_size()];
+        let mut nonce_buf = vec![0u8; cs.aead_impl.nonce_size()];
+        hkdf::expand_label(cs.hash_impl, sender_data_secret, b"sd key", sample, &mut key_buf);
+        hkdf::expand_label(cs.hash_impl, sender_data_secret, b"sd nonce", sample, &mut nonce_buf);
+
+        let key = AeadKey::new_from_bytes(cs.aead_impl, &key_buf)?;
+        let nonce = AeadNonce::new_from_bytes(cs.aead_impl, &nonce_buf)?;
+        Ok((key, nonce))
     }
 
     /// Validates that this `ApplicationKeyChain` is created from the given `GroupState` and has
     /// sane values
     #[must_use]
-    fn validate_against_group_state(&self, group_state: &GroupState) -> Result<(), Error> {
+    pub(crate) fn validate_against_group_state(
+        &self,
+        group_state: &GroupState,
+    ) -> Result<(), Error> {
         // Check ownership
         if group_state.group_id != self.group_id {
             return Err(Error::ValidationError("Key chain does not belong to this group state"));
@@ -205,6 +423,51 @@ impl ApplicationKeyChain {
     }
 }
 
+/// Derives the key and nonce from the given write secret, as per section 9.1 of the MLS spec
+///
+/// Returns: `(write_key_bytes, write_nonce_bytes)`
+fn derive_key_nonce_bytes(cs: &CipherSuite, write_secret: &WriteSecret) -> (Vec<u8>, Vec<u8>) {
+    let mut key_buf = vec![0u8; cs.aead_impl.key_size()];
+    let mut nonce_buf = vec![0u8; cs.aead_impl.nonce_size()];
+    hkdf::expand_label(cs.hash_impl, &write_secret.0, b"key", b"", key_buf.as_mut_slice());
+    hkdf::expand_label(cs.hash_impl, &write_secret.0, b"nonce", b"", nonce_buf.as_mut_slice());
+
+    (key_buf, nonce_buf)
+}
+
+/// Ratchets the write secret of the member with roster index `sender` forward in place, and
+/// increments its generation
+///
+/// Returns: `Ok(())` on success. If `generation` is `u32::MAX`, returns an `Error::KdfError`.
+fn ratchet_write_secret(
+    cs: &CipherSuite,
+    sender: u32,
+    write_secret: &mut WriteSecret,
+    generation: &mut u32,
+) -> Result<(), Error> {
+    let current_secret = write_secret.clone();
+
+    // Ratchet the write secret, using its current value as a key
+    // write_secret_[sender]_[n] =
+    //     HKDF-Expand-Label(write_secret_[sender]_[n-1], "app sender", sender, Hash.length)
+    let serialized_sender = tls_ser::serialize_to_bytes(&sender).unwrap();
+    let prk: HmacKey = current_secret.into();
+    hkdf::expand_label(
+        cs.hash_impl,
+        &prk,
+        b"app sender",
+        &serialized_sender,
+        (write_secret.0).0.as_mut_slice(), // Overwrite the undelrying HmacKey
+    );
+
+    // Increment the generation
+    *generation = generation
+        .checked_add(1)
+        .ok_or(Error::KdfError("Write secret's generation has hit its max"))?;
+
+    Ok(())
+}
+
 //
 // Everything after this (not including tests) is non-standard
 //
@@ -238,6 +501,18 @@ pub struct ApplicationMessage {
     encrypted_content: Vec<u8>,
 }
 
+impl ApplicationMessage {
+    /// Returns the epoch of the key chain this message was encrypted with
+    pub(crate) fn get_epoch(&self) -> u32 {
+        self.epoch
+    }
+
+    /// Returns the roster index of this message's sender
+    pub(crate) fn get_sender(&self) -> u32 {
+        self.sender
+    }
+}
+
 #[derive(Deserialize, Serialize)]
 struct SignatureContent<'a> {
     #[serde(rename = "group_id__bound_u8")]
@@ -320,15 +595,38 @@ pub fn encrypt_application_message(
 }
 
 /// Decrypts the given application message with the appropriate key and nonce derived from the
-/// sender's current `WriteSecret` in this application key chain
+/// sender's `WriteSecret` in this application key chain. Messages may arrive out of order. If the
+/// message is ahead of the sender's current generation, the keys in between are cached until
+/// their messages arrive. If it's behind, its key is taken from the cache and deleted. The bounds
+/// on both are set with `ApplicationKeyChain::set_skip_limits`. The key chain only changes if the
+/// message decrypts and its signature verifies.
 ///
 /// Returns: `Ok(plaintext)` on success. Otherwise, if one of myriad things goes wrong, returns some
 /// sort of `Error`.
 // Note that this still has to take in a `GroupState` because the group's roster is liable to change
 // over time, and the roster is necessary to verify message signatures.
 pub fn decrypt_application_message(
+    app_message: ApplicationMessage,
+    group_state: &GroupState,
+    app_key_chain: &mut ApplicationKeyChain,
+) -> Result<Vec<u8>, Error> {
+    decrypt_application_message_with_roster(
+        app_message,
+        group_state,
+        &group_state.roster,
+        app_key_chain,
+    )
+}
+
+/// Like `decrypt_application_message`, but verifies the message's signature against the given
+/// roster instead of `group_state`'s. This is for messages from a past epoch, whose sender may
+/// not be in the current roster anymore.
+///
+/// Returns: The same as `decrypt_application_message`
+pub(crate) fn decrypt_application_message_with_roster(
     mut app_message: ApplicationMessage,
     group_state: &GroupState,
+    roster: &Roster,
     app_key_chain: &mut ApplicationKeyChain,
 ) -> Result<Vec<u8>, Error> {
     // Check that this key chain really does belong to this group_state
@@ -354,21 +652,16 @@ pub fn decrypt_application_message(
         ));
     }
 
-    // Get the secrets necessary to decrypt it
-    let (key, nonce, generation) = app_key_chain.get_key_nonce_gen(app_message.sender as usize)?;
-
-    // The WriteSecret generations need to match up
-    if app_message.generation != generation {
-        return Err(Error::ValidationError(
-            "Application message's generation differs from the write secret's",
-        ));
-    }
+    // Get the secrets necessary to decrypt it. We don't update the key chain until we know the
+    // message is legit.
+    let generation = app_message.generation;
+    let (key, nonce, advance) =
+        app_key_chain.get_key_nonce_for_gen(app_message.sender as usize, generation)?;
 
     // Get the sender's public key and preferred signature scheme from the roster. There are two
     // things that can go wrong here: either the sender index is bad, or the index is good but the
     // roster entry is empty.
-    let sender_credential = group_state
-        .roster
+    let sender_credential = roster
         .0
         .get(app_message.sender as usize)
         .ok_or(Error::ValidationError("Application message's sender index is out of bounds"))?
@@ -400,17 +693,152 @@ pub fn decrypt_application_message(
     let hashed_signature_content = cs.hash_impl.hash_serializable(&signature_content)?;
     sender_ss.verify(sender_pubkey, hashed_signature_content.as_bytes(), &signature)?;
 
-    // All good. Now ratchet the write secret forward
-    app_key_chain.ratchet(app_message.sender as usize)?;
+    // All good. Now ratchet the write secret forward, or delete the cached key we used
+    app_key_chain.advance(app_message.sender as usize, advance);
 
     Ok(plaintext)
 }
 
+// struct {
+//     uint32 sender;
+//     uint32 generation;
+// } SenderData;
+/// The part of an `ApplicationMessage` that a `PrivateApplicationMessage` encrypts
+#[derive(Deserialize, Serialize)]
+struct SenderData {
+    sender: u32,
+    generation: u32,
+}
+
+/// The associated data of an encrypted `SenderData`. This binds it to the group and epoch the
+/// message was sent in.
+#[derive(Serialize)]
+struct SenderDataAad<'a> {
+    #[serde(rename = "group_id__bound_u8")]
+    group_id: &'a [u8],
+    epoch: u32,
+}
+
+/// An `ApplicationMessage` whose sender and generation are encrypted, so that only members of the
+/// group can tell who sent it. Only the group ID and epoch are in the clear. The sender data is
+/// encrypted under a key derived from the epoch's sender data secret and a sample of the
+/// encrypted content.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct PrivateApplicationMessage {
+    // opaque group_id<0..255>;
+    #[serde(rename = "group_id__bound_u8")]
+    group_id: Vec<u8>,
+
+    epoch: u32,
+
+    // opaque encrypted_sender_data<0..255>;
+    /// The encrypted `SenderData` of the message
+    #[serde(rename = "encrypted_sender_data__bound_u8")]
+    encrypted_sender_data: Vec<u8>,
+
+    // opaque encrypted_content<0..2^32-1>;
+    #[serde(rename = "encrypted_content__bound_u32")]
+    encrypted_content: Vec<u8>,
+}
+
+impl ApplicationMessage {
+    /// Encrypts the sender and generation of this message with the given key chain, which must be
+    /// the one the message was encrypted with
+    ///
+    /// Returns: `Ok(private_app_message)` on success. Returns an `Error::ValidationError` if the
+    /// key chain isn't from this message's group and epoch, or has no sender data secret.
+    /// Otherwise, returns some other sort of `Error`.
+    pub fn encrypt_sender_data(
+        self,
+        app_key_chain: &ApplicationKeyChain,
+    ) -> Result<PrivateApplicationMessage, Error> {
+        if self.group_id != app_key_chain.group_id
+            || self.epoch != app_key_chain.group_epoch_at_creation
+        {
+            return Err(Error::ValidationError(
+                "Application message is not from the key chain's group and epoch",
+            ));
+        }
+
+        let cs = app_key_chain.group_cs;
+        let (key, nonce) = app_key_chain.get_sender_data_key_nonce(&self.encrypted_content)?;
+        let aad = tls_ser::serialize_to_bytes(&SenderDataAad {
+            group_id: &self.group_id,
+            epoch: self.epoch,
+        })?;
+
+        // Serialize the SenderData and make room for the tag
+        let mut encrypted_sender_data = tls_ser::serialize_to_bytes(&SenderData {
+            sender: self.sender,
+            generation: self.generation,
+        })?;
+        encrypted_sender_data.extend(vec![0u8; cs.aead_impl.tag_size()]);
+        cs.aead_impl.seal_with_ad(&key, nonce, &aad, &mut encrypted_sender_data)?;
+
+        Ok(PrivateApplicationMessage {
+            group_id: self.group_id,
+            epoch: self.epoch,
+            encrypted_sender_data,
+            encrypted_content: self.encrypted_content,
+        })
+    }
+}
+
+impl PrivateApplicationMessage {
+    /// Returns the epoch of the key chain this message was encrypted with
+    pub(crate) fn get_epoch(&self) -> u32 {
+        self.epoch
+    }
+
+    /// Decrypts the sender and generation of this message with the given key chain. The result can
+    /// be passed to `decrypt_application_message`.
+    ///
+    /// Returns: `Ok(app_message)` on success. Returns an `Error::ValidationError` if the key chain
+    /// isn't from this message's group and epoch, or has no sender data secret. Otherwise, returns
+    /// some other sort of `Error`.
+    pub fn decrypt_sender_data(
+        mut self,
+        app_key_chain: &ApplicationKeyChain,
+    ) -> Result<ApplicationMessage, Error> {
+        if self.group_id != app_key_chain.group_id
+            || self.epoch != app_key_chain.group_epoch_at_creation
+        {
+            return Err(Error::ValidationError(
+                "Application message is not from the key chain's group and epoch",
+            ));
+        }
+
+        let cs = app_key_chain.group_cs;
+        let (key, nonce) = app_key_chain.get_sender_data_key_nonce(&self.encrypted_content)?;
+        let aad = tls_ser::serialize_to_bytes(&SenderDataAad {
+            group_id: &self.group_id,
+            epoch: self.epoch,
+        })?;
+
+        let serialized_sender_data =
+            cs.aead_impl.open_with_ad(&key, nonce, &aad, &mut self.encrypted_sender_data)?;
+        let sender_data = {
+            let mut cursor: &[u8] = serialized_sender_data;
+            let mut deserializer = TlsDeserializer::from_reader(&mut cursor);
+            SenderData::deserialize(&mut deserializer)?
+        };
+
+        Ok(ApplicationMessage {
+            group_id: self.group_id,
+            epoch: self.epoch,
+            generation: sender_data.generation,
+            sender: sender_data.sender,
+            encrypted_content: self.encrypted_content,
+        })
+    }
+}
+
 #[cfg(test)]
 mod test {
     use crate::{
         application::{
             decrypt_application_message, encrypt_application_message, ApplicationKeyChain,
+            ApplicationMessage, PrivateApplicationMessage,
         },
         crypto::{
             aead::{AeadKey, AeadNonce},
@@ -425,7 +853,7 @@ mod test {
     };
 
     use quickcheck_macros::quickcheck;
-    use rand::{self, SeedableRng};
+    use rand::{self, seq::SliceRandom, Rng, SeedableRng};
     use serde::de::Deserialize;
 
     // Does an update operation on the two given groups and returns the resulting key chains
@@ -773,6 +1201,149 @@ mod test {
         );
     }
 
+    // Checks that messages can be decrypted in any order, exactly once each, and that the skip
+    // limits are enforced
+    #[quickcheck]
+    fn out_of_order_correctness(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+
+        // Make two perspectives of the same group
+        let (mut group_state1, identity_keys) = test_utils::random_full_group_state(2, &mut rng);
+        let new_roster_idx = test_utils::random_roster_index_with_exceptions(
+            group_state1.roster.len(),
+            &[group_state1.roster_index.unwrap() as usize],
+            &mut rng,
+        );
+        let mut group_state2 =
+            test_utils::change_self_index(&group_state1, &identity_keys, new_roster_idx);
+        let (mut app_key_chain1, mut app_key_chain2) =
+            do_update_op(&mut group_state1, &mut group_state2, &mut rng);
+
+        // Group 1 sends a bunch of messages, and Group 2 gets them in a random order
+        let num_messages = rng.gen_range(2, 10);
+        let mut messages: Vec<(Vec<u8>, ApplicationMessage)> = (0..num_messages)
+            .map(|i| {
+                let msg = format!("message #{}", i).into_bytes();
+                let app_message =
+                    encrypt_application_message(msg.clone(), &group_state1, &mut app_key_chain1)
+                        .unwrap();
+                (msg, app_message)
+            })
+            .collect();
+        messages.shuffle(&mut rng);
+
+        for (orig_msg, app_message) in messages.iter() {
+            let plaintext = decrypt_application_message(
+                app_message.clone(),
+                &group_state2,
+                &mut app_key_chain2,
+            )
+            .unwrap();
+            assert_eq!(&plaintext, orig_msg);
+        }
+        // Every skipped key has been used up
+        assert_eq!(app_key_chain2.num_skipped_keys(), 0);
+
+        // No message can be decrypted twice
+        for (_, app_message) in messages.into_iter() {
+            assert!(decrypt_application_message(app_message, &group_state2, &mut app_key_chain2)
+                .is_err());
+        }
+
+        // Now only allow skipping ahead 2 generations, and only cache 1 skipped key
+        app_key_chain2.set_skip_limits(2, 1);
+        let messages: Vec<ApplicationMessage> = (0..4)
+            .map(|_| {
+                encrypt_application_message(b"hi".to_vec(), &group_state1, &mut app_key_chain1)
+                    .unwrap()
+            })
+            .collect();
+
+        // The last message is too far ahead
+        assert!(decrypt_application_message(
+            messages[3].clone(),
+            &group_state2,
+            &mut app_key_chain2
+        )
+        .is_err());
+
+        // A forged message doesn't change the key chain
+        let mut forged_message = messages[2].clone();
+        forged_message.encrypted_content[0] ^= 1;
+        assert!(decrypt_application_message(forged_message, &group_state2, &mut app_key_chain2)
+            .is_err());
+        assert_eq!(app_key_chain2.num_skipped_keys(), 0);
+
+        // The real one skips 2 keys, of which only the newer one is kept
+        decrypt_application_message(messages[2].clone(), &group_state2, &mut app_key_chain2)
+            .unwrap();
+        assert_eq!(app_key_chain2.num_skipped_keys(), 1);
+        assert!(decrypt_application_message(
+            messages[0].clone(),
+            &group_state2,
+            &mut app_key_chain2
+        )
+        .is_err());
+        decrypt_application_message(messages[1].clone(), &group_state2, &mut app_key_chain2)
+            .unwrap();
+        decrypt_application_message(messages[3].clone(), &group_state2, &mut app_key_chain2)
+            .unwrap();
+    }
+
+    // Checks that messages with encrypted sender data can be decrypted by the rest of the group,
+    // and that the sender data is bound to the rest of the message
+    #[quickcheck]
+    fn private_application_message_correctness(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+
+        // Make two perspectives of the same group
+        let (mut group_state1, identity_keys) = test_utils::random_full_group_state(2, &mut rng);
+        let new_roster_idx = test_utils::random_roster_index_with_exceptions(
+            group_state1.roster.len(),
+            &[group_state1.roster_index.unwrap() as usize],
+            &mut rng,
+        );
+        let mut group_state2 =
+            test_utils::change_self_index(&group_state1, &identity_keys, new_roster_idx);
+        let (mut app_key_chain1, mut app_key_chain2) =
+            do_update_op(&mut group_state1, &mut group_state2, &mut rng);
+
+        // Group 1 sends two messages with encrypted sender data
+        let orig_msg = b"nobody knows it's me";
+        let private_messages: Vec<PrivateApplicationMessage> = (0..2)
+            .map(|_| {
+                encrypt_application_message(orig_msg.to_vec(), &group_state1, &mut app_key_chain1)
+                    .unwrap()
+                    .encrypt_sender_data(&app_key_chain1)
+                    .unwrap()
+            })
+            .collect();
+
+        // Sender data can't be moved from one message to another
+        let mut spliced_message = private_messages[0].clone();
+        spliced_message.encrypted_sender_data = private_messages[1].encrypted_sender_data.clone();
+        assert!(spliced_message.decrypt_sender_data(&app_key_chain2).is_err());
+
+        // Group 2 decrypts the sender data and then the message
+        for private_message in private_messages.into_iter().rev() {
+            let app_message = private_message.decrypt_sender_data(&app_key_chain2).unwrap();
+            assert_eq!(app_message.sender, group_state1.roster_index.unwrap());
+            let plaintext =
+                decrypt_application_message(app_message, &group_state2, &mut app_key_chain2)
+                    .unwrap();
+            assert_eq!(plaintext.as_slice(), orig_msg);
+        }
+
+        // A key chain from another epoch can't decrypt the sender data
+        let private_message =
+            encrypt_application_message(orig_msg.to_vec(), &group_state1, &mut app_key_chain1)
+                .unwrap()
+                .encrypt_sender_data(&app_key_chain1)
+                .unwrap();
+        let (_, next_app_key_chain2) = do_update_op(&mut group_state1, &mut group_state2, &mut rng);
+        assert!(private_message.decrypt_sender_data(&next_app_key_chain2).is_err());
+    }
+
     // A cursory test that our validation checks and ratcheting mechanism is working sufficiently
     // well to prevent misuse
     #[quickcheck]
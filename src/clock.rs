@@ -0,0 +1,30 @@
+//! Defines `Clock`, which is how this crate finds out what time it is. The only thing that depends
+//! on the time is whether a `UserInitKey` is within its `Lifetime`.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A caller-provided source of the current time
+pub trait Clock {
+    /// Returns: The current time, in seconds since the Unix epoch
+    fn now(&self) -> u64;
+}
+
+/// The clock used when the caller doesn't provide one. It reads the system time.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        // A system clock that's set before 1970 is treated as being at 1970
+        SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+    }
+}
+
+/// A clock that is stopped at the given time, in seconds since the Unix epoch. This is useful when
+/// the time comes from somewhere other than the system, e.g., a trusted server.
+pub struct FixedClock(pub u64);
+
+impl Clock for FixedClock {
+    fn now(&self) -> u64 {
+        self.0
+    }
+}
@@ -99,7 +99,23 @@ impl AeadScheme {
         nonce: AeadNonce,
         ciphertext_and_tag_modified_in_place: &'a mut [u8],
     ) -> Result<&'a mut [u8], Error> {
-        self.0.open(key, nonce, ciphertext_and_tag_modified_in_place)
+        self.0.open(key, nonce, b"", ciphertext_and_tag_modified_in_place)
+    }
+
+    // This just passes through to AeadSchemeInterface::open
+    /// Does the same thing as `open`, but also authenticates the given associated data. The
+    /// associated data MUST be identical to the associated data that the ciphertext was sealed
+    /// with.
+    ///
+    /// Returns: The same thing as `open`
+    pub(crate) fn open_with_ad<'a>(
+        &self,
+        key: &AeadKey,
+        nonce: AeadNonce,
+        associated_data: &[u8],
+        ciphertext_and_tag_modified_in_place: &'a mut [u8],
+    ) -> Result<&'a mut [u8], Error> {
+        self.0.open(key, nonce, associated_data, ciphertext_and_tag_modified_in_place)
     }
 
     // This just passes through to AeadSchemeInterface::seal
@@ -120,15 +136,31 @@ impl AeadScheme {
         nonce: AeadNonce,
         plaintext: &mut [u8],
     ) -> Result<(), Error> {
-        self.0.seal(key, nonce, plaintext)
+        self.0.seal(key, nonce, b"", plaintext)
+    }
+
+    // This just passes through to AeadSchemeInterface::seal
+    /// Does the same thing as `seal`, but also authenticates the given associated data. The
+    /// associated data is not included in the ciphertext.
+    ///
+    /// Requires: `plaintext.len() >= self.tag_size()`
+    ///
+    /// Returns: The same thing as `seal`
+    pub(crate) fn seal_with_ad(
+        &self,
+        key: &AeadKey,
+        nonce: AeadNonce,
+        associated_data: &[u8],
+        plaintext: &mut [u8],
+    ) -> Result<(), Error> {
+        self.0.seal(key, nonce, associated_data, plaintext)
     }
 }
 
-/// A trait representing an authenticated encryption algorithm. Note that this makes no mention of
-/// associated data, since it is not used anywhere in MLS.
+/// A trait representing an authenticated encryption algorithm. MLS itself doesn't use associated
+/// data, but sealed snapshots do, so it's supported here.
 // ring does algorithm specification at runtime, but I'd rather encode these things in the type
-// system. So, similar to the Digest trait, we're making an AuthenticatedEncryption trait. I don't
-// think we'll need associated data in this crate, so we leave it out for simplicity
+// system. So, similar to the Digest trait, we're making an AuthenticatedEncryption trait.
 trait AeadSchemeInterface {
     // Recall we can't have const trait methods if we want this to be a trait object
     fn key_size(&self) -> usize;
@@ -143,10 +175,17 @@ trait AeadSchemeInterface {
         &self,
         key: &AeadKey,
         nonce: AeadNonce,
+        associated_data: &[u8],
         ciphertext_and_tag: &'a mut [u8],
     ) -> Result<&'a mut [u8], Error>;
 
-    fn seal(&self, key: &AeadKey, nonce: AeadNonce, plaintext: &mut [u8]) -> Result<(), Error>;
+    fn seal(
+        &self,
+        key: &AeadKey,
+        nonce: AeadNonce,
+        associated_data: &[u8],
+        plaintext: &mut [u8],
+    ) -> Result<(), Error>;
 }
 
 /// This represents the AES-128-GCM authenticated encryption algorithm. Notably, it implements
@@ -232,12 +271,13 @@ impl AeadSchemeInterface for Aes128Gcm {
         &self,
         key: &AeadKey,
         nonce: AeadNonce,
+        associated_data: &[u8],
         ciphertext_and_tag_modified_in_place: &'a mut [u8],
     ) -> Result<&'a mut [u8], Error> {
         let key = enum_variant!(key, AeadKey::Aes128GcmKey);
         let nonce = enum_variant!(nonce, AeadNonce::Aes128GcmNonce);
 
-        // We use the standard decryption function with no associated data, and no "prefix bytes".
+        // We use the standard decryption function with no "prefix bytes".
         // The length of the buffer is checked by the ring library. The function returns a
         // plaintext = ciphertext_and_tag[..plaintext.len()] For more details on this function, see
         // docs on ring::aead::open_in_place at
@@ -245,7 +285,7 @@ impl AeadSchemeInterface for Aes128Gcm {
         ring::aead::open_in_place(
             &key.opening_key,
             nonce,
-            ring::aead::Aad::empty(),
+            ring::aead::Aad::from(associated_data),
             0,
             ciphertext_and_tag_modified_in_place,
         )
@@ -262,18 +302,24 @@ impl AeadSchemeInterface for Aes128Gcm {
     /// Returns: `Ok(())` on sucess, indicating that the inputted buffer contains the tagged
     /// ciphertext. If there is an error in any part of this process, it will be returned as an
     /// `Error::CryptoError` with description "Unspecified".
-    fn seal(&self, key: &AeadKey, nonce: AeadNonce, plaintext: &mut [u8]) -> Result<(), Error> {
+    fn seal(
+        &self,
+        key: &AeadKey,
+        nonce: AeadNonce,
+        associated_data: &[u8],
+        plaintext: &mut [u8],
+    ) -> Result<(), Error> {
         let key = enum_variant!(key, AeadKey::Aes128GcmKey);
         let nonce = enum_variant!(nonce, AeadNonce::Aes128GcmNonce);
 
-        // We use the standard encryption function with no associated data. The length of the
-        // buffer is checked by the ring library.
+        // We use the standard encryption function. The length of the buffer is checked by the
+        // ring library.
         // For more details on this function, see docs on ring::aead::seal_in_place at
         // https://briansmith.org/rustdoc/ring/aead/fn.seal_in_place.html
         let res = ring::aead::seal_in_place(
             &key.sealing_key,
             nonce,
-            ring::aead::Aad::empty(),
+            ring::aead::Aad::from(associated_data),
             plaintext,
             AES_128_GCM_TAG_SIZE,
         );
@@ -430,4 +476,37 @@ mod test {
         let res = scheme.open(&key, nonce2, auth_ciphertext);
         assert!(res.is_err());
     }
+
+    // Test that a ciphertext sealed with associated data fails to open under different associated
+    // data
+    #[quickcheck]
+    fn aes_gcm_integrity_ad(mut plaintext: Vec<u8>, associated_data: Vec<u8>, rng_seed: u64) {
+        // We're only working with AES-128 GCM
+        let scheme = &AES128GCM_IMPL;
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+
+        // The open method consumes our nonce, so make two nonces
+        let (nonce1, nonce2) = gen_nonce_pair(scheme, &mut rng);
+        let key = gen_key(scheme, &mut rng);
+
+        // Make sure there's enough room in the plaintext for the tag
+        plaintext.extend(vec![0u8; scheme.tag_size()]);
+
+        // Encrypt
+        scheme
+            .seal_with_ad(&key, nonce1, &associated_data, plaintext.as_mut_slice())
+            .expect("failed to encrypt");
+
+        // Rename for clarity, since plaintext was modified in-place
+        let auth_ciphertext = plaintext.as_mut_slice();
+
+        // Change the associated data by appending a byte to it
+        let mut other_associated_data = associated_data.clone();
+        other_associated_data.push(rng.next_u32() as u8);
+
+        // Make sure this fails to open
+        let res = scheme.open_with_ad(&key, nonce2, &other_associated_data, auth_ciphertext);
+        assert!(res.is_err());
+    }
 }
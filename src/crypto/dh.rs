@@ -44,6 +44,16 @@ impl DhPrivateKey {
     {
         scheme.0.private_key_from_random(csprng)
     }
+
+    // TODO: make this not allocate
+    /// Returns the bytes-representation of the private key. This is the same representation that
+    /// `DhPrivateKey::new_from_bytes` expects. Do not use this method unless you really really need
+    /// to.
+    pub(crate) fn as_bytes(&self) -> Vec<u8> {
+        match self {
+            DhPrivateKey::X25519PrivateKey(s) => s.to_bytes().to_vec(),
+        }
+    }
 }
 
 impl core::fmt::Debug for DhPrivateKey {
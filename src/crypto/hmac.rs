@@ -34,7 +34,7 @@ impl HmacKey {
 
 // This is <0..255> since the only signature in MLS is
 // Handshake::confirmation<0..255>
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename = "Mac__bound_u8")]
 pub(crate) struct Mac(Vec<u8>);
 
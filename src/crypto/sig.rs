@@ -76,6 +76,15 @@ impl SigSecretKey {
     {
         ss.0.secret_key_from_random(csprng)
     }
+
+    /// Returns the bytes-representation of the secret key. This is the same representation that
+    /// `SigSecretKey::new_from_bytes` expects. Do not use this method unless you really really need
+    /// to.
+    pub(crate) fn as_bytes(&self) -> &[u8] {
+        match self {
+            SigSecretKey::Ed25519SecretKey(s) => s.as_bytes(),
+        }
+    }
 }
 
 // We only really need this in order to derive(Clone) for GroupState
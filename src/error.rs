@@ -19,6 +19,8 @@ pub enum Error {
     TreeError(&'static str),
     /// For errors concerning invalid data structures
     ValidationError(&'static str),
+    /// For when a group's `MembershipPolicy` forbids an operation
+    AuthorizationError(&'static str),
     /// For when we need randomness and there's none left
     OutOfEntropy,
     /// For when we've been removed from a group
@@ -40,6 +42,7 @@ impl std::error::Error for Error {
             Error::DhError(e) => e,
             Error::TreeError(e) => e,
             Error::ValidationError(e) => e,
+            Error::AuthorizationError(e) => e,
             Error::SignatureError(e) => e,
             Error::KdfError(e) => e,
             Error::SerdeError(e) => e.description(),
@@ -0,0 +1,57 @@
+//! Defines group context extensions. These carry application-defined metadata about a group, such
+//! as its name, its admins, or the capabilities its members are required to have. Extensions are
+//! part of the `GroupState`, so they are bound into the key schedule, and every member who agrees
+//! on the group's secrets also agrees on its extensions.
+
+use crate::error::Error;
+
+/// Identifies the kind of an `Extension`. What each type means is up to the application.
+// uint16 ExtensionType;
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ExtensionType(pub u16);
+
+/// A single group context extension
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Extension {
+    /// What kind of extension this is
+    pub(crate) extension_type: ExtensionType,
+
+    // opaque extension_data<0..2^16-1>;
+    /// The contents of the extension, interpreted according to `extension_type`
+    #[serde(rename = "extension_data__bound_u16")]
+    pub(crate) extension_data: Vec<u8>,
+}
+
+impl Extension {
+    /// Makes a new `Extension` of the given type with the given contents
+    pub fn new(extension_type: ExtensionType, extension_data: Vec<u8>) -> Extension {
+        Extension {
+            extension_type,
+            extension_data,
+        }
+    }
+
+    /// Returns the type of this extension
+    pub fn get_type(&self) -> ExtensionType {
+        self.extension_type
+    }
+
+    /// Returns the contents of this extension
+    pub fn get_data(&self) -> &[u8] {
+        self.extension_data.as_slice()
+    }
+}
+
+/// Checks that the given extension list is fit to be a group's extensions
+///
+/// Returns: `Ok(())` on success. Returns an `Error::ValidationError` if two extensions have the
+/// same type.
+pub(crate) fn validate_extensions(extensions: &[Extension]) -> Result<(), Error> {
+    for (i, ext) in extensions.iter().enumerate() {
+        if extensions[..i].iter().any(|other| other.extension_type == ext.extension_type) {
+            return Err(Error::ValidationError("Extension list has duplicate extension types"));
+        }
+    }
+
+    Ok(())
+}
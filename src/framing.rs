@@ -0,0 +1,645 @@
+//! Defines `MlsMessage`, the single wire format for everything a member sends to the rest of the
+//! group. An `MlsMessage` is either an `MlsPlaintext`, which is signed by its sender, or a
+//! `PrivateApplicationMessage`, which is encrypted with the application key schedule. Either way,
+//! it carries an `MlsContent`, whose tag says whether it's a handshake, application data, or a
+//! proposal. Incoming messages are handled by `GroupState::process_message`.
+
+use crate::{
+    application::{self, ApplicationKeyChain, PrivateApplicationMessage},
+    credential::Credential,
+    crypto::sig::Signature,
+    error::Error,
+    group_state::{FailedHandshakes, GroupState},
+    handshake::{GroupOperation, Handshake, Proposal},
+    tls_de::TlsDeserializer,
+    tls_ser,
+    upcast::{CryptoCtx, CryptoUpcast},
+};
+
+use serde::de::Deserialize;
+
+// opaque application_data<0..2^32-1>;
+/// The bytes of an application message
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(rename = "ApplicationData__bound_u32")]
+pub struct ApplicationData(pub Vec<u8>);
+
+// enum {
+//     handshake(0),
+//     application(1),
+//     proposal(2),
+// } ContentType;
+//
+// struct {
+//     ContentType content_type;
+//     select (content_type) {
+//         case handshake:   Handshake;
+//         case application: ApplicationData;
+//         case proposal:    Proposal;
+//     };
+// } MlsContent;
+/// Anything a member can send to the group. The variant is the message's content type.
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(rename = "MlsContent__enum_u8")]
+pub enum MlsContent {
+    Handshake(Handshake),
+    Application(ApplicationData),
+    Proposal(Proposal),
+}
+
+impl MlsContent {
+    /// Returns: The roster index that this content says it was made by. Application data doesn't
+    /// say, so this returns `None` for it.
+    fn get_signer_index(&self) -> Option<u32> {
+        match self {
+            MlsContent::Handshake(handshake) => Some(handshake.signer_index),
+            MlsContent::Application(_) => None,
+            MlsContent::Proposal(proposal) => Some(proposal.sender_index),
+        }
+    }
+
+    /// Returns: The epoch that this content says it was made in. Application data doesn't say, so
+    /// this returns `None` for it.
+    fn get_epoch(&self) -> Option<u32> {
+        match self {
+            MlsContent::Handshake(handshake) => Some(handshake.prior_epoch),
+            MlsContent::Application(_) => None,
+            MlsContent::Proposal(proposal) => Some(proposal.prior_epoch),
+        }
+    }
+
+    /// Checks that this content agrees with the sender and epoch of the message that carries it.
+    /// This way, a member can't wrap someone else's `Handshake` or `Proposal` in their own message.
+    ///
+    /// Returns: `Ok(())` on success, and an `Error::ValidationError` otherwise
+    fn validate_framing(&self, sender: u32, epoch: u32) -> Result<(), Error> {
+        if matches!(self.get_signer_index(), Some(signer) if signer != sender) {
+            return Err(Error::ValidationError(
+                "Message's sender differs from its content's signer",
+            ));
+        }
+        if matches!(self.get_epoch(), Some(content_epoch) if content_epoch != epoch) {
+            return Err(Error::ValidationError("Message's epoch differs from its content's epoch"));
+        }
+
+        Ok(())
+    }
+}
+
+/// An `MlsContent` in the clear, signed by its sender. The delivery service can read all of it.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct MlsPlaintext {
+    // opaque group_id<0..255>;
+    #[serde(rename = "group_id__bound_u8")]
+    pub(crate) group_id: Vec<u8>,
+
+    /// The epoch the content was made in
+    pub(crate) epoch: u32,
+
+    /// The roster index of the sender
+    pub(crate) sender: u32,
+
+    // opaque content<0..2^32-1>;
+    /// The content, length-prefixed since a `Handshake` may end in data the spec has no room for.
+    /// See `WireHandshake`.
+    #[serde(rename = "content__bound_u32")]
+    pub(crate) content: MlsContent,
+
+    /// Signature over all the other fields of this struct:
+    /// `MlsPlaintext.signature = Sign(identity_key, group_id || epoch || sender || content)`
+    pub(crate) signature: Signature,
+}
+
+// This struct is everything but the last field in MlsPlaintext. We use the serialized form of this
+// as the message that the signature is computed over.
+#[derive(Serialize)]
+struct PartialMlsPlaintext<'a> {
+    #[serde(rename = "group_id__bound_u8")]
+    group_id: &'a [u8],
+    epoch: u32,
+    sender: u32,
+    #[serde(rename = "content__bound_u32")]
+    content: &'a MlsContent,
+}
+
+impl MlsPlaintext {
+    /// Checks that this message was sent by a member of the given group in its current epoch, and
+    /// that its signature verifies. A `Handshake` from a future epoch is the exception. It's
+    /// checked against the current roster if its sender is in it. Otherwise, the sender may not
+    /// have joined yet, so its signature isn't checked here. See below.
+    ///
+    /// Returns: `Ok(content)` on success. Returns an `Error::ValidationError` if the message isn't
+    /// from this group and epoch, or if its sender can't be found, and an `Error::SignatureError`
+    /// if its signature doesn't verify.
+    pub(crate) fn verify(self, group_state: &GroupState) -> Result<MlsContent, Error> {
+        if self.group_id != group_state.group_id {
+            return Err(Error::ValidationError("Message's group_id differs from the group's"));
+        }
+        let is_handshake = matches!(self.content, MlsContent::Handshake(_));
+        self.content.validate_framing(self.sender, self.epoch)?;
+
+        // A Handshake from a future epoch gets buffered. If its sender is someone we know, we check
+        // the signature now, so that nobody can fill the queue in their name. Otherwise, they may
+        // have joined in an epoch we haven't reached, so we can't look them up yet. This is fine,
+        // since the Handshake carries its own signature, which is checked against the roster of
+        // its epoch when it's applied, and the queue only holds a few of these.
+        let is_future_handshake = is_handshake && self.epoch > group_state.epoch;
+        let is_external_join = matches!(
+            self.content,
+            MlsContent::Handshake(Handshake {
+                operation: GroupOperation::ExternalJoin(_),
+                ..
+            })
+        );
+        if is_future_handshake && !is_external_join {
+            let sender_is_known =
+                matches!(group_state.roster.0.get(self.sender as usize), Some(Some(_)));
+            if !sender_is_known {
+                return Ok(self.content);
+            }
+        }
+
+        // A Handshake can also be from the previous epoch, in which case it might win a fork. Its
+        // sender is looked up in the previous epoch's roster.
+        let roster = if self.epoch == group_state.epoch || is_future_handshake {
+            &group_state.roster
+        } else {
+            match group_state.fork_point {
+                Some(ref fork_point) if is_handshake && self.epoch == fork_point.prior_epoch => {
+                    &fork_point.prior_roster
+                }
+                _ => return Err(Error::ValidationError("Message's epoch isn't the current epoch")),
+            }
+        };
+
+        // Someone who is joining externally isn't in the roster yet, so they bring their own
+        // credential. Everyone else's is in the roster.
+        let sender_credential: &Credential = match self.content {
+            MlsContent::Handshake(Handshake {
+                operation: GroupOperation::ExternalJoin(ref join),
+                ..
+            }) => &join.init_key.credential,
+            _ => roster
+                .0
+                .get(self.sender as usize)
+                .ok_or(Error::ValidationError("Message's sender index is out of bounds"))?
+                .as_ref()
+                .ok_or(Error::ValidationError("Message's sender credential is empty"))?,
+        };
+
+        let partial = PartialMlsPlaintext {
+            group_id: &self.group_id,
+            epoch: self.epoch,
+            sender: self.sender,
+            content: &self.content,
+        };
+        let serialized_partial = tls_ser::serialize_to_bytes(&partial)?;
+        let sig_scheme = sender_credential.get_signature_scheme();
+        let public_key = sender_credential.get_public_key();
+        sig_scheme.verify(public_key, &serialized_partial, &self.signature)?;
+
+        Ok(self.content)
+    }
+}
+
+// enum {
+//     plaintext(0),
+//     ciphertext(1),
+// } WireFormat;
+//
+// struct {
+//     WireFormat wire_format;
+//     select (wire_format) {
+//         case plaintext:  MlsPlaintext;
+//         case ciphertext: PrivateApplicationMessage;
+//     };
+// } MlsMessage;
+/// A framed message. This is what members send to each other, and what
+/// `GroupState::process_message` takes.
+///
+/// A `Ciphertext` is a `PrivateApplicationMessage` whose plaintext is a serialized `MlsContent`.
+/// This hides the content type and sender from everyone outside the group, but it can only be
+/// read by members with the sending epoch's `ApplicationKeyChain`. In particular, this means that
+/// a `Handshake` that adds someone must still be sent to them as an `MlsPlaintext`, and that only
+/// an `MlsPlaintext` `Handshake` can be buffered or win a fork.
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(rename = "MlsMessage__enum_u8")]
+pub enum MlsMessage {
+    Plaintext(MlsPlaintext),
+    Ciphertext(PrivateApplicationMessage),
+}
+
+/// The result of `GroupState::process_message`
+#[allow(clippy::large_enum_variant)]
+pub enum ProcessedMessage {
+    /// The message was a `Handshake`. This contains the same as
+    /// `GroupState::process_or_buffer_handshake` returns: the new group state, the key chain of
+    /// every epoch that was entered, in order, and the pending `Handshake`s that failed to process
+    /// along the way. If the `Handshake` was buffered, there are no key chains. If it won a fork,
+    /// this is the state it rolled back to.
+    Handshake(GroupState, Vec<ApplicationKeyChain>, FailedHandshakes),
+
+    /// The message was a `Handshake` from the previous epoch that doesn't replace the one this
+    /// member applied. Either it's the same one, or it lost the fork. The group state is
+    /// unchanged.
+    ForkIgnored,
+
+    /// The message was a `Handshake` from the previous epoch that beats the one this member
+    /// applied, so this member is on the losing side of a fork. This contains the `Handshake`,
+    /// which can be passed to `GroupState::resolve_fork` along with the previous epoch's
+    /// `GroupState` to roll back to it. Its signature has been verified.
+    ForkLost(Handshake),
+
+    /// The message was application data. This contains the plaintext.
+    Application(Vec<u8>),
+
+    /// The message was a `Proposal`. Its signature has been verified, and it can be passed to
+    /// `GroupState::create_and_apply_commit_handshake`.
+    Proposal(Proposal),
+}
+
+/// Frames the given content in an `MlsPlaintext`, signed by this member of the group. `group_state`
+/// must be the state that the content was made in. For `Handshake`s made with a
+/// `create_and_apply_*` method, this means the state the method was called on, not the one it
+/// returned. The only exception is an external join, whose `Handshake` is framed with the joiner's
+/// new state.
+///
+/// Returns: `Ok(message)` on success. Returns an `Error::ValidationError` if the content wasn't
+/// made by this member. Otherwise, returns some other sort of `Error`.
+pub fn frame_plaintext(content: MlsContent, group_state: &GroupState) -> Result<MlsMessage, Error> {
+    let sender = group_state
+        .roster_index
+        .ok_or(Error::ValidationError("Cannot frame a message with a preliminary GroupState"))?;
+    // Handshakes and proposals say what epoch they're from. Application data is from this one.
+    let epoch = content.get_epoch().unwrap_or(group_state.epoch);
+    content.validate_framing(sender, epoch)?;
+
+    let partial = PartialMlsPlaintext {
+        group_id: &group_state.group_id,
+        epoch,
+        sender,
+        content: &content,
+    };
+    let serialized_partial = tls_ser::serialize_to_bytes(&partial)?;
+    let signature =
+        group_state.get_signature_scheme().sign(&group_state.identity_key, &serialized_partial);
+
+    Ok(MlsMessage::Plaintext(MlsPlaintext {
+        group_id: group_state.group_id.clone(),
+        epoch,
+        sender,
+        content,
+        signature,
+    }))
+}
+
+/// Frames the given content in a `PrivateApplicationMessage`, encrypted with the given key chain.
+/// `group_state` and `app_key_chain` must be from the epoch that the content was made in. For
+/// `Handshake`s made with a `create_and_apply_*` method, this means the state the method was called
+/// on, not the one it returned.
+///
+/// Returns: `Ok(message)` on success. Returns an `Error::ValidationError` if the content wasn't
+/// made by this member in this epoch, or if the key chain isn't this epoch's. Otherwise, returns
+/// some other sort of `Error`.
+pub fn frame_ciphertext(
+    content: MlsContent,
+    group_state: &GroupState,
+    app_key_chain: &mut ApplicationKeyChain,
+) -> Result<MlsMessage, Error> {
+    let sender = group_state
+        .roster_index
+        .ok_or(Error::ValidationError("Cannot frame a message with a preliminary GroupState"))?;
+    content.validate_framing(sender, group_state.epoch)?;
+
+    let serialized_content = tls_ser::serialize_to_bytes(&content)?;
+    let app_message =
+        application::encrypt_application_message(serialized_content, group_state, app_key_chain)?;
+    let private_app_message = app_message.encrypt_sender_data(app_key_chain)?;
+
+    Ok(MlsMessage::Ciphertext(private_app_message))
+}
+
+/// Decrypts and authenticates the content of a framed `PrivateApplicationMessage` with the given
+/// key chain
+///
+/// Returns: `Ok(content)` on success. Returns an `Error::ValidationError` if the content doesn't
+/// agree with the sender of the message. Otherwise, returns whatever
+/// `decrypt_application_message` would.
+pub(crate) fn decrypt_ciphertext(
+    private_app_message: PrivateApplicationMessage,
+    group_state: &GroupState,
+    app_key_chain: &mut ApplicationKeyChain,
+) -> Result<MlsContent, Error> {
+    let app_message = private_app_message.decrypt_sender_data(app_key_chain)?;
+    let sender = app_message.get_sender();
+    let serialized_content =
+        application::decrypt_application_message(app_message, group_state, app_key_chain)?;
+
+    // The message's signature verified, so the sender is in the roster. Their signature scheme
+    // tells us how to interpret the signatures inside the content.
+    let sender_ss = group_state
+        .roster
+        .0
+        .get(sender as usize)
+        .and_then(Option::as_ref)
+        .ok_or(Error::ValidationError("Message's sender credential is empty"))?
+        .get_signature_scheme();
+    let content = {
+        let mut cursor = serialized_content.as_slice();
+        let mut deserializer = TlsDeserializer::from_bytes(&mut cursor);
+        let mut content = MlsContent::deserialize(&mut deserializer)?;
+        let ctx = CryptoCtx::new().set_cipher_suite(group_state.cs).set_signature_scheme(sender_ss);
+        content.upcast_crypto_values(&ctx)?;
+        content
+    };
+    content.validate_framing(sender, group_state.epoch)?;
+
+    Ok(content)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{frame_ciphertext, frame_plaintext, ApplicationData, MlsContent, MlsMessage};
+    use crate::{
+        application::ApplicationKeyChain,
+        crypto::sig::SignatureScheme,
+        error::Error,
+        framing::ProcessedMessage,
+        group_state::{GroupState, Welcome},
+        ratchet_tree::PathSecret,
+        test_utils,
+        tls_de::TlsDeserializer,
+        tls_ser,
+        upcast::{CryptoCtx, CryptoUpcast},
+    };
+
+    use core::convert::TryFrom;
+
+    use quickcheck_macros::quickcheck;
+    use rand::{rngs::StdRng, SeedableRng};
+    use serde::de::Deserialize;
+
+    // Frames the given content with the given group state, encrypting it if a key chain is given
+    fn frame(
+        content: MlsContent,
+        group_state: &GroupState,
+        app_key_chain: Option<&mut ApplicationKeyChain>,
+    ) -> MlsMessage {
+        match app_key_chain {
+            Some(app_key_chain) => frame_ciphertext(content, group_state, app_key_chain).unwrap(),
+            None => frame_plaintext(content, group_state).unwrap(),
+        }
+    }
+
+    // Sends the given message over the "wire". This is what the receiver has to work with.
+    fn send(
+        message: &MlsMessage,
+        group_state: &GroupState,
+        ss: &'static SignatureScheme,
+    ) -> MlsMessage {
+        let bytes = tls_ser::serialize_to_bytes(message).unwrap();
+        let mut cursor = bytes.as_slice();
+        let mut deserializer = TlsDeserializer::from_reader(&mut cursor);
+        let mut message = MlsMessage::deserialize(&mut deserializer).unwrap();
+        let ctx = CryptoCtx::new().set_cipher_suite(group_state.cs).set_signature_scheme(ss);
+        message.upcast_crypto_values(&ctx).unwrap();
+        message
+    }
+
+    // Checks that every kind of content survives being framed, sent, and processed, both in the
+    // clear and encrypted, and that content can't be framed by someone other than its signer
+    #[quickcheck]
+    fn framing_correctness(rng_seed: u64) {
+        let mut rng = StdRng::seed_from_u64(rng_seed);
+
+        // Make two perspectives of the same group
+        let (group1, identity_keys) = test_utils::random_full_group_state(2, &mut rng);
+        let idx1 = group1.roster_index.unwrap();
+        let idx2 = test_utils::random_roster_index_with_exceptions(
+            group1.roster.len(),
+            &[idx1 as usize],
+            &mut rng,
+        );
+        let group2 = test_utils::change_self_index(&group1, &identity_keys, idx2);
+        let ss = group1.get_signature_scheme();
+
+        // Do an Update so that both members have a key chain
+        let new_path_secret = PathSecret::new_from_random(group1.cs, &mut rng);
+        let (handshake, mut group1, mut key_chain1) =
+            group1.create_and_apply_update_handshake(new_path_secret, &mut rng).unwrap();
+        let (mut group2, mut key_chain2) = group2.process_handshake(&handshake).unwrap();
+
+        for &encrypt in &[false, true] {
+            // Application data
+            let content = MlsContent::Application(ApplicationData(b"hello".to_vec()));
+            let message = frame(content, &group1, Some(&mut key_chain1).filter(|_| encrypt));
+            match group2.process_message(send(&message, &group2, ss), &mut key_chain2).unwrap() {
+                ProcessedMessage::Application(plaintext) => assert_eq!(plaintext, b"hello"),
+                _ => panic!("Application data was processed as something else"),
+            }
+
+            // A proposal
+            let proposal = group1.create_remove_proposal(idx2).unwrap();
+            let content = MlsContent::Proposal(proposal.clone());
+            let message = frame(content, &group1, Some(&mut key_chain1).filter(|_| encrypt));
+            match group2.process_message(send(&message, &group2, ss), &mut key_chain2).unwrap() {
+                ProcessedMessage::Proposal(p) => {
+                    assert_serialized_eq!(p, proposal);
+                }
+                _ => panic!("Proposal was processed as something else"),
+            }
+
+            // Nobody else can frame that proposal
+            match frame_plaintext(MlsContent::Proposal(proposal), &group2) {
+                Err(Error::ValidationError(_)) => (),
+                _ => panic!("Someone else's proposal was framed"),
+            }
+
+            // A handshake is framed with the state it was made in
+            let new_path_secret = PathSecret::new_from_random(group1.cs, &mut rng);
+            let (handshake, new_group1, new_key_chain1) =
+                group1.create_and_apply_update_handshake(new_path_secret, &mut rng).unwrap();
+            let content = MlsContent::Handshake(handshake);
+            let message = frame(content, &group1, Some(&mut key_chain1).filter(|_| encrypt));
+            match group2.process_message(send(&message, &group2, ss), &mut key_chain2).unwrap() {
+                ProcessedMessage::Handshake(new_group2, mut new_key_chains2, _) => {
+                    assert_serialized_eq!(new_group1, new_group2, "GroupStates disagree");
+                    assert_eq!(new_key_chains2.len(), 1);
+                    group2 = new_group2;
+                    key_chain2 = new_key_chains2.pop().unwrap();
+                }
+                _ => panic!("Handshake was processed as something else"),
+            }
+            group1 = new_group1;
+            key_chain1 = new_key_chain1;
+        }
+
+        // Tampering with a plaintext breaks its signature
+        let content = MlsContent::Application(ApplicationData(b"hello".to_vec()));
+        let mut message = frame_plaintext(content, &group1).unwrap();
+        match message {
+            MlsMessage::Plaintext(ref mut plaintext) => {
+                plaintext.content = MlsContent::Application(ApplicationData(b"bye".to_vec()))
+            }
+            MlsMessage::Ciphertext(_) => unreachable!(),
+        }
+        match group2.process_message(send(&message, &group2, ss), &mut key_chain2) {
+            Err(Error::SignatureError(_)) => (),
+            _ => panic!("Tampered message was accepted"),
+        }
+    }
+
+    // Checks that framed Handshakes from a future epoch are buffered, and that framed Handshakes
+    // from the previous epoch resolve forks
+    #[quickcheck]
+    fn framed_handshake_ordering(rng_seed: u64) {
+        let mut rng = StdRng::seed_from_u64(rng_seed);
+
+        // Make two perspectives of the same group, and a key chain for the receiver
+        let (group1, identity_keys) = test_utils::random_full_group_state(2, &mut rng);
+        let idx1 = group1.roster_index.unwrap();
+        let idx2 = test_utils::random_roster_index_with_exceptions(
+            group1.roster.len(),
+            &[idx1 as usize],
+            &mut rng,
+        );
+        let group2 = test_utils::change_self_index(&group1, &identity_keys, idx2);
+        let ss = group1.get_signature_scheme();
+        let new_path_secret = PathSecret::new_from_random(group1.cs, &mut rng);
+        let (handshake, group1, _) =
+            group1.create_and_apply_update_handshake(new_path_secret, &mut rng).unwrap();
+        let (group2, mut key_chain2) = group2.process_handshake(&handshake).unwrap();
+
+        // Make two Updates in a row, and deliver the second one first. It gets buffered.
+        let new_path_secret = PathSecret::new_from_random(group1.cs, &mut rng);
+        let (first_handshake, first_group1, _) =
+            group1.create_and_apply_update_handshake(new_path_secret, &mut rng).unwrap();
+        let new_path_secret = PathSecret::new_from_random(group1.cs, &mut rng);
+        let (second_handshake, second_group1, _) =
+            first_group1.create_and_apply_update_handshake(new_path_secret, &mut rng).unwrap();
+        let first_message =
+            frame_plaintext(MlsContent::Handshake(first_handshake), &group1).unwrap();
+        let second_message =
+            frame_plaintext(MlsContent::Handshake(second_handshake), &first_group1).unwrap();
+
+        // Its signer is in group2's roster, so a forgery in their name doesn't get buffered
+        let mut forged_message = second_message.clone();
+        match (&mut forged_message, &first_message) {
+            (MlsMessage::Plaintext(forged), MlsMessage::Plaintext(first)) => {
+                forged.signature = first.signature.clone()
+            }
+            _ => unreachable!(),
+        }
+        match group2.process_message(send(&forged_message, &group2, ss), &mut key_chain2) {
+            Err(Error::SignatureError(_)) => (),
+            _ => panic!("Forged future Handshake was accepted"),
+        }
+
+        let group2 = match group2
+            .process_message(send(&second_message, &group2, ss), &mut key_chain2)
+            .unwrap()
+        {
+            ProcessedMessage::Handshake(new_group2, new_key_chains2, _) => {
+                assert!(new_key_chains2.is_empty());
+                assert_eq!(new_group2.get_pending_handshakes().len(), 1);
+                new_group2
+            }
+            _ => panic!("Handshake was processed as something else"),
+        };
+
+        // Delivering the first one catches up on both
+        let group2 = match group2
+            .process_message(send(&first_message, &group2, ss), &mut key_chain2)
+            .unwrap()
+        {
+            ProcessedMessage::Handshake(new_group2, new_key_chains2, _) => {
+                assert_eq!(new_key_chains2.len(), 2);
+                new_group2
+            }
+            _ => panic!("Handshake was processed as something else"),
+        };
+        assert_serialized_eq!(second_group1, group2, "GroupStates disagree after catching up");
+        let group1 = second_group1;
+
+        // Now both make an Update from the same epoch. Each frames theirs and processes the
+        // other's.
+        let new_path_secret = PathSecret::new_from_random(group1.cs, &mut rng);
+        let (handshake1, forked_group1, mut forked_key_chain1) =
+            group1.create_and_apply_update_handshake(new_path_secret, &mut rng).unwrap();
+        let new_path_secret = PathSecret::new_from_random(group2.cs, &mut rng);
+        let (handshake2, forked_group2, mut forked_key_chain2) =
+            group2.create_and_apply_update_handshake(new_path_secret, &mut rng).unwrap();
+        let message1 = frame_plaintext(MlsContent::Handshake(handshake1), &group1).unwrap();
+        let message2 = frame_plaintext(MlsContent::Handshake(handshake2), &group2).unwrap();
+        let res1 = forked_group1
+            .process_message(send(&message2, &forked_group1, ss), &mut forked_key_chain1)
+            .unwrap();
+        let res2 = forked_group2
+            .process_message(send(&message1, &forked_group2, ss), &mut forked_key_chain2)
+            .unwrap();
+
+        // The winner stays put, the loser rolls back from the state they came from, and they end
+        // up in the same place
+        let (resolved_group1, resolved_group2) = match (res1, res2) {
+            (ProcessedMessage::ForkIgnored, ProcessedMessage::ForkLost(handshake))
+                if idx1 < idx2 =>
+            {
+                let (resolved_group2, _) = forked_group2.resolve_fork(&group2, &handshake).unwrap();
+                (forked_group1, resolved_group2)
+            }
+            (ProcessedMessage::ForkLost(handshake), ProcessedMessage::ForkIgnored)
+                if idx2 < idx1 =>
+            {
+                let (resolved_group1, _) = forked_group1.resolve_fork(&group1, &handshake).unwrap();
+                (resolved_group1, forked_group2)
+            }
+            _ => panic!("Fork wasn't resolved in favor of the lower roster index"),
+        };
+        assert_serialized_eq!(resolved_group1, resolved_group2, "GroupStates disagree after fork");
+        let (group1, mut key_chain2) = (resolved_group1, forked_key_chain2);
+        let group2 = resolved_group2;
+
+        // Someone new is added, and their first Update reaches group2 before the Add does. Their
+        // signer isn't in group2's roster yet, but the Update is buffered anyway, and applied once
+        // the Add arrives.
+        let (init_key, identity_key) = test_utils::random_user_init_key(&mut rng, group1.cs);
+        let (welcome, welcome_info_hash) =
+            Welcome::from_group_state(&group1, &init_key, &mut rng).unwrap();
+        let new_roster_idx = u32::try_from(group1.roster.len()).unwrap();
+        let (add_handshake, added_group1, _) = group1
+            .create_and_apply_add_handshake(new_roster_idx, init_key.clone(), &welcome_info_hash)
+            .unwrap();
+        let new_group = GroupState::from_welcome(welcome, identity_key, init_key).unwrap();
+        let (new_group, _) = new_group.process_handshake(&add_handshake).unwrap();
+        let new_path_secret = PathSecret::new_from_random(new_group.cs, &mut rng);
+        let (update_handshake, updated_new_group, _) =
+            new_group.create_and_apply_update_handshake(new_path_secret, &mut rng).unwrap();
+        let add_message = frame_plaintext(MlsContent::Handshake(add_handshake), &group1).unwrap();
+        assert_serialized_eq!(added_group1, new_group, "GroupStates disagree after Add");
+        let update_message =
+            frame_plaintext(MlsContent::Handshake(update_handshake), &new_group).unwrap();
+        let group2 = match group2
+            .process_message(send(&update_message, &group2, ss), &mut key_chain2)
+            .unwrap()
+        {
+            ProcessedMessage::Handshake(new_group2, new_key_chains2, _) => {
+                assert!(new_key_chains2.is_empty());
+                assert_eq!(new_group2.get_pending_handshakes().len(), 1);
+                new_group2
+            }
+            _ => panic!("Handshake was processed as something else"),
+        };
+        let group2 =
+            match group2.process_message(send(&add_message, &group2, ss), &mut key_chain2).unwrap()
+            {
+                ProcessedMessage::Handshake(new_group2, new_key_chains2, failed) => {
+                    assert_eq!(new_key_chains2.len(), 2);
+                    assert!(failed.is_empty());
+                    new_group2
+                }
+                _ => panic!("Handshake was processed as something else"),
+            };
+        assert_serialized_eq!(updated_new_group, group2, "GroupStates disagree after catching up");
+    }
+}
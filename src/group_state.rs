@@ -1,32 +1,64 @@
 //! Defines the `GroupState` object, which is the primary interface for creating and processing MLS
 //! group operations
+//!
+//! Some of what's here has no counterpart in the spec: the external key pair, reinitialization and
+//! branching, mixing the tree hash into the key schedule, and `WelcomeInfoExtras`. These are our
+//! own constructions. The secrets among them are derived in the style of the key schedule.
 
 use crate::{
     application::ApplicationKeyChain,
+    clock::{Clock, SystemClock},
     credential::{Credential, Roster},
     crypto::{
+        aead::{AeadKey, AeadNonce},
         ciphersuite::CipherSuite,
-        dh::DhPrivateKey,
+        dh::{DhPrivateKey, DhPublicKey},
         ecies::{self, EciesCiphertext},
         hash::Digest,
         hkdf,
         hmac::{self, HmacKey},
         rng::CryptoRng,
-        sig::{SigSecretKey, SignatureScheme},
+        sig::{SigSecretKey, Signature, SignatureRaw, SignatureScheme},
     },
     error::Error,
+    extension::{self, Extension},
+    framing::{self, MlsContent, MlsMessage, ProcessedMessage},
     handshake::{
-        GroupAdd, GroupOperation, GroupRemove, GroupUpdate, Handshake, ProtocolVersion, UserInitKey,
+        AddProposal, DirectPathMessage, GroupAdd, GroupCommit, GroupExtensions, GroupExternalJoin,
+        GroupInit, GroupMultiAdd, GroupOperation, GroupReInit, GroupRemove, GroupUpdate, Handshake,
+        Proposal, ProposalBody, ProtocolVersion, PskProposal, RemoveProposal, SealedOperation,
+        UpdateProposal, UserInitKey, WireOperation,
     },
-    ratchet_tree::{NodeSecret, PathSecret, RatchetTree, RatchetTreeNode},
+    policy::{AllowAll, MembershipPolicy},
+    psk::{self, NoPsks, PskStore},
+    ratchet_tree::{self, NodeParentHash, NodeSecret, PathSecret, RatchetTree, RatchetTreeNode},
     tls_de::TlsDeserializer,
     tls_ser,
     upcast::{CryptoCtx, CryptoUpcast},
 };
 
+use core::convert::TryFrom;
+
 use serde::de::Deserialize;
 use subtle::ConstantTimeEq;
 
+/// The maximum number of future-epoch `Handshake`s a `GroupState` will hold on to. This is also
+/// how many epochs ahead of the current one a `Handshake` can be and still get buffered.
+pub const MAX_PENDING_HANDSHAKES: usize = 32;
+
+/// The maximum number of future-epoch `Handshake`s a `GroupState` will hold on to from any one
+/// signer. This keeps a single signer from crowding everyone else out of the pending queue.
+pub const MAX_PENDING_HANDSHAKES_PER_SENDER: usize = 8;
+
+/// The maximum number of future-epoch `Handshake`s a `GroupState` will hold on to from signers
+/// outside its current roster, all together. Nothing about these can be checked until they're
+/// applied, so anyone can make them up, under as many signer indices as they like.
+pub const MAX_PENDING_HANDSHAKES_FROM_UNKNOWN_SIGNERS: usize = 4;
+
+/// The pending `Handshake`s that became ready but failed to process, each with the reason it
+/// failed. See `GroupState::process_or_buffer_handshake`.
+pub type FailedHandshakes = Vec<(Handshake, Error)>;
+
 /// This is called the `application_secret` in the MLS key schedule
 pub(crate) struct ApplicationSecret(HmacKey);
 
@@ -83,6 +115,109 @@ impl From<NodeSecret> for UpdateSecret {
     }
 }
 
+/// Describes whether a member is still in the group, from that member's perspective
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename = "MembershipStatus__enum_u8")]
+pub enum MembershipStatus {
+    /// An ordinary member of the group
+    Member,
+    /// This member has announced that they're leaving, but no handshake has removed them yet
+    Leaving,
+    /// This member has been removed after asking to leave. This is a terminal state: a
+    /// `GroupState` in this state can't process or create any more messages.
+    Left,
+}
+
+/// Describes how a `Handshake` relates to the `Handshake` that brought the group to its current
+/// epoch. When two members make a `Handshake` from the same epoch, the group forks. The fork is
+/// resolved by letting the `Handshake` with the lowest `signer_index` win, no matter who made it
+/// or in which order a member received them.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ForkStatus {
+    /// The `Handshake` does not compete with the one this member applied
+    NoFork,
+    /// The `Handshake` competes with the one this member applied, and the applied one wins. There
+    /// is nothing to do. Members who applied the other `Handshake` should roll back.
+    Won,
+    /// The `Handshake` competes with the one this member applied, and the other one wins. This
+    /// member should roll back with `GroupState::resolve_fork`.
+    Lost,
+}
+
+/// Everything besides the `Handshake` itself that processing it depends on: where the PSKs
+/// referenced by a `Commit` are looked up, which `MembershipPolicy` may veto the operation, and
+/// the `Clock` that the lifetimes of new members' `UserInitKey`s are checked against. Making a
+/// `Handshake` depends on the same things, since the maker has to apply it just like everyone
+/// else. The default context has no PSKs, a policy that allows everything but external joins, and
+/// the system clock.
+pub struct ProcessingContext<'a> {
+    psks: &'a dyn PskStore,
+    policy: &'a dyn MembershipPolicy,
+    clock: &'a dyn Clock,
+}
+
+impl<'a> ProcessingContext<'a> {
+    /// Makes the default context
+    pub fn new() -> ProcessingContext<'a> {
+        ProcessingContext {
+            psks: &NoPsks,
+            policy: &AllowAll,
+            clock: &SystemClock,
+        }
+    }
+
+    /// Looks up the PSKs referenced by a `Commit` in the given `PskStore`
+    pub fn with_psks(self, psks: &'a dyn PskStore) -> ProcessingContext<'a> {
+        ProcessingContext {
+            psks,
+            ..self
+        }
+    }
+
+    /// Asks the given `MembershipPolicy` whether the sender may perform each operation
+    pub fn with_policy(self, policy: &'a dyn MembershipPolicy) -> ProcessingContext<'a> {
+        ProcessingContext {
+            policy,
+            ..self
+        }
+    }
+
+    /// Checks the lifetimes of the `UserInitKey`s of new members against the given `Clock`
+    pub fn with_clock(self, clock: &'a dyn Clock) -> ProcessingContext<'a> {
+        ProcessingContext {
+            clock,
+            ..self
+        }
+    }
+}
+
+impl Default for ProcessingContext<'_> {
+    fn default() -> Self {
+        ProcessingContext::new()
+    }
+}
+
+/// Where a `GroupState` came from. This is kept for one epoch, so that a `Handshake` that competes
+/// with the one that was applied to get here can be recognized. It's only the public part of the
+/// previous epoch. None of its secrets are kept, so rolling back takes the previous `GroupState`,
+/// which only the caller has.
+#[derive(Clone, Deserialize, Serialize)]
+pub(crate) struct ForkPoint {
+    /// The epoch that the applied `Handshake` was made in
+    pub(crate) prior_epoch: u32,
+
+    // optional<Credential> prior_roster<1..2^32-1>;
+    /// The roster of that epoch. Competing `Handshake`s are signed by members of it.
+    #[serde(rename = "prior_roster__bound_u32")]
+    pub(crate) prior_roster: Roster,
+
+    /// The transcript hash of that epoch. Competing `Handshake`s are applied on top of it.
+    pub(crate) prior_transcript_hash: Digest,
+
+    /// The roster index of whoever signed the applied `Handshake`
+    pub(crate) signer_index: u32,
+}
+
 /// Contains all group state
 #[derive(Clone, Serialize)]
 pub struct GroupState {
@@ -118,10 +253,22 @@ pub struct GroupState {
     /// this group. The number of leaves in this tree MUST be equal to the length of `roster`
     pub(crate) tree: RatchetTree,
 
+    /// The hash of `tree`, which also covers the parent hashes that the serialized `tree` leaves
+    /// out. It isn't serialized. It is kept up to date by the key schedule, which mixes it into the
+    /// epoch secret.
+    #[serde(skip)]
+    pub(crate) tree_hash: Digest,
+
     // opaque transcript_hash<0..255>;
     /// Contains a running hash of `GroupOperation` messages that led to this state
     pub(crate) transcript_hash: Digest,
 
+    // Extension extensions<0..2^16-1>;
+    /// Application-defined metadata about the group. It is omitted from the serialized form when
+    /// empty, so that groups without extensions serialize as the spec says.
+    #[serde(rename = "extensions__bound_u16", skip_serializing_if = "Vec::is_empty")]
+    pub(crate) extensions: Vec<Extension>,
+
     /// The member's position in the roster. This is also known as `signer_index`. It is `None` iff
     /// this `GroupState` is in a preliminary state, i.e., iff it is between a `Welcome` and `Add`
     /// operation.
@@ -134,6 +281,68 @@ pub struct GroupState {
     #[serde(skip)]
     pub(crate) initializing_user_init_key: Option<UserInitKey>,
 
+    /// The public key of the last-resort `UserInitKey` this member joined with, if it joined with
+    /// one. Until this member's leaf stops having this key, the member should send an `Update`.
+    #[serde(skip)]
+    pub(crate) last_resort_public_key: Option<DhPublicKey>,
+
+    /// The private key corresponding to this member's most recent `Update` proposal in the current
+    /// epoch, if any. This is needed if that proposal gets committed.
+    #[serde(skip)]
+    pub(crate) pending_update_private_key: Option<DhPrivateKey>,
+
+    /// Whether this member is in the group, on their way out, or gone
+    #[serde(skip)]
+    pub(crate) membership_status: MembershipStatus,
+
+    /// Handshakes that arrived for a future epoch, in the order they arrived. These are applied
+    /// once the epochs before them have been processed, and dropped once the group moves past
+    /// their epoch. There are at most `MAX_PENDING_HANDSHAKES` of them, at most
+    /// `MAX_PENDING_HANDSHAKES_PER_SENDER` from any one signer, at most
+    /// `MAX_PENDING_HANDSHAKES_FROM_UNKNOWN_SIGNERS` from signers outside the roster, and none of
+    /// them more than `MAX_PENDING_HANDSHAKES` epochs ahead.
+    #[serde(skip)]
+    pub(crate) pending_handshakes: Vec<Handshake>,
+
+    /// The public part of the previous epoch, i.e., its epoch number, roster, and transcript hash,
+    /// along with the roster index of whoever signed the `Handshake` that brought the group from
+    /// there to the current epoch, whether that was this member or someone else. This is enough to
+    /// tell whether a competing `Handshake` wins a fork. It holds none of the previous epoch's
+    /// secrets, so rolling back takes the previous `GroupState`. See `GroupState::resolve_fork`.
+    #[serde(skip)]
+    pub(crate) fork_point: Option<ForkPoint>,
+
+    /// The `ReInit` operation that ended this group, if any. A group that has been reinitialized
+    /// accepts no further `Handshake`s. Its members move to the successor group instead.
+    #[serde(skip)]
+    pub(crate) reinit: Option<GroupReInit>,
+
+    /// The secret that `GroupState::export_secret` derives from. This is `None` until this member
+    /// has seen an epoch change, since a new member is only told the init secret.
+    #[serde(skip)]
+    pub(crate) exporter_secret: Option<HmacKey>,
+
+    /// The secret that successor groups are linked to. Like `exporter_secret`, this is `None`
+    /// until this member has seen an epoch change.
+    #[serde(skip)]
+    pub(crate) resumption_secret: Option<HmacKey>,
+
+    /// The secret that the keys protecting the senders of application messages are derived from.
+    /// Like `exporter_secret`, this is `None` until this member has seen an epoch change.
+    #[serde(skip)]
+    pub(crate) sender_data_secret: Option<HmacKey>,
+
+    /// The secret that the keys for sealing `Handshake`s are derived from. Like `exporter_secret`,
+    /// this is `None` until this member has seen an epoch change.
+    #[serde(skip)]
+    pub(crate) handshake_secret: Option<HmacKey>,
+
+    /// The seal secret of whoever sent this member their `Welcome`, for the epoch the `Welcome` is
+    /// from. This opens the `Handshake` that adds this member, if it's sealed. It's forgotten once
+    /// the epoch changes.
+    #[serde(skip)]
+    pub(crate) welcome_seal_secret: Option<SealSecret>,
+
     /// The initial secret used to derive `application_secret` and `confirmation_key`
     #[serde(skip)]
     pub(crate) init_secret: HmacKey,
@@ -159,19 +368,25 @@ impl GroupState {
     where
         R: CryptoRng,
     {
-        // Turn the credential into a singleton roster
-        let roster = Roster(vec![Some(my_credential)]);
-        let my_roster_index = 0u32;
-
-        // Make an ephemeral keypair and turn it into a tree
+        // Make an ephemeral keypair and turn it into a tree. We sign our leaf, since nobody else
+        // will vouch for it.
         let my_ephemeral_secret = DhPrivateKey::new_from_random(cs.dh_impl, csprng)?;
-        let my_node = RatchetTreeNode::new_from_private_key(cs, my_ephemeral_secret);
+        let my_public_key = DhPublicKey::new_from_private_key(cs.dh_impl, &my_ephemeral_secret);
+        let my_leaf_content = ratchet_tree::leaf_signature_content(&my_public_key, &[])?;
+        let my_signature =
+            my_credential.get_signature_scheme().sign(&identity_key, &my_leaf_content);
+        let my_node = RatchetTreeNode::new_from_private_key(cs, my_ephemeral_secret)
+            .with_leaf_signature(my_signature);
         let tree = RatchetTree {
             nodes: vec![my_node],
         };
 
+        // Turn the credential into a singleton roster
+        let roster = Roster(vec![Some(my_credential)]);
+        let my_roster_index = 0u32;
+
         // Now make the GroupState normally
-        Ok(GroupState::new_from_parts(
+        GroupState::new_from_parts(
             cs,
             protocol_version,
             identity_key,
@@ -179,10 +394,13 @@ impl GroupState {
             roster,
             my_roster_index,
             tree,
-        ))
+        )
     }
 
     /// Creates a new `GroupState` from its constituent parts
+    ///
+    /// Returns: `Ok(group_state)` on success. If hashing the tree fails, returns some sort of
+    /// `Error`.
     pub(crate) fn new_from_parts(
         cs: &'static CipherSuite,
         protocol_version: ProtocolVersion,
@@ -191,12 +409,13 @@ impl GroupState {
         roster: Roster,
         roster_index: u32,
         tree: RatchetTree,
-    ) -> GroupState {
+    ) -> Result<GroupState, Error> {
         // Transcript hash and init secrets are both zeros to begin with
         let transcript_hash = Digest::new_from_zeros(cs.hash_impl);
         let init_secret = HmacKey::new_from_zeros(cs.hash_impl);
+        let tree_hash = tree.tree_hash(cs)?;
 
-        GroupState {
+        Ok(GroupState {
             cs,
             protocol_version,
             identity_key,
@@ -204,20 +423,34 @@ impl GroupState {
             epoch: 0,
             roster,
             tree,
+            tree_hash,
             transcript_hash,
+            extensions: Vec::new(),
             roster_index: Some(roster_index),
             initializing_user_init_key: None,
+            last_resort_public_key: None,
+            pending_update_private_key: None,
+            membership_status: MembershipStatus::Member,
+            pending_handshakes: Vec::new(),
+            fork_point: None,
+            reinit: None,
+            exporter_secret: None,
+            resumption_secret: None,
+            sender_data_secret: None,
+            handshake_secret: None,
+            welcome_seal_secret: None,
             init_secret,
-        }
+        })
     }
 
     /// Initializes a preliminary `GroupState` with the given `WelcomeInfo` information, this
     /// member's identity key, and the `UserInitKey` used to encrypt the `Welcome` that the
     /// `WelcomeInfo` came from.
     ///
-    /// Returns: A `GroupState` in a "preliminary state", meaning that `roster_index` is `None` and
-    /// `initializing_user_init_key` is `Some`. The only thing to do with a preliminary
-    /// `GroupState` is give it an `Add` operation to add yourself to it.
+    /// Returns: `Ok(group_state)` on success, where `group_state` is in a "preliminary state",
+    /// meaning that `roster_index` is `None` and `initializing_user_init_key` is `Some`. The only
+    /// thing to do with a preliminary `GroupState` is give it an `Add` or `Init` operation to add
+    /// yourself to it. Returns some sort of `Error` if the tree's parent hashes don't check out.
     // This is different from new_from_parts in that the epoch is not 0, the transcript hash is not
     // 0, the init secret is not 0, and the roster index is None
     pub(crate) fn from_welcome_info(
@@ -225,45 +458,123 @@ impl GroupState {
         w: WelcomeInfo,
         my_identity_key: SigSecretKey,
         initializing_user_init_key: UserInitKey,
-    ) -> GroupState {
+    ) -> Result<GroupState, Error> {
+        // Don't trust the tree we were given until every key in it is vouched for by a member
+        let mut tree = w.tree;
+        tree.set_parent_hashes(w.extras.tree_parent_hashes)?;
+        tree.verify_parent_hashes(cs, &w.roster)?;
+        let tree_hash = tree.tree_hash(cs)?;
+
         // Make a new preliminary group (notice how roster is None and initializing_user_init_key
         // is Some)
-        GroupState {
+        Ok(GroupState {
             cs,
             protocol_version: w.protocol_version,
             identity_key: my_identity_key,
             group_id: w.group_id,
             epoch: w.epoch,
             roster: w.roster,
-            tree: w.tree,
+            tree,
+            tree_hash,
             transcript_hash: w.transcript_hash,
+            extensions: w.extras.extensions,
             roster_index: None,
             initializing_user_init_key: Some(initializing_user_init_key),
+            last_resort_public_key: None,
+            pending_update_private_key: None,
+            membership_status: MembershipStatus::Member,
+            pending_handshakes: Vec::new(),
+            fork_point: None,
+            reinit: None,
+            exporter_secret: None,
+            resumption_secret: None,
+            sender_data_secret: None,
+            handshake_secret: None,
+            welcome_seal_secret: w.extras.seal_secret,
             init_secret: w.init_secret,
-        }
+        })
     }
 
     /// Creates a new `GroupState` from a `Welcome` message, this member's identity key, and the
-    /// `UserInitKey` this member used to introduce themselves to the group
+    /// `UserInitKey` this member used to introduce themselves to the group. The `UserInitKey`'s
+    /// lifetime is checked against the system clock.
     ///
     /// Requires: That the `init_key` is the `UserInitKey` that the `Welcome` was encrypted with
     /// (i.e., `init_key.user_init_key_id == self.user_init_key_id`) and `init_key.private_keys`
     /// is not `None`
+    pub fn from_welcome(
+        welcome: Welcome,
+        identity_secret_key: SigSecretKey,
+        init_key: UserInitKey,
+    ) -> Result<GroupState, Error> {
+        GroupState::from_welcome_with_clock(welcome, identity_secret_key, init_key, &SystemClock)
+    }
+
+    /// Like `from_welcome`, but checks the `UserInitKey`'s lifetime against the given `Clock`
+    ///
+    /// Returns: The same as `from_welcome`. If the current time is outside of `init_key`'s
+    /// lifetime, returns an `Error::ValidationError`.
     // This is just a convenient wrapper around welcome.into_welcome_info_cipher_suite and
     // GroupState::from_welcome_info
-    pub fn from_welcome(
+    pub fn from_welcome_with_clock<C: Clock>(
         welcome: Welcome,
         identity_secret_key: SigSecretKey,
         init_key: UserInitKey,
+        clock: &C,
     ) -> Result<GroupState, Error> {
         // Decrypt the `WelcomeInfo` and make a group out of it
-        let (welcome_info, cipher_suite) = welcome.into_welcome_info_cipher_suite(&init_key)?;
+        let (welcome_info, cipher_suite) =
+            welcome.into_welcome_info_cipher_suite(&init_key, clock.now())?;
         let group_state = GroupState::from_welcome_info(
             cipher_suite,
             welcome_info,
             identity_secret_key,
             init_key,
-        );
+        )?;
+
+        Ok(group_state)
+    }
+
+    /// Creates a new `GroupState` from a `MultiWelcome` message, this member's identity key, and
+    /// the `UserInitKey` this member used to introduce themselves to the group. The
+    /// `UserInitKey`'s lifetime is checked against the system clock.
+    ///
+    /// Requires: That the `init_key` is one of the `UserInitKey`s that the `MultiWelcome` was
+    /// encrypted to, and `init_key.private_keys` is not `None`
+    pub fn from_multi_welcome(
+        multi_welcome: MultiWelcome,
+        identity_secret_key: SigSecretKey,
+        init_key: UserInitKey,
+    ) -> Result<GroupState, Error> {
+        GroupState::from_multi_welcome_with_clock(
+            multi_welcome,
+            identity_secret_key,
+            init_key,
+            &SystemClock,
+        )
+    }
+
+    /// Like `from_multi_welcome`, but checks the `UserInitKey`'s lifetime against the given
+    /// `Clock`
+    ///
+    /// Returns: The same as `from_multi_welcome`. If the current time is outside of `init_key`'s
+    /// lifetime, returns an `Error::ValidationError`.
+    // This is just a convenient wrapper around multi_welcome.into_welcome_info_cipher_suite and
+    // GroupState::from_welcome_info
+    pub fn from_multi_welcome_with_clock<C: Clock>(
+        multi_welcome: MultiWelcome,
+        identity_secret_key: SigSecretKey,
+        init_key: UserInitKey,
+        clock: &C,
+    ) -> Result<GroupState, Error> {
+        let (welcome_info, cipher_suite) =
+            multi_welcome.into_welcome_info_cipher_suite(&init_key, clock.now())?;
+        let group_state = GroupState::from_welcome_info(
+            cipher_suite,
+            welcome_info,
+            identity_secret_key,
+            init_key,
+        )?;
 
         Ok(group_state)
     }
@@ -278,7 +589,73 @@ impl GroupState {
             tree: self.tree.clone(),
             transcript_hash: self.transcript_hash.clone(),
             init_secret: self.init_secret.clone(),
+            extras: WelcomeInfoExtras {
+                extensions: self.extensions.clone(),
+                tree_parent_hashes: self.tree.get_parent_hashes(),
+                seal_secret: None,
+            },
+        }
+    }
+
+    /// Creates the `WelcomeInfo` that new members are sent, along with its hash. Besides the
+    /// group's state, this carries this member's seal secret for this epoch, if they know it, so
+    /// that the new members can open the `Handshake` that adds them if it's sealed. The seal secret
+    /// is left out of the hash, since everyone else checks the hash against the `WelcomeInfo` they
+    /// make themselves.
+    ///
+    /// Returns: `Ok((welcome_info, welcome_info_hash))` on success. Otherwise, returns an
+    /// `Error::SerdeError`.
+    fn as_welcome_info_for_new_members(&self) -> Result<(WelcomeInfo, WelcomeInfoHash), Error> {
+        let mut welcome_info = self.as_welcome_info();
+        let welcome_info_hash = self.cs.hash_impl.hash_serializable(&welcome_info)?;
+        welcome_info.extras.seal_secret = match (self.roster_index, &self.handshake_secret) {
+            (Some(roster_index), Some(_)) => Some(self.seal_secret(roster_index)?),
+            _ => None,
+        };
+
+        Ok((welcome_info, welcome_info_hash.into()))
+    }
+
+    /// Returns a copy of this `GroupState` in the terminal `MembershipStatus::Left` state. A member
+    /// who has left has no business knowing any group secrets, so those are all forgotten.
+    fn as_left(&self) -> GroupState {
+        let mut left_state = self.clone();
+        left_state.membership_status = MembershipStatus::Left;
+
+        // TODO: Make this erasure secure
+        for node in left_state.tree.nodes.iter_mut() {
+            if let RatchetTreeNode::Filled {
+                ref mut private_key,
+                ..
+            } = node
+            {
+                *private_key = None;
+            }
         }
+        left_state.pending_update_private_key = None;
+        left_state.pending_handshakes.clear();
+        left_state.fork_point = None;
+        left_state.exporter_secret = None;
+        left_state.resumption_secret = None;
+        left_state.sender_data_secret = None;
+        left_state.handshake_secret = None;
+        left_state.welcome_seal_secret = None;
+        left_state.init_secret = HmacKey::new_from_zeros(self.cs.hash_impl);
+
+        left_state
+    }
+
+    /// Returns this `GroupState` with `prior_state` recorded as its fork point. This is called on
+    /// the result of applying a `Handshake` signed by `signer_index` to `prior_state`, whether
+    /// this member made that `Handshake` or processed it.
+    fn with_fork_point(mut self, prior_state: &GroupState, signer_index: u32) -> GroupState {
+        self.fork_point = Some(ForkPoint {
+            prior_epoch: prior_state.epoch,
+            prior_roster: prior_state.roster.clone(),
+            prior_transcript_hash: prior_state.transcript_hash.clone(),
+            signer_index,
+        });
+        self
     }
 
     /// Returns the signature scheme of this member of the group. This is determined by the
@@ -314,7 +691,7 @@ impl GroupState {
         my_credential.get_signature_scheme()
     }
 
-    /// Increments the epoch counter by 1
+    /// Increments the epoch counter by 1 and forgets any pending `Update` proposal secret
     ///
     /// Returns: An `Error::ValidationError` if the epoch value is at its max
     fn increment_epoch(&mut self) -> Result<(), Error> {
@@ -324,6 +701,17 @@ impl GroupState {
             .ok_or(Error::ValidationError("Cannot increment epoch past its maximum"))?;
         self.epoch = new_epoch;
 
+        // Proposals are only valid in the epoch they were made in, so any pending Update secret
+        // is useless now
+        // TODO: Make this erasure secure
+        self.pending_update_private_key = None;
+
+        // The seal secret from our Welcome only opens Handshakes made in the epoch we joined in
+        self.welcome_seal_secret = None;
+
+        // Likewise, a pending Handshake for an epoch we've now passed can never be applied
+        self.pending_handshakes.retain(|h| h.prior_epoch >= new_epoch);
+
         Ok(())
     }
 
@@ -331,31 +719,51 @@ impl GroupState {
     ///
     /// Returns: An `Error::SerdeError` if there was an issue during serialization
     fn update_transcript_hash(&mut self, operation: &GroupOperation) -> Result<(), Error> {
-        // Compute the new transcript hash
-        // From section 5.7: transcript_hash_[n] = Hash(transcript_hash_[n-1] || operation)
-        self.transcript_hash = {
-            let mut ctx = self.cs.hash_impl.new_context();
-            ctx.feed_bytes(self.transcript_hash.as_bytes());
-            ctx.feed_serializable(&operation)?;
-            ctx.finalize()
-        };
-
+        self.transcript_hash = next_transcript_hash(self.cs, &self.transcript_hash, operation)?;
         Ok(())
     }
 
-    /// Derives and sets the next generation of Group secrets as per the "Key Schedule" section of
-    /// the spec. Specifically, this sets the init secret of the group, and returns the confirmation
-    /// key and application secret. This is done this way because the latter two values must be used
-    /// immediately in `process_handshake`.
+    /// Brings the tree hash up to date and derives and sets the next generation of Group secrets,
+    /// so that they commit to the tree as it is now. See `derive_epoch_secrets`.
     fn update_epoch_secrets(
         &mut self,
         update_secret: &UpdateSecret,
+        psk_secret: Option<&HmacKey>,
+    ) -> Result<(ApplicationSecret, ConfirmationKey), Error> {
+        self.tree_hash = self.tree.tree_hash(self.cs)?;
+        let tree_hash = self.tree_hash.clone();
+        self.derive_epoch_secrets(update_secret, psk_secret, Some(&tree_hash))
+    }
+
+    /// Derives and sets the next generation of Group secrets as per the "Key Schedule" section of
+    /// the spec. Specifically, this sets the init, exporter, resumption, sender data, and handshake
+    /// secrets of the group, and returns the confirmation key and application secret. This is done
+    /// this way because the latter two values must be used immediately in `process_handshake`. If
+    /// `psk_secret` or `tree_hash` is given, it is mixed into the epoch secret.
+    fn derive_epoch_secrets(
+        &mut self,
+        update_secret: &UpdateSecret,
+        psk_secret: Option<&HmacKey>,
+        tree_hash: Option<&Digest>,
     ) -> Result<(ApplicationSecret, ConfirmationKey), Error> {
         let hash_impl = self.cs.hash_impl;
 
         // epoch_secret = HKDF-Extract(salt=init_secret_[n-1] (or 0), ikm=update_secret)
         let ikm = update_secret.as_bytes();
-        let epoch_secret: HmacKey = hkdf::extract(hash_impl, &self.init_secret, ikm);
+        let mut epoch_secret: HmacKey = hkdf::extract(hash_impl, &self.init_secret, ikm);
+
+        // This is our own addition. If there are PSKs:
+        // epoch_secret = HKDF-Extract(salt=epoch_secret, ikm=psk_secret)
+        if let Some(psk_secret) = psk_secret {
+            epoch_secret = hkdf::extract(hash_impl, &epoch_secret, &psk_secret.0);
+        }
+
+        // So is this. It commits the secrets below to the parent hashes, which the serialized
+        // GroupState leaves out:
+        // epoch_secret = HKDF-Extract(salt=epoch_secret, ikm=tree_hash)
+        if let Some(tree_hash) = tree_hash {
+            epoch_secret = hkdf::extract(hash_impl, &epoch_secret, tree_hash.as_bytes());
+        }
 
         // Set my new init_secret first. We don't have to worry about this update affecting
         // subsequent serializations of this GroupState object in the lines below, since
@@ -370,9 +778,194 @@ impl GroupState {
         // confirmation_key = Derive-Secret(epoch_secret, "confirm", GroupState_[n])
         let confirmation_key = hkdf::derive_secret(hash_impl, &epoch_secret, b"confirm", self)?;
 
+        // exporter_secret = Derive-Secret(epoch_secret, "exporter", GroupState_[n])
+        self.exporter_secret =
+            Some(hkdf::derive_secret(hash_impl, &epoch_secret, b"exporter", self)?);
+
+        // resumption_secret = Derive-Secret(epoch_secret, "resumption", GroupState_[n])
+        self.resumption_secret =
+            Some(hkdf::derive_secret(hash_impl, &epoch_secret, b"resumption", self)?);
+
+        // This is our own addition, modeled after later drafts.
+        // sender_data_secret = Derive-Secret(epoch_secret, "sender data", GroupState_[n])
+        self.sender_data_secret =
+            Some(hkdf::derive_secret(hash_impl, &epoch_secret, b"sender data", self)?);
+
+        // So is this.
+        // handshake_secret = Derive-Secret(epoch_secret, "handshake", GroupState_[n])
+        self.handshake_secret =
+            Some(hkdf::derive_secret(hash_impl, &epoch_secret, b"handshake", self)?);
+
         Ok((application_secret.into(), confirmation_key.into()))
     }
 
+    /// Derives this epoch's external key pair. New members encrypt a fresh init secret to the
+    /// public half when they join via the group's `PublicGroupInfo`.
+    fn external_key_pair(&self) -> Result<(DhPublicKey, DhPrivateKey), Error> {
+        // external_secret = Derive-Secret(init_secret, "external", GroupState)
+        let external_secret =
+            hkdf::derive_secret(self.cs.hash_impl, &self.init_secret, b"external", self)?;
+        self.cs.derive_key_pair(&external_secret.0)
+    }
+
+    /// Derives the seal secret of the member at `signer_index` for this epoch. Every member can
+    /// derive anyone's. A new member is only given the one of whoever sent their `Welcome`.
+    ///
+    /// Returns: `Ok(seal_secret)` on success. Returns an `Error::ValidationError` if this member
+    /// doesn't know this epoch's handshake secret.
+    fn seal_secret(&self, signer_index: u32) -> Result<SealSecret, Error> {
+        let handshake_secret = self.handshake_secret.as_ref().ok_or(Error::ValidationError(
+            "Handshake secret is unknown until this member sees an epoch change",
+        ))?;
+
+        // seal_secret = HKDF-Expand-Label(handshake_secret, "hs seal", signer_index, Hash.length)
+        let mut secret_buf = vec![0u8; self.cs.hash_impl.digest_size()];
+        hkdf::expand_label(
+            self.cs.hash_impl,
+            handshake_secret,
+            b"hs seal",
+            &signer_index.to_be_bytes(),
+            &mut secret_buf,
+        );
+        Ok(SealSecret {
+            signer_index,
+            secret: HmacKey::new_from_bytes(&secret_buf),
+        })
+    }
+
+    /// Derives the key that the member at `signer_index` seals `Handshake`s under in this epoch
+    ///
+    /// Returns: `Ok(key)` on success. Returns an `Error::ValidationError` if this member doesn't
+    /// know this epoch's handshake secret, and wasn't given that member's seal secret in their
+    /// `Welcome`.
+    fn handshake_key(&self, signer_index: u32) -> Result<AeadKey, Error> {
+        let seal_secret = match self.welcome_seal_secret {
+            Some(ref seal_secret) if seal_secret.signer_index == signer_index => {
+                seal_secret.clone()
+            }
+            _ => self.seal_secret(signer_index)?,
+        };
+
+        // handshake_key = HKDF-Expand-Label(seal_secret, "hs key", "", key_length)
+        let mut key_buf = vec![0u8; self.cs.aead_impl.key_size()];
+        hkdf::expand_label(self.cs.hash_impl, &seal_secret.secret, b"hs key", b"", &mut key_buf);
+        AeadKey::new_from_bytes(self.cs.aead_impl, &key_buf)
+    }
+
+    /// Checks that a future-epoch `Handshake` is something this member is willing to hold on to:
+    /// it's not too far ahead. Its signer isn't checked, since they may join in an epoch this
+    /// member hasn't reached yet. That happens when the `Handshake` is applied.
+    fn check_bufferable(&self, handshake: &Handshake) -> Result<(), Error> {
+        let epochs_ahead = handshake.prior_epoch.saturating_sub(self.epoch);
+        if epochs_ahead as usize > MAX_PENDING_HANDSHAKES {
+            return Err(Error::ValidationError("Handshake is too far in the future to buffer"));
+        }
+
+        Ok(())
+    }
+
+    /// Picks the pending `Handshake` to evict, if the queue holds too many. If some signer holds
+    /// more than `MAX_PENDING_HANDSHAKES_PER_SENDER`, it's their furthest-ahead one. If signers
+    /// outside the current roster hold more than `MAX_PENDING_HANDSHAKES_FROM_UNKNOWN_SIGNERS`
+    /// between them, it's the furthest-ahead one of whichever of them holds the most. If the queue
+    /// holds more than `MAX_PENDING_HANDSHAKES`, it's the furthest-ahead one of whoever holds the
+    /// most, looking at signers outside the current roster first. Ties go to the latest arrival.
+    ///
+    /// Returns: `Some(idx)`, where `idx` is the index of the `Handshake` to evict in
+    /// `pending_handshakes`, or `None` if nothing needs evicting
+    fn pending_handshake_to_evict(&self) -> Option<usize> {
+        let count_from = |signer_index: u32| {
+            self.pending_handshakes.iter().filter(|h| h.signer_index == signer_index).count()
+        };
+        let is_known =
+            |signer_index: u32| matches!(self.roster.0.get(signer_index as usize), Some(Some(_)));
+
+        let overfull_signer = self
+            .pending_handshakes
+            .iter()
+            .map(|h| h.signer_index)
+            .find(|&signer_index| count_from(signer_index) > MAX_PENDING_HANDSHAKES_PER_SENDER);
+        let num_from_unknown =
+            self.pending_handshakes.iter().filter(|h| !is_known(h.signer_index)).count();
+        let candidates: Vec<(usize, &Handshake)> = match overfull_signer {
+            Some(signer_index) => self
+                .pending_handshakes
+                .iter()
+                .enumerate()
+                .filter(|(_, h)| h.signer_index == signer_index)
+                .collect(),
+            None if num_from_unknown > MAX_PENDING_HANDSHAKES_FROM_UNKNOWN_SIGNERS => self
+                .pending_handshakes
+                .iter()
+                .enumerate()
+                .filter(|(_, h)| !is_known(h.signer_index))
+                .collect(),
+            None if self.pending_handshakes.len() > MAX_PENDING_HANDSHAKES => {
+                self.pending_handshakes.iter().enumerate().collect()
+            }
+            None => return None,
+        };
+
+        candidates
+            .into_iter()
+            .max_by_key(|(idx, h)| {
+                (!is_known(h.signer_index), count_from(h.signer_index), h.prior_epoch, *idx)
+            })
+            .map(|(idx, _)| idx)
+    }
+
+    /// Decrypts the operation of the given sealed `Handshake` with its signer's handshake key for
+    /// this epoch. See `GroupState::seal_handshake`.
+    ///
+    /// Returns: `Ok(handshake)` on success, where `handshake` is the original, unsealed
+    /// `Handshake`. Returns an `Error::ValidationError` if this member can't derive the signer's
+    /// handshake key, if the signer isn't in the roster, or if the operation is sealed twice.
+    /// Otherwise, returns some other sort of `Error`.
+    fn unseal_handshake(
+        &self,
+        handshake: &Handshake,
+        sealed: &SealedOperation,
+    ) -> Result<Handshake, Error> {
+        let key = self.handshake_key(handshake.signer_index)?;
+        let nonce = AeadNonce::new_from_bytes(self.cs.aead_impl, &sealed.nonce)?;
+        let aad = tls_ser::serialize_to_bytes(&SealedOperationAad {
+            group_id: &self.group_id,
+            prior_epoch: handshake.prior_epoch,
+            signer_index: handshake.signer_index,
+        })?;
+
+        // Only members can seal, and they bring no credential of their own, so the signer's
+        // signature scheme is in the roster
+        let signer_ss = self
+            .roster
+            .0
+            .get(handshake.signer_index as usize)
+            .and_then(Option::as_ref)
+            .ok_or(Error::ValidationError("Sealed Handshake's signer credential is empty"))?
+            .get_signature_scheme();
+
+        let mut ciphertext = sealed.ciphertext.clone();
+        let serialized_operation =
+            self.cs.aead_impl.open_with_ad(&key, nonce, &aad, &mut ciphertext)?;
+        let operation = {
+            let mut cursor: &[u8] = serialized_operation;
+            let mut deserializer = TlsDeserializer::from_bytes(&mut cursor);
+            let mut operation =
+                GroupOperation::from(WireOperation::deserialize(&mut deserializer)?);
+            let ctx = CryptoCtx::new().set_cipher_suite(self.cs).set_signature_scheme(signer_ss);
+            operation.upcast_crypto_values(&ctx)?;
+            operation
+        };
+        if let GroupOperation::Sealed(_) = operation {
+            return Err(Error::ValidationError("Handshake's operation is sealed twice"));
+        }
+
+        Ok(Handshake {
+            operation,
+            ..handshake.clone()
+        })
+    }
+
     /// Converts the index of a roster entry into the index of the corresponding leaf node of the
     /// ratchet tree
     ///
@@ -388,7 +981,7 @@ impl GroupState {
 
     /// Performs an update operation on the `GroupState`, where `new_path_secret` is the node
     /// secret we will propagate starting at the index `start_idx`. This is the core updating logic
-    /// that is used in `process_incoming_update_op` and `create_and_apply_update_op`.
+    /// that is used in `process_incoming_direct_path` and `create_and_apply_update_op`.
     ///
     /// Returns: `Ok(update_secret)` on success, where `update_secret` is the update secret
     /// necessary for generating new epoch secrets
@@ -406,45 +999,29 @@ impl GroupState {
         Ok(UpdateSecret::from(root_node_secret))
     }
 
-    /// Performs and validates an incoming (i.e., one we did not generate) Update operation on the
-    /// `GroupState`, where `sender_tree_idx` is the tree index of the sender of this operation
+    /// Decrypts the given `DirectPathMessage` sent by the member at tree index `sender_tree_idx`,
+    /// propagates the resulting path secret through the tree, and checks that the public keys in
+    /// the message match the ones we derived. This is the core of processing an incoming `Update`
+    /// or `Init`.
     ///
     /// Returns: `Ok(update_secret)` on success, where `update_secret` is the update secret
     /// necessary for generating new epoch secrets.
-    fn process_incoming_update_op(
+    fn process_incoming_direct_path(
         &mut self,
-        update: &GroupUpdate,
+        path: &DirectPathMessage,
         sender_tree_idx: usize,
+        my_tree_idx: usize,
     ) -> Result<UpdateSecret, Error> {
-        // We do three things: compute the new ratchet tree, compute the new transcript hash, and
-        // compute the new epoch secrets. We shove all these new values into a delta. To validate
-        // the operation, we check that the derived public keys match the ones in the message. If
-        // they do not, this is an error.
-
-        // Decrypt the path secret from the GroupUpdate and propagate it through our tree
-        // Recall that roster_index is just another (IMO clearer) name for signer_index
-        let my_tree_idx = {
-            // Safely unwrap the roster index. A preliminary GroupState is one that has just been
-            // initialized with a Welcome message
-            let roster_index = self
-                .roster_index
-                .ok_or(Error::ValidationError("Cannot do an Update on a preliminary GroupState"))?;
-            GroupState::roster_index_to_tree_index(roster_index)?
-        };
-        let (path_secret, common_ancestor) = self.tree.decrypt_direct_path_message(
-            self.cs,
-            &update.path,
-            sender_tree_idx,
-            my_tree_idx,
-        )?;
+        let (path_secret, common_ancestor) =
+            self.tree.decrypt_direct_path_message(self.cs, path, sender_tree_idx, my_tree_idx)?;
         let update_secret = self.apply_update(path_secret, common_ancestor)?;
 
         // Update all the public keys of the nodes in the direct path that are below our common
         // ancestor, i.e., all the ones whose secret we don't know. Note that this step is not
         // performed in apply_update, because this only happens when we're not the ones who created
-        // the Update operation.
+        // the operation.
         let direct_path_public_keys =
-            update.path.node_messages.iter().map(|node_msg| &node_msg.public_key);
+            path.node_messages.iter().map(|node_msg| &node_msg.public_key);
         self.tree.set_public_keys_with_bound(
             sender_tree_idx,
             common_ancestor,
@@ -454,21 +1031,39 @@ impl GroupState {
         // Make sure the public keys in the message match the ones we derived
         self.tree.validate_direct_path_public_keys(sender_tree_idx, direct_path_public_keys)?;
 
+        // Make sure the sender vouched for the path, and remember that they did
+        self.set_direct_path_parent_hashes(path, sender_tree_idx)?;
+
         // All done
         Ok(update_secret)
     }
 
-    /// Performs and validates Remove operation on the `GroupState`. This will (necessarily) error
-    /// if this member is the one being removed.
+    /// Checks that the member at leaf `sender_tree_idx` signed the given `DirectPathMessage`, and
+    /// records the path's parent hashes and signature in the tree. This is done once the public
+    /// keys of the path have been set.
+    ///
+    /// Returns: `Ok(())` on success. Returns an `Error::SignatureError` if the signature doesn't
+    /// verify, and some sort of `Error::ValidationError` if the sender isn't in the roster or the
+    /// path doesn't fit in the tree.
+    fn set_direct_path_parent_hashes(
+        &mut self,
+        path: &DirectPathMessage,
+        sender_tree_idx: usize,
+    ) -> Result<(), Error> {
+        set_direct_path_parent_hashes(self.cs, &self.roster, &mut self.tree, path, sender_tree_idx)
+    }
+
+    /// Applies the path of an incoming or outgoing `Remove` operation to the tree. This has to
+    /// happen before the removed member's leaf is blanked, since the path starts there. The rest
+    /// of the `Remove` is done by `apply_operation`.
     ///
     /// Returns: `Ok(update_secret)` on success, where `update_secret` is the update secret
     /// necessary for generating new epoch secrets. Returns an `Error::IAmRemoved` iff this member
     /// is the one who has been removed. Otherwise returns some other kind of `Error`.
-    // NOTE: There is no corresponding "apply_remove" method because the creator of a Remove is
-    // able to process the Remove, whereas the creator of an Update cannot process their own
-    // operation (this is because the creator's own path secret is never put into the
-    // DirectPathMessage).
-    fn process_remove_op(&mut self, remove: &GroupRemove) -> Result<UpdateSecret, Error> {
+    // NOTE: The creator of a Remove applies its path with this too, whereas the creator of an
+    // Update cannot process their own operation (this is because the creator's own path secret is
+    // never put into the DirectPathMessage).
+    fn process_remove_path(&mut self, remove: &GroupRemove) -> Result<UpdateSecret, Error> {
         // Find the entropy provided in remove.path that we'll use to update the tree before
         // blanking out the removed node
         let my_tree_idx = {
@@ -505,6 +1100,7 @@ impl GroupState {
         //   and leaf node are non-null
         // * Update the ratchet tree by setting to blank all nodes in the direct path of the
         //   removed leaf
+        // This does the second step. The rest are done by apply_remove.
 
         // Update the ratchet tree with the entropy provided in path_secret
         let root_node_secret =
@@ -526,189 +1122,368 @@ impl GroupState {
         // the message match the ones we derived
         self.tree.validate_direct_path_public_keys(remove_tree_idx, direct_path_public_keys)?;
 
-        // Blank out the roster location
-        self.roster
-            .0
-            .get_mut(remove.removed_roster_index as usize)
-            .map(|cred| *cred = None)
-            .ok_or(Error::ValidationError("Invalid roster index"))?;
-
-        // Try to prune the blanks from the end. Finding yourself in an empty group after a Remove
-        // operation should be an impossible state.
-        // Proof: First, a claim
-        //     Claim: If you have this `GroupState` object, you are in the roster.
-        //     Proof: If you are not, then why do you have it? Get rid of it! Alternatively, you
-        //            could not be in the roster because this is a preliminary group state. If this
-        //            were the case, we would have thrown an error at the beginning of this method
-        //            (see above).
-        // Proof (cont.): If you are in the roster, and it is impossible to remove yourself from
-        //     the group (see Error::IAmRemoved conditions in process_handshake and
-        //     create_and_apply_remove_op), then it is impossible to have any fewer than 1 group
-        //     member. QED
-        self.roster.truncate_to_last_nonblank().expect("Remove resulted in an empty group");
-
-        // Blank out the direct path of remove_tree_idx
-        self.tree.propagate_blank(remove_tree_idx);
-        // Truncate the tree in a similar fashion to the roster
-        self.tree.truncate_to_last_nonblank();
-
-        // And that's it
         Ok(update_secret)
     }
 
-    /// Performs and validates an Add operation on the `GroupState`. Requires a `WelcomeInfo`
-    /// representing the `GroupState` before this handshake was received.
+    /// Puts the member described by `init_key` in the roster and tree at `new_roster_index`, which
+    /// must either be an empty roster entry or be equal to the size of the roster. If `init_key`
+    /// is this member's initializing `UserInitKey`, then this also sets this member's roster index.
+    /// This does not verify `init_key`, nor does it touch the initializing `UserInitKey`.
     ///
-    /// Requires: If the member being added is this member, then this `GroupState` must be
-    /// "preliminary", i.e., its `roster_index` must be `None`, i.e., it must have just been
-    /// created from a `Welcome`.
-    ///
-    /// Returns: `Ok(update_secret)` on success, where `update_secret` is the update secret
-    /// necessary for generating new epoch secrets.
-    // NOTE: There is no corresponding "apply_add" method because the creator of an Add is able to
-    // process the Add, whereas the creator of an Update cannot process their own operation (this
-    // is because the creator's own path secret is never put into the DirectPathMessage).
-    fn process_add_op(
+    /// Returns: `Ok(())` on success. Returns an `Error::ValidationError` if `new_roster_index` is
+    /// out of bounds or occupied, or if `init_key` has no public key for this group's ciphersuite.
+    fn insert_new_member(
         &mut self,
-        add: &GroupAdd,
-        prior_welcome_info_hash: &WelcomeInfoHash,
-    ) -> Result<UpdateSecret, Error> {
-        // What we have to do, in order
-        // 1. If the index value is equal to the size of the group, increment the size of the
-        //    group, and extend the tree and roster accordingly
-        // 2. Verify the signature on the included UserInitKey; if the signature verification
-        //    fails, abort
-        // 3. Generate a WelcomeInfo object describing the state prior to the add, and verify that
-        //    its hash is the same as the value of the welcome_info_hash field
-        // 4. Set the roster entry at position index to the credential in the included UserInitKey
-        // 5. Update the ratchet tree by setting to blank all nodes in the direct path of the new
-        //    node
-        // 6. Set the leaf node in the tree at position index to a new node containing the public
-        //    key from the UserInitKey in the Add corresponding to the ciphersuite in use
-
-        let add_roster_index = add.roster_index;
-        if add_roster_index as usize > self.tree.size() {
-            return Err(Error::ValidationError("Invalid insertion index in Add operation"));
-        }
-
-        // Constant-time compare the WelcomeInfo hashes (no reason for constant-time other than it
-        // feels icky not to do it)
-        let hashes_match: bool = prior_welcome_info_hash.ct_eq(&add.welcome_info_hash).into();
-        if !hashes_match {
-            return Err(Error::ValidationError("Invalid WelcomeInfo hash in Add operation"));
-        }
-
-        // Check if we're a "preliminary" GroupState, i.e., whether or not we were just created by
-        // a Welcome. This is true iff self.roster_index is null and iff
-        // self.initializing_user_init_key is non-null.
-        let is_preliminary = self.roster_index.is_none();
-
-        // Check all the UserInitKeys involved
-        add.init_key.verify_sig()?;
-        add.init_key.validate()?;
-        self.initializing_user_init_key.as_ref().map(|uik| uik.verify_sig()).transpose()?;
-        self.initializing_user_init_key.as_ref().map(|uik| uik.validate()).transpose()?;
-
-        // If we just received a WelcomeInfo, we want to use the UserInitKey we created, since it
+        init_key: &UserInitKey,
+        new_roster_index: u32,
+    ) -> Result<(), Error> {
+        // If this is the UserInitKey we were Welcomed with, use our copy of it instead, since it
         // contains the private key to our ratchet tree node
-        let init_key = if is_preliminary {
-            let uik = self.initializing_user_init_key.as_ref().ok_or(Error::ValidationError(
-                "Preliminary GroupState has no initializing UserInitKey",
-            ))?;
-            // If it's an initializing key, let's make sure that its ID matches that of the
-            // provided UserInitKey
-            if uik.user_init_key_id != add.init_key.user_init_key_id {
-                return Err(Error::ValidationError(
-                    "Add's UserInitKey and GroupState's initialized UserInitKey differ",
-                ));
-            }
-            uik
-        } else {
-            &add.init_key
-        };
-
-        // Is this an appending Add or is it an in-place Add? If in-place, we have to make sure
-        // we're not overwriting any existing members in the group
-        let is_append = add_roster_index as usize == self.roster.len();
-
-        // Update the roster
-        let new_credential = init_key.credential.clone();
-        if is_append {
-            self.roster.0.push(Some(new_credential))
-        } else {
-            // It's an in-place add. Check that we're only overwriting an empty roster entry
-            let entry_to_update = self
-                .roster
-                .0
-                .get_mut(add_roster_index as usize)
-                .ok_or(Error::ValidationError("Out of bounds roster index"))?;
-
-            if entry_to_update.is_some() {
-                return Err(Error::ValidationError("Add tried to overwrite non-null roster entry"));
-            } else {
-                *entry_to_update = Some(new_credential);
-            }
-        }
-
-        // Update the tree. We add a new blank node in the correct position, then set the leaf node
-        // to the appropriate value
-        if is_append {
-            // If we're adding a new node to the end of the tree, we have to make new nodes
-            self.tree.add_leaf_node(RatchetTreeNode::Blank);
-        }
-
-        if is_preliminary {
-            // If we're one being Added, then this index is us
-            self.roster_index = Some(add_roster_index);
-        }
-
-        // Propagate the blank up the tree before we overwrite the new leaf with the new
-        // member's pubkey info
-        let add_tree_index = GroupState::roster_index_to_tree_index(add_roster_index)?;
-        self.tree.propagate_blank(add_tree_index);
+        let my_uik = self
+            .initializing_user_init_key
+            .as_ref()
+            .filter(|my_uik| my_uik.user_init_key_id == init_key.user_init_key_id);
+        let init_key = my_uik.unwrap_or(init_key);
+        let is_me = my_uik.is_some();
 
-        // Now find the node keypair information and make our node in the ratchet tree. The keypair
-        // we associate to the new member is the one that corresponds to our current ciphersuite.
+        // Find the node keypair information corresponding to our current ciphersuite. We do this
+        // before modifying anything, since this is the only part that can fail.
         let public_key = init_key.get_public_key(self.cs)?.ok_or(Error::ValidationError(
             "UserInitKey has no public keys for group's ciphersuite",
         ))?;
         let private_key = init_key.get_private_key(self.cs)?.cloned();
-
-        // The new node we add has the public key we found, and no known secrets
-        let new_node = RatchetTreeNode::Filled {
-            public_key: public_key.clone(),
-            private_key,
-        };
-
-        // Check that we're only overwriting a Blank node.
-        let node_to_overwrite = self.tree.get_mut(add_tree_index).unwrap();
-        if node_to_overwrite.is_filled() {
-            return Err(Error::ValidationError("Add tried to overwrite non-blank node"));
+        let new_node = RatchetTreeNode::new_from_public_key(public_key.clone(), private_key)
+            .with_user_init_key(init_key);
+
+        insert_member(
+            &mut self.roster,
+            &mut self.tree,
+            init_key.credential.clone(),
+            new_node,
+            new_roster_index,
+        )?;
+        if is_me {
+            self.roster_index = Some(new_roster_index);
         }
 
-        // Finally, do the overwrite
-        *node_to_overwrite = new_node;
+        Ok(())
+    }
 
-        // Alright, we're done with the init_key. Make sure that we don't have our initializing
-        // UserInitKey hanging around after this
+    /// Throws away this member's initializing `UserInitKey`, now that this member has been added
+    /// with it. If it was a last-resort `UserInitKey`, this remembers its public key, since that
+    /// is now this member's leaf key in every group the `UserInitKey` was used to join.
+    ///
+    /// Returns: `Ok(())` on success. Returns an `Error::ValidationError` if the `UserInitKey` is
+    /// invalid or has no public key for this group's ciphersuite.
+    fn discard_initializing_user_init_key(&mut self) -> Result<(), Error> {
         // TODO: Make this erasure secure
-        self.initializing_user_init_key = None;
+        if let Some(uik) = self.initializing_user_init_key.take() {
+            if uik.is_last_resort() {
+                let public_key = uik.get_public_key(self.cs)?.ok_or(Error::ValidationError(
+                    "UserInitKey has no public keys for group's ciphersuite",
+                ))?;
+                self.last_resort_public_key = Some(public_key.clone());
+            }
+        }
 
-        // "The update secret resulting from this change is an all-zero octet string of length
-        // Hash.length."
-        Ok(UpdateSecret::new_from_zeros(self.cs.hash_impl.digest_size()))
+        Ok(())
     }
 
-    /// Processes the given `Handshake` and, if successful, produces a new `GroupState` and
+    /// Derives the init secret of the successor of this reinitialized group
+    ///
+    /// Returns: `Ok((reinit, init_secret))` on success, where `reinit` describes the successor
+    /// group. Returns an `Error::ValidationError` if this group hasn't been reinitialized.
+    fn successor_init_secret(&self) -> Result<(&GroupReInit, HmacKey), Error> {
+        let reinit = self
+            .reinit
+            .as_ref()
+            .ok_or(Error::ValidationError("This group has not been reinitialized"))?;
+        // Everyone who processed the ReInit has seen an epoch change
+        let resumption_secret = self.resumption_secret.as_ref().ok_or(Error::ValidationError(
+            "Reinitialized group is missing its resumption secret",
+        ))?;
+
+        // successor_init_secret =
+        //     HKDF-Expand-Label(resumption_secret, "reinit", Hash(ReInit), Hash'.length)
+        // where Hash' is the hash function of the successor's ciphersuite
+        let context = self.cs.hash_impl.hash_serializable(reinit)?;
+        let mut init_secret_buf = vec![0u8; reinit.cipher_suite.hash_impl.digest_size()];
+        hkdf::expand_label(
+            self.cs.hash_impl,
+            resumption_secret,
+            b"reinit",
+            context.as_bytes(),
+            &mut init_secret_buf,
+        );
+
+        Ok((reinit, HmacKey::new_from_bytes(&init_secret_buf)))
+    }
+
+    /// Makes the epoch 0 state of a branch of this group with group ID `new_group_id`, containing
+    /// the members at the given roster indices, in that order. The members bring their current
+    /// credentials and leaf keys along, and the branch's init secret is derived from this epoch's
+    /// resumption secret. Everyone in the branch computes the same state, from their own
+    /// perspective.
+    ///
+    /// Returns: `Ok(group_state)` on success. Returns an `Error::ValidationError` if this member
+    /// doesn't know the resumption secret, if `members` isn't strictly increasing, or if it
+    /// contains an empty roster entry or doesn't contain this member.
+    fn branch_start_state(
+        &self,
+        members: &[u32],
+        new_group_id: Vec<u8>,
+    ) -> Result<GroupState, Error> {
+        let resumption_secret = self.resumption_secret.as_ref().ok_or(Error::ValidationError(
+            "Cannot branch before this member has seen an epoch change",
+        ))?;
+        let my_roster_idx = self
+            .roster_index
+            .ok_or(Error::ValidationError("Cannot branch from a preliminary GroupState"))?;
+        if members.windows(2).any(|pair| pair[0] >= pair[1]) {
+            return Err(Error::ValidationError("Branch members must be strictly increasing"));
+        }
+
+        // Copy over the selected credentials and leaves. The only private key we have is our own.
+        let mut roster = Roster(Vec::new());
+        let mut tree = RatchetTree {
+            nodes: Vec::new(),
+        };
+        for member_roster_idx in members.iter() {
+            let credential = self
+                .roster
+                .0
+                .get(*member_roster_idx as usize)
+                .and_then(|entry| entry.as_ref())
+                .ok_or(Error::ValidationError("Branch member's roster entry is empty"))?;
+            let member_tree_idx = GroupState::roster_index_to_tree_index(*member_roster_idx)?;
+            let leaf = self
+                .tree
+                .get(member_tree_idx)
+                .filter(|node| node.is_filled())
+                .ok_or(Error::ValidationError("Branch member's leaf is blank"))?;
+            roster.0.push(Some(credential.clone()));
+            tree.add_leaf_node(leaf.clone());
+        }
+        let my_branch_idx = members
+            .iter()
+            .position(|idx| *idx == my_roster_idx)
+            .ok_or(Error::ValidationError("Branch does not contain this member"))?;
+
+        let mut branch = GroupState::new_from_parts(
+            self.cs,
+            self.protocol_version,
+            self.identity_key.clone(),
+            new_group_id,
+            roster,
+            my_branch_idx as u32,
+            tree,
+        )?;
+
+        // branch_init_secret = Derive-Secret(resumption_secret, "branch", Branch_[0])
+        // where Branch_[0] is the epoch 0 state of the branch
+        branch.init_secret =
+            hkdf::derive_secret(self.cs.hash_impl, resumption_secret, b"branch", &branch)?;
+
+        Ok(branch)
+    }
+
+    /// Returns whether the given `UserInitKey`s all have distinct IDs. A preliminary member finds
+    /// themselves among new members by `UserInitKey` ID, so these had better be unique.
+    pub(crate) fn user_init_key_ids_are_unique(init_keys: &[UserInitKey]) -> bool {
+        // Sort them, dedup them, and see if the number has decreased
+        let mut uik_ids: Vec<&[u8]> =
+            init_keys.iter().map(|uik| uik.user_init_key_id.as_slice()).collect();
+        uik_ids.sort();
+        uik_ids.dedup();
+        uik_ids.len() == init_keys.len()
+    }
+
+    /// Performs `operation`, signed by the member at roster index `signer_index`, on `new_state`,
+    /// which is a copy of this `GroupState` with its transcript hash and epoch already updated.
+    /// `now` is the current time, in seconds since the Unix epoch. The changes to the roster and
+    /// the public side of the tree are made by `apply_operation`, same as in `GroupObserver`. This
+    /// does the rest, which needs the group's secrets. This does no signature or MAC verification.
+    ///
+    /// Returns: `Ok(update_secret)` on success, where `update_secret` is the update secret
+    /// necessary for generating new epoch secrets. Returns `Error::IAmRemoved` iff this member is
+    /// removed by the operation. Otherwise returns some other kind of `Error`.
+    fn process_operation(
+        &self,
+        new_state: &mut GroupState,
+        operation: &GroupOperation,
+        signer_index: u32,
+        now: u64,
+    ) -> Result<UpdateSecret, Error> {
+        // Anything that adds members references the state of this group. The WelcomeInfo that
+        // created this group is just this group.
+        let prior_welcome_info_hash = match *operation {
+            GroupOperation::Add(_)
+            | GroupOperation::Init(_)
+            | GroupOperation::Commit(_)
+            | GroupOperation::MultiAdd(_) => {
+                let prior_welcome_info = self.as_welcome_info();
+                let digest = self.cs.hash_impl.hash_serializable(&prior_welcome_info)?;
+                Some(WelcomeInfoHash::from(digest))
+            }
+            _ => None,
+        };
+
+        // Some things have to happen before the roster and tree change
+        let remove_update_secret = match *operation {
+            // Existing members can't decrypt an Init's path, since it is only encrypted to the new
+            // members
+            GroupOperation::Init(_) if self.roster_index.is_some() => {
+                return Err(Error::ValidationError(
+                    "Only new members can process an Init operation",
+                ));
+            }
+            // A Remove's path starts at the removed member's leaf, so it has to be applied before
+            // that leaf is blanked
+            GroupOperation::Remove(ref remove) => Some(new_state.process_remove_path(remove)?),
+            _ => None,
+        };
+
+        // Check if we're a "preliminary" GroupState, i.e., whether or not we were just created by
+        // a Welcome. If so, this had better add us.
+        let is_preliminary = self.roster_index.is_none();
+        new_state.initializing_user_init_key.as_ref().map(|uik| uik.verify_sig()).transpose()?;
+        new_state.initializing_user_init_key.as_ref().map(|uik| uik.validate()).transpose()?;
+
+        // Change the roster and tree. The pending Update key is taken from the current state,
+        // since it doesn't survive the epoch change in new_state.
+        let mut target = GroupStateTarget {
+            state: &mut *new_state,
+            my_pending_update_key: self.pending_update_private_key.as_ref(),
+        };
+        apply_operation(
+            &mut target,
+            operation,
+            signer_index,
+            self.epoch,
+            prior_welcome_info_hash.as_ref(),
+            now,
+        )?;
+
+        if is_preliminary && new_state.roster_index.is_none() {
+            return Err(Error::ValidationError(
+                "Handshake does not add GroupState's initializing UserInitKey",
+            ));
+        }
+        // Alright, we're done with the new members. Make sure that we don't have our initializing
+        // UserInitKey hanging around after this
+        new_state.discard_initializing_user_init_key()?;
+
+        let path = match *operation {
+            GroupOperation::Update(ref update) => &update.path,
+            GroupOperation::Init(ref init) => &init.path,
+            GroupOperation::Commit(ref commit) => &commit.path,
+            GroupOperation::ExternalJoin(ref join) => {
+                // The joiner's init secret replaces ours. They encrypted it to the prior epoch's
+                // external key.
+                let (_, external_private_key) = self.external_key_pair()?;
+                let init_secret_bytes = ecies::decrypt(
+                    self.cs,
+                    &external_private_key,
+                    join.encrypted_init_secret.clone(),
+                )?;
+                new_state.init_secret = HmacKey::new_from_bytes(&init_secret_bytes);
+                &join.path
+            }
+            // Everything else either has no path or had it applied above. "The update secret
+            // resulting from this change is an all-zero octet string of length Hash.length."
+            _ => {
+                let zeros = UpdateSecret::new_from_zeros(self.cs.hash_impl.digest_size());
+                return Ok(remove_update_secret.unwrap_or(zeros));
+            }
+        };
+
+        // Now treat the rest like an Update coming from the sender. We can unwrap here because a
+        // preliminary GroupState errors above if it wasn't added.
+        let my_tree_idx = GroupState::roster_index_to_tree_index(new_state.roster_index.unwrap())?;
+        let sender_tree_idx = GroupState::roster_index_to_tree_index(signer_index)?;
+        new_state.process_incoming_direct_path(path, sender_tree_idx, my_tree_idx)
+    }
+
+    /// Processes the given framed message. A `Handshake` is passed to
+    /// `process_or_buffer_handshake`, so it's buffered if it's for a future epoch. A `Handshake`
+    /// for the previous epoch is checked for a fork with `detect_fork`. If it wins, it's returned,
+    /// so that the caller can roll back to it with `resolve_fork`. Otherwise, it's ignored.
+    /// Application data is returned as is. A `Proposal` is checked and returned, so that it can be
+    /// committed later. `app_key_chain` must be this epoch's. It's used to decrypt the message if
+    /// it's encrypted, and is otherwise left alone.
+    ///
+    /// Returns: `Ok(processed)` on success. Returns an `Error::ValidationError` if the message
+    /// isn't from this group and epoch or its sender differs from its content's signer, and an
+    /// `Error::SignatureError` if a signature doesn't verify. Otherwise, returns whatever
+    /// `process_or_buffer_handshake`, `detect_fork`, or `decrypt_application_message` would.
+    pub fn process_message(
+        &self,
+        message: MlsMessage,
+        app_key_chain: &mut ApplicationKeyChain,
+    ) -> Result<ProcessedMessage, Error> {
+        self.process_message_with_context(message, app_key_chain, &ProcessingContext::new())
+    }
+
+    /// Like `process_message`, but a `Handshake` is processed in the given `ProcessingContext`
+    ///
+    /// Returns: The same as `process_message`
+    pub fn process_message_with_context(
+        &self,
+        message: MlsMessage,
+        app_key_chain: &mut ApplicationKeyChain,
+        ctx: &ProcessingContext,
+    ) -> Result<ProcessedMessage, Error> {
+        let content = match message {
+            MlsMessage::Plaintext(plaintext) => plaintext.verify(self)?,
+            MlsMessage::Ciphertext(ciphertext) => {
+                framing::decrypt_ciphertext(ciphertext, self, app_key_chain)?
+            }
+        };
+
+        match content {
+            MlsContent::Handshake(handshake) => {
+                // A Handshake from the previous epoch competes with the one we applied. We only
+                // move if it wins.
+                if handshake.prior_epoch.checked_add(1) == Some(self.epoch) {
+                    return match self.detect_fork(&handshake)? {
+                        ForkStatus::Lost => Ok(ProcessedMessage::ForkLost(handshake)),
+                        ForkStatus::NoFork | ForkStatus::Won => Ok(ProcessedMessage::ForkIgnored),
+                    };
+                }
+
+                let (new_state, new_app_key_chains, failed) =
+                    self.process_or_buffer_handshake_with_context(handshake, ctx)?;
+                Ok(ProcessedMessage::Handshake(new_state, new_app_key_chains, failed))
+            }
+            MlsContent::Application(data) => Ok(ProcessedMessage::Application(data.0)),
+            MlsContent::Proposal(proposal) => {
+                // The framing checked that the proposal is from this epoch and from the message's
+                // sender, who is in the roster. It remains to check the proposal's own signature.
+                let sender_credential = self
+                    .roster
+                    .0
+                    .get(proposal.sender_index as usize)
+                    .and_then(Option::as_ref)
+                    .ok_or(Error::ValidationError("Proposal's sender credential is empty"))?;
+                proposal.verify_sig(&self.group_id, sender_credential)?;
+                Ok(ProcessedMessage::Proposal(proposal))
+            }
+        }
+    }
+
+    /// Processes the given `Handshake` and, if successful, produces a new `GroupState` and
     /// associated `ApplicationKeyChain` This does not mutate the current `GroupState`. Instead, it
     /// returns the next version of the `GroupState`, where the operation contained by the
     /// `Handshake` has been applied.
     ///
     /// Returns: `Ok((group_state, app_key_chain))` on success, where `group_state` is the
     /// `GroupState` after the given handshake has been applied, and `app_key_chain` is the
-    /// `ApplicationKeyChain` belonging to `group_state`. Returns `Error::IAmRemoved` iff this
-    /// member is the subject of a group `Remove` operation. Otherwise, returns some other sort of
-    /// `Error`.
+    /// `ApplicationKeyChain` belonging to `group_state`. If this member announced that they're
+    /// leaving (see `GroupState::create_leave_proposal`) and the handshake removes them,
+    /// `group_state` is in the terminal `MembershipStatus::Left` state, and `app_key_chain` has no
+    /// keys, since a member who has left doesn't get to know the new epoch's secrets. Returns
+    /// `Error::IAmRemoved` iff this member is otherwise the subject of a group `Remove` operation.
+    /// Otherwise, returns some other sort of `Error`.
     // According to the spec, this is how we process handshakes:
     // 1. Verify that the prior_epoch field of the Handshake message is equal the epoch field of
     //    the current GroupState object.
@@ -728,12 +1503,57 @@ impl GroupState {
         &self,
         handshake: &Handshake,
     ) -> Result<(GroupState, ApplicationKeyChain), Error> {
+        self.process_handshake_with_context(handshake, &ProcessingContext::new())
+    }
+
+    /// Like `process_handshake`, but in the given `ProcessingContext`. `process_handshake` uses
+    /// the default context.
+    ///
+    /// Returns: The same as `process_handshake`. If the `Handshake` references a PSK that isn't in
+    /// the context's `PskStore`, or adds someone whose `UserInitKey` is not valid at the context's
+    /// current time, returns an `Error::ValidationError`. If the context's `MembershipPolicy`
+    /// vetoes the operation, returns the policy's `Error`, which is an `Error::AuthorizationError`
+    /// for the policies in this crate.
+    pub fn process_handshake_with_context(
+        &self,
+        handshake: &Handshake,
+        ctx: &ProcessingContext,
+    ) -> Result<(GroupState, ApplicationKeyChain), Error> {
+        if self.membership_status == MembershipStatus::Left {
+            return Err(Error::ValidationError(
+                "Cannot process a Handshake after leaving the group",
+            ));
+        }
+        if self.reinit.is_some() {
+            return Err(Error::ValidationError(
+                "Cannot process a Handshake after the group has been reinitialized",
+            ));
+        }
+        if self.fork_point.is_some() && handshake.prior_epoch.checked_add(1) == Some(self.epoch) {
+            return Err(Error::ValidationError(
+                "Handshake is from the previous epoch. Use GroupState::detect_fork",
+            ));
+        }
         if handshake.prior_epoch != self.epoch {
             return Err(Error::ValidationError("Handshake's prior epoch isn't the current epoch"));
         }
 
+        // A sealed Handshake is opened with its signer's handshake key for this epoch. From then
+        // on, it's treated like any other.
+        let unsealed_handshake;
+        let handshake = match handshake.operation {
+            GroupOperation::Sealed(ref sealed) => {
+                unsealed_handshake = self.unseal_handshake(handshake, sealed)?;
+                &unsealed_handshake
+            }
+            _ => handshake,
+        };
+
+        // Someone who is joining externally isn't in the group yet. Their position is checked when
+        // the operation is processed.
+        let is_external_join = matches!(handshake.operation, GroupOperation::ExternalJoin(_));
         let sender_tree_idx = GroupState::roster_index_to_tree_index(handshake.signer_index)?;
-        if sender_tree_idx >= self.tree.size() {
+        if sender_tree_idx >= self.tree.size() && !is_external_join {
             return Err(Error::ValidationError("Handshake sender tree index is out of range"));
         }
 
@@ -745,39 +1565,59 @@ impl GroupState {
 
         // Get the sender's public key and preferred signature scheme from the roster. There are
         // two things that can go wrong here: either the sender index is bad, or the index is good
-        // but the roster entry is empty.
-        let sender_credential = self
-            .roster
-            .0
-            .get(handshake.signer_index as usize)
-            .ok_or(Error::ValidationError("Handshake's signer index is out of bounds"))?
-            .as_ref()
-            .ok_or(Error::ValidationError("Handshake's signer credential is empty"))?;
+        // but the roster entry is empty. An external joiner brings their own credential.
+        let sender_credential = match handshake.operation {
+            GroupOperation::ExternalJoin(ref join) => &join.init_key.credential,
+            _ => self
+                .roster
+                .0
+                .get(handshake.signer_index as usize)
+                .ok_or(Error::ValidationError("Handshake's signer index is out of bounds"))?
+                .as_ref()
+                .ok_or(Error::ValidationError("Handshake's signer credential is empty"))?,
+        };
         let sender_public_key = sender_credential.get_public_key();
         let sender_ss = sender_credential.get_signature_scheme();
 
+        // Ask the policy before anything is applied. In particular, an unauthorized Remove must not
+        // make us think we've been removed. Anyone can make an ExternalJoin from a PublicGroupInfo,
+        // so the policy has to opt into those.
+        if is_external_join && !ctx.policy.allows_external_joins() {
+            return Err(Error::AuthorizationError("External joins are not allowed by the policy"));
+        }
+        ctx.policy.authorize(sender_credential, handshake, self)?;
+
         // Do the handshake operation on the preliminary new state. This returns an update secret
         // that the new epoch secrets are derived from.
-        let update_secret = match handshake.operation {
-            GroupOperation::Update(ref update) => {
-                new_state.process_incoming_update_op(update, sender_tree_idx)?
+        let update_secret = match self.process_operation(
+            &mut new_state,
+            &handshake.operation,
+            handshake.signer_index,
+            ctx.clock.now(),
+        ) {
+            Err(Error::IAmRemoved) if self.membership_status == MembershipStatus::Leaving => {
+                // We asked to leave and this handshake honors that. We don't get to know the
+                // new epoch's secrets, so we can't check the MAC, but we can still check that
+                // the handshake came from who it says it came from.
+                let sig_data = new_state.transcript_hash.as_bytes();
+                sender_ss.verify(sender_public_key, sig_data, &handshake.signature)?;
+                let left_state = self.as_left();
+                let app_key_chain = ApplicationKeyChain::for_left_group(&left_state);
+                return Ok((left_state, app_key_chain));
             }
-            GroupOperation::Remove(ref remove) => new_state.process_remove_op(remove)?,
-            GroupOperation::Add(ref add) => {
-                // Compute the hash of the welcome_info that created this group, which is
-                // just the state of this group
-                let prior_welcome_info_hash = {
-                    let prior_welcome_info = self.as_welcome_info();
-                    let digest = self.cs.hash_impl.hash_serializable(&prior_welcome_info)?;
-                    WelcomeInfoHash::from(digest)
-                };
-                new_state.process_add_op(add, &prior_welcome_info_hash)?
+            res => res?,
+        };
+
+        // A Commit can reference PSKs. This fails if we don't have all of them.
+        let psk_secret = match handshake.operation {
+            GroupOperation::Commit(ref commit) => {
+                psk::derive_psk_secret(self.cs, &commit.get_psk_ids(), ctx.psks)?
             }
-            // The spec hasn't weighed on group Init yet
-            GroupOperation::Init(_) => unimplemented!(),
+            _ => None,
         };
 
-        let (app_secret, confirmation_key) = new_state.update_epoch_secrets(&update_secret)?;
+        let (app_secret, confirmation_key) =
+            new_state.update_epoch_secrets(&update_secret, psk_secret.as_ref())?;
 
         //
         // Now validate the new state. If it's valid, we set the current state to the new one.
@@ -805,11 +1645,271 @@ impl GroupState {
             &handshake.confirmation,
         )?;
 
-        // All is well. Make the new application key chain and send it along
+        // All is well. Make the new application key chain and send it along. Keep where we came
+        // from, in case this Handshake loses a fork.
         let app_key_chain = ApplicationKeyChain::from_application_secret(&new_state, app_secret);
+        let new_state = new_state.with_fork_point(self, handshake.signer_index);
+        Ok((new_state, app_key_chain))
+    }
+
+    /// Like `process_handshake`, but tolerant of handshakes arriving out of order. If the given
+    /// `Handshake` is for a future epoch, it is put in the pending queue of the returned
+    /// `GroupState`. If it is for the current epoch, it is processed, and then every pending
+    /// `Handshake` that has become ready is processed as well, in epoch order.
+    ///
+    /// A future-epoch `Handshake` is buffered without checking its signer or signature, since its
+    /// signer may join in an epoch this member hasn't reached yet. Both are checked against the
+    /// roster of its epoch once it becomes ready, and it fails to process if they don't check out.
+    /// Buffering an exact duplicate of a pending `Handshake` does nothing.
+    ///
+    /// Callers must get `Handshake`s here through `process_message`, which checks the framing
+    /// signature of one whose signer is already in the roster. Calling this directly lets anyone
+    /// fill the queue in a member's name.
+    ///
+    /// The queue holds at most `MAX_PENDING_HANDSHAKES_PER_SENDER` `Handshake`s from any one
+    /// signer, and at most `MAX_PENDING_HANDSHAKES_FROM_UNKNOWN_SIGNERS` from signers who aren't
+    /// in the current roster, all together, since nothing about theirs has been checked. Past
+    /// either, the furthest-ahead one of whoever holds the most is evicted. When the queue is
+    /// full, the same goes for everyone, preferring signers who aren't in the current roster.
+    ///
+    /// Returns: `Ok((group_state, app_key_chains, failed))` on success, where `app_key_chains`
+    /// contains the `ApplicationKeyChain` of every epoch that was entered, in order. This is empty
+    /// if the `Handshake` was buffered. `failed` contains every pending `Handshake` that became
+    /// ready but failed to process, along with why. This includes the ones that lost to a
+    /// competing `Handshake` for the same epoch. These are no longer pending, but the caller can
+    /// retry them, e.g., once a missing PSK is available. If the `Handshake` is the one that would
+    /// be evicted, or is more than `MAX_PENDING_HANDSHAKES` epochs ahead, is for a past epoch, or
+    /// fails to process, returns an `Error`. If the `Handshake` or a pending one honors this
+    /// member's leave, `group_state` is in the terminal `MembershipStatus::Left` state, and
+    /// `app_key_chains` only holds the key chains of the epochs entered before that.
+    pub fn process_or_buffer_handshake(
+        &self,
+        handshake: Handshake,
+    ) -> Result<(GroupState, Vec<ApplicationKeyChain>, FailedHandshakes), Error> {
+        self.process_or_buffer_handshake_with_context(handshake, &ProcessingContext::new())
+    }
+
+    /// Like `process_or_buffer_handshake`, but processes every `Handshake` that gets applied in
+    /// the given `ProcessingContext`. Buffered `Handshake`s are checked against it when they
+    /// become ready, not when they arrive.
+    ///
+    /// Returns: The same as `process_or_buffer_handshake`, and the same errors as
+    /// `process_handshake_with_context` for the given `Handshake`
+    pub fn process_or_buffer_handshake_with_context(
+        &self,
+        handshake: Handshake,
+        ctx: &ProcessingContext,
+    ) -> Result<(GroupState, Vec<ApplicationKeyChain>, FailedHandshakes), Error> {
+        if handshake.prior_epoch > self.epoch && self.membership_status != MembershipStatus::Left {
+            self.check_bufferable(&handshake)?;
+
+            // An exact duplicate of something we're already holding changes nothing
+            let serialized_handshake = tls_ser::serialize_to_bytes(&handshake)?;
+            for pending in self.pending_handshakes.iter() {
+                if tls_ser::serialize_to_bytes(pending)? == serialized_handshake {
+                    return Ok((self.clone(), Vec::new(), Vec::new()));
+                }
+            }
+
+            let mut new_state = self.clone();
+            new_state.pending_handshakes.push(handshake);
+            let new_idx = new_state.pending_handshakes.len() - 1;
+            if let Some(evicted_idx) = new_state.pending_handshake_to_evict() {
+                if evicted_idx == new_idx {
+                    return Err(Error::ValidationError("Pending handshake queue is full"));
+                }
+                new_state.pending_handshakes.remove(evicted_idx);
+            }
+            return Ok((new_state, Vec::new(), Vec::new()));
+        }
+
+        let (mut state, app_key_chain) = self.process_handshake_with_context(&handshake, ctx)?;
+        if state.membership_status == MembershipStatus::Left {
+            return Ok((state, Vec::new(), Vec::new()));
+        }
+        let mut app_key_chains = vec![app_key_chain];
+        let mut failed = Vec::new();
+
+        // Keep applying pending handshakes until none of them are for the current epoch. Anything
+        // for an epoch we've passed was dropped when the epoch was incremented.
+        loop {
+            let pending = core::mem::take(&mut state.pending_handshakes);
+            let (ready, rest): (Vec<Handshake>, Vec<Handshake>) =
+                pending.into_iter().partition(|h| h.prior_epoch == state.epoch);
+
+            // Competing handshakes for the same epoch are tried in the order that forks are
+            // resolved, i.e., lowest signer_index first. The first one that succeeds wins, and the
+            // ones after it lost the fork. Those and the ones before it that failed are handed
+            // back to the caller.
+            let mut ready = ready;
+            ready.sort_by_key(|h| h.signer_index);
+            let mut next = None;
+            for h in ready {
+                if next.is_some() {
+                    let e = Error::ValidationError("Handshake lost to one for the same epoch");
+                    failed.push((h, e));
+                    continue;
+                }
+                match state.process_handshake_with_context(&h, ctx) {
+                    Ok(res) => next = Some(res),
+                    Err(e) => failed.push((h, e)),
+                }
+            }
+
+            match next {
+                // If it honors our leave, there's no epoch to enter and nothing left to apply
+                Some((next_state, _)) if next_state.membership_status == MembershipStatus::Left => {
+                    state = next_state;
+                    break;
+                }
+                Some((mut next_state, app_key_chain)) => {
+                    // The queue in next_state is the one we took out above. Put the remainder in
+                    // its place.
+                    next_state.pending_handshakes = rest;
+                    app_key_chains.push(app_key_chain);
+                    state = next_state;
+                }
+                None => {
+                    state.pending_handshakes = rest;
+                    break;
+                }
+            }
+        }
+
+        Ok((state, app_key_chains, failed))
+    }
+
+    /// Checks whether `handshake` was made from the same epoch as the `Handshake` that brought the
+    /// group to its current epoch, and if so, which of the two wins the fork. The `Handshake` with
+    /// the lowest `signer_index` wins. This works the same whether the applied `Handshake` was
+    /// this member's own or someone else's, so every member ends up on the same branch. A fork is
+    /// detected by applying `handshake`'s operation to the previous epoch's transcript hash and
+    /// seeing that the result differs from ours. Only the signature of `handshake` is checked,
+    /// since the secrets of the previous epoch aren't kept. The rest is checked by
+    /// `resolve_fork`.
+    ///
+    /// Returns: `Ok(fork_status)` on success. If `handshake` competes with the applied
+    /// `Handshake` but its signature doesn't verify, returns an `Error::SignatureError`. A sealed
+    /// `Handshake` can't be checked without the previous epoch's handshake key, so this returns an
+    /// `Error::ValidationError` for one that competes. Pass it to `resolve_fork` instead.
+    pub fn detect_fork(&self, handshake: &Handshake) -> Result<ForkStatus, Error> {
+        let fork_point = match self.fork_point {
+            Some(ref fork_point) if handshake.prior_epoch == fork_point.prior_epoch => fork_point,
+            _ => return Ok(ForkStatus::NoFork),
+        };
+        // The applied Handshake doesn't compete with itself, and a member can't compete with
+        // themselves either, since the tie-break can't tell them apart. Our own Handshakes can't
+        // be processed by us, and if one of them got rolled back, it already lost.
+        if handshake.signer_index == fork_point.signer_index
+            || Some(handshake.signer_index) == self.roster_index
+        {
+            return Ok(ForkStatus::NoFork);
+        }
+        if let GroupOperation::Sealed(_) = handshake.operation {
+            return Err(Error::ValidationError(
+                "Cannot check a sealed Handshake for a fork. Use GroupState::resolve_fork",
+            ));
+        }
+
+        // Apply the other Handshake's operation to where we were. If it leads somewhere else,
+        // it's a fork.
+        let transcript_hash =
+            next_transcript_hash(self.cs, &fork_point.prior_transcript_hash, &handshake.operation)?;
+        if transcript_hash.as_bytes() == self.transcript_hash.as_bytes() {
+            return Ok(ForkStatus::NoFork);
+        }
+
+        // Same as in process_handshake, the signature is over the new transcript hash. An
+        // external joiner brings their own credential.
+        let sender_credential = match handshake.operation {
+            GroupOperation::ExternalJoin(ref join) => &join.init_key.credential,
+            _ => fork_point
+                .prior_roster
+                .0
+                .get(handshake.signer_index as usize)
+                .and_then(Option::as_ref)
+                .ok_or(Error::ValidationError("Handshake's signer credential is empty"))?,
+        };
+        sender_credential.get_signature_scheme().verify(
+            sender_credential.get_public_key(),
+            transcript_hash.as_bytes(),
+            &handshake.signature,
+        )?;
+
+        if self.loses_fork_to(handshake) {
+            Ok(ForkStatus::Lost)
+        } else {
+            Ok(ForkStatus::Won)
+        }
+    }
+
+    /// Rolls back the `Handshake` that brought the group to its current epoch and applies
+    /// `handshake` in its place. This is what a member does after `GroupState::detect_fork` tells
+    /// them that the `Handshake` they applied lost a fork. `prior_state` must be the `GroupState`
+    /// this member had in the previous epoch, i.e., the one that the applied `Handshake` was
+    /// created or processed from. A `GroupState` doesn't keep the secrets of the previous epoch,
+    /// so a member who wants to be able to roll back has to hold on to it until the next epoch.
+    /// Any application messages sent in the epoch that was rolled back must be re-sent.
+    ///
+    /// Returns: `Ok((group_state, app_key_chain))` on success, just like
+    /// `GroupState::process_handshake`. If `prior_state` isn't where this `GroupState` came from,
+    /// or `handshake` does not win a fork against the applied `Handshake`, returns an
+    /// `Error::ValidationError`. If `handshake` is invalid, returns some other sort of `Error`.
+    pub fn resolve_fork(
+        &self,
+        prior_state: &GroupState,
+        handshake: &Handshake,
+    ) -> Result<(GroupState, ApplicationKeyChain), Error> {
+        self.resolve_fork_with_context(prior_state, handshake, &ProcessingContext::new())
+    }
+
+    /// Like `resolve_fork`, but applies `handshake` in the given `ProcessingContext`
+    ///
+    /// Returns: The same as `resolve_fork`, and the same errors as
+    /// `process_handshake_with_context`
+    pub fn resolve_fork_with_context(
+        &self,
+        prior_state: &GroupState,
+        handshake: &Handshake,
+        ctx: &ProcessingContext,
+    ) -> Result<(GroupState, ApplicationKeyChain), Error> {
+        let fork_point = self
+            .fork_point
+            .as_ref()
+            .ok_or(Error::ValidationError("There is no Handshake to roll back"))?;
+        let is_prior_state = prior_state.epoch == fork_point.prior_epoch
+            && prior_state.group_id == self.group_id
+            && prior_state.roster_index == self.roster_index
+            && prior_state.transcript_hash.as_bytes()
+                == fork_point.prior_transcript_hash.as_bytes();
+        if !is_prior_state {
+            return Err(Error::ValidationError("GroupState is not the one this state came from"));
+        }
+        // See detect_fork
+        if handshake.signer_index == fork_point.signer_index
+            || Some(handshake.signer_index) == self.roster_index
+            || !self.loses_fork_to(handshake)
+        {
+            return Err(Error::ValidationError("Handshake does not win a fork"));
+        }
+
+        // Apply the other Handshake to where we were. If it leads somewhere else, it's a fork.
+        let (new_state, app_key_chain) =
+            prior_state.process_handshake_with_context(handshake, ctx)?;
+        if new_state.transcript_hash.as_bytes() == self.transcript_hash.as_bytes() {
+            return Err(Error::ValidationError("Handshake does not win a fork"));
+        }
         Ok((new_state, app_key_chain))
     }
 
+    /// Returns whether `handshake` would beat the `Handshake` that brought the group to its
+    /// current epoch in a fork
+    fn loses_fork_to(&self, handshake: &Handshake) -> bool {
+        let applied_signer_index =
+            self.fork_point.as_ref().map(|fork_point| fork_point.signer_index);
+        matches!(applied_signer_index, Some(idx) if handshake.signer_index < idx)
+    }
+
     /// Creates and applies a `GroupUpdate` operation with the given path secret information. This
     /// method does not mutate this `GroupState`, the operation is rather applied to the returned
     /// `GroupState`.
@@ -847,10 +1947,13 @@ impl GroupState {
         // Now package the update into a GroupUpdate structure
         let direct_path_msg = new_group_state.tree.encrypt_direct_path_secrets(
             new_group_state.cs,
+            new_group_state.get_signature_scheme(),
+            &new_group_state.identity_key,
             my_tree_idx,
             new_path_secret,
             csprng,
         )?;
+        new_group_state.set_direct_path_parent_hashes(&direct_path_msg, my_tree_idx)?;
         let update = GroupUpdate {
             path: direct_path_msg,
         };
@@ -860,7 +1963,7 @@ impl GroupState {
 
         // Final modification: update my epoch secrets and make the new ApplicationKeyChain
         let (app_secret, confirmation_key) =
-            new_group_state.update_epoch_secrets(&update_secret)?;
+            new_group_state.update_epoch_secrets(&update_secret, None)?;
         let app_key_chain =
             ApplicationKeyChain::from_application_secret(&new_group_state, app_secret);
 
@@ -885,6 +1988,7 @@ impl GroupState {
         new_roster_index: u32,
         init_key: UserInitKey,
         prior_welcome_info_hash: &WelcomeInfoHash,
+        now: u64,
     ) -> Result<(GroupState, ApplicationKeyChain, GroupOperation, ConfirmationKey), Error> {
         // Ugh, a full group state clone, I know
         let mut new_group_state = self.clone();
@@ -897,12 +2001,143 @@ impl GroupState {
         };
         // Apply the Add, log the operation in the transcript hash, increment the epoch, update
         // the epoch secrets, and make the new ApplicationKeyChain
-        let update_secret = new_group_state.process_add_op(&add, prior_welcome_info_hash)?;
+        let mut target = GroupStateTarget {
+            state: &mut new_group_state,
+            my_pending_update_key: None,
+        };
+        apply_add(&mut target, &add, Some(prior_welcome_info_hash), now)?;
+        let update_secret = UpdateSecret::new_from_zeros(self.cs.hash_impl.digest_size());
         let op = GroupOperation::Add(add);
         new_group_state.update_transcript_hash(&op)?;
         new_group_state.increment_epoch()?;
         let (app_secret, confirmation_key) =
-            new_group_state.update_epoch_secrets(&update_secret)?;
+            new_group_state.update_epoch_secrets(&update_secret, None)?;
+        let app_key_chain =
+            ApplicationKeyChain::from_application_secret(&new_group_state, app_secret);
+
+        Ok((new_group_state, app_key_chain, op, confirmation_key))
+    }
+
+    /// Creates and applies a `GroupMultiAdd` operation for the members with the target
+    /// `init_keys`. This method does not mutate this `GroupState`, the operation is rather applied
+    /// to the returned `GroupState`.
+    ///
+    /// Returns: `Ok((group_state, app_key_chain, group_op, confirmation_key))` on success, where
+    /// `group_state` is the group state after having applied the multi-add operation,
+    /// `app_key_chain` is the resulting application key chain (again, after having applied the
+    /// multi-add operation), `group_op` is the raw `GroupOperation` object, and
+    /// `confirmation_key` is the derived confirmation key we'll use to compute the MAC in the
+    /// `Handshake` that will end up containing the `GroupOperation`.
+    pub(crate) fn create_and_apply_multi_add_op(
+        &self,
+        init_keys: Vec<UserInitKey>,
+        prior_welcome_info_hash: &WelcomeInfoHash,
+        now: u64,
+    ) -> Result<(GroupState, ApplicationKeyChain, GroupOperation, ConfirmationKey), Error> {
+        // Ugh, a full group state clone, I know
+        let mut new_group_state = self.clone();
+
+        // Make the MultiAdd op
+        let multi_add = GroupMultiAdd {
+            init_keys,
+            welcome_info_hash: prior_welcome_info_hash.clone(),
+        };
+        // Apply the MultiAdd, log the operation in the transcript hash, increment the epoch,
+        // update the epoch secrets, and make the new ApplicationKeyChain
+        let mut target = GroupStateTarget {
+            state: &mut new_group_state,
+            my_pending_update_key: None,
+        };
+        apply_multi_add(&mut target, &multi_add, Some(prior_welcome_info_hash), now)?;
+        let update_secret = UpdateSecret::new_from_zeros(self.cs.hash_impl.digest_size());
+        let op = GroupOperation::MultiAdd(multi_add);
+        new_group_state.update_transcript_hash(&op)?;
+        new_group_state.increment_epoch()?;
+        let (app_secret, confirmation_key) =
+            new_group_state.update_epoch_secrets(&update_secret, None)?;
+        let app_key_chain =
+            ApplicationKeyChain::from_application_secret(&new_group_state, app_secret);
+
+        Ok((new_group_state, app_key_chain, op, confirmation_key))
+    }
+
+    /// Creates and applies a `GroupReInit` operation that ends this group in favor of a successor
+    /// with the given group ID, ciphersuite, and protocol version. This method does not mutate
+    /// this `GroupState`, the operation is rather applied to the returned `GroupState`.
+    ///
+    /// Returns: `Ok((group_state, app_key_chain, group_op, confirmation_key))` on success, where
+    /// `group_state` is the group state after having applied the reinit operation,
+    /// `app_key_chain` is the resulting application key chain (again, after having applied the
+    /// reinit operation), `group_op` is the raw `GroupOperation` object, and `confirmation_key`
+    /// is the derived confirmation key we'll use to compute the MAC in the `Handshake` that will
+    /// end up containing the `GroupOperation`.
+    pub(crate) fn create_and_apply_reinit_op(
+        &self,
+        new_group_id: Vec<u8>,
+        new_cs: &'static CipherSuite,
+        new_protocol_version: ProtocolVersion,
+    ) -> Result<(GroupState, ApplicationKeyChain, GroupOperation, ConfirmationKey), Error> {
+        // Ugh, a full group state clone, I know
+        let mut new_group_state = self.clone();
+
+        // Make the ReInit op
+        let reinit = GroupReInit {
+            group_id: new_group_id,
+            protocol_version: new_protocol_version,
+            cipher_suite: new_cs,
+        };
+        // Apply the ReInit, log the operation in the transcript hash, increment the epoch, update
+        // the epoch secrets, and make the new ApplicationKeyChain
+        let mut target = GroupStateTarget {
+            state: &mut new_group_state,
+            my_pending_update_key: None,
+        };
+        apply_reinit(&mut target, &reinit)?;
+        let update_secret = UpdateSecret::new_from_zeros(self.cs.hash_impl.digest_size());
+        let op = GroupOperation::ReInit(reinit);
+        new_group_state.update_transcript_hash(&op)?;
+        new_group_state.increment_epoch()?;
+        let (app_secret, confirmation_key) =
+            new_group_state.update_epoch_secrets(&update_secret, None)?;
+        let app_key_chain =
+            ApplicationKeyChain::from_application_secret(&new_group_state, app_secret);
+
+        Ok((new_group_state, app_key_chain, op, confirmation_key))
+    }
+
+    /// Creates and applies a `GroupExtensions` operation that replaces the group's extensions with
+    /// `extensions`. This method does not mutate this `GroupState`, the operation is rather
+    /// applied to the returned `GroupState`.
+    ///
+    /// Returns: `Ok((group_state, app_key_chain, group_op, confirmation_key))` on success, where
+    /// `group_state` is the group state after having applied the extensions operation,
+    /// `app_key_chain` is the resulting application key chain (again, after having applied the
+    /// extensions operation), `group_op` is the raw `GroupOperation` object, and
+    /// `confirmation_key` is the derived confirmation key we'll use to compute the MAC in the
+    /// `Handshake` that will end up containing the `GroupOperation`.
+    pub(crate) fn create_and_apply_extensions_op(
+        &self,
+        extensions: Vec<Extension>,
+    ) -> Result<(GroupState, ApplicationKeyChain, GroupOperation, ConfirmationKey), Error> {
+        // Ugh, a full group state clone, I know
+        let mut new_group_state = self.clone();
+
+        // Apply the new extensions, log the operation in the transcript hash, increment the epoch,
+        // update the epoch secrets, and make the new ApplicationKeyChain
+        let extensions_op = GroupExtensions {
+            extensions,
+        };
+        let mut target = GroupStateTarget {
+            state: &mut new_group_state,
+            my_pending_update_key: None,
+        };
+        apply_extensions(&mut target, &extensions_op)?;
+        let update_secret = UpdateSecret::new_from_zeros(self.cs.hash_impl.digest_size());
+        let op = GroupOperation::Extensions(extensions_op);
+        new_group_state.update_transcript_hash(&op)?;
+        new_group_state.increment_epoch()?;
+        let (app_secret, confirmation_key) =
+            new_group_state.update_epoch_secrets(&update_secret, None)?;
         let app_key_chain =
             ApplicationKeyChain::from_application_secret(&new_group_state, app_secret);
 
@@ -933,6 +2168,12 @@ impl GroupState {
     where
         R: CryptoRng,
     {
+        // Safely unwrap the roster index. A preliminary GroupState is one that has just been
+        // initialized with a Welcome message
+        let my_roster_idx = self
+            .roster_index
+            .ok_or(Error::ValidationError("Cannot make a Remove from a preliminary GroupState"))?;
+
         // Ugh, a full group state clone, I know
         let mut new_group_state = self.clone();
 
@@ -940,6 +2181,8 @@ impl GroupState {
         // Encrypt the new entropy for the tree
         let direct_path_msg = new_group_state.tree.encrypt_direct_path_secrets(
             new_group_state.cs,
+            new_group_state.get_signature_scheme(),
+            &new_group_state.identity_key,
             removed_tree_index,
             new_path_secret,
             csprng,
@@ -953,46 +2196,289 @@ impl GroupState {
 
         // Apply the Remove, log the operation in the transcript hash, increment the epoch, update
         // the epoch secrets, and make the new ApplicationKeyChain
-        let update_secret = new_group_state.process_remove_op(&remove)?;
+        let update_secret = new_group_state.process_remove_path(&remove)?;
+        let mut target = GroupStateTarget {
+            state: &mut new_group_state,
+            my_pending_update_key: None,
+        };
+        apply_remove(&mut target, &remove, my_roster_idx)?;
         let op = GroupOperation::Remove(remove);
         new_group_state.update_transcript_hash(&op)?;
         new_group_state.increment_epoch()?;
         let (app_secret, confirmation_key) =
-            new_group_state.update_epoch_secrets(&update_secret)?;
+            new_group_state.update_epoch_secrets(&update_secret, None)?;
         let app_key_chain =
             ApplicationKeyChain::from_application_secret(&new_group_state, app_secret);
 
         Ok((new_group_state, app_key_chain, op, confirmation_key))
     }
 
-    /// Creates a `Handshake` message by packaging the given `GroupOperation`
+    /// Creates and applies a `GroupInit` operation that appends every member in `init_keys` to the
+    /// group and introduces a new path secret `new_path_secret` at this member's index. `now` is
+    /// the current time, in seconds since the Unix epoch. This method does not mutate this
+    /// `GroupState`, the operation is rather applied to the returned `GroupState`.
     ///
-    /// Requires: For correctness, that the given `GroupOperation` has already been applied to this
-    /// `GroupState`.
+    /// Requires: This `GroupState` is at epoch 0, i.e., no other operation has been applied to it.
     ///
-    /// NOTE: This is intended to be called only on objects returned from `create_and_apply_*_op`,
-    /// where `*` is `add` or `update` or `remove`. This makes no sense otherwise.
-    fn create_handshake(
+    /// Returns: `Ok((group_state, app_key_chain, group_op, confirmation_key))` on success, where
+    /// `group_state` is the group state after having applied the init operation, `app_key_chain`
+    /// is the resulting application key chain (again, after having applied the init operation),
+    /// `group_op` is the raw `GroupOperation` object, and `confirmation_key` is the derived
+    /// confirmation key we'll use to compute the MAC in the `Handshake` that will end up
+    /// containing the `GroupOperation`.
+    pub(crate) fn create_and_apply_init_op<R>(
         &self,
-        prior_epoch: u32,
-        operation: GroupOperation,
-        confirmation_key: ConfirmationKey,
-    ) -> Result<Handshake, Error> {
-        // signature = Sign(identity_key, GroupState.transcript_hash)
-        let my_ss = self.get_signature_scheme();
-        let signature = my_ss.sign(&self.identity_key, self.transcript_hash.as_bytes());
+        init_keys: Vec<UserInitKey>,
+        prior_welcome_info_hash: &WelcomeInfoHash,
+        new_path_secret: PathSecret,
+        now: u64,
+        csprng: &mut R,
+    ) -> Result<(GroupState, ApplicationKeyChain, GroupOperation, ConfirmationKey), Error>
+    where
+        R: CryptoRng,
+    {
+        if self.epoch != 0 {
+            return Err(Error::ValidationError("Init operation is only valid at epoch 0"));
+        }
 
-        // Update the epoch secrets and use the resulting key to compute the MAC of the Handshake
+        // Ugh, a full group state clone, I know
+        let mut new_group_state = self.clone();
 
-        // confirmation = HMAC(confirmation_key, confirmation_data)
-        // where confirmation_data = GroupState.transcript_hash || Handshake.signature
-        let confirmation = {
-            let mut ctx = hmac::new_signing_context(self.cs.hash_impl, &confirmation_key.0);
-            ctx.feed_bytes(self.transcript_hash.as_bytes());
-            ctx.feed_bytes(&signature.as_bytes());
+        let my_tree_idx = {
+            // Safely unwrap the roster index. A preliminary GroupState is one that has just been
+            // initialized with a Welcome message
+            let roster_index = new_group_state.roster_index.ok_or(Error::ValidationError(
+                "Cannot make an Init from a preliminary GroupState",
+            ))?;
+            GroupState::roster_index_to_tree_index(roster_index)?
+        };
 
-            ctx.finalize()
+        // Add all the new members first, so that the path secrets below get encrypted to them
+        let mut target = GroupStateTarget {
+            state: &mut new_group_state,
+            my_pending_update_key: None,
+        };
+        apply_init_members(
+            &mut target,
+            &init_keys,
+            prior_welcome_info_hash,
+            Some(prior_welcome_info_hash),
+            now,
+        )?;
+
+        // Do the update and increment the epoch
+        let update_secret = new_group_state.apply_update(new_path_secret.clone(), my_tree_idx)?;
+        new_group_state.increment_epoch()?;
+
+        // Now package everything into a GroupInit structure
+        let direct_path_msg = new_group_state.tree.encrypt_direct_path_secrets(
+            new_group_state.cs,
+            new_group_state.get_signature_scheme(),
+            &new_group_state.identity_key,
+            my_tree_idx,
+            new_path_secret,
+            csprng,
+        )?;
+        new_group_state.set_direct_path_parent_hashes(&direct_path_msg, my_tree_idx)?;
+        let init = GroupInit {
+            init_keys,
+            welcome_info_hash: prior_welcome_info_hash.clone(),
+            path: direct_path_msg,
+        };
+        let op = GroupOperation::Init(init);
+
+        new_group_state.update_transcript_hash(&op)?;
+
+        // Final modification: update my epoch secrets and make the new ApplicationKeyChain
+        let (app_secret, confirmation_key) =
+            new_group_state.update_epoch_secrets(&update_secret, None)?;
+        let app_key_chain =
+            ApplicationKeyChain::from_application_secret(&new_group_state, app_secret);
+
+        Ok((new_group_state, app_key_chain, op, confirmation_key))
+    }
+
+    /// Creates and applies a `GroupCommit` operation that applies the given proposals and
+    /// introduces a new path secret `new_path_secret` at this member's index. `now` is the current
+    /// time, in seconds since the Unix epoch. This method does not mutate this `GroupState`, the
+    /// operation is rather applied to the returned `GroupState`.
+    ///
+    /// Returns: `Ok((group_state, app_key_chain, group_op, confirmation_key))` on success, where
+    /// `group_state` is the group state after having applied the commit operation,
+    /// `app_key_chain` is the resulting application key chain (again, after having applied the
+    /// commit operation), `group_op` is the raw `GroupOperation` object, and `confirmation_key` is
+    /// the derived confirmation key we'll use to compute the MAC in the `Handshake` that will end
+    /// up containing the `GroupOperation`.
+    pub(crate) fn create_and_apply_commit_op<P, R>(
+        &self,
+        proposals: Vec<Proposal>,
+        psks: &P,
+        new_path_secret: PathSecret,
+        now: u64,
+        csprng: &mut R,
+    ) -> Result<(GroupState, ApplicationKeyChain, GroupOperation, ConfirmationKey), Error>
+    where
+        P: PskStore + ?Sized,
+        R: CryptoRng,
+    {
+        // Safely unwrap the roster index. A preliminary GroupState is one that has just been
+        // initialized with a Welcome message
+        let my_roster_idx = self
+            .roster_index
+            .ok_or(Error::ValidationError("Cannot make a Commit from a preliminary GroupState"))?;
+        let my_tree_idx = GroupState::roster_index_to_tree_index(my_roster_idx)?;
+
+        // The Add proposals had better reference this state
+        let prior_welcome_info_hash = {
+            let prior_welcome_info = self.as_welcome_info();
+            let digest = self.cs.hash_impl.hash_serializable(&prior_welcome_info)?;
+            WelcomeInfoHash::from(digest)
+        };
+
+        // Ugh, a full group state clone, I know
+        let mut new_group_state = self.clone();
+
+        // Apply the proposals first, so that the path secrets below get encrypted to the new
+        // members and the new leaf keys. Our own Updates are ignored, so we don't need our pending
+        // Update key. Everyone else rejects the Commit if someone it adds is outside their
+        // UserInitKey's lifetime, so this checks that too.
+        let mut target = GroupStateTarget {
+            state: &mut new_group_state,
+            my_pending_update_key: None,
+        };
+        apply_proposals(
+            &mut target,
+            &proposals,
+            self.epoch,
+            Some(&prior_welcome_info_hash),
+            my_roster_idx,
+            now,
+        )?;
+
+        // Do the update and increment the epoch
+        let update_secret = new_group_state.apply_update(new_path_secret.clone(), my_tree_idx)?;
+        new_group_state.increment_epoch()?;
+
+        // Now package everything into a GroupCommit structure
+        let direct_path_msg = new_group_state.tree.encrypt_direct_path_secrets(
+            new_group_state.cs,
+            new_group_state.get_signature_scheme(),
+            &new_group_state.identity_key,
+            my_tree_idx,
+            new_path_secret,
+            csprng,
+        )?;
+        new_group_state.set_direct_path_parent_hashes(&direct_path_msg, my_tree_idx)?;
+        let commit = GroupCommit {
+            proposals,
+            path: direct_path_msg,
         };
+        let psk_secret = psk::derive_psk_secret(self.cs, &commit.get_psk_ids(), psks)?;
+        let op = GroupOperation::Commit(commit);
+
+        new_group_state.update_transcript_hash(&op)?;
+
+        // Final modification: update my epoch secrets and make the new ApplicationKeyChain
+        let (app_secret, confirmation_key) =
+            new_group_state.update_epoch_secrets(&update_secret, psk_secret.as_ref())?;
+        let app_key_chain =
+            ApplicationKeyChain::from_application_secret(&new_group_state, app_secret);
+
+        Ok((new_group_state, app_key_chain, op, confirmation_key))
+    }
+
+    /// Makes a `Proposal` with the given contents, signed by this member
+    ///
+    /// Returns: `Ok(proposal)` on success. Returns an `Error::ValidationError` if this
+    /// `GroupState` is preliminary or has left the group.
+    fn create_proposal(&self, body: ProposalBody) -> Result<Proposal, Error> {
+        if self.membership_status == MembershipStatus::Left {
+            return Err(Error::ValidationError("Cannot make a Proposal after leaving the group"));
+        }
+        if self.reinit.is_some() {
+            return Err(Error::ValidationError(
+                "Cannot make a Proposal after the group has been reinitialized",
+            ));
+        }
+
+        // Safely unwrap the roster index. A preliminary GroupState is one that has just been
+        // initialized with a Welcome message
+        let roster_index = self.roster_index.ok_or(Error::ValidationError(
+            "Cannot make a Proposal from a preliminary GroupState",
+        ))?;
+        let my_credential = self
+            .roster
+            .0
+            .get(roster_index as usize)
+            .and_then(|entry| entry.as_ref())
+            .ok_or(Error::ValidationError("This member's roster entry is empty"))?;
+
+        Proposal::new(
+            &self.identity_key,
+            my_credential,
+            &self.group_id,
+            self.epoch,
+            roster_index,
+            body,
+        )
+    }
+
+    /// Packages the given `GroupOperation` into a `Handshake`, and asks the policy in `ctx`
+    /// whether this member may perform it. Everyone else asks their policy before applying the
+    /// `Handshake`, so one that the policy vetoes would leave this member on a branch of their own.
+    ///
+    /// Requires: `new_group_state` is the result of applying `operation` to this `GroupState`
+    ///
+    /// Returns: `Ok((handshake, group_state))` on success, where `group_state` is
+    /// `new_group_state`, remembering this `GroupState` as its fork point. If the policy vetoes the
+    /// operation, returns an `Error::AuthorizationError`. Otherwise, returns whatever
+    /// `create_handshake` would.
+    fn finish_handshake(
+        &self,
+        new_group_state: GroupState,
+        operation: GroupOperation,
+        confirmation_key: ConfirmationKey,
+        ctx: &ProcessingContext,
+    ) -> Result<(Handshake, GroupState), Error> {
+        let handshake =
+            new_group_state.create_handshake(self.epoch, operation, confirmation_key)?;
+        let my_credential = self
+            .roster
+            .0
+            .get(handshake.signer_index as usize)
+            .and_then(Option::as_ref)
+            .ok_or(Error::ValidationError("This member's roster entry is empty"))?;
+        ctx.policy.authorize(my_credential, &handshake, self)?;
+        let new_group_state = new_group_state.with_fork_point(self, handshake.signer_index);
+
+        Ok((handshake, new_group_state))
+    }
+
+    /// Creates a `Handshake` message by packaging the given `GroupOperation`
+    ///
+    /// Requires: For correctness, that the given `GroupOperation` has already been applied to this
+    /// `GroupState`.
+    ///
+    /// NOTE: This is intended to be called only on objects returned from `create_and_apply_*_op`,
+    /// where `*` is `init` or `add` or `update` or `remove` or `commit`. This makes no sense
+    /// otherwise.
+    fn create_handshake(
+        &self,
+        prior_epoch: u32,
+        operation: GroupOperation,
+        confirmation_key: ConfirmationKey,
+    ) -> Result<Handshake, Error> {
+        // Check that we're allowed to make a Handshake at all before signing anything
+        if self.membership_status == MembershipStatus::Left {
+            return Err(Error::ValidationError("Cannot make a Handshake after leaving the group"));
+        }
+        // The only Handshake that leaves a group reinitialized is the ReInit itself
+        let is_reinit = matches!(operation, GroupOperation::ReInit(_));
+        if self.reinit.is_some() && !is_reinit {
+            return Err(Error::ValidationError(
+                "Cannot make a Handshake after the group has been reinitialized",
+            ));
+        }
 
         // Safely unwrap the roster index. A preliminary GroupState is one that has just been
         // initialized with a Welcome message
@@ -1000,6 +2486,22 @@ impl GroupState {
             "Cannot make a Handshake from a preliminary GroupState",
         ))?;
 
+        // signature = Sign(identity_key, GroupState.transcript_hash)
+        let my_ss = self.get_signature_scheme();
+        let signature = my_ss.sign(&self.identity_key, self.transcript_hash.as_bytes());
+
+        // Update the epoch secrets and use the resulting key to compute the MAC of the Handshake
+
+        // confirmation = HMAC(confirmation_key, confirmation_data)
+        // where confirmation_data = GroupState.transcript_hash || Handshake.signature
+        let confirmation = {
+            let mut ctx = hmac::new_signing_context(self.cs.hash_impl, &confirmation_key.0);
+            ctx.feed_bytes(self.transcript_hash.as_bytes());
+            ctx.feed_bytes(&signature.as_bytes());
+
+            ctx.finalize()
+        };
+
         let handshake = Handshake {
             prior_epoch,
             operation,
@@ -1019,6 +2521,262 @@ impl GroupState {
         &self.roster
     }
 
+    /// Returns this group's current epoch
+    pub fn get_epoch(&self) -> u32 {
+        self.epoch
+    }
+
+    /// Returns whether this member is in the group, leaving it, or has left it
+    pub fn get_membership_status(&self) -> MembershipStatus {
+        self.membership_status
+    }
+
+    /// Returns whether this member should send an `Update` soon. This is the case when this member
+    /// joined with a last-resort `UserInitKey` and still has its public key as their leaf key,
+    /// since that key is shared with every other group the `UserInitKey` was used to join.
+    pub fn update_due(&self) -> bool {
+        let last_resort_public_key = match self.last_resort_public_key {
+            Some(ref k) => k,
+            None => return false,
+        };
+        let my_leaf_public_key = self
+            .roster_index
+            .and_then(|idx| GroupState::roster_index_to_tree_index(idx).ok())
+            .and_then(|tree_idx| self.tree.get(tree_idx))
+            .and_then(|node| node.get_public_key());
+
+        match my_leaf_public_key {
+            Some(k) => k.ct_eq(last_resort_public_key).into(),
+            None => false,
+        }
+    }
+
+    /// Returns the `Handshake`s for future epochs that are waiting to be applied, in the order
+    /// they arrived
+    pub fn get_pending_handshakes(&self) -> &[Handshake] {
+        &self.pending_handshakes
+    }
+
+    /// Returns this group's extensions
+    pub fn get_extensions(&self) -> &[Extension] {
+        self.extensions.as_slice()
+    }
+
+    /// Returns the group ID of this group's successor, if this group has been reinitialized
+    pub fn get_successor_group_id(&self) -> Option<&[u8]> {
+        self.reinit.as_ref().map(|reinit| reinit.group_id.as_slice())
+    }
+
+    /// Derives a secret of `length` bytes from the current epoch, for use by the application,
+    /// e.g., as a media or file encryption key. Every member derives the same secret for the same
+    /// `label` and `context`. Different labels give independent secrets, and the secrets change
+    /// every epoch.
+    ///
+    /// Requires: `label.len() <= 249` and `length <= 255 * Hash.length`
+    ///
+    /// Returns: `Ok(secret)` on success. Returns an `Error::ValidationError` if the above
+    /// requirement is not met, or if this member has not seen an epoch change yet, i.e., this
+    /// `GroupState` is preliminary or was just created by `GroupState::new_singleton_group`.
+    // This is our own version of the MLS-Exporter function of later drafts of the spec:
+    // MLS-Exporter(Label, Context, Length) =
+    //     HKDF-Expand-Label(Derive-Secret(exporter_secret, Label), "exporter", Hash(Context),
+    //                       Length)
+    // where Derive-Secret has an empty context here
+    pub fn export_secret(
+        &self,
+        label: &[u8],
+        context: &[u8],
+        length: usize,
+    ) -> Result<Vec<u8>, Error> {
+        let hash_impl = self.cs.hash_impl;
+        let exporter_secret = self.exporter_secret.as_ref().ok_or(Error::ValidationError(
+            "There is no exporter secret before the first epoch change",
+        ))?;
+        // These are the limits of HKDF-Expand-Label
+        if label.len() > 249 {
+            return Err(Error::ValidationError("Exporter label is too long"));
+        }
+        if length > 255 * hash_impl.digest_size() {
+            return Err(Error::ValidationError("Exported secret is too long"));
+        }
+
+        let label_secret = {
+            let mut buf = vec![0u8; hash_impl.digest_size()];
+            hkdf::expand_label(hash_impl, exporter_secret, label, b"", &mut buf);
+            HmacKey::new_from_bytes(&buf)
+        };
+        let hashed_context = hash_impl.hash_bytes(context);
+        let mut secret = vec![0u8; length];
+        hkdf::expand_label(
+            hash_impl,
+            &label_secret,
+            b"exporter",
+            hashed_context.as_bytes(),
+            &mut secret,
+        );
+
+        Ok(secret)
+    }
+
+    /// Creates a signed `PublicGroupInfo` describing the current state of this group. A new
+    /// member can use this to join the group without a `Welcome`. It is only good for the current
+    /// epoch. The other members only accept the join if their `MembershipPolicy` allows external
+    /// joins.
+    ///
+    /// Returns: `Ok(group_info)` on success. Returns an `Error::ValidationError` if this
+    /// `GroupState` is preliminary or has left the group. Otherwise returns some other sort of
+    /// `Error`.
+    pub fn create_public_group_info(&self) -> Result<PublicGroupInfo, Error> {
+        if self.membership_status == MembershipStatus::Left {
+            return Err(Error::ValidationError("Cannot describe a group after leaving it"));
+        }
+        let signer_index = self.roster_index.ok_or(Error::ValidationError(
+            "Cannot make a PublicGroupInfo from a preliminary GroupState",
+        ))?;
+        let (external_public_key, _) = self.external_key_pair()?;
+
+        let mut group_info = PublicGroupInfo {
+            cipher_suite: self.cs,
+            protocol_version: self.protocol_version,
+            group_id: self.group_id.clone(),
+            epoch: self.epoch,
+            roster: self.roster.clone(),
+            tree: self.tree.clone(),
+            tree_parent_hashes: self.tree.get_parent_hashes(),
+            transcript_hash: self.transcript_hash.clone(),
+            extensions: self.extensions.clone(),
+            external_public_key,
+            signer_index,
+            // This is a placeholder until we sign below
+            signature: Signature::Raw(SignatureRaw(Vec::new())),
+        };
+        let serialized_info = tls_ser::serialize_to_bytes(&group_info.as_partial())?;
+        group_info.signature =
+            self.get_signature_scheme().sign(&self.identity_key, &serialized_info);
+
+        Ok(group_info)
+    }
+
+    /// Creates an `ExternalJoin` operation by which the member described by `init_key` adds
+    /// themselves to the group described by `group_info`, and introduces a new path secret
+    /// `new_path_secret` at their new leaf. Unlike the other `create_and_apply_*_handshake`
+    /// methods, this is done by someone who is not yet in the group. The new member goes in the
+    /// leftmost empty roster entry, or at the end if there is none.
+    ///
+    /// Requires: `identity_key` is the signing key of `init_key`'s credential, and
+    /// `init_key.private_keys` is not `None`. The caller is responsible for deciding whether the
+    /// signer of `group_info` is trustworthy.
+    ///
+    /// Returns: `Ok((handshake, group_state, app_key_chain))` on success, where `handshake` is the
+    /// `Handshake` message the existing members must process to admit the new member,
+    /// `group_state` is the new member's state after the join, and `app_key_chain` is the newly
+    /// derived application key schedule object. Returns an `Error::ValidationError` or
+    /// `Error::SignatureError` if `group_info` or `init_key` is invalid.
+    pub fn create_external_join_handshake<R>(
+        group_info: PublicGroupInfo,
+        identity_key: SigSecretKey,
+        init_key: UserInitKey,
+        new_path_secret: PathSecret,
+        csprng: &mut R,
+    ) -> Result<(Handshake, GroupState, ApplicationKeyChain), Error>
+    where
+        R: CryptoRng,
+    {
+        group_info.verify_sig()?;
+        init_key.verify_sig()?;
+        init_key.validate()?;
+
+        // Same version check as for Welcomes
+        let cs = group_info.cipher_suite;
+        let supported_version = init_key.get_supported_version(cs)?.ok_or(
+            Error::ValidationError("UserInitKey does not support the group's ciphersuite"),
+        )?;
+        if group_info.protocol_version != supported_version {
+            return Err(Error::ValidationError(
+                "PublicGroupInfo's protocol version does not match the UserInitKey's",
+            ));
+        }
+
+        // Start from a preliminary GroupState that knows everything but the group's secrets. The
+        // init secret is a fresh one of our own, which we share with the group below.
+        let external_public_key = group_info.external_public_key;
+        let prior_epoch = group_info.epoch;
+        // Like with a Welcome, don't trust the tree until its keys are all vouched for
+        let mut tree = group_info.tree;
+        tree.set_parent_hashes(group_info.tree_parent_hashes)?;
+        tree.verify_parent_hashes(cs, &group_info.roster)?;
+        let tree_hash = tree.tree_hash(cs)?;
+        let mut new_group_state = GroupState {
+            cs,
+            protocol_version: group_info.protocol_version,
+            identity_key,
+            group_id: group_info.group_id,
+            epoch: group_info.epoch,
+            roster: group_info.roster,
+            tree,
+            tree_hash,
+            transcript_hash: group_info.transcript_hash,
+            extensions: group_info.extensions,
+            roster_index: None,
+            initializing_user_init_key: Some(init_key.clone()),
+            last_resort_public_key: None,
+            pending_update_private_key: None,
+            membership_status: MembershipStatus::Member,
+            pending_handshakes: Vec::new(),
+            fork_point: None,
+            reinit: None,
+            exporter_secret: None,
+            resumption_secret: None,
+            sender_data_secret: None,
+            handshake_secret: None,
+            welcome_seal_secret: None,
+            init_secret: HmacKey::new_from_random(cs.hash_impl, csprng),
+        };
+        let encrypted_init_secret = ecies::encrypt(
+            cs,
+            &external_public_key,
+            new_group_state.init_secret.0.clone(),
+            csprng,
+        )?;
+
+        // Add ourselves where new members go, then do the update and increment the epoch
+        let my_roster_idx = next_free_roster_index(&new_group_state.roster)?;
+        new_group_state.insert_new_member(&init_key, my_roster_idx)?;
+        new_group_state.discard_initializing_user_init_key()?;
+        let my_tree_idx = GroupState::roster_index_to_tree_index(my_roster_idx)?;
+        let update_secret = new_group_state.apply_update(new_path_secret.clone(), my_tree_idx)?;
+        new_group_state.increment_epoch()?;
+
+        // Now package everything into a GroupExternalJoin structure. Everyone else already knows
+        // our public keys, but they have no business knowing our private keys.
+        let direct_path_msg = new_group_state.tree.encrypt_direct_path_secrets(
+            cs,
+            new_group_state.get_signature_scheme(),
+            &new_group_state.identity_key,
+            my_tree_idx,
+            new_path_secret,
+            csprng,
+        )?;
+        new_group_state.set_direct_path_parent_hashes(&direct_path_msg, my_tree_idx)?;
+        let mut public_init_key = init_key;
+        public_init_key.private_keys = None;
+        let join = GroupExternalJoin {
+            init_key: public_init_key,
+            encrypted_init_secret,
+            path: direct_path_msg,
+        };
+        let op = GroupOperation::ExternalJoin(join);
+        new_group_state.update_transcript_hash(&op)?;
+
+        // Final modification: update my epoch secrets and make the new ApplicationKeyChain
+        let (app_secret, conf_key) = new_group_state.update_epoch_secrets(&update_secret, None)?;
+        let app_key_chain =
+            ApplicationKeyChain::from_application_secret(&new_group_state, app_secret);
+        let handshake = new_group_state.create_handshake(prior_epoch, op, conf_key)?;
+
+        Ok((handshake, new_group_state, app_key_chain))
+    }
+
     /// Creates and applies a `GroupUpdate` operation with the given path secret information. This
     /// method does not mutate this `GroupState`, the operation is rather applied to the returned
     /// `GroupState`.
@@ -1027,80 +2785,1555 @@ impl GroupState {
     /// `Handshake` message representing the specified update operation, `group_state` is the new
     /// group state after the update has been applied, `app_key_chain` is the newly derived
     /// application key schedule object
-    // This is just a wrapper around self.create_and_apply_update_op and self.create_handshake
     pub fn create_and_apply_update_handshake<R>(
         &self,
         new_path_secret: PathSecret,
         csprng: &mut R,
     ) -> Result<(Handshake, GroupState, ApplicationKeyChain), Error>
+    where
+        R: CryptoRng,
+    {
+        let ctx = ProcessingContext::new();
+        self.create_and_apply_update_handshake_with_context(new_path_secret, &ctx, csprng)
+    }
+
+    /// Like `create_and_apply_update_handshake`, but asks the policy in the given
+    /// `ProcessingContext` whether this member may make the `Update`
+    ///
+    /// Returns: The same as `create_and_apply_update_handshake`. If the policy vetoes the
+    /// `Update`, returns an `Error::AuthorizationError`.
+    // This is just a wrapper around self.create_and_apply_update_op and self.finish_handshake
+    pub fn create_and_apply_update_handshake_with_context<R>(
+        &self,
+        new_path_secret: PathSecret,
+        ctx: &ProcessingContext,
+        csprng: &mut R,
+    ) -> Result<(Handshake, GroupState, ApplicationKeyChain), Error>
     where
         R: CryptoRng,
     {
         let (new_group_state, app_key_chain, update_op, conf_key) =
             self.create_and_apply_update_op(new_path_secret, csprng)?;
-        let prior_epoch = self.epoch;
-        let handshake = new_group_state.create_handshake(prior_epoch, update_op, conf_key)?;
+        let (handshake, new_group_state) =
+            self.finish_handshake(new_group_state, update_op, conf_key, ctx)?;
 
         Ok((handshake, new_group_state, app_key_chain))
     }
 
     /// Creates and applies a `GroupAdd` operation for a member at index `new_roster_index` with
     /// the target `init_key`. This method does not mutate this `GroupState`, the operation is
-    /// rather applied to the returned `GroupState`.
+    /// rather applied to the returned `GroupState`. The `init_key`'s lifetime is checked against
+    /// the system clock.
     ///
     /// Returns: `Ok((handshake, group_state, app_key_chain))` on success, where `handshake` is the
     /// `Handshake` message representing the specified add operation, `group_state` is the new
     /// group state after the add has been applied, `app_key_chain` is the newly derived
     /// application key schedule object
-    // This is just a wrapper around self.create_and_apply_add_op and self.create_handshake
     pub fn create_and_apply_add_handshake(
         &self,
         new_roster_index: u32,
         init_key: UserInitKey,
         prior_welcome_info_hash: &WelcomeInfoHash,
     ) -> Result<(Handshake, GroupState, ApplicationKeyChain), Error> {
-        let (new_group_state, app_key_chain, add_op, conf_key) =
-            self.create_and_apply_add_op(new_roster_index, init_key, prior_welcome_info_hash)?;
-        let prior_epoch = self.epoch;
-        let handshake = new_group_state.create_handshake(prior_epoch, add_op, conf_key)?;
+        self.create_and_apply_add_handshake_with_clock(
+            new_roster_index,
+            init_key,
+            prior_welcome_info_hash,
+            &SystemClock,
+        )
+    }
 
-        Ok((handshake, new_group_state, app_key_chain))
+    /// Like `create_and_apply_add_handshake`, but checks `init_key`'s lifetime against the given
+    /// `Clock`
+    pub fn create_and_apply_add_handshake_with_clock<C: Clock>(
+        &self,
+        new_roster_index: u32,
+        init_key: UserInitKey,
+        prior_welcome_info_hash: &WelcomeInfoHash,
+        clock: &C,
+    ) -> Result<(Handshake, GroupState, ApplicationKeyChain), Error> {
+        let ctx = ProcessingContext::new().with_clock(clock);
+        self.create_and_apply_add_handshake_with_context(
+            new_roster_index,
+            init_key,
+            prior_welcome_info_hash,
+            &ctx,
+        )
     }
 
-    /// Creates and applies a `GroupRemove` operation for a member at roster index
-    /// `removed_roster_index` and introduces a new path secret `new_path_secret` at the removed
-    /// index. This method does not mutate this `GroupState`, the operation is rather applied to
-    /// the returned `GroupState`.
-    ///
-    /// Requires: `removed_roster_index != self.roster_index`. That is, a member cannot remove
-    /// themselves from the group. An attempt to do so will result in an `Error::IAmRemoved`.
+    /// Like `create_and_apply_add_handshake`, but checks `init_key`'s lifetime against the clock
+    /// in the given `ProcessingContext`, and asks its policy whether this member may make the
+    /// `Add`
+    ///
+    /// Returns: The same as `create_and_apply_add_handshake`. If the policy vetoes the `Add`,
+    /// returns an `Error::AuthorizationError`.
+    // This is just a wrapper around self.create_and_apply_add_op and self.finish_handshake
+    pub fn create_and_apply_add_handshake_with_context(
+        &self,
+        new_roster_index: u32,
+        init_key: UserInitKey,
+        prior_welcome_info_hash: &WelcomeInfoHash,
+        ctx: &ProcessingContext,
+    ) -> Result<(Handshake, GroupState, ApplicationKeyChain), Error> {
+        let (new_group_state, app_key_chain, add_op, conf_key) = self.create_and_apply_add_op(
+            new_roster_index,
+            init_key,
+            prior_welcome_info_hash,
+            ctx.clock.now(),
+        )?;
+        let (handshake, new_group_state) =
+            self.finish_handshake(new_group_state, add_op, conf_key, ctx)?;
+
+        Ok((handshake, new_group_state, app_key_chain))
+    }
+
+    /// Creates and applies a `GroupMultiAdd` operation that adds every member in `init_keys` to
+    /// the group at once. Each new member goes in the leftmost empty roster entry, or at the end if
+    /// there is none. This method does not mutate this `GroupState`, the operation is rather
+    /// applied to the returned `GroupState`. The lifetimes of `init_keys` are checked against the
+    /// system clock.
+    ///
+    /// Requires: `prior_welcome_info_hash` is the hash returned by `MultiWelcome::from_group_state`
+    /// when called on this `GroupState` with the same `init_keys`. Each new member must receive
+    /// that `MultiWelcome` and then process the returned `Handshake` using the `GroupState` they
+    /// got from it. Every new member ends up at the same epoch.
+    ///
+    /// Returns: `Ok((handshake, group_state, app_key_chain))` on success, where `handshake` is the
+    /// `Handshake` message representing the multi-add operation, `group_state` is the new group
+    /// state after the multi-add has been applied, `app_key_chain` is the newly derived
+    /// application key schedule object
+    pub fn create_and_apply_multi_add_handshake(
+        &self,
+        init_keys: Vec<UserInitKey>,
+        prior_welcome_info_hash: &WelcomeInfoHash,
+    ) -> Result<(Handshake, GroupState, ApplicationKeyChain), Error> {
+        self.create_and_apply_multi_add_handshake_with_clock(
+            init_keys,
+            prior_welcome_info_hash,
+            &SystemClock,
+        )
+    }
+
+    /// Like `create_and_apply_multi_add_handshake`, but checks the lifetimes of `init_keys`
+    /// against the given `Clock`
+    pub fn create_and_apply_multi_add_handshake_with_clock<C: Clock>(
+        &self,
+        init_keys: Vec<UserInitKey>,
+        prior_welcome_info_hash: &WelcomeInfoHash,
+        clock: &C,
+    ) -> Result<(Handshake, GroupState, ApplicationKeyChain), Error> {
+        let ctx = ProcessingContext::new().with_clock(clock);
+        self.create_and_apply_multi_add_handshake_with_context(
+            init_keys,
+            prior_welcome_info_hash,
+            &ctx,
+        )
+    }
+
+    /// Like `create_and_apply_multi_add_handshake`, but checks the lifetimes of `init_keys`
+    /// against the clock in the given `ProcessingContext`, and asks its policy whether this member
+    /// may make the `MultiAdd`
+    ///
+    /// Returns: The same as `create_and_apply_multi_add_handshake`. If the policy vetoes the
+    /// `MultiAdd`, returns an `Error::AuthorizationError`.
+    // This is just a wrapper around self.create_and_apply_multi_add_op and self.finish_handshake
+    pub fn create_and_apply_multi_add_handshake_with_context(
+        &self,
+        init_keys: Vec<UserInitKey>,
+        prior_welcome_info_hash: &WelcomeInfoHash,
+        ctx: &ProcessingContext,
+    ) -> Result<(Handshake, GroupState, ApplicationKeyChain), Error> {
+        let (new_group_state, app_key_chain, multi_add_op, conf_key) = self
+            .create_and_apply_multi_add_op(init_keys, prior_welcome_info_hash, ctx.clock.now())?;
+        let (handshake, new_group_state) =
+            self.finish_handshake(new_group_state, multi_add_op, conf_key, ctx)?;
+
+        Ok((handshake, new_group_state, app_key_chain))
+    }
+
+    /// Creates and applies a `GroupInit` operation that adds every member in `init_keys` to this
+    /// freshly created group at once, and introduces a new path secret `new_path_secret` at this
+    /// member's index. Also creates a `Welcome` for each new member. This method does not mutate
+    /// this `GroupState`, the operation is rather applied to the returned `GroupState`.
+    ///
+    /// Requires: This `GroupState` is at epoch 0, e.g., it was just made by
+    /// `GroupState::new_singleton_group`. Each new member must receive their `Welcome` and then
+    /// process the returned `Handshake` using the `GroupState` they got from that `Welcome`.
+    ///
+    /// Returns: `Ok((handshake, welcomes, group_state, app_key_chain))` on success, where
+    /// `handshake` is the `Handshake` message representing the init operation, `welcomes`
+    /// contains a `Welcome` for each element of `init_keys` (in the same order), `group_state` is
+    /// the new group state after the init has been applied, and `app_key_chain` is the newly
+    /// derived application key schedule object
+    pub fn create_and_apply_init_handshake<R>(
+        &self,
+        init_keys: Vec<UserInitKey>,
+        new_path_secret: PathSecret,
+        csprng: &mut R,
+    ) -> Result<(Handshake, Vec<Welcome>, GroupState, ApplicationKeyChain), Error>
+    where
+        R: CryptoRng,
+    {
+        let ctx = ProcessingContext::new();
+        self.create_and_apply_init_handshake_with_context(init_keys, new_path_secret, &ctx, csprng)
+    }
+
+    /// Like `create_and_apply_init_handshake`, but asks the policy in the given
+    /// `ProcessingContext` whether this member may make the `Init`
+    ///
+    /// Returns: The same as `create_and_apply_init_handshake`. If the policy vetoes the `Init`,
+    /// returns an `Error::AuthorizationError`.
+    // This is just a wrapper around Welcome::from_welcome_info, self.create_and_apply_init_op, and
+    // self.finish_handshake
+    pub fn create_and_apply_init_handshake_with_context<R>(
+        &self,
+        init_keys: Vec<UserInitKey>,
+        new_path_secret: PathSecret,
+        ctx: &ProcessingContext,
+        csprng: &mut R,
+    ) -> Result<(Handshake, Vec<Welcome>, GroupState, ApplicationKeyChain), Error>
+    where
+        R: CryptoRng,
+    {
+        // Everyone gets Welcomed into the same prior state, so we only need one WelcomeInfo
+        let (welcome_info, welcome_info_hash) = self.as_welcome_info_for_new_members()?;
+        let welcomes = init_keys
+            .iter()
+            .map(|init_key| Welcome::from_welcome_info(self.cs, init_key, &welcome_info, csprng))
+            .collect::<Result<Vec<Welcome>, Error>>()?;
+
+        let (new_group_state, app_key_chain, init_op, conf_key) = self.create_and_apply_init_op(
+            init_keys,
+            &welcome_info_hash,
+            new_path_secret,
+            ctx.clock.now(),
+            csprng,
+        )?;
+        let (handshake, new_group_state) =
+            self.finish_handshake(new_group_state, init_op, conf_key, ctx)?;
+
+        Ok((handshake, welcomes, new_group_state, app_key_chain))
+    }
+
+    /// Creates and applies a `GroupRemove` operation for a member at roster index
+    /// `removed_roster_index` and introduces a new path secret `new_path_secret` at the removed
+    /// index. This method does not mutate this `GroupState`, the operation is rather applied to
+    /// the returned `GroupState`.
+    ///
+    /// Requires: `removed_roster_index != self.roster_index`. That is, a member cannot remove
+    /// themselves from the group. An attempt to do so will result in an `Error::IAmRemoved`.
     ///
     /// Returns: `Ok((handshake, app_key_chain))` on success, where `handshake` is the `Handshake`
     /// message representing the specified remove operation, and `app_key_chain` is the newly
     /// derived application key schedule object.
-    // This is just a wrapper around self.create_and_apply_remove_op and self.create_handshake
     pub fn create_and_apply_remove_handshake<R>(
         &self,
         removed_roster_index: u32,
         new_path_secret: PathSecret,
         csprng: &mut R,
     ) -> Result<(Handshake, GroupState, ApplicationKeyChain), Error>
+    where
+        R: CryptoRng,
+    {
+        let ctx = ProcessingContext::new();
+        self.create_and_apply_remove_handshake_with_context(
+            removed_roster_index,
+            new_path_secret,
+            &ctx,
+            csprng,
+        )
+    }
+
+    /// Like `create_and_apply_remove_handshake`, but asks the policy in the given
+    /// `ProcessingContext` whether this member may make the `Remove`
+    ///
+    /// Returns: The same as `create_and_apply_remove_handshake`. If the policy vetoes the
+    /// `Remove`, returns an `Error::AuthorizationError`.
+    // This is just a wrapper around self.create_and_apply_remove_op and self.finish_handshake
+    pub fn create_and_apply_remove_handshake_with_context<R>(
+        &self,
+        removed_roster_index: u32,
+        new_path_secret: PathSecret,
+        ctx: &ProcessingContext,
+        csprng: &mut R,
+    ) -> Result<(Handshake, GroupState, ApplicationKeyChain), Error>
     where
         R: CryptoRng,
     {
         let (new_group_state, app_key_chain, remove_op, conf_key) =
             self.create_and_apply_remove_op(removed_roster_index, new_path_secret, csprng)?;
-        let prior_epoch = self.epoch;
-        let handshake = new_group_state.create_handshake(prior_epoch, remove_op, conf_key)?;
+        let (handshake, new_group_state) =
+            self.finish_handshake(new_group_state, remove_op, conf_key, ctx)?;
+
+        Ok((handshake, new_group_state, app_key_chain))
+    }
+
+    /// Creates a `Proposal` to add the member described by `init_key` to the group. The new
+    /// member should be sent a `Welcome` made from this `GroupState`, and `welcome_info_hash`
+    /// should be the hash returned alongside that `Welcome`.
+    ///
+    /// Returns: `Ok(proposal)` on success. Returns an `Error::ValidationError` if this
+    /// `GroupState` is preliminary or if `init_key` is invalid.
+    pub fn create_add_proposal(
+        &self,
+        init_key: UserInitKey,
+        welcome_info_hash: &WelcomeInfoHash,
+    ) -> Result<Proposal, Error> {
+        init_key.validate()?;
+        let add = AddProposal {
+            init_key,
+            welcome_info_hash: welcome_info_hash.clone(),
+        };
+        self.create_proposal(ProposalBody::Add(add))
+    }
+
+    /// Creates a `Proposal` to replace this member's leaf key with a fresh one. This method does
+    /// not mutate this `GroupState`. Rather, the returned `GroupState` remembers the new private
+    /// key until the end of the epoch, so that it can process a `Commit` containing the proposal.
+    /// Only the most recent `Update` proposal's key is remembered.
+    ///
+    /// Returns: `Ok((proposal, group_state))` on success. Returns an `Error::ValidationError` if
+    /// this `GroupState` is preliminary. Otherwise returns some other sort of `Error`.
+    pub fn create_update_proposal<R>(&self, csprng: &mut R) -> Result<(Proposal, GroupState), Error>
+    where
+        R: CryptoRng,
+    {
+        let private_key = DhPrivateKey::new_from_random(self.cs.dh_impl, csprng)?;
+        let public_key = DhPublicKey::new_from_private_key(self.cs.dh_impl, &private_key);
+        let leaf_content = ratchet_tree::leaf_signature_content(&public_key, &[])?;
+        let signature = self.get_signature_scheme().sign(&self.identity_key, &leaf_content);
+        let proposal = self.create_proposal(ProposalBody::Update(UpdateProposal {
+            public_key,
+            signature,
+        }))?;
+
+        let mut new_group_state = self.clone();
+        new_group_state.pending_update_private_key = Some(private_key);
+
+        Ok((proposal, new_group_state))
+    }
+
+    /// Creates a `Proposal` to remove the member at roster index `removed_roster_index`
+    ///
+    /// Requires: `removed_roster_index != self.roster_index`. That is, a member cannot propose
+    /// to remove themselves from the group. An attempt to do so will result in an
+    /// `Error::IAmRemoved`. To leave the group, use `GroupState::create_leave_proposal` instead.
+    ///
+    /// Returns: `Ok(proposal)` on success. Returns an `Error::ValidationError` if this
+    /// `GroupState` is preliminary or if there is no member at `removed_roster_index`.
+    pub fn create_remove_proposal(&self, removed_roster_index: u32) -> Result<Proposal, Error> {
+        if Some(removed_roster_index) == self.roster_index {
+            return Err(Error::IAmRemoved);
+        }
+        let is_occupied = matches!(self.roster.0.get(removed_roster_index as usize), Some(Some(_)));
+        if !is_occupied {
+            return Err(Error::ValidationError("Cannot propose to remove an empty roster entry"));
+        }
+
+        self.create_proposal(ProposalBody::Remove(RemoveProposal {
+            removed_roster_index,
+        }))
+    }
+
+    /// Creates a `Proposal` to remove this member from the group, i.e., announces that this member
+    /// is leaving. This method does not mutate this `GroupState`. Rather, the returned
+    /// `GroupState` is in the `MembershipStatus::Leaving` state. Once another member honors the
+    /// proposal, either by committing it or by making a `Remove` handshake for this member,
+    /// processing that handshake gives the terminal `MembershipStatus::Left` state instead of
+    /// returning `Error::IAmRemoved`.
+    ///
+    /// Like every proposal, this is only valid in the current epoch. If the epoch changes without
+    /// this member being removed, they remain `Leaving` and should make a new leave proposal.
+    ///
+    /// Returns: `Ok((proposal, group_state))` on success. Returns an `Error::ValidationError` if
+    /// this `GroupState` is preliminary or has already left the group.
+    pub fn create_leave_proposal(&self) -> Result<(Proposal, GroupState), Error> {
+        let roster_index = self
+            .roster_index
+            .ok_or(Error::ValidationError("Cannot leave from a preliminary GroupState"))?;
+        let proposal = self.create_proposal(ProposalBody::Remove(RemoveProposal {
+            removed_roster_index: roster_index,
+        }))?;
+
+        let mut new_group_state = self.clone();
+        new_group_state.membership_status = MembershipStatus::Leaving;
+
+        Ok((proposal, new_group_state))
+    }
+
+    /// Creates a `Proposal` to mix the pre-shared key with ID `psk_id` into the key schedule of
+    /// the next epoch. The committer and every other member need the PSK in their `PskStore` in
+    /// order to commit or process the commit, respectively. The proposer does not.
+    ///
+    /// Returns: `Ok(proposal)` on success. Returns an `Error::ValidationError` if this
+    /// `GroupState` is preliminary or if `psk_id` is longer than 255 bytes.
+    pub fn create_psk_proposal(&self, psk_id: &[u8]) -> Result<Proposal, Error> {
+        if psk_id.len() > 255 {
+            return Err(Error::ValidationError("PSK ID is too long"));
+        }
+        self.create_proposal(ProposalBody::Psk(PskProposal {
+            psk_id: psk_id.to_vec(),
+        }))
+    }
+
+    /// Creates and applies a `GroupCommit` operation that applies all the given proposals in a
+    /// single epoch change, and introduces a new path secret `new_path_secret` at this member's
+    /// index. Conflicting proposals are resolved as described in the documentation of
+    /// `GroupCommit`. This method does not mutate this `GroupState`, the operation is rather
+    /// applied to the returned `GroupState`.
+    ///
+    /// Requires: Every proposal was made in the current epoch, and none of them removes this
+    /// member.
+    ///
+    /// Returns: `Ok((handshake, group_state, app_key_chain))` on success, where `handshake` is the
+    /// `Handshake` message representing the commit operation, `group_state` is the new group state
+    /// after the commit has been applied, `app_key_chain` is the newly derived application key
+    /// schedule object. If an `Add` proposal's `UserInitKey` is expired or not yet valid, returns
+    /// an `Error::ValidationError`.
+    pub fn create_and_apply_commit_handshake<R>(
+        &self,
+        proposals: Vec<Proposal>,
+        new_path_secret: PathSecret,
+        csprng: &mut R,
+    ) -> Result<(Handshake, GroupState, ApplicationKeyChain), Error>
+    where
+        R: CryptoRng,
+    {
+        self.create_and_apply_commit_handshake_with_psks(
+            proposals,
+            &NoPsks,
+            new_path_secret,
+            csprng,
+        )
+    }
+
+    /// Like `create_and_apply_commit_handshake`, but looks up the PSKs referenced by the given
+    /// proposals in `psks`. `create_and_apply_commit_handshake` uses an empty store, so it fails
+    /// if any PSK is referenced.
+    ///
+    /// Returns: The same as `create_and_apply_commit_handshake`. If one of the proposals references
+    /// a PSK that isn't in `psks`, returns an `Error::ValidationError`.
+    pub fn create_and_apply_commit_handshake_with_psks<P, R>(
+        &self,
+        proposals: Vec<Proposal>,
+        psks: &P,
+        new_path_secret: PathSecret,
+        csprng: &mut R,
+    ) -> Result<(Handshake, GroupState, ApplicationKeyChain), Error>
+    where
+        P: PskStore,
+        R: CryptoRng,
+    {
+        let ctx = ProcessingContext::new().with_psks(psks);
+        self.create_and_apply_commit_handshake_with_context(
+            proposals,
+            new_path_secret,
+            &ctx,
+            csprng,
+        )
+    }
+
+    /// Like `create_and_apply_commit_handshake`, but checks the lifetimes of the `UserInitKey`s in
+    /// the `Add` proposals against the given `Clock`
+    pub fn create_and_apply_commit_handshake_with_clock<C, R>(
+        &self,
+        proposals: Vec<Proposal>,
+        new_path_secret: PathSecret,
+        clock: &C,
+        csprng: &mut R,
+    ) -> Result<(Handshake, GroupState, ApplicationKeyChain), Error>
+    where
+        C: Clock,
+        R: CryptoRng,
+    {
+        let ctx = ProcessingContext::new().with_clock(clock);
+        self.create_and_apply_commit_handshake_with_context(
+            proposals,
+            new_path_secret,
+            &ctx,
+            csprng,
+        )
+    }
+
+    /// Like `create_and_apply_commit_handshake`, but looks up the PSKs referenced by the given
+    /// proposals in the given `ProcessingContext`, checks the lifetimes of the `UserInitKey`s in
+    /// the `Add` proposals against its clock, and asks its policy whether this member may make the
+    /// `Commit`
+    ///
+    /// Returns: The same as `create_and_apply_commit_handshake_with_psks`. If the policy vetoes
+    /// the `Commit`, returns an `Error::AuthorizationError`.
+    // This is just a wrapper around self.create_and_apply_commit_op and self.finish_handshake
+    pub fn create_and_apply_commit_handshake_with_context<R>(
+        &self,
+        proposals: Vec<Proposal>,
+        new_path_secret: PathSecret,
+        ctx: &ProcessingContext,
+        csprng: &mut R,
+    ) -> Result<(Handshake, GroupState, ApplicationKeyChain), Error>
+    where
+        R: CryptoRng,
+    {
+        let (new_group_state, app_key_chain, commit_op, conf_key) = self
+            .create_and_apply_commit_op(
+                proposals,
+                ctx.psks,
+                new_path_secret,
+                ctx.clock.now(),
+                csprng,
+            )?;
+        let (handshake, new_group_state) =
+            self.finish_handshake(new_group_state, commit_op, conf_key, ctx)?;
+
+        Ok((handshake, new_group_state, app_key_chain))
+    }
+
+    /// Creates and applies a `GroupReInit` operation that ends this group in favor of a successor
+    /// with group ID `new_group_id`, ciphersuite `new_cs`, and protocol version
+    /// `new_protocol_version`. This method does not mutate this `GroupState`, the operation is
+    /// rather applied to the returned `GroupState`.
+    ///
+    /// Once every member has processed the returned `Handshake`, one of them makes the successor
+    /// group with `GroupState::create_reinit_successor_handshake`, and the rest join it with
+    /// `GroupState::from_reinit_welcome`.
+    ///
+    /// Returns: `Ok((handshake, group_state, app_key_chain))` on success, where `handshake` is the
+    /// `Handshake` message representing the reinit operation, `group_state` is the new group state
+    /// after the reinit has been applied, `app_key_chain` is the newly derived application key
+    /// schedule object. Returns an `Error::ValidationError` if this group has already been
+    /// reinitialized or `new_group_id` is the current group ID.
+    pub fn create_and_apply_reinit_handshake(
+        &self,
+        new_group_id: Vec<u8>,
+        new_cs: &'static CipherSuite,
+        new_protocol_version: ProtocolVersion,
+    ) -> Result<(Handshake, GroupState, ApplicationKeyChain), Error> {
+        self.create_and_apply_reinit_handshake_with_context(
+            new_group_id,
+            new_cs,
+            new_protocol_version,
+            &ProcessingContext::new(),
+        )
+    }
+
+    /// Like `create_and_apply_reinit_handshake`, but asks the policy in the given
+    /// `ProcessingContext` whether this member may make the `ReInit`
+    ///
+    /// Returns: The same as `create_and_apply_reinit_handshake`. If the policy vetoes the
+    /// `ReInit`, returns an `Error::AuthorizationError`.
+    // This is just a wrapper around self.create_and_apply_reinit_op and self.finish_handshake
+    pub fn create_and_apply_reinit_handshake_with_context(
+        &self,
+        new_group_id: Vec<u8>,
+        new_cs: &'static CipherSuite,
+        new_protocol_version: ProtocolVersion,
+        ctx: &ProcessingContext,
+    ) -> Result<(Handshake, GroupState, ApplicationKeyChain), Error> {
+        let (new_group_state, app_key_chain, reinit_op, conf_key) =
+            self.create_and_apply_reinit_op(new_group_id, new_cs, new_protocol_version)?;
+        let (handshake, new_group_state) =
+            self.finish_handshake(new_group_state, reinit_op, conf_key, ctx)?;
+
+        Ok((handshake, new_group_state, app_key_chain))
+    }
+
+    /// Creates and applies a `GroupExtensions` operation that replaces this group's extensions
+    /// with `extensions`. This method does not mutate this `GroupState`, the operation is rather
+    /// applied to the returned `GroupState`.
+    ///
+    /// Returns: `Ok((handshake, group_state, app_key_chain))` on success, where `handshake` is the
+    /// `Handshake` message representing the extensions operation, `group_state` is the new group
+    /// state after the extensions have been replaced, `app_key_chain` is the newly derived
+    /// application key schedule object. Returns an `Error::ValidationError` if two of the
+    /// extensions have the same type.
+    pub fn create_and_apply_extensions_handshake(
+        &self,
+        extensions: Vec<Extension>,
+    ) -> Result<(Handshake, GroupState, ApplicationKeyChain), Error> {
+        self.create_and_apply_extensions_handshake_with_context(
+            extensions,
+            &ProcessingContext::new(),
+        )
+    }
+
+    /// Like `create_and_apply_extensions_handshake`, but asks the policy in the given
+    /// `ProcessingContext` whether this member may replace the extensions
+    ///
+    /// Returns: The same as `create_and_apply_extensions_handshake`. If the policy vetoes the
+    /// change, returns an `Error::AuthorizationError`.
+    // This is just a wrapper around self.create_and_apply_extensions_op and self.finish_handshake
+    pub fn create_and_apply_extensions_handshake_with_context(
+        &self,
+        extensions: Vec<Extension>,
+        ctx: &ProcessingContext,
+    ) -> Result<(Handshake, GroupState, ApplicationKeyChain), Error> {
+        let (new_group_state, app_key_chain, extensions_op, conf_key) =
+            self.create_and_apply_extensions_op(extensions)?;
+        let (handshake, new_group_state) =
+            self.finish_handshake(new_group_state, extensions_op, conf_key, ctx)?;
 
         Ok((handshake, new_group_state, app_key_chain))
     }
+
+    /// Makes the successor of this reinitialized group, with this member in it, and then creates
+    /// and applies a `GroupInit` operation that adds every other member of this group to it. The
+    /// successor's init secret is derived from the final epoch of this group. This method does not
+    /// mutate this `GroupState`.
+    ///
+    /// Requires: `init_keys` contains precisely one `UserInitKey` for each other member of this
+    /// group, with the same credential that member has here, and supporting the successor's
+    /// ciphersuite and protocol version. `new_path_secret` is a path secret for the successor's
+    /// ciphersuite. Each other member must receive their `Welcome` and pass it to
+    /// `GroupState::from_reinit_welcome`, then process the returned `Handshake`.
+    ///
+    /// Returns: `Ok((handshake, welcomes, group_state, app_key_chain))` on success, where
+    /// `handshake` is the `Handshake` message representing the init operation of the successor,
+    /// `welcomes` contains a `Welcome` for each element of `init_keys` (in the same order),
+    /// `group_state` is the successor group state after the init has been applied, and
+    /// `app_key_chain` is its application key schedule object. Returns an
+    /// `Error::ValidationError` if this group hasn't been reinitialized or if `init_keys` doesn't
+    /// match the membership of this group.
+    pub fn create_reinit_successor_handshake<R>(
+        &self,
+        init_keys: Vec<UserInitKey>,
+        new_path_secret: PathSecret,
+        csprng: &mut R,
+    ) -> Result<(Handshake, Vec<Welcome>, GroupState, ApplicationKeyChain), Error>
+    where
+        R: CryptoRng,
+    {
+        let (reinit, successor_init_secret) = self.successor_init_secret()?;
+        let my_roster_idx = self.roster_index.ok_or(Error::ValidationError(
+            "Cannot make a successor group from a preliminary GroupState",
+        ))?;
+
+        // Membership carries over, so there must be exactly one UserInitKey for everyone else
+        let my_credential = self
+            .roster
+            .0
+            .get(my_roster_idx as usize)
+            .and_then(|entry| entry.as_ref())
+            .ok_or(Error::ValidationError("This member's roster entry is empty"))?;
+        let other_credentials: Vec<&Credential> = self
+            .roster
+            .0
+            .iter()
+            .enumerate()
+            .filter(|(idx, _)| *idx != my_roster_idx as usize)
+            .filter_map(|(_, entry)| entry.as_ref())
+            .collect();
+        let all_carried_over = other_credentials
+            .iter()
+            .all(|cred| init_keys.iter().any(|uik| &uik.credential == *cred));
+        if init_keys.len() != other_credentials.len() || !all_carried_over {
+            return Err(Error::ValidationError(
+                "Successor UserInitKeys don't match the membership of this group",
+            ));
+        }
+
+        // Make the successor with just us in it. Its init secret links it to this group.
+        let mut successor = GroupState::new_singleton_group(
+            reinit.cipher_suite,
+            reinit.protocol_version,
+            self.identity_key.clone(),
+            reinit.group_id.clone(),
+            my_credential.clone(),
+            csprng,
+        )?;
+        successor.init_secret = successor_init_secret;
+
+        // Now bring everyone else in
+        successor.create_and_apply_init_handshake(init_keys, new_path_secret, csprng)
+    }
+
+    /// Creates the preliminary `GroupState` of the successor of this reinitialized group from a
+    /// `Welcome` message and the `UserInitKey` this member made for the successor. This member's
+    /// identity key carries over from this group. The `UserInitKey`'s lifetime is checked against
+    /// the system clock.
+    ///
+    /// Requires: That the `init_key` is the `UserInitKey` that the `Welcome` was encrypted with,
+    /// and `init_key.private_keys` is not `None`
+    ///
+    /// Returns: `Ok(group_state)` on success. Returns an `Error::ValidationError` if this group
+    /// hasn't been reinitialized, or if the `Welcome` doesn't describe its successor as announced
+    /// in the `ReInit` and linked to the final epoch of this group. Otherwise returns some other
+    /// kind of `Error`.
+    pub fn from_reinit_welcome(
+        &self,
+        welcome: Welcome,
+        init_key: UserInitKey,
+    ) -> Result<GroupState, Error> {
+        self.from_reinit_welcome_with_clock(welcome, init_key, &SystemClock)
+    }
+
+    /// Like `from_reinit_welcome`, but checks the `UserInitKey`'s lifetime against the given
+    /// `Clock`
+    ///
+    /// Returns: The same as `from_reinit_welcome`. If the current time is outside of `init_key`'s
+    /// lifetime, returns an `Error::ValidationError`.
+    pub fn from_reinit_welcome_with_clock<C: Clock>(
+        &self,
+        welcome: Welcome,
+        init_key: UserInitKey,
+        clock: &C,
+    ) -> Result<GroupState, Error> {
+        let (reinit, successor_init_secret) = self.successor_init_secret()?;
+        let (welcome_info, cipher_suite) =
+            welcome.into_welcome_info_cipher_suite(&init_key, clock.now())?;
+
+        // The successor has to be the one that was announced, made by a member of this group
+        if cipher_suite != reinit.cipher_suite
+            || welcome_info.protocol_version != reinit.protocol_version
+            || welcome_info.group_id != reinit.group_id
+            || welcome_info.epoch != 0
+        {
+            return Err(Error::ValidationError("Welcome doesn't match the announced successor"));
+        }
+        let creator_is_member = welcome_info
+            .roster
+            .0
+            .iter()
+            .filter_map(|entry| entry.as_ref())
+            .all(|cred| self.roster.0.iter().any(|entry| entry.as_ref() == Some(cred)));
+        if !creator_is_member {
+            return Err(Error::ValidationError("Successor was made by a non-member"));
+        }
+
+        // And it has to be linked to this group's final epoch
+        let secrets_match: bool = welcome_info.init_secret.0.ct_eq(&successor_init_secret.0).into();
+        if !secrets_match {
+            return Err(Error::ValidationError("Successor isn't linked to this group"));
+        }
+
+        GroupState::from_welcome_info(
+            cipher_suite,
+            welcome_info,
+            self.identity_key.clone(),
+            init_key,
+        )
+    }
+
+    /// Makes a branch of this group with group ID `new_group_id`, containing only the members at
+    /// the given roster indices. The members keep their current credentials and leaf keys, so no
+    /// `UserInitKey`s are needed. The branch is keyed from this epoch's resumption secret, and
+    /// then this member introduces a new path secret `new_path_secret` at their index in it. This
+    /// method does not mutate this `GroupState`.
+    ///
+    /// Requires: `members` is strictly increasing and includes this member. The other members
+    /// pass the returned `Branch` to `GroupState::from_branch` while they are still in this
+    /// epoch.
+    ///
+    /// Returns: `Ok((branch, group_state, app_key_chain))` on success, where `branch` is the
+    /// message to send to the other members, `group_state` is the state of the branch after this
+    /// member's update, and `app_key_chain` is its application key schedule object. Returns an
+    /// `Error::ValidationError` if `members` is invalid or this member doesn't know the current
+    /// resumption secret.
+    pub fn branch<R>(
+        &self,
+        members: &[u32],
+        new_group_id: Vec<u8>,
+        new_path_secret: PathSecret,
+        csprng: &mut R,
+    ) -> Result<(Branch, GroupState, ApplicationKeyChain), Error>
+    where
+        R: CryptoRng,
+    {
+        let branch_state = self.branch_start_state(members, new_group_id.clone())?;
+        let (handshake, branch_state, app_key_chain) =
+            branch_state.create_and_apply_update_handshake(new_path_secret, csprng)?;
+
+        let branch = Branch {
+            group_id: new_group_id,
+            parent_epoch: self.epoch,
+            members: members.to_vec(),
+            handshake,
+        };
+        Ok((branch, branch_state, app_key_chain))
+    }
+
+    /// Joins the branch of this group described by the given `Branch` message. See
+    /// `GroupState::branch`.
+    ///
+    /// Returns: `Ok((group_state, app_key_chain))` on success, where `group_state` is the state of
+    /// the branch and `app_key_chain` is its application key schedule object. Returns an
+    /// `Error::ValidationError` if the branch was made in a different epoch or doesn't contain
+    /// this member. Otherwise returns some other kind of `Error`.
+    pub fn from_branch(&self, branch: &Branch) -> Result<(GroupState, ApplicationKeyChain), Error> {
+        self.from_branch_with_policy(branch, &AllowAll)
+    }
+
+    /// Like `from_branch`, but first asks the given `MembershipPolicy` whether the branch's
+    /// creator may perform the branch's `Update`
+    ///
+    /// Returns: The same as `from_branch`. If `policy` vetoes the `Update`, returns the policy's
+    /// `Error`.
+    pub fn from_branch_with_policy<A: MembershipPolicy>(
+        &self,
+        branch: &Branch,
+        policy: &A,
+    ) -> Result<(GroupState, ApplicationKeyChain), Error> {
+        if branch.parent_epoch != self.epoch {
+            return Err(Error::ValidationError("Branch was made in a different epoch"));
+        }
+
+        // Start where the creator started, then apply their update. If we disagree on the start,
+        // the confirmation MAC won't check out.
+        let branch_state = self.branch_start_state(&branch.members, branch.group_id.clone())?;
+        let ctx = ProcessingContext::new().with_policy(policy);
+        branch_state.process_handshake_with_context(&branch.handshake, &ctx)
+    }
+
+    /// Seals the given `Handshake`, so that only members of the group can read what it does. Its
+    /// operation is encrypted under its signer's handshake key for this epoch. The epoch, signer,
+    /// signature and confirmation stay in the clear. A sealed `Handshake` is processed like any
+    /// other, since `GroupState::process_handshake` unseals it first. A `GroupObserver` cannot
+    /// process it.
+    ///
+    /// `handshake` must have been made in this epoch. For `Handshake`s made with a
+    /// `create_and_apply_*` method, this means the state the method was called on, not the one it
+    /// returned.
+    ///
+    /// A `Handshake` that adds members can be sealed too. New members don't get the handshake
+    /// secret of the epoch they're added in. Instead, the `Welcome`s this member makes carry their
+    /// seal secret for this epoch, which only opens `Handshake`s signed by this member in this
+    /// epoch. So a `Handshake` that adds members must be sealed by the member who made their
+    /// `Welcome`s. An `ExternalJoin` can't be sealed, since its signer isn't a member yet.
+    ///
+    /// Returns: `Ok(sealed_handshake)` on success. Returns an `Error::ValidationError` if
+    /// `handshake` isn't from this epoch, is already sealed, or is an `ExternalJoin`, or if this
+    /// member can't derive its signer's handshake key. Otherwise, returns some other sort of
+    /// `Error`.
+    pub fn seal_handshake<R>(
+        &self,
+        handshake: Handshake,
+        csprng: &mut R,
+    ) -> Result<Handshake, Error>
+    where
+        R: CryptoRng,
+    {
+        if handshake.prior_epoch != self.epoch {
+            return Err(Error::ValidationError("Handshake's prior epoch isn't the current epoch"));
+        }
+        if let GroupOperation::Sealed(_) = handshake.operation {
+            return Err(Error::ValidationError("Handshake is already sealed"));
+        }
+        if let GroupOperation::ExternalJoin(_) = handshake.operation {
+            return Err(Error::ValidationError("ExternalJoin can't be sealed"));
+        }
+
+        let key = self.handshake_key(handshake.signer_index)?;
+        let mut nonce_buf = vec![0u8; self.cs.aead_impl.nonce_size()];
+        csprng.try_fill_bytes(&mut nonce_buf).map_err(|_| Error::OutOfEntropy)?;
+        let nonce = AeadNonce::new_from_bytes(self.cs.aead_impl, &nonce_buf)?;
+        let aad = tls_ser::serialize_to_bytes(&SealedOperationAad {
+            group_id: &self.group_id,
+            prior_epoch: handshake.prior_epoch,
+            signer_index: handshake.signer_index,
+        })?;
+
+        // Serialize the operation, along with what the spec has no room for, and make room for the
+        // tag
+        let wire_operation = WireOperation::from(handshake.operation);
+        let mut ciphertext = tls_ser::serialize_to_bytes(&wire_operation)?;
+        ciphertext.extend(vec![0u8; self.cs.aead_impl.tag_size()]);
+        self.cs.aead_impl.seal_with_ad(&key, nonce, &aad, &mut ciphertext)?;
+
+        Ok(Handshake {
+            operation: GroupOperation::Sealed(SealedOperation {
+                nonce: nonce_buf,
+                ciphertext,
+            }),
+            ..handshake
+        })
+    }
+}
+
+/// The secret that one member's sealed `Handshake`s are encrypted under in one epoch. Every member
+/// derives it from the epoch's handshake secret. New members are given the one of whoever made
+/// their `Welcome`. See `GroupState::seal_handshake`.
+// struct {
+//     uint32 signer_index;
+//     opaque secret<0..255>;
+// } SealSecret;
+#[derive(Clone, Deserialize, Serialize)]
+#[cfg_attr(test, derive(Debug))]
+pub(crate) struct SealSecret {
+    /// The roster index of the member whose `Handshake`s this opens
+    pub(crate) signer_index: u32,
+
+    /// The secret itself
+    secret: HmacKey,
+}
+
+/// The associated data of a `SealedOperation`. This binds it to the group and to the clear fields
+/// of its `Handshake`.
+#[derive(Serialize)]
+struct SealedOperationAad<'a> {
+    #[serde(rename = "group_id__bound_u8")]
+    group_id: &'a [u8],
+    prior_epoch: u32,
+    signer_index: u32,
+}
+
+/// The changes that a `Commit`'s proposals make to the roster and tree, once the conflicts
+/// between them are resolved as described in the documentation of `GroupCommit`
+pub(crate) struct ResolvedProposals<'a> {
+    /// The roster indices of the members to remove
+    pub(crate) removes: Vec<u32>,
+    /// The roster indices of the members whose leaves get new public keys, with the `Update`
+    /// proposals carrying those keys
+    pub(crate) updates: Vec<(u32, &'a UpdateProposal)>,
+    /// The `UserInitKey`s of the members to add, in order
+    pub(crate) adds: Vec<&'a UserInitKey>,
+}
+
+/// Verifies the given proposals against the roster of the group they were made in, and resolves
+/// the conflicts between them. Every member added by an `Add` proposal has to be within their
+/// `UserInitKey`'s lifetime at time `now`, in seconds since the Unix epoch, even if they're added
+/// more than once. `Add` proposals are checked against `prior_welcome_info_hash`, if it's given.
+/// Someone who doesn't know the group's secrets can't compute that hash.
+///
+/// Returns: `Ok(resolved)` on success. Returns an `Error::ValidationError` if any proposal is
+/// invalid or the committer is removed, and an `Error::SignatureError` if a proposal's signature
+/// doesn't verify.
+pub(crate) fn resolve_proposals<'a>(
+    proposals: &'a [Proposal],
+    group_id: &[u8],
+    roster: &Roster,
+    prior_epoch: u32,
+    prior_welcome_info_hash: Option<&WelcomeInfoHash>,
+    committer_index: u32,
+    now: u64,
+) -> Result<ResolvedProposals<'a>, Error> {
+    let mut removes: Vec<u32> = Vec::new();
+    let mut updates: Vec<(u32, &UpdateProposal)> = Vec::new();
+    let mut adds: Vec<&UserInitKey> = Vec::new();
+    for proposal in proposals.iter() {
+        if proposal.prior_epoch != prior_epoch {
+            return Err(Error::ValidationError("Proposal's prior epoch isn't the current epoch"));
+        }
+        let sender_credential = roster
+            .0
+            .get(proposal.sender_index as usize)
+            .ok_or(Error::ValidationError("Proposal's sender index is out of bounds"))?
+            .as_ref()
+            .ok_or(Error::ValidationError("Proposal's sender credential is empty"))?;
+        proposal.verify_sig(group_id, sender_credential)?;
+
+        match proposal.body {
+            ProposalBody::Remove(ref remove) => {
+                let removed_index = remove.removed_roster_index;
+                let is_occupied = matches!(roster.0.get(removed_index as usize), Some(Some(_)));
+                if !is_occupied {
+                    return Err(Error::ValidationError(
+                        "Remove proposal targets an empty roster entry",
+                    ));
+                }
+                if removed_index == committer_index {
+                    return Err(Error::ValidationError("Commit cannot remove the committer"));
+                }
+                // Removing someone twice is the same as removing them once
+                if !removes.contains(&removed_index) {
+                    removes.push(removed_index);
+                }
+            }
+            ProposalBody::Update(ref update) => {
+                // The new leaf has to be signed by the sender, same as a leaf set by a direct path
+                let leaf_content = ratchet_tree::leaf_signature_content(&update.public_key, &[])?;
+                sender_credential.get_signature_scheme().verify(
+                    sender_credential.get_public_key(),
+                    &leaf_content,
+                    &update.signature,
+                )?;
+                // A member's later Update supersedes their earlier ones
+                updates.retain(|(sender, _)| *sender != proposal.sender_index);
+                updates.push((proposal.sender_index, update));
+            }
+            ProposalBody::Add(ref add) => {
+                check_new_member(&add.init_key, now)?;
+                check_welcome_info_hash(
+                    &add.welcome_info_hash,
+                    prior_welcome_info_hash,
+                    "Invalid WelcomeInfo hash in Add proposal",
+                )?;
+                // Adding someone twice is the same as adding them once
+                let id = &add.init_key.user_init_key_id;
+                if !adds.iter().any(|uik| &uik.user_init_key_id == id) {
+                    adds.push(&add.init_key);
+                }
+            }
+            // PSKs don't touch the roster or tree. They're mixed into the key schedule after the
+            // commit is applied.
+            ProposalBody::Psk(_) => (),
+        }
+    }
+    // Updates from removed members are moot, and the committer's leaf gets refreshed by the
+    // commit's path anyway
+    updates.retain(|(sender, _)| *sender != committer_index && !removes.contains(sender));
+
+    Ok(ResolvedProposals {
+        removes,
+        updates,
+        adds,
+    })
+}
+
+// The functions below are the parts of processing a Handshake that only touch the roster and the
+// public side of the tree. GroupState and GroupObserver both use them, so that the two agree on
+// where everyone ends up.
+
+/// Computes the transcript hash that follows `prior_transcript_hash` once `operation` is applied
+///
+/// Returns: `Ok(transcript_hash)` on success. Otherwise, returns an `Error::SerdeError`.
+pub(crate) fn next_transcript_hash(
+    cs: &'static CipherSuite,
+    prior_transcript_hash: &Digest,
+    operation: &GroupOperation,
+) -> Result<Digest, Error> {
+    // From section 5.7: transcript_hash_[n] = Hash(transcript_hash_[n-1] || operation)
+    let mut ctx = cs.hash_impl.new_context();
+    ctx.feed_bytes(prior_transcript_hash.as_bytes());
+    ctx.feed_serializable(&operation)?;
+    Ok(ctx.finalize())
+}
+
+/// Returns the roster index that new members are put in, i.e., the leftmost empty roster entry,
+/// or the end of the roster if there is none. This is where `Commit`, `MultiAdd`, and
+/// `ExternalJoin` operations put new members.
+///
+/// Returns: `Ok(roster_index)` on success. Returns an `Error::ValidationError` if the roster is
+/// too big to append to.
+pub(crate) fn next_free_roster_index(roster: &Roster) -> Result<u32, Error> {
+    let idx = roster.0.iter().position(|entry| entry.is_none()).unwrap_or_else(|| roster.len());
+    u32::try_from(idx).map_err(|_| Error::ValidationError("Roster is too big to append to"))
+}
+
+/// Puts a member with the given credential and leaf node in the roster and tree at
+/// `new_roster_index`, which must either be an empty roster entry or be equal to the size of the
+/// roster. The direct path of the new leaf is blanked.
+///
+/// Returns: `Ok(())` on success. Returns an `Error::ValidationError` if `new_roster_index` is out
+/// of bounds or occupied.
+pub(crate) fn insert_member(
+    roster: &mut Roster,
+    tree: &mut RatchetTree,
+    credential: Credential,
+    leaf: RatchetTreeNode,
+    new_roster_index: u32,
+) -> Result<(), Error> {
+    // If we're appending, we have to make new nodes. Otherwise, we have to make sure we're not
+    // overwriting an existing member.
+    if new_roster_index as usize == roster.len() {
+        roster.0.push(Some(credential));
+        tree.add_leaf_node(RatchetTreeNode::Blank);
+    } else {
+        let new_tree_idx = GroupState::roster_index_to_tree_index(new_roster_index)?;
+        if matches!(tree.get(new_tree_idx), Some(node) if node.is_filled()) {
+            return Err(Error::ValidationError("Tried to overwrite non-blank node"));
+        }
+        let entry_to_update = roster
+            .0
+            .get_mut(new_roster_index as usize)
+            .ok_or(Error::ValidationError("Out of bounds roster index"))?;
+        if entry_to_update.is_some() {
+            return Err(Error::ValidationError("Tried to overwrite non-null roster entry"));
+        }
+        *entry_to_update = Some(credential);
+    }
+
+    // Blank the new leaf's direct path just like an Add would, then fill in the leaf
+    replace_leaf(tree, new_roster_index, leaf)
+}
+
+/// Replaces the leaf of the member at `roster_index` with `leaf`, and blanks its direct path. This
+/// is how a committed `Update` proposal is applied.
+///
+/// Returns: `Ok(())` on success. Returns an `Error::ValidationError` if `roster_index` is out of
+/// bounds.
+pub(crate) fn replace_leaf(
+    tree: &mut RatchetTree,
+    roster_index: u32,
+    leaf: RatchetTreeNode,
+) -> Result<(), Error> {
+    let tree_idx = GroupState::roster_index_to_tree_index(roster_index)?;
+    tree.propagate_blank(tree_idx);
+    let node = tree.get_mut(tree_idx).ok_or(Error::ValidationError("Out of bounds tree index"))?;
+    *node = leaf;
+
+    Ok(())
+}
+
+/// Checks that the member at leaf `sender_tree_idx` signed the given `DirectPathMessage`, and
+/// records the path's parent hashes and signature in `tree`. This is done once the public keys of
+/// the path have been set.
+///
+/// Returns: `Ok(())` on success. Returns an `Error::SignatureError` if the signature doesn't
+/// verify, and some sort of `Error::ValidationError` if the sender isn't in `roster` or the path
+/// doesn't fit in `tree`.
+pub(crate) fn set_direct_path_parent_hashes(
+    cs: &'static CipherSuite,
+    roster: &Roster,
+    tree: &mut RatchetTree,
+    path: &DirectPathMessage,
+    sender_tree_idx: usize,
+) -> Result<(), Error> {
+    let sender_credential = roster
+        .0
+        .get(sender_tree_idx / 2)
+        .and_then(|entry| entry.as_ref())
+        .ok_or(Error::ValidationError("Direct path sender is not in the roster"))?;
+    let parent_hashes = path.verify_parent_hashes(cs, sender_credential)?;
+    let signature =
+        path.signature.clone().ok_or(Error::ValidationError("Direct path is unsigned"))?;
+    tree.set_path_parent_hashes(sender_tree_idx, parent_hashes, signature)
+}
+
+/// Blanks the roster entry at `removed_roster_index` and the direct path of its leaf. This
+/// doesn't truncate the roster or tree.
+///
+/// Returns: `Ok(())` on success. Returns an `Error::ValidationError` if `removed_roster_index`
+/// is out of bounds.
+pub(crate) fn remove_member(
+    roster: &mut Roster,
+    tree: &mut RatchetTree,
+    removed_roster_index: u32,
+) -> Result<(), Error> {
+    roster
+        .0
+        .get_mut(removed_roster_index as usize)
+        .map(|cred| *cred = None)
+        .ok_or(Error::ValidationError("Invalid roster index"))?;
+    let removed_tree_idx = GroupState::roster_index_to_tree_index(removed_roster_index)?;
+    tree.propagate_blank(removed_tree_idx);
+
+    Ok(())
+}
+
+/// The parts of a group that operations change, aside from its secrets. `GroupState` and
+/// `GroupObserver` both apply operations to these with `apply_operation`.
+pub(crate) trait OperationTarget {
+    /// Returns the group's ID
+    fn group_id(&self) -> &[u8];
+
+    /// Returns the group's roster
+    fn roster(&self) -> &Roster;
+
+    /// Puts the member described by `init_key` in the roster and tree at `new_roster_index`, which
+    /// must either be an empty roster entry or be equal to the size of the roster. This does not
+    /// verify `init_key`.
+    ///
+    /// Returns: `Ok(())` on success. Returns an `Error::ValidationError` if `new_roster_index` is
+    /// out of bounds or occupied, or if `init_key` has no public key for the group's ciphersuite.
+    fn insert_new_member(
+        &mut self,
+        init_key: &UserInitKey,
+        new_roster_index: u32,
+    ) -> Result<(), Error>;
+
+    /// Gives the member at `roster_index` the leaf in their committed `Update` proposal, and
+    /// blanks its direct path
+    ///
+    /// Returns: `Ok(())` on success. Returns an `Error::ValidationError` if `roster_index` is out
+    /// of bounds.
+    fn replace_leaf(&mut self, roster_index: u32, update: &UpdateProposal) -> Result<(), Error>;
+
+    /// Blanks the roster entry at `removed_roster_index` and the direct path of its leaf. This
+    /// doesn't truncate the roster or tree.
+    ///
+    /// Returns: `Ok(())` on success. Returns `Error::IAmRemoved` iff the removed member is the
+    /// holder of this view of the group, and an `Error::ValidationError` if
+    /// `removed_roster_index` is out of bounds.
+    fn remove_member(&mut self, removed_roster_index: u32) -> Result<(), Error>;
+
+    /// Prunes the blank roster entries and leaves from the end of the roster and tree
+    ///
+    /// Returns: `Ok(())` on success. Returns an `Error::ValidationError` if the roster is empty.
+    fn truncate_to_last_nonblank(&mut self) -> Result<(), Error>;
+
+    /// Returns the successor group, if the group has been reinitialized
+    fn reinit_mut(&mut self) -> &mut Option<GroupReInit>;
+
+    /// Returns the group's extensions
+    fn extensions_mut(&mut self) -> &mut Vec<Extension>;
+}
+
+/// A `GroupState` that an operation is being applied to, along with this member's pending
+/// `Update` private key from the epoch the operation was made in, if any
+struct GroupStateTarget<'a> {
+    state: &'a mut GroupState,
+    my_pending_update_key: Option<&'a DhPrivateKey>,
+}
+
+impl<'a> OperationTarget for GroupStateTarget<'a> {
+    fn group_id(&self) -> &[u8] {
+        &self.state.group_id
+    }
+
+    fn roster(&self) -> &Roster {
+        &self.state.roster
+    }
+
+    fn insert_new_member(
+        &mut self,
+        init_key: &UserInitKey,
+        new_roster_index: u32,
+    ) -> Result<(), Error> {
+        self.state.insert_new_member(init_key, new_roster_index)
+    }
+
+    fn replace_leaf(&mut self, roster_index: u32, update: &UpdateProposal) -> Result<(), Error> {
+        let cs = self.state.cs;
+        let public_key = &update.public_key;
+        let private_key = if Some(roster_index) == self.state.roster_index {
+            // This is our own Update. We'd better still have the private key for it
+            let private_key = self.my_pending_update_key.ok_or(Error::ValidationError(
+                "No pending private key for this member's committed Update",
+            ))?;
+            let pending_public_key = DhPublicKey::new_from_private_key(cs.dh_impl, private_key);
+            let keys_match: bool = pending_public_key.ct_eq(public_key).into();
+            if !keys_match {
+                return Err(Error::ValidationError(
+                    "Committed Update doesn't match this member's pending Update",
+                ));
+            }
+            Some(private_key.clone())
+        } else {
+            None
+        };
+
+        let new_leaf = RatchetTreeNode::new_from_public_key(public_key.clone(), private_key)
+            .with_leaf_signature(update.signature.clone());
+        replace_leaf(&mut self.state.tree, roster_index, new_leaf)
+    }
+
+    fn remove_member(&mut self, removed_roster_index: u32) -> Result<(), Error> {
+        if Some(removed_roster_index) == self.state.roster_index {
+            return Err(Error::IAmRemoved);
+        }
+        remove_member(&mut self.state.roster, &mut self.state.tree, removed_roster_index)
+    }
+
+    fn truncate_to_last_nonblank(&mut self) -> Result<(), Error> {
+        self.state.roster.truncate_to_last_nonblank()?;
+        self.state.tree.truncate_to_last_nonblank();
+        Ok(())
+    }
+
+    fn reinit_mut(&mut self) -> &mut Option<GroupReInit> {
+        &mut self.state.reinit
+    }
+
+    fn extensions_mut(&mut self) -> &mut Vec<Extension> {
+        &mut self.state.extensions
+    }
+}
+
+/// Applies `operation`, signed by the member at roster index `signer_index`, to the roster and
+/// public side of the tree of `target`, along with its extensions and successor. The operation's
+/// direct path, if any, is left to the caller, since what can be done with it depends on which of
+/// the group's secrets the caller knows. `prior_epoch` is the epoch the operation was made in, and
+/// `now` is the current time, in seconds since the Unix epoch. `WelcomeInfo` hashes are checked
+/// against `prior_welcome_info_hash`, if it's given. Someone who doesn't know the group's secrets
+/// can't compute that hash.
+///
+/// Returns: `Ok(())` on success. Returns `Error::IAmRemoved` iff the holder of `target` is removed
+/// by the operation, an `Error::SignatureError` if a `UserInitKey` or proposal signature doesn't
+/// verify, and an `Error::ValidationError` if the operation is otherwise invalid.
+pub(crate) fn apply_operation<T: OperationTarget>(
+    target: &mut T,
+    operation: &GroupOperation,
+    signer_index: u32,
+    prior_epoch: u32,
+    prior_welcome_info_hash: Option<&WelcomeInfoHash>,
+    now: u64,
+) -> Result<(), Error> {
+    match *operation {
+        // An Update only touches its sender's direct path
+        GroupOperation::Update(_) => Ok(()),
+        GroupOperation::Remove(ref remove) => apply_remove(target, remove, signer_index),
+        GroupOperation::Add(ref add) => apply_add(target, add, prior_welcome_info_hash, now),
+        GroupOperation::Init(ref init) => {
+            // Init is only for populating a freshly created group
+            if prior_epoch != 0 {
+                return Err(Error::ValidationError("Init operation is only valid at epoch 0"));
+            }
+            apply_init_members(
+                target,
+                &init.init_keys,
+                &init.welcome_info_hash,
+                prior_welcome_info_hash,
+                now,
+            )
+        }
+        GroupOperation::Commit(ref commit) => apply_proposals(
+            target,
+            &commit.proposals,
+            prior_epoch,
+            prior_welcome_info_hash,
+            signer_index,
+            now,
+        ),
+        GroupOperation::MultiAdd(ref multi_add) => {
+            apply_multi_add(target, multi_add, prior_welcome_info_hash, now)
+        }
+        GroupOperation::ExternalJoin(ref join) => {
+            apply_external_join(target, join, signer_index, now)
+        }
+        GroupOperation::ReInit(ref reinit) => apply_reinit(target, reinit),
+        GroupOperation::Extensions(ref op) => apply_extensions(target, op),
+        // Sealed operations are unsealed before they get here, if they can be
+        GroupOperation::Sealed(_) => Err(Error::ValidationError("Cannot apply a sealed operation")),
+    }
+}
+
+/// Checks `welcome_info_hash` against `prior_welcome_info_hash`, if it's given. `err` is the
+/// error message if they differ.
+fn check_welcome_info_hash(
+    welcome_info_hash: &WelcomeInfoHash,
+    prior_welcome_info_hash: Option<&WelcomeInfoHash>,
+    err: &'static str,
+) -> Result<(), Error> {
+    // Constant-time compare the WelcomeInfo hashes (no reason for constant-time other than it
+    // feels icky not to do it)
+    if let Some(prior_welcome_info_hash) = prior_welcome_info_hash {
+        let hashes_match: bool = prior_welcome_info_hash.ct_eq(welcome_info_hash).into();
+        if !hashes_match {
+            return Err(Error::ValidationError(err));
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks that `init_key` is signed, valid, and within its lifetime at time `now`, in seconds
+/// since the Unix epoch
+fn check_new_member(init_key: &UserInitKey, now: u64) -> Result<(), Error> {
+    init_key.verify_sig()?;
+    init_key.validate()?;
+    init_key.validate_lifetime(now)
+}
+
+/// Removes the member targeted by a `Remove` operation sent by the member at `signer_index`
+///
+/// Returns: The same as `apply_operation`
+fn apply_remove<T: OperationTarget>(
+    target: &mut T,
+    remove: &GroupRemove,
+    signer_index: u32,
+) -> Result<(), Error> {
+    if signer_index == remove.removed_roster_index {
+        return Err(Error::ValidationError("Remove cannot remove its sender"));
+    }
+    target.remove_member(remove.removed_roster_index)?;
+
+    // Try to prune the blanks from the end. This can't empty the group, since the sender isn't the
+    // one removed.
+    target.truncate_to_last_nonblank()
+}
+
+/// Puts the member added by an `Add` operation in the roster and tree
+///
+/// Returns: The same as `apply_operation`
+fn apply_add<T: OperationTarget>(
+    target: &mut T,
+    add: &GroupAdd,
+    prior_welcome_info_hash: Option<&WelcomeInfoHash>,
+    now: u64,
+) -> Result<(), Error> {
+    // What we have to do, in order
+    // 1. If the index value is equal to the size of the group, increment the size of the
+    //    group, and extend the tree and roster accordingly
+    // 2. Verify the signature on the included UserInitKey; if the signature verification
+    //    fails, abort
+    // 3. Generate a WelcomeInfo object describing the state prior to the add, and verify that
+    //    its hash is the same as the value of the welcome_info_hash field
+    // 4. Set the roster entry at position index to the credential in the included UserInitKey
+    // 5. Update the ratchet tree by setting to blank all nodes in the direct path of the new
+    //    node
+    // 6. Set the leaf node in the tree at position index to a new node containing the public
+    //    key from the UserInitKey in the Add corresponding to the ciphersuite in use
+    if add.roster_index as usize > target.roster().len() {
+        return Err(Error::ValidationError("Invalid insertion index in Add operation"));
+    }
+    check_welcome_info_hash(
+        &add.welcome_info_hash,
+        prior_welcome_info_hash,
+        "Invalid WelcomeInfo hash in Add operation",
+    )?;
+    check_new_member(&add.init_key, now)?;
+
+    target.insert_new_member(&add.init_key, add.roster_index)
+}
+
+/// Appends the members described by `init_keys` to the roster and tree. This is the part of an
+/// `Init` operation that does not touch any secrets.
+///
+/// Returns: The same as `apply_operation`
+fn apply_init_members<T: OperationTarget>(
+    target: &mut T,
+    init_keys: &[UserInitKey],
+    welcome_info_hash: &WelcomeInfoHash,
+    prior_welcome_info_hash: Option<&WelcomeInfoHash>,
+    now: u64,
+) -> Result<(), Error> {
+    if init_keys.is_empty() {
+        return Err(Error::ValidationError("Init operation must add at least one member"));
+    }
+    check_welcome_info_hash(
+        welcome_info_hash,
+        prior_welcome_info_hash,
+        "Invalid WelcomeInfo hash in Init operation",
+    )?;
+    if !GroupState::user_init_key_ids_are_unique(init_keys) {
+        return Err(Error::ValidationError("Init operation has duplicate UserInitKey IDs"));
+    }
+
+    for uik in init_keys.iter() {
+        check_new_member(uik, now)?;
+
+        // Every member of an Init is appended to the end of the roster
+        let new_roster_index = u32::try_from(target.roster().len())
+            .map_err(|_| Error::ValidationError("Roster is too big to append to"))?;
+        target.insert_new_member(uik, new_roster_index)?;
+    }
+
+    Ok(())
+}
+
+/// Puts the members added by a `MultiAdd` operation in the leftmost empty roster entries, or at
+/// the end if there are none, in order
+///
+/// Returns: The same as `apply_operation`
+fn apply_multi_add<T: OperationTarget>(
+    target: &mut T,
+    multi_add: &GroupMultiAdd,
+    prior_welcome_info_hash: Option<&WelcomeInfoHash>,
+    now: u64,
+) -> Result<(), Error> {
+    if multi_add.init_keys.is_empty() {
+        return Err(Error::ValidationError("MultiAdd operation must add at least one member"));
+    }
+    check_welcome_info_hash(
+        &multi_add.welcome_info_hash,
+        prior_welcome_info_hash,
+        "Invalid WelcomeInfo hash in MultiAdd operation",
+    )?;
+    for uik in multi_add.init_keys.iter() {
+        check_new_member(uik, now)?;
+    }
+    if !GroupState::user_init_key_ids_are_unique(&multi_add.init_keys) {
+        return Err(Error::ValidationError("MultiAdd operation has duplicate UserInitKey IDs"));
+    }
+
+    for uik in multi_add.init_keys.iter() {
+        let new_roster_index = next_free_roster_index(target.roster())?;
+        target.insert_new_member(uik, new_roster_index)?;
+    }
+
+    Ok(())
+}
+
+/// Verifies the given proposals, resolves conflicts among them as described in the documentation
+/// of `GroupCommit`, and applies them to the roster and tree. This is the part of a `Commit`
+/// operation that does not touch any path secrets.
+///
+/// Returns: The same as `apply_operation`. In particular, this errors if the committer is removed.
+fn apply_proposals<T: OperationTarget>(
+    target: &mut T,
+    proposals: &[Proposal],
+    prior_epoch: u32,
+    prior_welcome_info_hash: Option<&WelcomeInfoHash>,
+    committer_index: u32,
+    now: u64,
+) -> Result<(), Error> {
+    // First verify every proposal and resolve the conflicts between them. The roster at this
+    // point is still the roster of the prior epoch.
+    let ResolvedProposals {
+        removes,
+        updates,
+        adds,
+    } = resolve_proposals(
+        proposals,
+        target.group_id(),
+        target.roster(),
+        prior_epoch,
+        prior_welcome_info_hash,
+        committer_index,
+        now,
+    )?;
+
+    // Apply the Updates. This replaces the sender's leaf and blanks its direct path, which gets
+    // refilled by the commit's path.
+    for (sender_index, update) in updates {
+        target.replace_leaf(sender_index, update)?;
+    }
+
+    // Apply the Removes
+    for removed_index in removes {
+        target.remove_member(removed_index)?;
+    }
+
+    // Apply the Adds
+    for init_key in adds {
+        let new_roster_index = next_free_roster_index(target.roster())?;
+        target.insert_new_member(init_key, new_roster_index)?;
+    }
+
+    // Finally, prune the blanks from the end. This can't empty the group, since the committer
+    // can't be removed.
+    target.truncate_to_last_nonblank()
+}
+
+/// Puts the member joining by an `ExternalJoin` operation in the roster and tree, where
+/// `joiner_roster_idx` is the `signer_index` of the `Handshake` containing the operation
+///
+/// Returns: The same as `apply_operation`. In particular, this errors if the joiner is not where
+/// new members go.
+fn apply_external_join<T: OperationTarget>(
+    target: &mut T,
+    join: &GroupExternalJoin,
+    joiner_roster_idx: u32,
+    now: u64,
+) -> Result<(), Error> {
+    check_new_member(&join.init_key, now)?;
+
+    // The joiner goes where new members go
+    if joiner_roster_idx != next_free_roster_index(target.roster())? {
+        return Err(Error::ValidationError("ExternalJoin's signer index is in the wrong place"));
+    }
+    target.insert_new_member(&join.init_key, joiner_roster_idx)
+}
+
+/// Marks the group as ended in favor of the successor described by a `ReInit` operation
+///
+/// Returns: The same as `apply_operation`. In particular, this errors if the group was already
+/// reinitialized or if the successor group has the same group ID as this one.
+fn apply_reinit<T: OperationTarget>(target: &mut T, reinit: &GroupReInit) -> Result<(), Error> {
+    if target.reinit_mut().is_some() {
+        return Err(Error::ValidationError("Group has already been reinitialized"));
+    }
+    if reinit.group_id.as_slice() == target.group_id() {
+        return Err(Error::ValidationError("Successor group must have a new group ID"));
+    }
+    *target.reinit_mut() = Some(reinit.clone());
+
+    Ok(())
+}
+
+/// Replaces the group's extensions with the ones in an `Extensions` operation
+///
+/// Returns: The same as `apply_operation`. In particular, this errors if the new extensions are
+/// invalid.
+fn apply_extensions<T: OperationTarget>(target: &mut T, op: &GroupExtensions) -> Result<(), Error> {
+    extension::validate_extensions(&op.extensions)?;
+    *target.extensions_mut() = op.extensions.clone();
+
+    Ok(())
 }
 
 // TODO: Make this COW so we don't have to clone everything in GroupState::as_welcome_info
 
 /// Contains everything a new user needs to know to join a group. This is always followed by an
-/// `Add` operation.
+/// `Add` or `Init` operation.
 #[derive(Deserialize, Serialize)]
 #[cfg_attr(test, derive(Debug))]
 pub(crate) struct WelcomeInfo {
@@ -1134,6 +4367,51 @@ pub(crate) struct WelcomeInfo {
     // opaque init_secret<0..255>;
     /// The initial secret used to derive all the rest
     init_secret: HmacKey,
+
+    // WelcomeInfoExtras extras; // Left out if empty
+    /// The parts of this `WelcomeInfo` that the spec has no room for. These go after everything
+    /// the spec defines, and are left out entirely when empty, so that a `WelcomeInfo` that only
+    /// has what the spec defines is encoded exactly as the spec says.
+    #[serde(
+        rename = "extras__trailing",
+        default,
+        skip_serializing_if = "WelcomeInfoExtras::is_empty"
+    )]
+    pub(crate) extras: WelcomeInfoExtras,
+}
+
+/// The parts of a `WelcomeInfo` that the spec has no room for
+// struct {
+//     Extension extensions<0..2^16-1>;
+//     NodeParentHash tree_parent_hashes<0..2^32-1>;
+//     optional<SealSecret> seal_secret;
+// } WelcomeInfoExtras;
+#[derive(Default, Deserialize, Serialize)]
+#[cfg_attr(test, derive(Debug))]
+pub(crate) struct WelcomeInfoExtras {
+    /// The group's extensions
+    #[serde(rename = "extensions__bound_u16")]
+    extensions: Vec<Extension>,
+
+    /// The parent hashes and leaf signatures of the nodes in the `WelcomeInfo`'s tree, in order.
+    /// Joining with a `WelcomeInfo` that leaves these out fails, since nothing vouches for the
+    /// tree's leaves.
+    #[serde(rename = "tree_parent_hashes__bound_u32")]
+    pub(crate) tree_parent_hashes: Vec<NodeParentHash>,
+
+    /// The seal secret of the `Welcome`'s sender for the `WelcomeInfo`'s epoch, if they know it.
+    /// This isn't covered by the `WelcomeInfoHash`.
+    seal_secret: Option<SealSecret>,
+}
+
+impl WelcomeInfoExtras {
+    /// Returns whether there's nothing here, i.e., whether the `WelcomeInfo` only has what the
+    /// spec defines
+    fn is_empty(&self) -> bool {
+        self.extensions.is_empty()
+            && self.tree_parent_hashes.is_empty()
+            && self.seal_secret.is_none()
+    }
 }
 
 // This is public-facing
@@ -1156,6 +4434,48 @@ impl subtle::ConstantTimeEq for WelcomeInfoHash {
     }
 }
 
+impl WelcomeInfo {
+    /// Deserializes and upcasts a decrypted `WelcomeInfo`, and checks it against the
+    /// `UserInitKey` it was decrypted with
+    ///
+    /// Requires: `init_key` has a private key for the ciphersuite `cs`
+    ///
+    /// Returns: `Ok(welcome_info)` on success. If the bytes are malformed, or the `WelcomeInfo`'s
+    /// protocol version is not the one `init_key` supports for `cs`, returns some sort of `Error`.
+    fn from_decrypted_bytes(
+        bytes: &[u8],
+        cs: &'static CipherSuite,
+        init_key: &UserInitKey,
+    ) -> Result<WelcomeInfo, Error> {
+        // Deserialize it and upcast it
+        let welcome_info = {
+            let mut cursor = bytes;
+            let mut deserializer = TlsDeserializer::from_bytes(&mut cursor);
+            let mut w = WelcomeInfo::deserialize(&mut deserializer)?;
+
+            // Once it's deserialized, make it nice and typesafe
+            let ctx = CryptoCtx::new().set_cipher_suite(cs);
+            w.upcast_crypto_values(&ctx)?;
+            w
+        };
+
+        // TODO: Figure out if a versioning scheme should accept versions that are less than the
+        // requested one.
+
+        // Check that the WelcomeInfo has precisely the supported version
+        let supported_version = init_key.get_supported_version(cs)?.ok_or(
+            Error::ValidationError("UserInitKey has no supported version for this ciphersuite"),
+        )?;
+        if welcome_info.protocol_version != supported_version {
+            return Err(Error::ValidationError(
+                "WelcomeInfo's supported protocol version does not match the UserInitKey's",
+            ));
+        }
+
+        Ok(welcome_info)
+    }
+}
+
 /// This contains an encrypted `WelcomeInfo` for new group members
 #[derive(Deserialize, Serialize)]
 #[cfg_attr(test, derive(Debug))]
@@ -1212,18 +4532,15 @@ impl Welcome {
     where
         R: CryptoRng,
     {
-        // Make a WelcomeInfo from the group
-        let welcome_info = group_state.as_welcome_info();
-
-        // Take the hash of the WelcomeInfo. This is necessary if the caller wants to make an Add.
-        // The caller can't derive it themselves, because we wrap the WelcomeInfo in a Welcome in
-        // the next step.
-        let welcome_info_hash = group_state.cs.hash_impl.hash_serializable(&welcome_info)?;
+        // Make a WelcomeInfo from the group and take its hash. The hash is necessary if the
+        // caller wants to make an Add. The caller can't derive it themselves, because we wrap the
+        // WelcomeInfo in a Welcome in the next step.
+        let (welcome_info, welcome_info_hash) = group_state.as_welcome_info_for_new_members()?;
 
         // Encrypt it up
         let welcome = Welcome::from_welcome_info(&group_state.cs, init_key, &welcome_info, csprng)?;
 
-        Ok((welcome, welcome_info_hash.into()))
+        Ok((welcome, welcome_info_hash))
     }
 
     /// Decrypts the `Welcome` with the given `UserInitKey`
@@ -1233,14 +4550,17 @@ impl Welcome {
     /// is not `None`
     ///
     /// Returns: `Ok((welcome_info, cs))` on success, where `welcome_info` is the decrypted
-    /// `WelcomeInfo` that this `Welcome` contained, and `cs` is this group's cipher suite
+    /// `WelcomeInfo` that this `Welcome` contained, and `cs` is this group's cipher suite. Returns
+    /// an `Error::ValidationError` if the time `now` is outside of `init_key`'s lifetime.
     fn into_welcome_info_cipher_suite(
         self,
         init_key: &UserInitKey,
+        now: u64,
     ) -> Result<(WelcomeInfo, &'static CipherSuite), Error> {
         // Verify the UserInitKey signature and validate its contents
         init_key.verify_sig()?;
         init_key.validate()?;
+        init_key.validate_lifetime(now)?;
         // Verify that the supplied UserInitKey is the one that the Welcome message references
         if self.user_init_key_id != init_key.user_init_key_id {
             return Err(Error::ValidationError("Supplied UserInitKey ID doesn't match Welcome's"));
@@ -1251,56 +4571,383 @@ impl Welcome {
             .get_private_key(cs)?
             .ok_or(Error::ValidationError("Can't decrypt Welcome without a private key"))?;
 
-        // Decrypt the WelcomeInfo, deserialize it, upcast it, and return it
+        // Decrypt the WelcomeInfo and make sense of it
         let welcome_info_bytes = ecies::decrypt(cs, dh_private_key, self.encrypted_welcome_info)?;
-        let welcome_info = {
-            let mut cursor = welcome_info_bytes.as_slice();
-            let mut deserializer = TlsDeserializer::from_reader(&mut cursor);
-            let mut w = WelcomeInfo::deserialize(&mut deserializer)?;
+        let welcome_info = WelcomeInfo::from_decrypted_bytes(&welcome_info_bytes, cs, init_key)?;
 
-            // Once it's deserialized, make it nice and typesafe
-            let ctx = CryptoCtx::new().set_cipher_suite(cs);
-            w.upcast_crypto_values(&ctx)?;
-            w
+        Ok((welcome_info, cs))
+    }
+
+    /// Returns the `user_init_key_id` associated with this `Welcome`
+    pub fn get_user_init_key_id(&self) -> &[u8] {
+        self.user_init_key_id.as_slice()
+    }
+}
+
+/// The key to a `MultiWelcome`'s `WelcomeInfo`, encrypted to one of its recipients
+#[derive(Deserialize, Serialize)]
+#[cfg_attr(test, derive(Debug))]
+pub(crate) struct EncryptedWelcomeKey {
+    // opaque user_init_key_id<0..255>;
+    /// The ID of the `UserInitKey` this is encrypted to
+    #[serde(rename = "user_init_key_id__bound_u8")]
+    user_init_key_id: Vec<u8>,
+
+    /// The AEAD key that the `WelcomeInfo` is sealed under, encrypted under the public key of the
+    /// `UserInitKey`
+    pub(crate) encrypted_key: EciesCiphertext,
+}
+
+/// This contains a single `WelcomeInfo` for many new group members. The `WelcomeInfo` is sealed
+/// once under a fresh AEAD key, and that key is encrypted to each new member's `UserInitKey`. This
+/// ordinarily precedes a `MultiAdd`.
+#[derive(Deserialize, Serialize)]
+#[cfg_attr(test, derive(Debug))]
+pub struct MultiWelcome {
+    pub(crate) cipher_suite: &'static CipherSuite,
+
+    // opaque group_id<0..255>;
+    /// The ID of the group being joined. This and `epoch` are bound to the sealed `WelcomeInfo`.
+    #[serde(rename = "group_id__bound_u8")]
+    pub(crate) group_id: Vec<u8>,
+
+    /// The epoch of the `WelcomeInfo`
+    pub(crate) epoch: u32,
+
+    // EncryptedWelcomeKey recipients<1..2^32-1>;
+    /// One encrypted key per new member
+    #[serde(rename = "recipients__bound_u32")]
+    pub(crate) recipients: Vec<EncryptedWelcomeKey>,
+
+    // opaque encrypted_welcome_info<0..2^32-1>;
+    /// The `WelcomeInfo`, sealed with an all-zero nonce. A nonce only has to be unique per key,
+    /// and the key is freshly generated for this `MultiWelcome` and never used again, so the one
+    /// nonce it's ever used with may as well be zero.
+    #[serde(rename = "encrypted_welcome_info__bound_u32")]
+    encrypted_welcome_info: Vec<u8>,
+}
+
+/// The associated data of a `MultiWelcome`'s sealed `WelcomeInfo`. This binds it to the group and
+/// epoch it says it's from.
+#[derive(Serialize)]
+struct MultiWelcomeAad<'a> {
+    #[serde(rename = "group_id__bound_u8")]
+    group_id: &'a [u8],
+    epoch: u32,
+}
+
+impl MultiWelcome {
+    /// Creates a `MultiWelcome` object for all the target `UserInitKey`s. The `MultiWelcome`
+    /// contains all the current state information. This operation ordinarily precedes a
+    /// `MultiAdd`.
+    ///
+    /// Returns: `Ok((multi_welcome, welcome_info_hash))` on success where `multi_welcome` is a
+    /// `MultiWelcome` message representing the group's current state, and `welcome_info_hash` is
+    /// the hash of the underlying `WelcomeInfo` object. The hash is relevant for `MultiAdd`
+    /// operations. If `init_keys` is empty, or one of them has no public key for the group's
+    /// ciphersuite, returns an `Error::ValidationError`.
+    pub fn from_group_state<R>(
+        group_state: &GroupState,
+        init_keys: &[UserInitKey],
+        csprng: &mut R,
+    ) -> Result<(MultiWelcome, WelcomeInfoHash), Error>
+    where
+        R: CryptoRng,
+    {
+        if init_keys.is_empty() {
+            return Err(Error::ValidationError("MultiWelcome must have at least one recipient"));
+        }
+        let cs = group_state.cs;
+
+        // Make a WelcomeInfo from the group and take its hash, same as in Welcome
+        let (welcome_info, welcome_info_hash) = group_state.as_welcome_info_for_new_members()?;
+
+        // Seal the WelcomeInfo under a fresh key. Make room for the tag first.
+        let mut key_bytes = vec![0u8; cs.aead_impl.key_size()];
+        csprng.try_fill_bytes(&mut key_bytes).map_err(|_| Error::OutOfEntropy)?;
+        let key = AeadKey::new_from_bytes(cs.aead_impl, &key_bytes)?;
+        let nonce = AeadNonce::new_from_bytes(cs.aead_impl, &vec![0u8; cs.aead_impl.nonce_size()])?;
+        let aad = tls_ser::serialize_to_bytes(&MultiWelcomeAad {
+            group_id: &group_state.group_id,
+            epoch: group_state.epoch,
+        })?;
+        let mut encrypted_welcome_info = tls_ser::serialize_to_bytes(&welcome_info)?;
+        let tagged_len = encrypted_welcome_info.len() + cs.aead_impl.tag_size();
+        encrypted_welcome_info.resize(tagged_len, 0u8);
+        cs.aead_impl.seal_with_ad(&key, nonce, &aad, &mut encrypted_welcome_info)?;
+
+        // Now encrypt the key to everyone
+        let mut recipients = Vec::new();
+        for init_key in init_keys.iter() {
+            let public_key = init_key.get_public_key(cs)?.ok_or(Error::ValidationError(
+                "No corresponding public key for given ciphersuite",
+            ))?;
+            let encrypted_key = ecies::encrypt(cs, public_key, key_bytes.clone(), csprng)?;
+            recipients.push(EncryptedWelcomeKey {
+                user_init_key_id: init_key.user_init_key_id.clone(),
+                encrypted_key,
+            });
+        }
+
+        let multi_welcome = MultiWelcome {
+            cipher_suite: cs,
+            group_id: group_state.group_id.clone(),
+            epoch: group_state.epoch,
+            recipients,
+            encrypted_welcome_info,
         };
+        Ok((multi_welcome, welcome_info_hash))
+    }
 
-        // TODO: Figure out if a versioning scheme should accept versions that are less than the
-        // requested one.
+    /// Decrypts the `MultiWelcome` with the given `UserInitKey`
+    ///
+    /// Requires: That the `init_key` is one of the `UserInitKey`s that the `MultiWelcome` was
+    /// encrypted to, and `init_key.private_keys` is not `None`
+    ///
+    /// Returns: `Ok((welcome_info, cs))` on success, where `welcome_info` is the decrypted
+    /// `WelcomeInfo` that this `MultiWelcome` contained, and `cs` is this group's cipher suite.
+    /// Returns an `Error::ValidationError` if the time `now` is outside of `init_key`'s lifetime.
+    fn into_welcome_info_cipher_suite(
+        self,
+        init_key: &UserInitKey,
+        now: u64,
+    ) -> Result<(WelcomeInfo, &'static CipherSuite), Error> {
+        // Verify the UserInitKey signature and validate its contents
+        init_key.verify_sig()?;
+        init_key.validate()?;
+        init_key.validate_lifetime(now)?;
 
-        // Check that the WelcomeInfo has precisely the supported version. We can unwrap here
-        // because we already found the private key corresponding to this ciphersuite above.
-        let supported_version = init_key.get_supported_version(cs)?.unwrap();
-        if welcome_info.protocol_version != supported_version {
+        // Find our copy of the key
+        let cs = self.cipher_suite;
+        let MultiWelcome {
+            group_id,
+            epoch,
+            recipients,
+            mut encrypted_welcome_info,
+            ..
+        } = self;
+        let encrypted_key = recipients
+            .into_iter()
+            .find(|r| r.user_init_key_id == init_key.user_init_key_id)
+            .ok_or(Error::ValidationError("Supplied UserInitKey is not a MultiWelcome recipient"))?
+            .encrypted_key;
+        let dh_private_key = init_key
+            .get_private_key(cs)?
+            .ok_or(Error::ValidationError("Can't decrypt MultiWelcome without a private key"))?;
+        let key_bytes = ecies::decrypt(cs, dh_private_key, encrypted_key)?;
+        let key = AeadKey::new_from_bytes(cs.aead_impl, &key_bytes)?;
+
+        // Now open the WelcomeInfo and make sense of it
+        let nonce = AeadNonce::new_from_bytes(cs.aead_impl, &vec![0u8; cs.aead_impl.nonce_size()])?;
+        let aad = tls_ser::serialize_to_bytes(&MultiWelcomeAad {
+            group_id: &group_id,
+            epoch,
+        })?;
+        let welcome_info_bytes =
+            cs.aead_impl.open_with_ad(&key, nonce, &aad, &mut encrypted_welcome_info)?;
+        let welcome_info = WelcomeInfo::from_decrypted_bytes(welcome_info_bytes, cs, init_key)?;
+        if welcome_info.group_id != group_id || welcome_info.epoch != epoch {
             return Err(Error::ValidationError(
-                "WelcomeInfo's supported protocol version does not match the UserInitKey's",
+                "MultiWelcome's WelcomeInfo is from a different group or epoch",
             ));
         }
 
         Ok((welcome_info, cs))
     }
 
-    /// Returns the `user_init_key_id` associated with this `Welcome`
-    pub fn get_user_init_key_id(&self) -> &[u8] {
-        self.user_init_key_id.as_slice()
+    /// Returns the `user_init_key_id`s of the recipients of this `MultiWelcome`
+    pub fn get_user_init_key_ids(&self) -> Vec<&[u8]> {
+        self.recipients.iter().map(|r| r.user_init_key_id.as_slice()).collect()
+    }
+}
+
+/// Tells the selected members of a group about a branch of it, made by `GroupState::branch`
+#[derive(Clone, Deserialize, Serialize)]
+#[cfg_attr(test, derive(Debug))]
+pub struct Branch {
+    // opaque group_id<0..255>;
+    /// The group ID of the branch
+    #[serde(rename = "group_id__bound_u8")]
+    group_id: Vec<u8>,
+
+    /// The epoch of the parent group that the branch is keyed from
+    parent_epoch: u32,
+
+    // uint32 members<1..2^32-1>;
+    /// The roster indices of the branch members in the parent group, in increasing order
+    #[serde(rename = "members__bound_u32")]
+    members: Vec<u32>,
+
+    /// The creator's `Update` of the branch, which moves it from epoch 0 to epoch 1
+    pub(crate) handshake: Handshake,
+}
+
+impl Branch {
+    /// Returns the ID of the branch
+    pub fn get_group_id(&self) -> &[u8] {
+        self.group_id.as_slice()
+    }
+
+    /// Returns the roster indices of the branch members in the parent group
+    pub fn get_members(&self) -> &[u32] {
+        self.members.as_slice()
+    }
+}
+
+/// Contains everything a new member needs to join a group on their own, via
+/// `GroupState::create_external_join_handshake`. Unlike a `WelcomeInfo`, this contains no secrets,
+/// so it can be published. It is signed by the member who made it.
+///
+/// Anyone holding this can make an `ExternalJoin`. Members reject those by default, and only
+/// accept them when processing with a `MembershipPolicy` that opts in, such as
+/// `policy::AllowExternalJoins`. Don't publish this for a group whose members won't.
+#[derive(Deserialize, Serialize)]
+#[cfg_attr(test, derive(Debug))]
+pub struct PublicGroupInfo {
+    /// The ciphersuite of the group
+    pub(crate) cipher_suite: &'static CipherSuite,
+
+    /// The protocol version of the group
+    pub(crate) protocol_version: ProtocolVersion,
+
+    // opaque group_id<0..255>;
+    /// An application-defined identifier for the group
+    #[serde(rename = "group_id__bound_u8")]
+    pub(crate) group_id: Vec<u8>,
+
+    /// The group's current epoch
+    pub(crate) epoch: u32,
+
+    // optional<Credential> roster<1..2^32-1>;
+    /// The group's roster
+    #[serde(rename = "roster__bound_u32")]
+    pub(crate) roster: Roster,
+
+    /// The public part of the group's ratchet tree
+    pub(crate) tree: RatchetTree,
+
+    // NodeParentHash tree_parent_hashes<0..2^32-1>;
+    /// The parent hashes and leaf signatures of the nodes in `tree`, in order
+    #[serde(rename = "tree_parent_hashes__bound_u32")]
+    pub(crate) tree_parent_hashes: Vec<NodeParentHash>,
+
+    // opaque transcript_hash<0..255>;
+    /// The group's current transcript hash
+    pub(crate) transcript_hash: Digest,
+
+    // Extension extensions<0..2^16-1>;
+    /// The group's extensions
+    #[serde(rename = "extensions__bound_u16")]
+    pub(crate) extensions: Vec<Extension>,
+
+    /// The public half of this epoch's external key pair. A new member encrypts their init secret
+    /// to this.
+    pub(crate) external_public_key: DhPublicKey,
+
+    /// The roster index of the member who made this
+    pub(crate) signer_index: u32,
+
+    /// Signature over all of the above
+    pub(crate) signature: Signature,
+}
+
+// This struct is everything but the last field in PublicGroupInfo. We use the serialized form of
+// this as the message that the signature is computed over.
+#[derive(Serialize)]
+struct PartialPublicGroupInfo<'a> {
+    cipher_suite: &'static CipherSuite,
+    protocol_version: ProtocolVersion,
+    #[serde(rename = "group_id__bound_u8")]
+    group_id: &'a [u8],
+    epoch: u32,
+    #[serde(rename = "roster__bound_u32")]
+    roster: &'a Roster,
+    tree: &'a RatchetTree,
+    #[serde(rename = "tree_parent_hashes__bound_u32")]
+    tree_parent_hashes: &'a [NodeParentHash],
+    transcript_hash: &'a Digest,
+    #[serde(rename = "extensions__bound_u16")]
+    extensions: &'a [Extension],
+    external_public_key: &'a DhPublicKey,
+    signer_index: u32,
+}
+
+impl PublicGroupInfo {
+    /// Makes the part of this `PublicGroupInfo` that is signed
+    fn as_partial(&self) -> PartialPublicGroupInfo<'_> {
+        PartialPublicGroupInfo {
+            cipher_suite: self.cipher_suite,
+            protocol_version: self.protocol_version,
+            group_id: &self.group_id,
+            epoch: self.epoch,
+            roster: &self.roster,
+            tree: &self.tree,
+            tree_parent_hashes: &self.tree_parent_hashes,
+            transcript_hash: &self.transcript_hash,
+            extensions: &self.extensions,
+            external_public_key: &self.external_public_key,
+            signer_index: self.signer_index,
+        }
+    }
+
+    /// Verifies this `PublicGroupInfo`'s signature under the credential of its signer
+    ///
+    /// Returns: `Ok(())` on success. Returns an `Error::ValidationError` if the signer is not in
+    /// the roster, an `Error::SignatureError` on verification failure, and `Error::SerdeError` on
+    /// some serialization failure.
+    pub(crate) fn verify_sig(&self) -> Result<(), Error> {
+        let signer_credential = self
+            .roster
+            .0
+            .get(self.signer_index as usize)
+            .and_then(|entry| entry.as_ref())
+            .ok_or(Error::ValidationError("PublicGroupInfo's signer is not in the roster"))?;
+        let serialized_info = tls_ser::serialize_to_bytes(&self.as_partial())?;
+
+        let sig_scheme = signer_credential.get_signature_scheme();
+        let public_key = signer_credential.get_public_key();
+        sig_scheme.verify(public_key, &serialized_info, &self.signature)
+    }
+
+    /// Returns the ID of the group this describes
+    pub fn get_group_id(&self) -> &[u8] {
+        self.group_id.as_slice()
+    }
+
+    /// Returns the epoch of the group this describes
+    pub fn get_epoch(&self) -> u32 {
+        self.epoch
+    }
+
+    /// Returns the roster of the group this describes
+    pub fn get_roster(&self) -> &Roster {
+        &self.roster
     }
 }
 
 #[cfg(test)]
 mod test {
     use crate::{
+        clock::{Clock, FixedClock},
         credential::Roster,
         crypto::{
             ciphersuite::{CipherSuite, X25519_SHA256_AES128GCM},
+            dh::{DhPrivateKey, DhPublicKey},
             hash::Digest,
             hmac::HmacKey,
             sig::{SigSecretKey, ED25519_IMPL},
         },
         error::Error,
-        group_state::{GroupState, UpdateSecret, Welcome},
-        handshake::{ProtocolVersion, UserInitKey, MLS_DUMMY_VERSION},
-        ratchet_tree::RatchetTree,
+        group_state::{GroupState, MembershipStatus, UpdateSecret, Welcome},
+        handshake::{
+            GroupAdd, GroupOperation, Handshake, Lifetime, OperationKind, ProtocolVersion,
+            UserInitKey, UserInitKeyBuilder, MLS_DUMMY_VERSION,
+        },
+        observer::GroupObserver,
+        ratchet_tree::{self, PathSecret, RatchetTree},
         test_utils,
         tls_de::TlsDeserializer,
+        tree_math,
         upcast::{CryptoCtx, CryptoUpcast},
     };
 
@@ -1356,11 +5003,465 @@ mod test {
         assert_serialized_eq!(group_state1, group_state2, "GroupStates disagree after a Welcome");
     }
 
-    // This is all the serializable bits of a GroupState. We have this separate because GroupState
-    // is only ever meant to be serialized. The fields in it that are for us and not for
-    // serialization require a Default instance in order for GroupState to impl Deserialize. Since
-    // I don't think that's a good idea, I'll just initialize all those things to 0 myself. See
-    // group_from_test_group.
+    // Checks that a UserInitKey can only be used to join a group within its lifetime, both by the
+    // new member opening a Welcome and by the existing members making and processing the Add
+    #[quickcheck]
+    fn lifetime_correctness(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let (group_state, _) = test_utils::random_full_group_state(2, &mut rng);
+
+        // Make a UserInitKey that's valid from time 1000 to time 2000, inclusive
+        let (new_credential, new_identity_key) = test_utils::random_basic_credential(&mut rng);
+        let init_key = UserInitKeyBuilder::new(
+            b"lifetime-test-id".to_vec(),
+            new_credential,
+            vec![group_state.cs],
+            vec![MLS_DUMMY_VERSION],
+        )
+        .with_lifetime(Lifetime::new(1000, 2000))
+        .build(&new_identity_key, &mut rng)
+        .unwrap();
+
+        for &(now, should_succeed) in &[(999, false), (1000, true), (2000, true), (2001, false)] {
+            let clock = FixedClock(now);
+
+            // The new member only accepts a Welcome within the lifetime of its UserInitKey
+            let (welcome, welcome_info_hash) =
+                Welcome::from_group_state(&group_state, &init_key, &mut rng).unwrap();
+            let res = GroupState::from_welcome_with_clock(
+                welcome,
+                new_identity_key.clone(),
+                init_key.clone(),
+                &clock,
+            );
+            match res {
+                Ok(_) => assert!(should_succeed, "Welcome accepted at time {}", now),
+                Err(Error::ValidationError(_)) => {
+                    assert!(!should_succeed, "Welcome rejected at time {}", now)
+                }
+                Err(e) => panic!("Welcome gave the wrong error: {}", e),
+            }
+
+            // The existing members only accept an Add within that same lifetime
+            let add = GroupOperation::Add(GroupAdd {
+                roster_index: group_state.roster.len() as u32,
+                init_key: init_key.clone(),
+                welcome_info_hash: welcome_info_hash.clone(),
+            });
+            let signer_index = group_state.roster_index.unwrap();
+            let res = group_state.process_operation(
+                &mut group_state.clone(),
+                &add,
+                signer_index,
+                clock.now(),
+            );
+            match res {
+                Ok(_) => assert!(should_succeed, "Add accepted at time {}", now),
+                Err(Error::ValidationError(_)) => {
+                    assert!(!should_succeed, "Add rejected at time {}", now)
+                }
+                Err(e) => panic!("Add gave the wrong error: {}", e),
+            }
+
+            // And the member making the Add checks it against the same clock
+            let res = group_state.create_and_apply_add_handshake_with_clock(
+                group_state.roster.len() as u32,
+                init_key.clone(),
+                &welcome_info_hash,
+                &clock,
+            );
+            match res {
+                Ok(_) => assert!(should_succeed, "Add created at time {}", now),
+                Err(Error::ValidationError(_)) => {
+                    assert!(!should_succeed, "Add refused at time {}", now)
+                }
+                Err(e) => panic!("Add creation gave the wrong error: {}", e),
+            }
+
+            // Same for a Commit of an Add proposal
+            let proposal = group_state.create_add_proposal(init_key.clone(), &welcome_info_hash);
+            let new_path_secret = PathSecret::new_from_random(group_state.cs, &mut rng);
+            let res = group_state.create_and_apply_commit_handshake_with_clock(
+                vec![proposal.unwrap()],
+                new_path_secret,
+                &clock,
+                &mut rng,
+            );
+            match res {
+                Ok(_) => assert!(should_succeed, "Commit created at time {}", now),
+                Err(Error::ValidationError(_)) => {
+                    assert!(!should_succeed, "Commit refused at time {}", now)
+                }
+                Err(e) => panic!("Commit creation gave the wrong error: {}", e),
+            }
+        }
+    }
+
+    // Checks that a sealed Handshake hides its operation from everyone outside the group, and is
+    // processed by the members just like the unsealed one
+    #[quickcheck]
+    fn sealed_handshake_correctness(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+
+        // Make two perspectives of a group with a third member to remove
+        let (group_state1, identity_keys) = test_utils::random_full_group_state(3, &mut rng);
+        let idx1 = group_state1.roster_index.unwrap();
+        let idx2 = test_utils::random_roster_index_with_exceptions(
+            group_state1.roster.len(),
+            &[idx1 as usize],
+            &mut rng,
+        );
+        let group_state2 = test_utils::change_self_index(&group_state1, &identity_keys, idx2);
+        let observer =
+            GroupObserver::from_public_group_info(group_state1.create_public_group_info().unwrap())
+                .unwrap();
+
+        // Nobody knows the handshake secret until the group's first epoch change
+        let new_path_secret = PathSecret::new_from_random(group_state1.cs, &mut rng);
+        let (handshake, new_group_state1, _) =
+            group_state1.create_and_apply_update_handshake(new_path_secret, &mut rng).unwrap();
+        match group_state1.seal_handshake(handshake.clone(), &mut rng) {
+            Err(Error::ValidationError(_)) => (),
+            _ => panic!("Handshake was sealed without a handshake secret"),
+        }
+        let (group_state1, group_state2) =
+            (new_group_state1, group_state2.process_handshake(&handshake).unwrap().0);
+        let observer = observer.process_handshake(&handshake).unwrap();
+
+        // Seal a Remove with the state it was made in
+        let removed_idx = test_utils::random_roster_index_with_exceptions(
+            group_state1.roster.len(),
+            &[idx1 as usize, idx2 as usize],
+            &mut rng,
+        );
+        let new_path_secret = PathSecret::new_from_random(group_state1.cs, &mut rng);
+        let (handshake, new_group_state1, _) = group_state1
+            .create_and_apply_remove_handshake(removed_idx, new_path_secret, &mut rng)
+            .unwrap();
+        let sealed_handshake = group_state1.seal_handshake(handshake.clone(), &mut rng).unwrap();
+
+        // Only the clear fields can be read from the outside, and an observer can't follow it
+        assert_eq!(sealed_handshake.get_operation_kind(), OperationKind::Sealed);
+        assert!(sealed_handshake.get_removed_roster_indices().is_empty());
+        assert_eq!(sealed_handshake.get_signer_index(), idx1);
+        assert!(observer.process_handshake(&sealed_handshake).is_err());
+
+        // It can't be sealed twice, nor by a state from another epoch
+        assert!(group_state1.seal_handshake(sealed_handshake.clone(), &mut rng).is_err());
+        assert!(new_group_state1.seal_handshake(handshake.clone(), &mut rng).is_err());
+
+        // Send it over the wire
+        let sealed_handshake = {
+            let bytes = crate::tls_ser::serialize_to_bytes(&sealed_handshake).unwrap();
+            let mut cursor = bytes.as_slice();
+            let mut deserializer = TlsDeserializer::from_bytes(&mut cursor);
+            let mut h = Handshake::deserialize(&mut deserializer).unwrap();
+            let ctx = CryptoCtx::new()
+                .set_cipher_suite(group_state2.cs)
+                .set_signature_scheme(group_state2.get_signature_scheme());
+            h.upcast_crypto_values(&ctx).unwrap();
+            h
+        };
+
+        // A member processes it like the unsealed one
+        let (new_group_state2, _) = group_state2.process_handshake(&sealed_handshake).unwrap();
+        assert_serialized_eq!(new_group_state1, new_group_state2, "GroupStates disagree");
+
+        // Tampering with the sealed operation or the clear fields makes it fail to unseal
+        let mut tampered_handshake = sealed_handshake.clone();
+        if let GroupOperation::Sealed(ref mut sealed) = tampered_handshake.operation {
+            sealed.ciphertext[0] ^= 1;
+        }
+        assert!(group_state2.process_handshake(&tampered_handshake).is_err());
+        let mut tampered_handshake = sealed_handshake;
+        tampered_handshake.signer_index = idx2;
+        assert!(group_state2.process_handshake(&tampered_handshake).is_err());
+
+        // A new member doesn't get the handshake secret in their Welcome, only what opens the
+        // Welcome sender's own Handshakes in this epoch. So the Add that adds them can be sealed,
+        // and it hides who's added.
+        let (init_key, new_identity_key) =
+            test_utils::random_user_init_key(&mut rng, new_group_state1.cs);
+        let (welcome, prior_welcome_info_hash) =
+            Welcome::from_group_state(&new_group_state1, &init_key, &mut rng).unwrap();
+        let (other_init_key, other_identity_key) =
+            test_utils::random_user_init_key(&mut rng, new_group_state2.cs);
+        let (other_welcome, other_prior_welcome_info_hash) =
+            Welcome::from_group_state(&new_group_state2, &other_init_key, &mut rng).unwrap();
+        assert_serialized_eq!(prior_welcome_info_hash, other_prior_welcome_info_hash);
+        let new_roster_index = new_group_state1.roster.len() as u32;
+        let (handshake, added_group_state1, _) = new_group_state1
+            .create_and_apply_add_handshake(
+                new_roster_index,
+                init_key.clone(),
+                &prior_welcome_info_hash,
+            )
+            .unwrap();
+        let sealed_handshake = new_group_state1.seal_handshake(handshake, &mut rng).unwrap();
+        assert!(sealed_handshake.get_added_credentials().is_empty());
+
+        let new_member_group_state =
+            GroupState::from_welcome(welcome, new_identity_key, init_key).unwrap();
+        assert!(new_member_group_state.handshake_secret.is_none());
+        let (new_member_group_state, _) =
+            new_member_group_state.process_handshake(&sealed_handshake).unwrap();
+        let (added_group_state2, _) =
+            new_group_state2.process_handshake(&sealed_handshake).unwrap();
+
+        // A Welcome from someone other than the signer doesn't open it
+        let other_new_member_group_state =
+            GroupState::from_welcome(other_welcome, other_identity_key, other_init_key).unwrap();
+        assert!(other_new_member_group_state.process_handshake(&sealed_handshake).is_err());
+        assert_serialized_eq!(added_group_state1, new_member_group_state, "GroupStates disagree");
+        assert_serialized_eq!(added_group_state1, added_group_state2, "GroupStates disagree");
+
+        // Once they're in, the new member derives the handshake secret like everyone else, and can
+        // unseal what comes next
+        let new_path_secret = PathSecret::new_from_random(added_group_state1.cs, &mut rng);
+        let (handshake, updated_group_state1, _) = added_group_state1
+            .create_and_apply_update_handshake(new_path_secret, &mut rng)
+            .unwrap();
+        let sealed_handshake = added_group_state1.seal_handshake(handshake, &mut rng).unwrap();
+        let (updated_new_member_group_state, _) =
+            new_member_group_state.process_handshake(&sealed_handshake).unwrap();
+        assert_serialized_eq!(
+            updated_group_state1,
+            updated_new_member_group_state,
+            "GroupStates disagree"
+        );
+    }
+
+    // Checks that parent hashes agree after an Update, and that a new member rejects a tree with a
+    // key that nobody vouched for
+    #[quickcheck]
+    fn parent_hash_correctness(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let (group_state1, identity_keys) = test_utils::random_full_group_state(2, &mut rng);
+        let cs = group_state1.cs;
+        let other_index = test_utils::random_roster_index_with_exceptions(
+            group_state1.roster.len(),
+            &[group_state1.roster_index.unwrap() as usize],
+            &mut rng,
+        );
+        let group_state2 =
+            test_utils::change_self_index(&group_state1, &identity_keys, other_index);
+
+        // After an Update, both members have the same tree hash and a tree that checks out
+        let new_path_secret = PathSecret::new_from_random(cs, &mut rng);
+        let (update_handshake, group_state1, _) =
+            group_state1.create_and_apply_update_handshake(new_path_secret, &mut rng).unwrap();
+        let (group_state2, _) = group_state2.process_handshake(&update_handshake).unwrap();
+        assert_eq!(group_state1.tree_hash.as_bytes(), group_state2.tree_hash.as_bytes());
+        group_state1.tree.verify_parent_hashes(cs, &group_state1.roster).unwrap();
+        group_state2.tree.verify_parent_hashes(cs, &group_state2.roster).unwrap();
+
+        // A new member accepts the tree as it is
+        let (init_key, new_identity_key) = test_utils::random_user_init_key(&mut rng, cs);
+        let new_member = GroupState::from_welcome_info(
+            cs,
+            group_state1.as_welcome_info(),
+            new_identity_key.clone(),
+            init_key.clone(),
+        )
+        .unwrap();
+        assert_serialized_eq!(group_state1, new_member, "GroupStates disagree after a Welcome");
+
+        // A new member rejects a tree whose root was swapped out
+        let num_leaves = tree_math::num_leaves_in_tree(group_state1.tree.size());
+        let bogus_public_key = {
+            let private_key = DhPrivateKey::new_from_random(cs.dh_impl, &mut rng).unwrap();
+            DhPublicKey::new_from_private_key(cs.dh_impl, &private_key)
+        };
+        let mut welcome_info = group_state1.as_welcome_info();
+        welcome_info
+            .tree
+            .get_mut(tree_math::root_idx(num_leaves))
+            .unwrap()
+            .update_public_key(bogus_public_key);
+        assert!(GroupState::from_welcome_info(
+            cs,
+            welcome_info,
+            new_identity_key.clone(),
+            init_key.clone()
+        )
+        .is_err());
+
+        // A new member rejects a leaf that was signed by someone other than its owner
+        let my_tree_idx = 2 * group_state1.roster_index.unwrap() as usize;
+        let mut welcome_info = group_state1.as_welcome_info();
+        let forged_signature = {
+            let leaf = &welcome_info.tree.get(my_tree_idx).unwrap();
+            let content = ratchet_tree::leaf_signature_content(
+                leaf.get_public_key().unwrap(),
+                leaf.get_parent_hash().unwrap(),
+            )
+            .unwrap();
+            group_state1.get_signature_scheme().sign(&identity_keys[other_index as usize], &content)
+        };
+        welcome_info.extras.tree_parent_hashes[my_tree_idx].signature = Some(forged_signature);
+        assert!(GroupState::from_welcome_info(
+            cs,
+            welcome_info,
+            new_identity_key.clone(),
+            init_key.clone()
+        )
+        .is_err());
+
+        // A new member rejects a leaf that nobody signed
+        let mut welcome_info = group_state1.as_welcome_info();
+        welcome_info.extras.tree_parent_hashes[my_tree_idx].signature = None;
+        assert!(GroupState::from_welcome_info(
+            cs,
+            welcome_info,
+            new_identity_key.clone(),
+            init_key.clone()
+        )
+        .is_err());
+
+        // A freshly added leaf is vouched for by its UserInitKey. A new member accepts it, but not
+        // once its key is swapped out.
+        let (added_init_key, _) = test_utils::random_user_init_key(&mut rng, cs);
+        let (_, welcome_info_hash) =
+            Welcome::from_group_state(&group_state1, &added_init_key, &mut rng).unwrap();
+        let added_roster_idx = group_state1.roster.len() as u32;
+        let (_, added_group_state, _) = group_state1
+            .create_and_apply_add_handshake(added_roster_idx, added_init_key, &welcome_info_hash)
+            .unwrap();
+        GroupState::from_welcome_info(
+            cs,
+            added_group_state.as_welcome_info(),
+            new_identity_key.clone(),
+            init_key.clone(),
+        )
+        .unwrap();
+        let bogus_public_key = {
+            let private_key = DhPrivateKey::new_from_random(cs.dh_impl, &mut rng).unwrap();
+            DhPublicKey::new_from_private_key(cs.dh_impl, &private_key)
+        };
+        let mut welcome_info = added_group_state.as_welcome_info();
+        let added_tree_idx = 2 * added_roster_idx as usize;
+        assert!(welcome_info.extras.tree_parent_hashes[added_tree_idx].user_init_key.is_some());
+        welcome_info.tree.get_mut(added_tree_idx).unwrap().update_public_key(bogus_public_key);
+        assert!(
+            GroupState::from_welcome_info(cs, welcome_info, new_identity_key, init_key).is_err()
+        );
+    }
+
+    // Checks that exported secrets agree between members, are separated by label and context, and
+    // change every epoch
+    #[quickcheck]
+    fn export_secret_correctness(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let (group_state1, identity_keys) = test_utils::random_full_group_state(2, &mut rng);
+        let other_index = test_utils::random_roster_index_with_exceptions(
+            group_state1.roster.len(),
+            &[group_state1.roster_index.unwrap() as usize],
+            &mut rng,
+        );
+        let group_state2 =
+            test_utils::change_self_index(&group_state1, &identity_keys, other_index);
+
+        // Nobody has an exporter secret until the epoch changes
+        assert!(group_state1.export_secret(b"label", b"context", 32).is_err());
+
+        let new_path_secret = PathSecret::new_from_random(group_state1.cs, &mut rng);
+        let (handshake, group_state1, _) =
+            group_state1.create_and_apply_update_handshake(new_path_secret, &mut rng).unwrap();
+        let (group_state2, _) = group_state2.process_handshake(&handshake).unwrap();
+
+        // Both members agree
+        let secret1 = group_state1.export_secret(b"label", b"context", 32).unwrap();
+        let secret2 = group_state2.export_secret(b"label", b"context", 32).unwrap();
+        assert_eq!(secret1.len(), 32);
+        assert_eq!(secret1, secret2);
+
+        // Different labels and contexts give different secrets
+        assert_ne!(secret1, group_state1.export_secret(b"other label", b"context", 32).unwrap());
+        assert_ne!(secret1, group_state1.export_secret(b"label", b"other context", 32).unwrap());
+
+        // The next epoch gives a different secret
+        let new_path_secret = PathSecret::new_from_random(group_state1.cs, &mut rng);
+        let (_, group_state1, _) =
+            group_state1.create_and_apply_update_handshake(new_path_secret, &mut rng).unwrap();
+        assert_ne!(secret1, group_state1.export_secret(b"label", b"context", 32).unwrap());
+
+        // Oversized requests are rejected rather than panicking
+        assert!(group_state1.export_secret(&[0u8; 250], b"context", 32).is_err());
+        let max_len = 255 * group_state1.cs.hash_impl.digest_size();
+        assert!(group_state1.export_secret(b"label", b"context", max_len + 1).is_err());
+    }
+
+    // Checks that the selected members of a group agree on a branch of it, and nobody else can
+    // join it
+    #[quickcheck]
+    fn branch_correctness(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let (group_state, identity_keys) = test_utils::random_full_group_state(3, &mut rng);
+        let roster_size = group_state.roster.len();
+
+        // Pick the branch creator, someone who's definitely in the branch, and someone who
+        // definitely isn't. Everyone else is a coin flip.
+        let creator_idx = group_state.roster_index.unwrap();
+        let other_idx = test_utils::random_roster_index_with_exceptions(
+            roster_size,
+            &[creator_idx as usize],
+            &mut rng,
+        );
+        let excluded_idx = test_utils::random_roster_index_with_exceptions(
+            roster_size,
+            &[creator_idx as usize, other_idx as usize],
+            &mut rng,
+        );
+        let members: Vec<u32> = (0..roster_size as u32)
+            .filter(|idx| {
+                *idx == creator_idx
+                    || *idx == other_idx
+                    || (*idx != excluded_idx && rand::Rng::gen(&mut rng))
+            })
+            .collect();
+
+        // Branches are keyed from the resumption secret, so we have to go through an epoch change
+        // first
+        let new_path_secret = PathSecret::new_from_random(group_state.cs, &mut rng);
+        let (update_handshake, creator, _) =
+            group_state.create_and_apply_update_handshake(new_path_secret, &mut rng).unwrap();
+        let parent_state = |idx: u32| {
+            let member = test_utils::change_self_index(&group_state, &identity_keys, idx);
+            member.process_handshake(&update_handshake).unwrap().0
+        };
+
+        // Branches with unsorted members or without their creator are rejected
+        let mut unsorted_members = members.clone();
+        unsorted_members.reverse();
+        let new_path_secret = PathSecret::new_from_random(group_state.cs, &mut rng);
+        let res = creator.branch(&unsorted_members, b"branch".to_vec(), new_path_secret, &mut rng);
+        assert!(res.is_err(), "Branch with unsorted members was made");
+        let new_path_secret = PathSecret::new_from_random(group_state.cs, &mut rng);
+        let res = creator.branch(&[other_idx], b"branch".to_vec(), new_path_secret, &mut rng);
+        assert!(res.is_err(), "Branch without its creator was made");
+
+        let new_path_secret = PathSecret::new_from_random(group_state.cs, &mut rng);
+        let (branch, creator_branch, _) =
+            creator.branch(&members, b"branch".to_vec(), new_path_secret, &mut rng).unwrap();
+        assert_eq!(creator_branch.roster.len(), members.len());
+
+        // Every selected member ends up in the same place as the creator
+        for idx in members.iter().filter(|idx| **idx != creator_idx) {
+            let (member_branch, _) = parent_state(*idx).from_branch(&branch).unwrap();
+            assert_serialized_eq!(creator_branch, member_branch, "GroupStates disagree on branch");
+        }
+
+        // Someone who wasn't selected can't join, and neither can a selected member who's in the
+        // wrong epoch
+        assert!(parent_state(excluded_idx).from_branch(&branch).is_err());
+        let stale_member = test_utils::change_self_index(&group_state, &identity_keys, other_idx);
+        assert!(stale_member.from_branch(&branch).is_err());
+    }
+
+    // This is all the serializable bits of a GroupState. We have this separate because the wire
+    // form of a GroupState omits all of its secrets, so it can't be restored with
+    // GroupState::from_snapshot. The test vectors only contain the wire form, so I'll just
+    // initialize all the missing things to 0 myself. See group_from_test_group.
     #[derive(Debug, Deserialize)]
     pub(crate) struct TestGroupState {
         #[serde(rename = "group_id__bound_u8")]
@@ -1389,10 +5490,23 @@ mod test {
             group_id: tgs.group_id,
             epoch: tgs.epoch,
             roster: tgs.roster,
+            tree_hash: tgs.tree.tree_hash(cs).unwrap(),
             tree: tgs.tree,
             transcript_hash: tgs.transcript_hash,
+            extensions: Vec::new(),
             roster_index: Some(0),
             initializing_user_init_key: None,
+            last_resort_public_key: None,
+            pending_update_private_key: None,
+            membership_status: MembershipStatus::Member,
+            pending_handshakes: Vec::new(),
+            fork_point: None,
+            reinit: None,
+            exporter_secret: None,
+            resumption_secret: None,
+            sender_data_secret: None,
+            handshake_secret: None,
+            welcome_seal_secret: None,
             init_secret: HmacKey::new_from_zeros(cs.hash_impl),
         }
     }
@@ -1483,7 +5597,9 @@ mod test {
         // resulting keys against the test vector.
         for epoch in case1.epochs.into_iter() {
             let update_secret = UpdateSecret(epoch.update_secret);
-            let (app_secret, conf_key) = group_state.update_epoch_secrets(&update_secret).unwrap();
+            // The spec's key schedule doesn't mix in the tree hash
+            let (app_secret, conf_key) =
+                group_state.derive_epoch_secrets(&update_secret, None, None).unwrap();
 
             // Wrap all the inputs in HmacKeys so we can compare them to other HmacKeys
             let epoch_application_secret = HmacKey::new_from_bytes(&epoch.application_secret);
@@ -1,4 +1,10 @@
 //! Defines group handshake-related data structures and operations. Not much public API here.
+//!
+//! Some of what's here has no counterpart in the spec: `GroupInit` (see the open issue in section
+//! 8.1), `GroupMultiAdd`, `GroupExternalJoin`, `GroupExtensions`, `GroupReInit`,
+//! `SealedOperation`, `OperationExtras`, direct path signatures, and the `UserInitKey` extensions
+//! and their code points. These are our own constructions. Anything the spec does define is still
+//! encoded the way it says.
 
 use crate::{
     credential::Credential,
@@ -11,10 +17,15 @@ use crate::{
         sig::{SigSecretKey, Signature},
     },
     error::Error,
+    extension::{self, Extension, ExtensionType},
     group_state::WelcomeInfoHash,
+    ratchet_tree,
+    tls_de::TlsDeserializer,
     tls_ser,
 };
 
+use serde::de::Deserialize;
+
 /// Represents a version of the MLS protocol
 // uint8 ProtocolVersion;
 #[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
@@ -26,7 +37,7 @@ pub const MLS_DUMMY_VERSION: ProtocolVersion = ProtocolVersion(0xba);
 
 /// Contains a node's new public key and the new node's secret, encrypted for everyone in that
 /// node's resolution
-#[derive(Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize)]
 #[cfg_attr(test, derive(Debug))]
 pub(crate) struct DirectPathNodeMessage {
     pub(crate) public_key: DhPublicKey,
@@ -37,12 +48,133 @@ pub(crate) struct DirectPathNodeMessage {
 
 /// Contains a direct path of node messages. The length of `node_secrets` for the first
 /// `DirectPathNodeMessage` MUST be zero.
-#[derive(Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize)]
 #[cfg_attr(test, derive(Debug))]
 pub(crate) struct DirectPathMessage {
     // DirectPathNodeMessage nodes<0..2^16-1>;
     #[serde(rename = "node_messages__bound_u16")]
     pub(crate) node_messages: Vec<DirectPathNodeMessage>,
+
+    /// The path creator's signature over the path's leaf public key and that leaf's parent hash,
+    /// which transitively covers every public key on the path. It's carried in the
+    /// `OperationExtras` of the operation the path belongs to. It is checked
+    /// wherever the path's parent hashes are kept, i.e., everywhere but in a Remove, whose path is
+    /// blanked right after it's applied.
+    #[serde(skip)]
+    pub(crate) signature: Option<Signature>,
+}
+
+impl DirectPathMessage {
+    /// Computes the parent hashes of the nodes on this path and checks that the path's creator
+    /// signed the leaf and its parent hash
+    ///
+    /// Returns: `Ok(parent_hashes)` on success, where `parent_hashes` is in the same order as
+    /// `node_messages`. Returns an `Error::SignatureError` if the signature doesn't verify under
+    /// `signer_credential`, and an `Error::ValidationError` if the path is unsigned.
+    pub(crate) fn verify_parent_hashes(
+        &self,
+        cs: &'static CipherSuite,
+        signer_credential: &Credential,
+    ) -> Result<Vec<Vec<u8>>, Error> {
+        let leaf_public_key = match self.node_messages.first() {
+            Some(node_msg) => &node_msg.public_key,
+            None => return Err(Error::ValidationError("Direct path is empty")),
+        };
+        let parent_hashes = ratchet_tree::compute_parent_hashes(
+            cs,
+            self.node_messages.iter().map(|node_msg| &node_msg.public_key),
+        )?;
+        let signature =
+            self.signature.as_ref().ok_or(Error::ValidationError("Direct path is unsigned"))?;
+        let signature_content =
+            ratchet_tree::leaf_signature_content(leaf_public_key, &parent_hashes[0])?;
+        signer_credential.get_signature_scheme().verify(
+            signer_credential.get_public_key(),
+            &signature_content,
+            signature,
+        )?;
+
+        Ok(parent_hashes)
+    }
+}
+
+/// The type of the `UserInitKey` extension that carries its `Lifetime`. A `UserInitKey` without one
+/// can be used at any time.
+pub const LIFETIME_EXTENSION_TYPE: ExtensionType = ExtensionType(0xff01);
+
+/// The type of the `UserInitKey` extension that marks it as last-resort. Its data is empty. A
+/// `UserInitKey` without one is single-use.
+pub const LAST_RESORT_EXTENSION_TYPE: ExtensionType = ExtensionType(0xff02);
+
+/// The period of time in which a `UserInitKey` can be used to add its creator to a group. Times are
+/// in seconds since the Unix epoch, and both ends are inclusive. This is modeled after the lifetime
+/// extension of later drafts. It's carried in the `UserInitKey` extension of type
+/// `LIFETIME_EXTENSION_TYPE`.
+// struct {
+//     uint64 not_before;
+//     uint64 not_after;
+// } Lifetime;
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Lifetime {
+    /// The first moment the `UserInitKey` is valid
+    pub(crate) not_before: u64,
+    /// The last moment the `UserInitKey` is valid
+    pub(crate) not_after: u64,
+}
+
+impl Lifetime {
+    /// Makes a `Lifetime` that starts at `not_before` and ends at `not_after`, inclusive
+    pub fn new(not_before: u64, not_after: u64) -> Lifetime {
+        Lifetime {
+            not_before,
+            not_after,
+        }
+    }
+
+    /// Returns the first moment of this `Lifetime`
+    pub fn get_not_before(&self) -> u64 {
+        self.not_before
+    }
+
+    /// Returns the last moment of this `Lifetime`
+    pub fn get_not_after(&self) -> u64 {
+        self.not_after
+    }
+
+    /// Returns whether this `Lifetime` covers all time, i.e., whether it's the lifetime of a
+    /// `UserInitKey` that has no lifetime extension
+    fn is_unbounded(&self) -> bool {
+        self.not_before == 0 && self.not_after == u64::MAX
+    }
+
+    /// Decodes the data of a lifetime extension
+    ///
+    /// Returns: `Ok(lifetime)` on success. Returns an `Error::ValidationError` if the data isn't
+    /// exactly one `Lifetime`.
+    fn from_extension_data(extension_data: &[u8]) -> Result<Lifetime, Error> {
+        let mut cursor = extension_data;
+        let mut deserializer = TlsDeserializer::from_reader(&mut cursor);
+        let lifetime = Lifetime::deserialize(&mut deserializer)
+            .map_err(|_| Error::ValidationError("UserInitKey's lifetime extension is malformed"))?;
+        if !cursor.is_empty() {
+            return Err(Error::ValidationError("UserInitKey's lifetime extension is malformed"));
+        }
+
+        Ok(lifetime)
+    }
+}
+
+/// Says how many times a `UserInitKey` may be used to add its creator to a group. This is not in
+/// draft 4. It's modeled after the last-resort extension of later drafts, and a last-resort
+/// `UserInitKey` carries an extension of type `LAST_RESORT_EXTENSION_TYPE`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum UserInitKeyUsage {
+    /// The `UserInitKey` is used once and then thrown away
+    SingleUse,
+    /// The `UserInitKey` is kept around for when all the single-use ones have run out, so it may
+    /// be used any number of times. Since its private key ends up in every group it's used to
+    /// join, a member who joins with it should send an `Update` soon after.
+    LastResort,
 }
 
 /// This is used in lieu of negotiating public keys when a member is added. This has a bunch of
@@ -88,10 +220,23 @@ pub struct UserInitKey {
     /// Contains the signature of all the other fields of this struct, under the identity key of
     /// the client.
     pub(crate) signature: Signature,
+
+    // Extension extensions<0..2^16-1>; // Left out if empty
+    /// Says when this `UserInitKey` can be used and whether it's last-resort. See
+    /// `LIFETIME_EXTENSION_TYPE` and `LAST_RESORT_EXTENSION_TYPE`. This goes last and is left out
+    /// when empty, so that other `UserInitKey`s are encoded the way draft 4 says. It's covered by
+    /// `signature` all the same.
+    #[serde(
+        rename = "extensions__trailing__bound_u16",
+        default,
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub(crate) extensions: Vec<Extension>,
 }
 
-// This struct is everything but the last field in UserInitKey. We use the serialized form
-// of this as the message that the signature is computed over
+// This struct is every field in UserInitKey but the signature. We use the serialized form of this
+// as the message that the signature is computed over. The extensions are left out when empty, so a
+// UserInitKey without any is signed exactly as the spec says.
 #[derive(Serialize)]
 struct PartialUserInitKey<'a> {
     #[serde(rename = "user_init_key_id__bound_u8")]
@@ -103,22 +248,142 @@ struct PartialUserInitKey<'a> {
     #[serde(rename = "init_keys__bound_u16")]
     init_keys: &'a [DhPublicKey],
     credential: &'a Credential,
+    #[serde(rename = "extensions__bound_u16", skip_serializing_if = "Vec::is_empty")]
+    extensions: &'a Vec<Extension>,
 }
 
-impl UserInitKey {
-    /// Generates a new `UserInitKey` with the key ID, credential, ciphersuites, and supported
-    /// versions. The identity key is needed to sign the resulting structure.
-    pub fn new_from_random<R>(
-        identity_key: &SigSecretKey,
+/// (De)serializes a list of `UserInitKey`s, each one prefixed with its length as a `uint32`. A
+/// `UserInitKey` may end in extensions that are left out when empty, so our own operations that
+/// carry several of them use this to tell where each one ends.
+pub(crate) mod bounded_user_init_keys {
+    use super::UserInitKey;
+
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Serialize)]
+    #[serde(rename = "UserInitKey__bound_u32")]
+    struct BoundedRef<'a>(&'a UserInitKey);
+
+    #[derive(Deserialize)]
+    #[serde(rename = "UserInitKey__bound_u32")]
+    struct Bounded(UserInitKey);
+
+    pub(crate) fn serialize<S: Serializer>(
+        init_keys: &[UserInitKey],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(init_keys.iter().map(BoundedRef))
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<UserInitKey>, D::Error> {
+        let init_keys = Vec::<Bounded>::deserialize(deserializer)?;
+        Ok(init_keys.into_iter().map(|Bounded(init_key)| init_key).collect())
+    }
+}
+
+/// A `UserInitKey` without its extensions, i.e., exactly as the spec encodes it. A `UserInitKey`
+/// that isn't length-prefixed and has other data after it is read as this, since there's no telling
+/// where its extensions would end. Its extensions, if it has any, are carried elsewhere.
+#[derive(Deserialize)]
+struct SpecUserInitKey {
+    #[serde(rename = "user_init_key_id__bound_u8")]
+    user_init_key_id: Vec<u8>,
+    #[serde(rename = "supported_versions__bound_u8")]
+    supported_versions: Vec<ProtocolVersion>,
+    #[serde(rename = "cipher_suites__bound_u8")]
+    cipher_suites: Vec<&'static CipherSuite>,
+    #[serde(rename = "init_keys__bound_u16")]
+    init_keys: Vec<DhPublicKey>,
+    credential: Credential,
+    signature: Signature,
+}
+
+/// Deserializes a `SpecUserInitKey` into a `UserInitKey` with no extensions
+fn deserialize_spec_user_init_key<'de, D>(deserializer: D) -> Result<UserInitKey, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let uik = SpecUserInitKey::deserialize(deserializer)?;
+    Ok(UserInitKey {
+        user_init_key_id: uik.user_init_key_id,
+        supported_versions: uik.supported_versions,
+        cipher_suites: uik.cipher_suites,
+        init_keys: uik.init_keys,
+        private_keys: None,
+        credential: uik.credential,
+        signature: uik.signature,
+        extensions: Vec::new(),
+    })
+}
+
+/// Makes a `UserInitKey` whose lifetime or usage differs from the defaults of
+/// `UserInitKey::new_from_random`, which makes single-use `UserInitKey`s that can be used at any
+/// time
+pub struct UserInitKeyBuilder {
+    user_init_key_id: Vec<u8>,
+    credential: Credential,
+    cipher_suites: Vec<&'static CipherSuite>,
+    supported_versions: Vec<ProtocolVersion>,
+    lifetime: Lifetime,
+    usage: UserInitKeyUsage,
+}
+
+impl UserInitKeyBuilder {
+    /// Starts a `UserInitKey` with the key ID, credential, ciphersuites, and supported versions.
+    /// Unless set otherwise, it is single-use and can be used at any time.
+    pub fn new(
         user_init_key_id: Vec<u8>,
         credential: Credential,
-        mut cipher_suites: Vec<&'static CipherSuite>,
+        cipher_suites: Vec<&'static CipherSuite>,
         supported_versions: Vec<ProtocolVersion>,
-        csprng: &mut R,
-    ) -> Result<UserInitKey, Error>
+    ) -> UserInitKeyBuilder {
+        UserInitKeyBuilder {
+            user_init_key_id,
+            credential,
+            cipher_suites,
+            supported_versions,
+            lifetime: Lifetime::new(0, u64::MAX),
+            usage: UserInitKeyUsage::SingleUse,
+        }
+    }
+
+    /// Sets the period of time in which the `UserInitKey` can be used
+    pub fn with_lifetime(self, lifetime: Lifetime) -> UserInitKeyBuilder {
+        UserInitKeyBuilder {
+            lifetime,
+            ..self
+        }
+    }
+
+    /// Sets whether the `UserInitKey` is single-use or last-resort
+    pub fn with_usage(self, usage: UserInitKeyUsage) -> UserInitKeyBuilder {
+        UserInitKeyBuilder {
+            usage,
+            ..self
+        }
+    }
+
+    /// Generates the `UserInitKey`'s init keys and signs it. The identity key must be the one
+    /// belonging to the credential.
+    ///
+    /// Returns: `Ok(init_key)` on success. Returns an `Error::ValidationError` if the ciphersuites
+    /// repeat or the ciphersuites and supported versions differ in length. Otherwise, returns some
+    /// other sort of `Error`.
+    pub fn build<R>(self, identity_key: &SigSecretKey, csprng: &mut R) -> Result<UserInitKey, Error>
     where
         R: CryptoRng,
     {
+        let UserInitKeyBuilder {
+            user_init_key_id,
+            credential,
+            mut cipher_suites,
+            supported_versions,
+            lifetime,
+            usage,
+        } = self;
+
         // Check the ciphersuite list for duplicates. We don't like this
         let old_cipher_suite_len = cipher_suites.len();
         cipher_suites.dedup();
@@ -148,6 +413,17 @@ impl UserInitKey {
         // The UserInitKey has this as an Option
         let private_keys = Some(private_keys);
 
+        // The defaults go without saying, so that a UserInitKey that uses them is exactly what the
+        // spec says it is
+        let mut extensions = Vec::new();
+        if !lifetime.is_unbounded() {
+            let extension_data = tls_ser::serialize_to_bytes(&lifetime)?;
+            extensions.push(Extension::new(LIFETIME_EXTENSION_TYPE, extension_data));
+        }
+        if usage == UserInitKeyUsage::LastResort {
+            extensions.push(Extension::new(LAST_RESORT_EXTENSION_TYPE, Vec::new()));
+        }
+
         // Now to compute the signature: Make the partial structure, serialize it, sign that
         let partial = PartialUserInitKey {
             user_init_key_id: user_init_key_id.as_slice(),
@@ -155,6 +431,7 @@ impl UserInitKey {
             cipher_suites: cipher_suites.as_slice(),
             init_keys: init_keys.as_slice(),
             credential: &credential,
+            extensions: &extensions,
         };
 
         let serialized_uik = tls_ser::serialize_to_bytes(&partial)?;
@@ -169,8 +446,49 @@ impl UserInitKey {
             private_keys,
             credential,
             signature,
+            extensions,
         })
     }
+}
+
+impl UserInitKey {
+    /// Generates a new single-use `UserInitKey` with the key ID, credential, ciphersuites, and
+    /// supported versions, which can be used at any time. The identity key is needed to sign the
+    /// resulting structure. To set a lifetime, use a `UserInitKeyBuilder`.
+    pub fn new_from_random<R>(
+        identity_key: &SigSecretKey,
+        user_init_key_id: Vec<u8>,
+        credential: Credential,
+        cipher_suites: Vec<&'static CipherSuite>,
+        supported_versions: Vec<ProtocolVersion>,
+        csprng: &mut R,
+    ) -> Result<UserInitKey, Error>
+    where
+        R: CryptoRng,
+    {
+        UserInitKeyBuilder::new(user_init_key_id, credential, cipher_suites, supported_versions)
+            .build(identity_key, csprng)
+    }
+
+    /// Generates a new last-resort `UserInitKey` with the given lifetime. This is the same as
+    /// `new_from_random`, except that the result may be used to join any number of groups.
+    pub fn new_last_resort_from_random<R>(
+        identity_key: &SigSecretKey,
+        user_init_key_id: Vec<u8>,
+        credential: Credential,
+        cipher_suites: Vec<&'static CipherSuite>,
+        supported_versions: Vec<ProtocolVersion>,
+        lifetime: Lifetime,
+        csprng: &mut R,
+    ) -> Result<UserInitKey, Error>
+    where
+        R: CryptoRng,
+    {
+        UserInitKeyBuilder::new(user_init_key_id, credential, cipher_suites, supported_versions)
+            .with_lifetime(lifetime)
+            .with_usage(UserInitKeyUsage::LastResort)
+            .build(identity_key, csprng)
+    }
 
     /// Verifies this `UserInitKey` under the identity key specified in the `credential` field
     ///
@@ -184,6 +502,7 @@ impl UserInitKey {
             cipher_suites: self.cipher_suites.as_slice(),
             init_keys: self.init_keys.as_slice(),
             credential: &self.credential,
+            extensions: &self.extensions,
         };
         let serialized_uik = tls_ser::serialize_to_bytes(&partial)?;
 
@@ -193,10 +512,69 @@ impl UserInitKey {
         sig_scheme.verify(public_key, &serialized_uik, &self.signature)
     }
 
-    // TODO: URGENT: Figure out how to implement the mandatory check specified in section 6:
-    // "UserInitKeys also contain an identifier chosen by the client, which the client MUST assure
-    // uniquely identifies a given UserInitKey object among the set of UserInitKeys created by this
-    // client."
+    // Section 6 says: "UserInitKeys also contain an identifier chosen by the client, which the
+    // client MUST assure uniquely identifies a given UserInitKey object among the set of
+    // UserInitKeys created by this client." Nothing here can check that. Clients that don't want to
+    // keep track of their IDs themselves should use a UserInitKeyStore.
+
+    /// Returns the ID of this `UserInitKey`
+    pub fn get_user_init_key_id(&self) -> &[u8] {
+        self.user_init_key_id.as_slice()
+    }
+
+    /// Returns whether this `UserInitKey` is single-use or last-resort
+    pub fn get_usage(&self) -> UserInitKeyUsage {
+        if self.get_extension(LAST_RESORT_EXTENSION_TYPE).is_some() {
+            UserInitKeyUsage::LastResort
+        } else {
+            UserInitKeyUsage::SingleUse
+        }
+    }
+
+    /// Returns whether this is a last-resort `UserInitKey`, i.e., whether a member who joins
+    /// with it should send an `Update` soon after
+    pub fn is_last_resort(&self) -> bool {
+        self.get_usage() == UserInitKeyUsage::LastResort
+    }
+
+    /// Returns the period of time in which this `UserInitKey` can be used. A `UserInitKey` whose
+    /// lifetime extension is malformed is never valid, so its lifetime is empty.
+    pub fn get_lifetime(&self) -> Lifetime {
+        self.decode_lifetime().unwrap_or(Lifetime::new(u64::MAX, 0))
+    }
+
+    /// Returns the extension of the given type, if this `UserInitKey` has one
+    fn get_extension(&self, extension_type: ExtensionType) -> Option<&Extension> {
+        self.extensions.iter().find(|ext| ext.extension_type == extension_type)
+    }
+
+    /// Returns the period of time in which this `UserInitKey` can be used. Without a lifetime
+    /// extension, this is all time.
+    ///
+    /// Returns: `Ok(lifetime)` on success. Returns an `Error::ValidationError` if the lifetime
+    /// extension is malformed.
+    fn decode_lifetime(&self) -> Result<Lifetime, Error> {
+        match self.get_extension(LIFETIME_EXTENSION_TYPE) {
+            Some(ext) => Lifetime::from_extension_data(&ext.extension_data),
+            None => Ok(Lifetime::new(0, u64::MAX)),
+        }
+    }
+
+    /// Checks that the time `now`, in seconds since the Unix epoch, is within this
+    /// `UserInitKey`'s lifetime. This is separate from `validate`, since a `UserInitKey` only has
+    /// to be current when someone is added with it.
+    ///
+    /// Returns: `Ok(())` on success. Otherwise, returns an `Error::ValidationError`.
+    pub(crate) fn validate_lifetime(&self, now: u64) -> Result<(), Error> {
+        let lifetime = self.decode_lifetime()?;
+        if now < lifetime.not_before {
+            Err(Error::ValidationError("UserInitKey is not valid yet"))
+        } else if now > lifetime.not_after {
+            Err(Error::ValidationError("UserInitKey has expired"))
+        } else {
+            Ok(())
+        }
+    }
 
     /// Validates the invariants that `UserInitKey` must satisfy, as in section 7 of the MLS spec
     #[must_use]
@@ -221,6 +599,18 @@ impl UserInitKey {
             }
         }
 
+        // The extensions must be unique and well-formed. A lifetime that ends before it starts is
+        // never valid.
+        extension::validate_extensions(&self.extensions)?;
+        let lifetime = self.decode_lifetime()?;
+        if lifetime.not_before > lifetime.not_after {
+            return Err(Error::ValidationError("UserInitKey's lifetime ends before it starts"));
+        }
+        let last_resort_ext = self.get_extension(LAST_RESORT_EXTENSION_TYPE);
+        if matches!(last_resort_ext, Some(ext) if !ext.extension_data.is_empty()) {
+            return Err(Error::ValidationError("UserInitKey's last-resort extension is malformed"));
+        }
+
         // The elements of cipher_suites MUST be unique. Sort them, dedup them, and see if the
         // number has decreased.
         let mut cipher_suites = self.cipher_suites.clone();
@@ -331,13 +721,28 @@ impl UserInitKey {
     }
 }
 
-/// This is currently not defined by the spec. See open issue in section 8.1
-#[derive(Deserialize, Serialize)]
+/// Operation to populate a freshly created group with many members at once. It is equivalent to a
+/// sequence of appending `Add`s followed by an `Update` from the signer, all in a single epoch.
+#[derive(Clone, Deserialize, Serialize)]
 #[cfg_attr(test, derive(Debug))]
-pub(crate) struct GroupInit;
+pub(crate) struct GroupInit {
+    // opaque init_keys<0..2^32-1>; // Each a UserInitKey prefixed with its uint32 length
+    /// Contains the public keys used to add the new members. The new members are appended to the
+    /// roster in the order they appear here.
+    #[serde(rename = "init_keys__bound_u32", with = "bounded_user_init_keys")]
+    pub(crate) init_keys: Vec<UserInitKey>,
+
+    // opaque welcome_info_hash<0..255>;
+    /// Contains the hash of the `WelcomeInfo` object that preceded this `Init`
+    pub(crate) welcome_info_hash: WelcomeInfoHash,
+
+    // DirectPath path;
+    /// New entropy for the tree, encrypted to every new member
+    pub(crate) path: DirectPathMessage,
+}
 
 /// Operation to add a partcipant to a group
-#[derive(Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize)]
 #[cfg_attr(test, derive(Debug))]
 pub(crate) struct GroupAdd {
     // uint32 index;
@@ -346,7 +751,9 @@ pub(crate) struct GroupAdd {
     pub(crate) roster_index: u32,
 
     // UserInitKey init_key;
-    /// Contains the public key used to add the new member
+    /// Contains the public key used to add the new member. Its extensions are carried in the
+    /// `OperationExtras` of the `Add`, so that this is encoded as the spec says.
+    #[serde(deserialize_with = "deserialize_spec_user_init_key")]
     pub(crate) init_key: UserInitKey,
 
     // opaque welcome_info_hash<0..255>;
@@ -354,15 +761,98 @@ pub(crate) struct GroupAdd {
     pub(crate) welcome_info_hash: WelcomeInfoHash,
 }
 
+/// Operation to add many participants to a group at once. It is equivalent to a sequence of `Add`s
+/// in a single epoch, where each new member goes in the leftmost empty roster entry, or at the end
+/// if there is none.
+#[derive(Clone, Deserialize, Serialize)]
+#[cfg_attr(test, derive(Debug))]
+pub(crate) struct GroupMultiAdd {
+    // opaque init_keys<1..2^32-1>; // Each a UserInitKey prefixed with its uint32 length
+    /// Contains the public keys used to add the new members, in the order they are added
+    #[serde(rename = "init_keys__bound_u32", with = "bounded_user_init_keys")]
+    pub(crate) init_keys: Vec<UserInitKey>,
+
+    // opaque welcome_info_hash<0..255>;
+    /// Contains the hash of the `WelcomeInfo` object that preceded this `MultiAdd`
+    pub(crate) welcome_info_hash: WelcomeInfoHash,
+}
+
+/// Operation by which a new member adds themselves to a group, using the group's
+/// `PublicGroupInfo` rather than a `Welcome`. The joiner goes in the leftmost empty roster entry,
+/// or at the end if there is none, and the `signer_index` of the `Handshake` containing this is
+/// that roster index.
+#[derive(Clone, Deserialize, Serialize)]
+#[cfg_attr(test, derive(Debug))]
+pub(crate) struct GroupExternalJoin {
+    // opaque init_key<0..2^32-1>; // A UserInitKey
+    /// Contains the joiner's credential and the public key of their new leaf. This is
+    /// length-prefixed, since a `UserInitKey` may end in extensions the spec has no room for.
+    #[serde(rename = "init_key__bound_u32")]
+    pub(crate) init_key: UserInitKey,
+
+    /// A fresh init secret, encrypted under the group's external public key. The joiner doesn't
+    /// know the group's current init secret, so this takes its place in the key schedule.
+    pub(crate) encrypted_init_secret: EciesCiphertext,
+
+    // DirectPath path;
+    /// New entropy for the tree, starting at the joiner's leaf
+    pub(crate) path: DirectPathMessage,
+}
+
+/// Operation to replace the group's extensions
+#[derive(Clone, Deserialize, Serialize)]
+#[cfg_attr(test, derive(Debug))]
+pub(crate) struct GroupExtensions {
+    // Extension extensions<0..2^16-1>;
+    /// The group's new extensions. These replace all the old ones.
+    #[serde(rename = "extensions__bound_u16")]
+    pub(crate) extensions: Vec<Extension>,
+}
+
+/// A `GroupOperation` that's encrypted under its signer's handshake key for the epoch it was made
+/// in, so that only members of the group, and new members it adds, can read it. See
+/// `GroupState::seal_handshake`.
+#[derive(Clone, Deserialize, Serialize)]
+#[cfg_attr(test, derive(Debug))]
+pub(crate) struct SealedOperation {
+    // opaque nonce<0..255>;
+    /// The random nonce the operation was encrypted with
+    #[serde(rename = "nonce__bound_u8")]
+    pub(crate) nonce: Vec<u8>,
+
+    // opaque ciphertext<0..2^32-1>;
+    /// The encrypted `GroupOperation`
+    #[serde(rename = "ciphertext__bound_u32")]
+    pub(crate) ciphertext: Vec<u8>,
+}
+
+/// Operation to end the group and announce its successor, which has a new group ID, ciphersuite
+/// and protocol version. No further `Handshake`s are accepted after this. The successor is made by
+/// one of the members, and its key schedule is linked to the final epoch of this group.
+#[derive(Clone, Deserialize, Serialize)]
+#[cfg_attr(test, derive(Debug))]
+pub(crate) struct GroupReInit {
+    // opaque group_id<0..255>;
+    /// The group ID of the successor group
+    #[serde(rename = "group_id__bound_u8")]
+    pub(crate) group_id: Vec<u8>,
+
+    /// The protocol version of the successor group
+    pub(crate) protocol_version: ProtocolVersion,
+
+    /// The ciphersuite of the successor group
+    pub(crate) cipher_suite: &'static CipherSuite,
+}
+
 /// Operation to add entropy to the group
-#[derive(Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize)]
 #[cfg_attr(test, derive(Debug))]
 pub(crate) struct GroupUpdate {
     pub(crate) path: DirectPathMessage,
 }
 
 /// Operation to remove a partcipant from the group
-#[derive(Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize)]
 #[cfg_attr(test, derive(Debug))]
 pub(crate) struct GroupRemove {
     /// The roster index of the removed member
@@ -372,8 +862,190 @@ pub(crate) struct GroupRemove {
     pub(crate) path: DirectPathMessage,
 }
 
+/// A proposal to add a new member to the group. Unlike `GroupAdd`, this does not say where the new
+/// member goes. That is decided when the proposal is committed.
+#[derive(Clone, Deserialize, Serialize)]
+#[cfg_attr(test, derive(Debug))]
+pub(crate) struct AddProposal {
+    // opaque init_key<0..2^32-1>; // A UserInitKey
+    /// Contains the public key used to add the new member. This is length-prefixed, since a
+    /// `UserInitKey` may end in extensions the spec has no room for.
+    #[serde(rename = "init_key__bound_u32")]
+    pub(crate) init_key: UserInitKey,
+
+    // opaque welcome_info_hash<0..255>;
+    /// Contains the hash of the `WelcomeInfo` object that was sent to the new member. This is the
+    /// state of the group in the epoch the proposal was made in.
+    pub(crate) welcome_info_hash: WelcomeInfoHash,
+}
+
+/// A proposal by the sender to replace their own leaf key
+#[derive(Clone, Deserialize, Serialize)]
+#[cfg_attr(test, derive(Debug))]
+pub(crate) struct UpdateProposal {
+    /// The sender's new leaf public key
+    pub(crate) public_key: DhPublicKey,
+    /// The sender's signature over their new leaf, as if it had no parent hash. This is what
+    /// vouches for the leaf once the proposal is committed.
+    pub(crate) signature: Signature,
+}
+
+/// A proposal to remove a member from the group
+#[derive(Clone, Deserialize, Serialize)]
+#[cfg_attr(test, derive(Debug))]
+pub(crate) struct RemoveProposal {
+    /// The roster index of the member to remove
+    pub(crate) removed_roster_index: u32,
+}
+
+/// A proposal to mix a pre-shared key into the key schedule of the next epoch. Every member must
+/// have the PSK in order to process the commit that contains this.
+#[derive(Clone, Deserialize, Serialize)]
+#[cfg_attr(test, derive(Debug))]
+pub(crate) struct PskProposal {
+    // opaque psk_id<0..255>;
+    /// The ID of the PSK, as known to the members' `PskStore`s
+    #[serde(rename = "psk_id__bound_u8")]
+    pub(crate) psk_id: Vec<u8>,
+}
+
+/// Enum of possible proposal contents
+#[derive(Clone, Deserialize, Serialize)]
+#[cfg_attr(test, derive(Debug))]
+#[serde(rename = "ProposalBody__enum_u8")]
+pub(crate) enum ProposalBody {
+    Add(AddProposal),
+    Update(UpdateProposal),
+    Remove(RemoveProposal),
+    Psk(PskProposal),
+}
+
+/// A `Proposal` message. This is a signed request by a member to change the group. It has no
+/// effect on its own. Rather, it takes effect once some member puts it in a `GroupCommit`.
+#[derive(Clone, Deserialize, Serialize)]
+#[cfg_attr(test, derive(Debug))]
+pub struct Proposal {
+    /// The epoch of the `GroupState` this proposal was made in. A proposal can only be committed
+    /// in that same epoch.
+    pub(crate) prior_epoch: u32,
+    /// Position of the signer in the roster
+    pub(crate) sender_index: u32,
+    /// What is being proposed
+    pub(crate) body: ProposalBody,
+    /// Signature over the group ID and all the other fields of this struct:
+    /// `Proposal.signature = Sign(identity_key, group_id || prior_epoch || sender_index || body)`
+    pub(crate) signature: Signature,
+}
+
+// This struct is everything but the last field in Proposal, prefixed by the group ID so that a
+// proposal can't be replayed in another group. We use the serialized form of this as the message
+// that the signature is computed over.
+#[derive(Serialize)]
+struct PartialProposal<'a> {
+    #[serde(rename = "group_id__bound_u8")]
+    group_id: &'a [u8],
+    prior_epoch: u32,
+    sender_index: u32,
+    body: &'a ProposalBody,
+}
+
+impl Proposal {
+    /// Makes a new `Proposal` with the given contents, signed by `identity_key` under the
+    /// signature scheme of `credential`
+    pub(crate) fn new(
+        identity_key: &SigSecretKey,
+        credential: &Credential,
+        group_id: &[u8],
+        prior_epoch: u32,
+        sender_index: u32,
+        body: ProposalBody,
+    ) -> Result<Proposal, Error> {
+        let partial = PartialProposal {
+            group_id,
+            prior_epoch,
+            sender_index,
+            body: &body,
+        };
+        let serialized_proposal = tls_ser::serialize_to_bytes(&partial)?;
+        let signature = credential.get_signature_scheme().sign(identity_key, &serialized_proposal);
+
+        Ok(Proposal {
+            prior_epoch,
+            sender_index,
+            body,
+            signature,
+        })
+    }
+
+    /// Verifies this `Proposal` under the identity key specified in `sender_credential`, where
+    /// `group_id` is the ID of the group the proposal was made in
+    ///
+    /// Returns: `Ok(())` on success, `Error::SignatureError` on verification failure, and
+    /// `Error::SerdeError` on some serialization failure.
+    pub(crate) fn verify_sig(
+        &self,
+        group_id: &[u8],
+        sender_credential: &Credential,
+    ) -> Result<(), Error> {
+        let partial = PartialProposal {
+            group_id,
+            prior_epoch: self.prior_epoch,
+            sender_index: self.sender_index,
+            body: &self.body,
+        };
+        let serialized_proposal = tls_ser::serialize_to_bytes(&partial)?;
+
+        let sig_scheme = sender_credential.get_signature_scheme();
+        let public_key = sender_credential.get_public_key();
+        sig_scheme.verify(public_key, &serialized_proposal, &self.signature)
+    }
+}
+
+/// Operation to apply a list of `Proposal`s in a single epoch change. The committer also provides
+/// fresh entropy for the tree.
+///
+/// Conflicting proposals are resolved as follows:
+///
+/// * A `Remove` of a member that is already removed by an earlier `Remove` in the list is ignored.
+/// * An `Update` from a member that is removed in the same commit is ignored.
+/// * If a member has several `Update`s in the list, only the last one is applied.
+/// * An `Update` from the committer is ignored, since `path` refreshes the committer's leaf anyway.
+/// * An `Add` whose `UserInitKey` ID was already seen in an earlier `Add` in the list is ignored.
+/// * A `Psk` whose ID was already seen in an earlier `Psk` in the list is ignored.
+/// * A commit that removes the committer is invalid.
+///
+/// After conflicts are resolved, all `Update`s are applied, then all `Remove`s, then all `Add`s.
+/// Each new member goes in the leftmost empty roster entry, or at the end if there is none.
+#[derive(Clone, Deserialize, Serialize)]
+#[cfg_attr(test, derive(Debug))]
+pub(crate) struct GroupCommit {
+    // Proposal proposals<0..2^32-1>;
+    /// The proposals to apply, in order
+    #[serde(rename = "proposals__bound_u32")]
+    pub(crate) proposals: Vec<Proposal>,
+
+    /// New entropy for the tree, starting at the committer's leaf
+    pub(crate) path: DirectPathMessage,
+}
+
+impl GroupCommit {
+    /// Returns the IDs of the PSKs this commit mixes into the key schedule, in order and with
+    /// duplicates removed
+    pub(crate) fn get_psk_ids(&self) -> Vec<&[u8]> {
+        let mut psk_ids: Vec<&[u8]> = Vec::new();
+        for proposal in self.proposals.iter() {
+            if let ProposalBody::Psk(ref psk) = proposal.body {
+                if !psk_ids.contains(&psk.psk_id.as_slice()) {
+                    psk_ids.push(&psk.psk_id);
+                }
+            }
+        }
+        psk_ids
+    }
+}
+
 /// Enum of possible group operations
-#[derive(Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize)]
 #[cfg_attr(test, derive(Debug))]
 #[serde(rename = "GroupOperation__enum_u8")]
 pub(crate) enum GroupOperation {
@@ -381,13 +1053,134 @@ pub(crate) enum GroupOperation {
     Add(GroupAdd),
     Update(GroupUpdate),
     Remove(GroupRemove),
+    Commit(GroupCommit),
+    MultiAdd(GroupMultiAdd),
+    ExternalJoin(GroupExternalJoin),
+    ReInit(GroupReInit),
+    Extensions(GroupExtensions),
+    Sealed(SealedOperation),
 }
 
-// TODO: Make confirmation a Mac enum for more type safety
+impl GroupOperation {
+    /// Returns the direct path this operation carries, if it has one
+    pub(crate) fn get_path_mut(&mut self) -> Option<&mut DirectPathMessage> {
+        match self {
+            GroupOperation::Init(init) => Some(&mut init.path),
+            GroupOperation::Update(update) => Some(&mut update.path),
+            GroupOperation::Remove(remove) => Some(&mut remove.path),
+            GroupOperation::Commit(commit) => Some(&mut commit.path),
+            GroupOperation::ExternalJoin(join) => Some(&mut join.path),
+            _ => None,
+        }
+    }
 
-/// A `Handshake` message, as defined in section 8 of the MLS spec
+    /// Takes out the parts of this operation that the spec has no room for. Returns `None` if
+    /// there are none, i.e., if the operation only uses what the spec defines.
+    pub(crate) fn take_extras(&mut self) -> Option<OperationExtras> {
+        let path_signature = self.get_path_mut().and_then(|path| path.signature.take());
+        let init_key_extensions = match self {
+            GroupOperation::Add(add) => core::mem::take(&mut add.init_key.extensions),
+            _ => Vec::new(),
+        };
+
+        if path_signature.is_none() && init_key_extensions.is_empty() {
+            None
+        } else {
+            Some(OperationExtras {
+                path_signature,
+                init_key_extensions,
+            })
+        }
+    }
+
+    /// Puts back the extras that `take_extras` took out of this operation
+    pub(crate) fn restore_extras(&mut self, extras: Option<OperationExtras>) {
+        let extras = match extras {
+            Some(extras) => extras,
+            None => return,
+        };
+        if let Some(path) = self.get_path_mut() {
+            path.signature = extras.path_signature;
+        }
+        if let GroupOperation::Add(add) = self {
+            add.init_key.extensions = extras.init_key_extensions;
+        }
+    }
+}
+
+/// The parts of a `GroupOperation` that the spec has no room for. These go after everything the
+/// spec defines, and are left out entirely when there are none, so that an operation that only
+/// uses what the spec defines is encoded exactly as the spec says.
+// struct {
+//     optional<Signature> path_signature;
+//     Extension init_key_extensions<0..2^16-1>;
+// } OperationExtras;
+#[derive(Clone, Deserialize, Serialize)]
+#[cfg_attr(test, derive(Debug))]
+pub(crate) struct OperationExtras {
+    /// The signature of the operation's direct path. See `DirectPathMessage::signature`.
+    path_signature: Option<Signature>,
+
+    /// The extensions of the `UserInitKey` of an `Add`. See `UserInitKey::extensions`. The other
+    /// operations that carry `UserInitKey`s aren't in the spec, so they length-prefix them instead.
+    #[serde(rename = "init_key_extensions__bound_u16")]
+    init_key_extensions: Vec<Extension>,
+}
+
+/// A `GroupOperation` followed by its `OperationExtras`, if it has any. This is what a
+/// `SealedOperation` encrypts.
+// struct {
+//     GroupOperation operation;
+//     optional<OperationExtras> extras; // Left out if absent
+// } WireOperation;
 #[derive(Deserialize, Serialize)]
+pub(crate) struct WireOperation {
+    operation: GroupOperation,
+    #[serde(rename = "extras__trailing", default, skip_serializing_if = "Option::is_none")]
+    extras: Option<OperationExtras>,
+}
+
+impl From<GroupOperation> for WireOperation {
+    fn from(mut operation: GroupOperation) -> WireOperation {
+        let extras = operation.take_extras();
+        WireOperation {
+            operation,
+            extras,
+        }
+    }
+}
+
+impl From<WireOperation> for GroupOperation {
+    fn from(wire: WireOperation) -> GroupOperation {
+        let mut operation = wire.operation;
+        operation.restore_extras(wire.extras);
+        operation
+    }
+}
+
+/// The kind of `GroupOperation` a `Handshake` performs
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum OperationKind {
+    Init,
+    Add,
+    Update,
+    Remove,
+    Commit,
+    MultiAdd,
+    ExternalJoin,
+    ReInit,
+    Extensions,
+    /// The operation is sealed. It can only be read by members of the group.
+    Sealed,
+}
+
+// TODO: Make confirmation a Mac enum for more type safety
+
+/// A `Handshake` message, as defined in section 8 of the MLS spec. On the wire, the
+/// `OperationExtras` of its operation follow the `confirmation`. See `WireHandshake`.
+#[derive(Clone, Deserialize, Serialize)]
 #[cfg_attr(test, derive(Debug))]
+#[serde(from = "WireHandshake", into = "WireHandshake")]
 pub struct Handshake {
     /// This is equal to the epoch of the current `GroupState`
     pub(crate) prior_epoch: u32,
@@ -405,39 +1198,351 @@ pub struct Handshake {
     pub(crate) confirmation: Mac,
 }
 
-#[cfg(test)]
-mod test {
-    use crate::{
-        crypto::{
-            ciphersuite::{CipherSuite, P256_SHA256_AES128GCM, X25519_SHA256_AES128GCM},
-            sig::SignatureScheme,
-        },
-        error::Error,
-        group_state::{GroupState, Welcome, WelcomeInfo},
-        handshake::{Handshake, ProtocolVersion, UserInitKey, MLS_DUMMY_VERSION},
-        ratchet_tree::PathSecret,
-        test_utils,
-        tls_de::TlsDeserializer,
-        tls_ser,
-        upcast::{CryptoCtx, CryptoUpcast},
-    };
+/// The encoding of a `Handshake`. This is the spec's `Handshake`, followed by the `OperationExtras`
+/// of its operation if it has any. Since those may be left out, anything that carries a `Handshake`
+/// before other data must length-prefix it.
+// struct {
+//     uint32 prior_epoch;
+//     GroupOperation operation;
+//     uint32 signer_index;
+//     opaque signature<1..2^16-1>;
+//     opaque confirmation<1..255>;
+//     optional<OperationExtras> extras; // Left out if absent
+// } WireHandshake;
+#[derive(Deserialize, Serialize)]
+struct WireHandshake {
+    prior_epoch: u32,
+    operation: GroupOperation,
+    signer_index: u32,
+    signature: Signature,
+    confirmation: Mac,
+    #[serde(rename = "extras__trailing", default, skip_serializing_if = "Option::is_none")]
+    extras: Option<OperationExtras>,
+}
+
+impl From<Handshake> for WireHandshake {
+    fn from(handshake: Handshake) -> WireHandshake {
+        let WireOperation {
+            operation,
+            extras,
+        } = WireOperation::from(handshake.operation);
+        WireHandshake {
+            prior_epoch: handshake.prior_epoch,
+            operation,
+            signer_index: handshake.signer_index,
+            signature: handshake.signature,
+            confirmation: handshake.confirmation,
+            extras,
+        }
+    }
+}
+
+impl From<WireHandshake> for Handshake {
+    fn from(wire: WireHandshake) -> Handshake {
+        let operation = GroupOperation::from(WireOperation {
+            operation: wire.operation,
+            extras: wire.extras,
+        });
+        Handshake {
+            prior_epoch: wire.prior_epoch,
+            operation,
+            signer_index: wire.signer_index,
+            signature: wire.signature,
+            confirmation: wire.confirmation,
+        }
+    }
+}
+
+impl Handshake {
+    /// Returns the epoch that this `Handshake` is meant to be applied to
+    pub fn get_prior_epoch(&self) -> u32 {
+        self.prior_epoch
+    }
+
+    /// Returns the roster index of the member who sent this `Handshake`
+    pub fn get_signer_index(&self) -> u32 {
+        self.signer_index
+    }
+
+    /// Returns the kind of operation this `Handshake` performs
+    pub fn get_operation_kind(&self) -> OperationKind {
+        match self.operation {
+            GroupOperation::Init(_) => OperationKind::Init,
+            GroupOperation::Add(_) => OperationKind::Add,
+            GroupOperation::Update(_) => OperationKind::Update,
+            GroupOperation::Remove(_) => OperationKind::Remove,
+            GroupOperation::Commit(_) => OperationKind::Commit,
+            GroupOperation::MultiAdd(_) => OperationKind::MultiAdd,
+            GroupOperation::ExternalJoin(_) => OperationKind::ExternalJoin,
+            GroupOperation::ReInit(_) => OperationKind::ReInit,
+            GroupOperation::Extensions(_) => OperationKind::Extensions,
+            GroupOperation::Sealed(_) => OperationKind::Sealed,
+        }
+    }
+
+    /// Returns the credentials of the members this `Handshake` adds to the group, in the order
+    /// they are added. `Add` proposals in a `Commit` that repeat a `UserInitKey` ID are counted
+    /// once. This is empty for a sealed `Handshake`.
+    pub fn get_added_credentials(&self) -> Vec<&Credential> {
+        match self.operation {
+            GroupOperation::Init(ref init) => {
+                init.init_keys.iter().map(|k| &k.credential).collect()
+            }
+            GroupOperation::Add(ref add) => vec![&add.init_key.credential],
+            GroupOperation::MultiAdd(ref multi_add) => {
+                multi_add.init_keys.iter().map(|k| &k.credential).collect()
+            }
+            GroupOperation::ExternalJoin(ref join) => vec![&join.init_key.credential],
+            GroupOperation::Commit(ref commit) => {
+                let mut seen_ids: Vec<&[u8]> = Vec::new();
+                let mut creds = Vec::new();
+                for proposal in commit.proposals.iter() {
+                    if let ProposalBody::Add(ref add) = proposal.body {
+                        let id = add.init_key.user_init_key_id.as_slice();
+                        if !seen_ids.contains(&id) {
+                            seen_ids.push(id);
+                            creds.push(&add.init_key.credential);
+                        }
+                    }
+                }
+                creds
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// Returns the roster indices of the members this `Handshake` removes from the group, in order
+    /// and with duplicates removed. This is empty for a sealed `Handshake`.
+    pub fn get_removed_roster_indices(&self) -> Vec<u32> {
+        match self.operation {
+            GroupOperation::Remove(ref remove) => vec![remove.removed_roster_index],
+            GroupOperation::Commit(ref commit) => {
+                let mut removed = Vec::new();
+                for proposal in commit.proposals.iter() {
+                    if let ProposalBody::Remove(ref remove) = proposal.body {
+                        if !removed.contains(&remove.removed_roster_index) {
+                            removed.push(remove.removed_roster_index);
+                        }
+                    }
+                }
+                removed
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        application::encrypt_application_message,
+        crypto::{
+            ciphersuite::{CipherSuite, X25519_SHA256_AES128GCM},
+            sig::{SigSecretKey, SignatureScheme},
+        },
+        error::Error,
+        extension::{Extension, ExtensionType},
+        framing::{frame_plaintext, MlsContent, ProcessedMessage},
+        group_state::{
+            ForkStatus, GroupState, MembershipStatus, MultiWelcome, ProcessingContext,
+            PublicGroupInfo, Welcome, WelcomeInfo, MAX_PENDING_HANDSHAKES,
+            MAX_PENDING_HANDSHAKES_FROM_UNKNOWN_SIGNERS, MAX_PENDING_HANDSHAKES_PER_SENDER,
+        },
+        handshake::{Handshake, ProtocolVersion, UserInitKey, MLS_DUMMY_VERSION},
+        policy::{AllowAll, AllowExternalJoins, MembershipPolicy, OnlyAdminsCanRemove},
+        ratchet_tree::PathSecret,
+        test_utils,
+        tls_de::TlsDeserializer,
+        tls_ser,
+        upcast::{CryptoCtx, CryptoUpcast},
+    };
+
+    use core::convert::TryFrom;
+    use std::{collections::HashMap, io::Read};
+
+    use quickcheck_macros::quickcheck;
+    use rand::{RngCore, SeedableRng};
+    use serde::{Deserialize, Serialize};
+
+    // Check that Update operations are consistent
+    #[quickcheck]
+    fn update_correctness(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        // Make a starting group of at least 2 people
+        let (group_state1, identity_keys) = test_utils::random_full_group_state(2, &mut rng);
+
+        // Make a copy of this group, but from another perspective. That is, we want the same group
+        // but with a different roster index
+        let new_index = test_utils::random_roster_index_with_exceptions(
+            group_state1.roster.len(),
+            &[group_state1.roster_index.unwrap() as usize],
+            &mut rng,
+        );
+        let group_state2 = test_utils::change_self_index(&group_state1, &identity_keys, new_index);
+
+        // Make a new path secret and make an Update object out of it and then make a Handshake
+        // object out of that Update
+        let new_path_secret = PathSecret::new_from_random(group_state1.cs, &mut rng);
+        let (handshake, group_state1, _) =
+            group_state1.create_and_apply_update_handshake(new_path_secret, &mut rng).unwrap();
+
+        // Apply the Handshake to the clone of the first group
+        let (group_state2, _) = group_state2.process_handshake(&handshake).unwrap();
+
+        // Now see if the group states agree
+        assert_serialized_eq!(group_state1, group_state2, "GroupStates disagree after Update");
+    }
+
+    // Check that Handshakes that arrive ahead of their epoch get applied once the gap closes
+    #[quickcheck]
+    fn out_of_order_correctness(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        // Big enough that the pending queue can be filled by members other than the sender
+        let (group_state1, identity_keys) = test_utils::random_full_group_state(5, &mut rng);
+        let new_index = test_utils::random_roster_index_with_exceptions(
+            group_state1.roster.len(),
+            &[group_state1.roster_index.unwrap() as usize],
+            &mut rng,
+        );
+        let group_state2 = test_utils::change_self_index(&group_state1, &identity_keys, new_index);
+
+        // Make two Updates in a row
+        let new_path_secret = PathSecret::new_from_random(group_state1.cs, &mut rng);
+        let (handshake1, group_state1, _) =
+            group_state1.create_and_apply_update_handshake(new_path_secret, &mut rng).unwrap();
+        let new_path_secret = PathSecret::new_from_random(group_state1.cs, &mut rng);
+        let (handshake2, group_state1, _) =
+            group_state1.create_and_apply_update_handshake(new_path_secret, &mut rng).unwrap();
+
+        // Deliver the second one first. It should be buffered and survive a snapshot.
+        let (buffered_group, app_key_chains, _) =
+            group_state2.process_or_buffer_handshake(handshake2.clone()).unwrap();
+        assert!(app_key_chains.is_empty());
+        assert_eq!(buffered_group.epoch, group_state2.epoch);
+        assert_eq!(buffered_group.get_pending_handshakes().len(), 1);
+        let buffered_group =
+            GroupState::from_snapshot(&buffered_group.to_snapshot().unwrap()).unwrap();
+
+        // Delivering it again changes nothing
+        let (dup_group, _, _) =
+            buffered_group.process_or_buffer_handshake(handshake2.clone()).unwrap();
+        assert_eq!(dup_group.get_pending_handshakes().len(), 1);
+
+        // Nothing too far ahead gets buffered
+        let mut far_handshake = handshake2.clone();
+        far_handshake.prior_epoch = buffered_group.epoch + MAX_PENDING_HANDSHAKES as u32 + 1;
+        assert!(buffered_group.process_or_buffer_handshake(far_handshake).is_err());
+
+        // Someone outside the roster might join before their Handshake's epoch, so it's buffered.
+        // It fails once it's ready, and is handed back.
+        let mut stranger_handshake = handshake2.clone();
+        stranger_handshake.signer_index = buffered_group.roster.len() as u32;
+        let (stranger_group, _, _) =
+            group_state2.process_or_buffer_handshake(stranger_handshake.clone()).unwrap();
+        assert_eq!(stranger_group.get_pending_handshakes().len(), 1);
+        let (stranger_group, app_key_chains, failed) =
+            stranger_group.process_or_buffer_handshake(handshake1.clone()).unwrap();
+        assert_eq!(app_key_chains.len(), 1);
+        assert_eq!(failed.len(), 1);
+        assert!(stranger_group.get_pending_handshakes().is_empty());
+
+        // If it competes with a real Handshake for the same epoch, it loses, and is handed back
+        // as well
+        let (competing_group, _, _) =
+            buffered_group.process_or_buffer_handshake(stranger_handshake.clone()).unwrap();
+        assert_eq!(competing_group.get_pending_handshakes().len(), 2);
+        let (competing_group, app_key_chains, failed) =
+            competing_group.process_or_buffer_handshake(handshake1.clone()).unwrap();
+        assert_eq!(app_key_chains.len(), 2);
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].0.signer_index, stranger_handshake.signer_index);
+        assert!(competing_group.get_pending_handshakes().is_empty());
+
+        // Any one signer's share of the queue is bounded. Past that, their furthest-ahead
+        // Handshake makes room, unless it's the new one.
+        let per_sender = MAX_PENDING_HANDSHAKES_PER_SENDER as u32;
+        let mut busy_group = group_state2.clone();
+        for i in 2..=per_sender + 1 {
+            let mut future_handshake = handshake2.clone();
+            future_handshake.prior_epoch = busy_group.epoch + i;
+            busy_group = busy_group.process_or_buffer_handshake(future_handshake).unwrap().0;
+        }
+        assert_eq!(busy_group.get_pending_handshakes().len(), MAX_PENDING_HANDSHAKES_PER_SENDER);
+        let mut furthest_handshake = handshake2.clone();
+        furthest_handshake.prior_epoch = busy_group.epoch + per_sender + 2;
+        assert!(busy_group.process_or_buffer_handshake(furthest_handshake).is_err());
+        let (busy_group, _, _) =
+            busy_group.process_or_buffer_handshake(handshake2.clone()).unwrap();
+        let pending = busy_group.get_pending_handshakes();
+        assert_eq!(pending.len(), MAX_PENDING_HANDSHAKES_PER_SENDER);
+        assert!(pending.iter().any(|h| h.prior_epoch == handshake2.prior_epoch));
+        assert!(!pending.iter().any(|h| h.prior_epoch == busy_group.epoch + per_sender + 1));
+
+        // Strangers only get a few entries between them, however many signer indices they use.
+        // Past that, the furthest-ahead one makes room. Someone in the roster still gets in.
+        let from_unknown = MAX_PENDING_HANDSHAKES_FROM_UNKNOWN_SIGNERS as u32;
+        let mut crowded_group = group_state2.clone();
+        for i in 0..=from_unknown {
+            let mut future_handshake = handshake2.clone();
+            future_handshake.signer_index = crowded_group.roster.len() as u32 + i;
+            future_handshake.prior_epoch = crowded_group.epoch + 1 + from_unknown - i;
+            crowded_group = crowded_group.process_or_buffer_handshake(future_handshake).unwrap().0;
+        }
+        let pending = crowded_group.get_pending_handshakes();
+        assert_eq!(pending.len(), MAX_PENDING_HANDSHAKES_FROM_UNKNOWN_SIGNERS);
+        assert!(!pending.iter().any(|h| h.prior_epoch == crowded_group.epoch + 1 + from_unknown));
+        let (crowded_group, _, _) =
+            crowded_group.process_or_buffer_handshake(handshake2.clone()).unwrap();
+        assert_eq!(
+            crowded_group.get_pending_handshakes().len(),
+            MAX_PENDING_HANDSHAKES_FROM_UNKNOWN_SIGNERS + 1
+        );
 
-    use core::convert::TryFrom;
-    use std::io::Read;
+        // The whole queue is bounded too. Once it's full, whoever holds the most makes room.
+        let fillers: Vec<u32> = (0..group_state2.roster.len() as u32)
+            .filter(|&idx| idx != handshake2.signer_index)
+            .take(MAX_PENDING_HANDSHAKES / MAX_PENDING_HANDSHAKES_PER_SENDER)
+            .collect();
+        let mut full_group = group_state2.clone();
+        for i in 0..(fillers.len() * MAX_PENDING_HANDSHAKES_PER_SENDER) as u32 {
+            let mut future_handshake = handshake2.clone();
+            future_handshake.signer_index = fillers[(i / per_sender) as usize];
+            future_handshake.prior_epoch = full_group.epoch + 1 + i % per_sender;
+            full_group = full_group.process_or_buffer_handshake(future_handshake).unwrap().0;
+        }
+        assert_eq!(full_group.get_pending_handshakes().len(), MAX_PENDING_HANDSHAKES);
+        let (full_group, _, _) =
+            full_group.process_or_buffer_handshake(handshake2.clone()).unwrap();
+        let pending = full_group.get_pending_handshakes();
+        assert_eq!(pending.len(), MAX_PENDING_HANDSHAKES);
+        assert!(pending.iter().any(|h| h.signer_index == handshake2.signer_index));
+
+        // Once the group moves past a pending Handshake's epoch, it's dropped
+        let (passed_group, _) = buffered_group.process_handshake(&handshake1).unwrap();
+        let (passed_group, _) = passed_group.process_handshake(&handshake2).unwrap();
+        assert!(passed_group.get_pending_handshakes().is_empty());
+
+        // Now deliver the first one. Both should get applied.
+        let (caught_up_group, app_key_chains, _) =
+            buffered_group.process_or_buffer_handshake(handshake1.clone()).unwrap();
+        assert_eq!(app_key_chains.len(), 2);
+        assert!(caught_up_group.get_pending_handshakes().is_empty());
+        assert_serialized_eq!(
+            group_state1,
+            caught_up_group,
+            "GroupStates disagree after catching up"
+        );
 
-    use quickcheck_macros::quickcheck;
-    use rand::{RngCore, SeedableRng};
-    use serde::Deserialize;
+        // A Handshake from the past is an error
+        assert!(caught_up_group.process_or_buffer_handshake(handshake1).is_err());
+    }
 
-    // Check that Update operations are consistent
+    // Check that two members who make Handshakes from the same epoch agree on who wins, and that
+    // the loser can roll back and end up in the same state as the winner
     #[quickcheck]
-    fn update_correctness(rng_seed: u64) {
+    fn fork_resolution(rng_seed: u64) {
         let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
-        // Make a starting group of at least 2 people
         let (group_state1, identity_keys) = test_utils::random_full_group_state(2, &mut rng);
-
-        // Make a copy of this group, but from another perspective. That is, we want the same group
-        // but with a different roster index
         let new_index = test_utils::random_roster_index_with_exceptions(
             group_state1.roster.len(),
             &[group_state1.roster_index.unwrap() as usize],
@@ -445,17 +1550,144 @@ mod test {
         );
         let group_state2 = test_utils::change_self_index(&group_state1, &identity_keys, new_index);
 
-        // Make a new path secret and make an Update object out of it and then make a Handshake
-        // object out of that Update
+        // Both members make an Update from the same epoch
         let new_path_secret = PathSecret::new_from_random(group_state1.cs, &mut rng);
-        let (handshake, group_state1, _) =
+        let (handshake1, forked_group1, _) =
             group_state1.create_and_apply_update_handshake(new_path_secret, &mut rng).unwrap();
+        let new_path_secret = PathSecret::new_from_random(group_state2.cs, &mut rng);
+        let (handshake2, forked_group2, _) =
+            group_state2.create_and_apply_update_handshake(new_path_secret, &mut rng).unwrap();
+
+        // Neither accepts the other's Handshake outright, and nobody thinks their own Handshake
+        // is a fork
+        assert!(forked_group1.process_handshake(&handshake2).is_err());
+        assert!(forked_group2.process_handshake(&handshake1).is_err());
+        assert_eq!(forked_group1.detect_fork(&handshake1).unwrap(), ForkStatus::NoFork);
+
+        // Exactly one of them wins, and it's the one with the lower roster index
+        let status1 = forked_group1.detect_fork(&handshake2).unwrap();
+        let status2 = forked_group2.detect_fork(&handshake1).unwrap();
+        let (winning_group, losing_group, winning_prior, losing_prior) =
+            if group_state1.roster_index.unwrap() < group_state2.roster_index.unwrap() {
+                assert_eq!((status1, status2), (ForkStatus::Won, ForkStatus::Lost));
+                (forked_group1, forked_group2, group_state1, group_state2)
+            } else {
+                assert_eq!((status1, status2), (ForkStatus::Lost, ForkStatus::Won));
+                (forked_group2, forked_group1, group_state2, group_state1)
+            };
+        let (winning_handshake, losing_handshake) = if status1 == ForkStatus::Won {
+            (handshake1, handshake2)
+        } else {
+            (handshake2, handshake1)
+        };
 
-        // Apply the Handshake to the clone of the first group
-        let (group_state2, _) = group_state2.process_handshake(&handshake).unwrap();
+        // The fork point doesn't keep any of the previous epoch's secrets, so the loser needs the
+        // state they came from to roll back. Anyone else's, or a later one, won't do.
+        assert!(losing_group.resolve_fork(&winning_prior, &winning_handshake).is_err());
+        assert!(losing_group.resolve_fork(&winning_group, &winning_handshake).is_err());
+
+        // The winner can't roll back, but the loser can, and ends up where the winner is
+        assert!(winning_group.resolve_fork(&winning_prior, &losing_handshake).is_err());
+        let (resolved_group, _) =
+            losing_group.resolve_fork(&losing_prior, &winning_handshake).unwrap();
+        assert_serialized_eq!(winning_group, resolved_group, "GroupStates disagree after fork");
+
+        // The loser's own Handshake is gone for good
+        assert_eq!(resolved_group.detect_fork(&losing_handshake).unwrap(), ForkStatus::NoFork);
+        assert!(resolved_group.resolve_fork(&losing_prior, &losing_handshake).is_err());
+
+        // A competing Handshake whose signature doesn't check out is rejected
+        let mut forged_handshake = winning_handshake.clone();
+        forged_handshake.signature = losing_handshake.signature.clone();
+        assert!(losing_group.detect_fork(&forged_handshake).is_err());
+
+        // Once the group has moved on, there is nothing left to roll back
+        let new_path_secret = PathSecret::new_from_random(resolved_group.cs, &mut rng);
+        let (_, moved_on_group, _) =
+            resolved_group.create_and_apply_update_handshake(new_path_secret, &mut rng).unwrap();
+        assert_eq!(moved_on_group.detect_fork(&losing_handshake).unwrap(), ForkStatus::NoFork);
+        assert_eq!(moved_on_group.detect_fork(&winning_handshake).unwrap(), ForkStatus::NoFork);
+    }
 
-        // Now see if the group states agree
-        assert_serialized_eq!(group_state1, group_state2, "GroupStates disagree after Update");
+    // Check that a member who isn't part of a fork ends up on the winning branch no matter which
+    // of the competing Handshakes they receive first
+    #[quickcheck]
+    fn bystander_fork_resolution(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let (group_state1, identity_keys) = test_utils::random_full_group_state(3, &mut rng);
+        let idx1 = group_state1.roster_index.unwrap();
+        let idx2 = test_utils::random_roster_index_with_exceptions(
+            group_state1.roster.len(),
+            &[idx1 as usize],
+            &mut rng,
+        );
+        let bystander_idx = test_utils::random_roster_index_with_exceptions(
+            group_state1.roster.len(),
+            &[idx1 as usize, idx2 as usize],
+            &mut rng,
+        );
+        let group_state2 = test_utils::change_self_index(&group_state1, &identity_keys, idx2);
+        let lagging_bystander_group =
+            test_utils::change_self_index(&group_state1, &identity_keys, bystander_idx);
+
+        // Start with an Update that everyone but one lagging bystander gets
+        let new_path_secret = PathSecret::new_from_random(group_state1.cs, &mut rng);
+        let (first_handshake, group_state1, _) =
+            group_state1.create_and_apply_update_handshake(new_path_secret, &mut rng).unwrap();
+        let (group_state2, _) = group_state2.process_handshake(&first_handshake).unwrap();
+        let (bystander_group, _) =
+            lagging_bystander_group.process_handshake(&first_handshake).unwrap();
+
+        // Two members make an Update from the same epoch. The lower roster index wins.
+        let new_path_secret = PathSecret::new_from_random(group_state1.cs, &mut rng);
+        let (handshake1, forked_group1, _) =
+            group_state1.create_and_apply_update_handshake(new_path_secret, &mut rng).unwrap();
+        let new_path_secret = PathSecret::new_from_random(group_state2.cs, &mut rng);
+        let (handshake2, forked_group2, _) =
+            group_state2.create_and_apply_update_handshake(new_path_secret, &mut rng).unwrap();
+        let (winning_group, winning_handshake, losing_handshake) = if idx1 < idx2 {
+            (forked_group1, handshake1, handshake2)
+        } else {
+            (forked_group2, handshake2, handshake1)
+        };
+
+        // The bystander gets the losing Handshake first and applies it. Then the winning one
+        // arrives, and they roll back to it.
+        let (losing_bystander, _) = bystander_group.process_handshake(&losing_handshake).unwrap();
+        assert!(losing_bystander.process_handshake(&winning_handshake).is_err());
+        assert_eq!(losing_bystander.detect_fork(&winning_handshake).unwrap(), ForkStatus::Lost);
+        let (resolved_bystander, _) =
+            losing_bystander.resolve_fork(&bystander_group, &winning_handshake).unwrap();
+        assert_serialized_eq!(
+            winning_group,
+            resolved_bystander,
+            "GroupStates disagree after bystander fork"
+        );
+
+        // Had they gotten the winning Handshake first, they'd have stayed put
+        let (winning_bystander, _) = bystander_group.process_handshake(&winning_handshake).unwrap();
+        assert_eq!(winning_bystander.detect_fork(&losing_handshake).unwrap(), ForkStatus::Won);
+        assert!(winning_bystander.resolve_fork(&bystander_group, &losing_handshake).is_err());
+        assert_serialized_eq!(
+            winning_group,
+            winning_bystander,
+            "GroupStates disagree after bystander fork"
+        );
+
+        // The same goes for the lagging bystander, who buffers both before catching up, since
+        // they're applied in fork order
+        let (buffered_bystander, _, _) =
+            lagging_bystander_group.process_or_buffer_handshake(losing_handshake).unwrap();
+        let (buffered_bystander, _, _) =
+            buffered_bystander.process_or_buffer_handshake(winning_handshake).unwrap();
+        let (buffered_bystander, app_key_chains, _) =
+            buffered_bystander.process_or_buffer_handshake(first_handshake).unwrap();
+        assert_eq!(app_key_chains.len(), 2);
+        assert_serialized_eq!(
+            winning_group,
+            buffered_bystander,
+            "GroupStates disagree after bystander fork"
+        );
     }
 
     // Check that Remove operations are consistent
@@ -708,6 +1940,703 @@ mod test {
         assert_serialized_eq!(group_state1, group_state2, "GroupStates disagree after Add");
     }
 
+    // Checks that
+    //
+    //   Welcome(B), Welcome(C), ..., Init(B, C, ...)
+    // A --------------------------------------------> B, C, ...
+    //
+    // produces identical groups A, B, C, ...
+    #[quickcheck]
+    fn init_correctness(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+
+        // Make a fresh one-person group
+        let group_state1 = {
+            let (credential, identity_key) = test_utils::random_basic_credential(&mut rng);
+            GroupState::new_singleton_group(
+                &X25519_SHA256_AES128GCM,
+                MLS_DUMMY_VERSION,
+                identity_key,
+                b"test group".to_vec(),
+                credential,
+                &mut rng,
+            )
+            .unwrap()
+        };
+
+        // Make a bunch of new members, each with their own UserInitKey
+        let num_new_members = rand::Rng::gen_range(&mut rng, 1, 20);
+        let new_members: Vec<(UserInitKey, SigSecretKey)> = (0..num_new_members)
+            .map(|_| test_utils::random_user_init_key(&mut rng, group_state1.cs))
+            .collect();
+
+        // Make the Init and all the Welcomes
+        let init_keys = new_members.iter().map(|(uik, _)| uik.clone()).collect();
+        let new_path_secret = PathSecret::new_from_random(group_state1.cs, &mut rng);
+        let (init_handshake, welcomes, group_state1, _) = group_state1
+            .create_and_apply_init_handshake(init_keys, new_path_secret, &mut rng)
+            .unwrap();
+        assert_eq!(group_state1.roster.len(), num_new_members + 1);
+
+        // Every new member should end up in the same place as the creator
+        let mut group_states = Vec::new();
+        for ((init_key, identity_key), welcome) in new_members.into_iter().zip(welcomes) {
+            let prelim_group = GroupState::from_welcome(welcome, identity_key, init_key).unwrap();
+            let (new_group, _) = prelim_group.process_handshake(&init_handshake).unwrap();
+            assert_serialized_eq!(group_state1, new_group, "GroupStates disagree after Init");
+            group_states.push(new_group);
+        }
+
+        // Now run an update from one of the new members just to make sure everything is working
+        let updater = group_states.pop().unwrap();
+        let new_path_secret = PathSecret::new_from_random(updater.cs, &mut rng);
+        let (update_handshake, updater, _) =
+            updater.create_and_apply_update_handshake(new_path_secret, &mut rng).unwrap();
+        let (group_state1, _) = group_state1.process_handshake(&update_handshake).unwrap();
+        assert_serialized_eq!(group_state1, updater, "GroupStates disagree after post-Init Update");
+    }
+
+    // Checks that a MultiAdd of several new members, all Welcomed by a single MultiWelcome, is
+    // processed identically by the creator, an existing member, and every new member
+    #[quickcheck]
+    fn multi_add_correctness(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let (group_state1, identity_keys) = test_utils::random_full_group_state(2, &mut rng);
+        let other_index = test_utils::random_roster_index_with_exceptions(
+            group_state1.roster.len(),
+            &[group_state1.roster_index.unwrap() as usize],
+            &mut rng,
+        );
+        let group_state2 =
+            test_utils::change_self_index(&group_state1, &identity_keys, other_index);
+
+        // Make a bunch of new members and Welcome them all at once
+        let num_new_members = rand::Rng::gen_range(&mut rng, 1, 10);
+        let new_members: Vec<(UserInitKey, SigSecretKey)> = (0..num_new_members)
+            .map(|_| test_utils::random_user_init_key(&mut rng, group_state1.cs))
+            .collect();
+        let init_keys: Vec<UserInitKey> = new_members.iter().map(|(uik, _)| uik.clone()).collect();
+        let (multi_welcome, welcome_info_hash) =
+            MultiWelcome::from_group_state(&group_state1, &init_keys, &mut rng).unwrap();
+
+        // Send the MultiWelcome over the wire to make sure it deserializes and upcasts correctly
+        let multi_welcome_bytes = tls_ser::serialize_to_bytes(&multi_welcome).unwrap();
+
+        // Make the MultiAdd. Everyone should be added in a single epoch.
+        let old_roster_len = group_state1.roster.len();
+        let (handshake, group_state1, _) = group_state1
+            .create_and_apply_multi_add_handshake(init_keys, &welcome_info_hash)
+            .unwrap();
+        assert_eq!(group_state1.roster.len(), old_roster_len + num_new_members);
+
+        // The existing member agrees
+        let (group_state2, _) = group_state2.process_handshake(&handshake).unwrap();
+        assert_serialized_eq!(group_state1, group_state2, "GroupStates disagree after MultiAdd");
+
+        // And so does every new member
+        for (init_key, identity_key) in new_members.into_iter() {
+            let multi_welcome = {
+                let mut cursor = multi_welcome_bytes.as_slice();
+                let mut deserializer = TlsDeserializer::from_reader(&mut cursor);
+                let mut w = MultiWelcome::deserialize(&mut deserializer).unwrap();
+                w.upcast_crypto_values(&CryptoCtx::new()).unwrap();
+                w
+            };
+            let prelim_group =
+                GroupState::from_multi_welcome(multi_welcome, identity_key, init_key).unwrap();
+            let (new_group, _) = prelim_group.process_handshake(&handshake).unwrap();
+            assert_eq!(new_group.epoch, group_state1.epoch);
+            assert_serialized_eq!(group_state1, new_group, "GroupStates disagree after MultiAdd");
+        }
+
+        // Someone who wasn't Welcomed can't read the MultiWelcome
+        let (outsider_key, outsider_identity_key) =
+            test_utils::random_user_init_key(&mut rng, group_state1.cs);
+        let (multi_welcome, _) = MultiWelcome::from_group_state(
+            &group_state1,
+            core::slice::from_ref(&outsider_key),
+            &mut rng,
+        )
+        .unwrap();
+        let (other_key, _) = test_utils::random_user_init_key(&mut rng, group_state1.cs);
+        assert!(GroupState::from_multi_welcome(multi_welcome, outsider_identity_key, other_key)
+            .is_err());
+
+        // A MultiWelcome that lies about which epoch it's from can't be opened
+        let (mut multi_welcome, _) = MultiWelcome::from_group_state(
+            &group_state1,
+            core::slice::from_ref(&outsider_key),
+            &mut rng,
+        )
+        .unwrap();
+        multi_welcome.epoch += 1;
+        let (_, outsider_identity_key) = test_utils::random_basic_credential(&mut rng);
+        assert!(GroupState::from_multi_welcome(multi_welcome, outsider_identity_key, outsider_key)
+            .is_err());
+    }
+
+    // Checks that a new member can join using a PublicGroupInfo, and that the existing members
+    // agree with the new member about the resulting state
+    #[quickcheck]
+    fn external_join_correctness(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let (group_state1, identity_keys) = test_utils::random_full_group_state(2, &mut rng);
+        let other_index = test_utils::random_roster_index_with_exceptions(
+            group_state1.roster.len(),
+            &[group_state1.roster_index.unwrap() as usize],
+            &mut rng,
+        );
+        let group_state2 =
+            test_utils::change_self_index(&group_state1, &identity_keys, other_index);
+
+        // Publish the group info and send it over the wire
+        let group_info = {
+            let group_info = group_state1.create_public_group_info().unwrap();
+            let bytes = tls_ser::serialize_to_bytes(&group_info).unwrap();
+            let mut cursor = bytes.as_slice();
+            let mut deserializer = TlsDeserializer::from_reader(&mut cursor);
+            let mut info = PublicGroupInfo::deserialize(&mut deserializer).unwrap();
+            info.upcast_crypto_values(&CryptoCtx::new()).unwrap();
+            info
+        };
+        assert_eq!(group_info.get_epoch(), group_state1.epoch);
+
+        // A new member joins on their own
+        let (init_key, identity_key) = test_utils::random_user_init_key(&mut rng, group_state1.cs);
+        let new_path_secret = PathSecret::new_from_random(group_state1.cs, &mut rng);
+        let (join_handshake, joiner_group, _) = GroupState::create_external_join_handshake(
+            group_info,
+            identity_key,
+            init_key,
+            new_path_secret,
+            &mut rng,
+        )
+        .unwrap();
+
+        // By default, members don't let outsiders in
+        match group_state1.process_handshake(&join_handshake) {
+            Err(Error::AuthorizationError(_)) => (),
+            _ => panic!("ExternalJoin was accepted without the policy allowing it"),
+        }
+        let restrictive = (AllowExternalJoins, OnlyAdminsCanRemove::new(Vec::new()));
+        assert!(restrictive.allows_external_joins());
+        assert!(!(AllowAll, OnlyAdminsCanRemove::new(Vec::new())).allows_external_joins());
+
+        // With a policy that opts in, everyone agrees
+        let (group_state1, _) = group_state1
+            .process_handshake_with_context(
+                &join_handshake,
+                &ProcessingContext::new().with_policy(&restrictive),
+            )
+            .unwrap();
+        let (group_state2, _) = group_state2
+            .process_handshake_with_context(
+                &join_handshake,
+                &ProcessingContext::new().with_policy(&AllowExternalJoins),
+            )
+            .unwrap();
+        assert_serialized_eq!(group_state1, joiner_group, "GroupStates disagree after join");
+        assert_serialized_eq!(group_state2, joiner_group, "GroupStates disagree after join");
+
+        // The new member can do things in the group
+        let new_path_secret = PathSecret::new_from_random(joiner_group.cs, &mut rng);
+        let (update_handshake, joiner_group, _) =
+            joiner_group.create_and_apply_update_handshake(new_path_secret, &mut rng).unwrap();
+        let (group_state1, _) = group_state1.process_handshake(&update_handshake).unwrap();
+        assert_serialized_eq!(group_state1, joiner_group, "GroupStates disagree after Update");
+
+        // A tampered PublicGroupInfo is rejected
+        let mut group_info = group_state1.create_public_group_info().unwrap();
+        group_info.signer_index = (group_info.signer_index + 1) % group_state1.roster.len() as u32;
+        let (init_key, identity_key) = test_utils::random_user_init_key(&mut rng, group_state1.cs);
+        let new_path_secret = PathSecret::new_from_random(group_state1.cs, &mut rng);
+        assert!(GroupState::create_external_join_handshake(
+            group_info,
+            identity_key,
+            init_key,
+            new_path_secret,
+            &mut rng
+        )
+        .is_err());
+    }
+
+    // Checks that a group can be reinitialized onto a new ciphersuite, that everyone carries over
+    // to the successor, and that a successor not linked to the old group is rejected
+    #[quickcheck]
+    fn reinit_correctness(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let (group_state, identity_keys) = test_utils::random_full_group_state(2, &mut rng);
+        // TODO: Move to a different ciphersuite once more are available
+        let new_cs: &'static CipherSuite = &X25519_SHA256_AES128GCM;
+        let new_version = ProtocolVersion(MLS_DUMMY_VERSION.0 + 1);
+        let new_group_id = b"successor group".to_vec();
+
+        // Someone announces the reinit and everyone else processes it
+        let (reinit_handshake, reinitializer, _) = group_state
+            .create_and_apply_reinit_handshake(new_group_id.clone(), new_cs, new_version)
+            .unwrap();
+        let reinitializer_idx = group_state.roster_index.unwrap();
+        let ended_states: Vec<GroupState> = (0..group_state.roster.len() as u32)
+            .map(|idx| {
+                if idx == reinitializer_idx {
+                    reinitializer.clone()
+                } else {
+                    let member = test_utils::change_self_index(&group_state, &identity_keys, idx);
+                    member.process_handshake(&reinit_handshake).unwrap().0
+                }
+            })
+            .collect();
+        for ended_state in ended_states.iter() {
+            assert_serialized_eq!(reinitializer, ended_state, "GroupStates disagree after ReInit");
+            assert_eq!(ended_state.get_successor_group_id(), Some(new_group_id.as_slice()));
+        }
+
+        // The old group is over
+        let new_path_secret = PathSecret::new_from_random(reinitializer.cs, &mut rng);
+        assert!(reinitializer
+            .create_and_apply_update_handshake(new_path_secret, &mut rng)
+            .is_err());
+
+        // Someone other than the reinitializer makes the successor. Everyone else makes a
+        // UserInitKey for the new ciphersuite with the credential they already have.
+        let creator_idx = test_utils::random_roster_index_with_exceptions(
+            group_state.roster.len(),
+            &[reinitializer_idx as usize],
+            &mut rng,
+        );
+        let init_keys: Vec<(u32, UserInitKey)> = (0..group_state.roster.len() as u32)
+            .filter(|idx| *idx != creator_idx)
+            .map(|idx| {
+                let mut user_init_key_id = [0u8; 16];
+                rng.fill_bytes(&mut user_init_key_id);
+                let uik = UserInitKey::new_from_random(
+                    &identity_keys[idx as usize],
+                    user_init_key_id.to_vec(),
+                    group_state.roster.0[idx as usize].clone().unwrap(),
+                    vec![new_cs],
+                    vec![new_version],
+                    &mut rng,
+                )
+                .unwrap();
+                (idx, uik)
+            })
+            .collect();
+        let creator = &ended_states[creator_idx as usize];
+        let public_init_keys: Vec<UserInitKey> =
+            init_keys.iter().map(|(_, uik)| uik.clone()).collect();
+
+        // Leaving a member out doesn't work
+        let new_path_secret = PathSecret::new_from_random(new_cs, &mut rng);
+        let res = creator.create_reinit_successor_handshake(
+            public_init_keys[1..].to_vec(),
+            new_path_secret,
+            &mut rng,
+        );
+        assert!(res.is_err(), "Successor was made without all the members");
+
+        let new_path_secret = PathSecret::new_from_random(new_cs, &mut rng);
+        let (init_handshake, welcomes, successor, _) = creator
+            .create_reinit_successor_handshake(public_init_keys, new_path_secret, &mut rng)
+            .unwrap();
+        assert_eq!(successor.protocol_version, new_version);
+        assert_eq!(successor.group_id, new_group_id);
+        assert_eq!(successor.roster.len(), group_state.roster.len());
+
+        // The other members join the successor and agree with the creator. Checking a few of them
+        // is plenty.
+        for ((idx, init_key), welcome) in init_keys.iter().zip(welcomes).take(3) {
+            let prelim_successor =
+                ended_states[*idx as usize].from_reinit_welcome(welcome, init_key.clone()).unwrap();
+            let (member_successor, _) =
+                prelim_successor.process_handshake(&init_handshake).unwrap();
+            assert_serialized_eq!(successor, member_successor, "GroupStates disagree after reinit");
+        }
+
+        // A successor that the creator made from scratch isn't linked to the old group, so nobody
+        // will join it
+        let fake_successor = GroupState::new_singleton_group(
+            new_cs,
+            new_version,
+            identity_keys[creator_idx as usize].clone(),
+            new_group_id,
+            group_state.roster.0[creator_idx as usize].clone().unwrap(),
+            &mut rng,
+        )
+        .unwrap();
+        let (idx, init_key) = &init_keys[0];
+        let (welcome, _) = Welcome::from_group_state(&fake_successor, init_key, &mut rng).unwrap();
+        let res = ended_states[*idx as usize].from_reinit_welcome(welcome, init_key.clone());
+        assert!(res.is_err(), "Joined a successor that isn't linked to the old group");
+    }
+
+    // Checks that a Commit of several proposals, some of which conflict, is processed identically
+    // by the committer, an existing member, and a newly added member, and that the removed member
+    // gets an Error::IAmRemoved
+    #[quickcheck]
+    fn commit_correctness(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        // Make a starting group of at least 3 members
+        let (committer_group, identity_keys) = test_utils::random_full_group_state(3, &mut rng);
+
+        // Pick someone to do an Update and someone to get removed. Neither is the committer.
+        let committer_idx = committer_group.roster_index.unwrap();
+        let updater_idx = test_utils::random_roster_index_with_exceptions(
+            committer_group.roster.len(),
+            &[committer_idx as usize],
+            &mut rng,
+        );
+        let removed_idx = test_utils::random_roster_index_with_exceptions(
+            committer_group.roster.len(),
+            &[committer_idx as usize, updater_idx as usize],
+            &mut rng,
+        );
+        let updater_group =
+            test_utils::change_self_index(&committer_group, &identity_keys, updater_idx);
+        let removed_group =
+            test_utils::change_self_index(&committer_group, &identity_keys, removed_idx);
+
+        // The updater proposes two Updates. Only the second one should be applied.
+        let (first_update_proposal, updater_group) =
+            updater_group.create_update_proposal(&mut rng).unwrap();
+        let (second_update_proposal, updater_group) =
+            updater_group.create_update_proposal(&mut rng).unwrap();
+        // The removed member proposes an Update too. This should be ignored.
+        let (removed_update_proposal, removed_group) =
+            removed_group.create_update_proposal(&mut rng).unwrap();
+        // The committer and the updater both propose to remove the same member
+        let first_remove_proposal = committer_group.create_remove_proposal(removed_idx).unwrap();
+        let second_remove_proposal = updater_group.create_remove_proposal(removed_idx).unwrap();
+
+        // The updater proposes to add a new member and Welcomes them
+        let (init_key, new_identity_key) =
+            test_utils::random_user_init_key(&mut rng, committer_group.cs);
+        let (welcome, welcome_info_hash) =
+            Welcome::from_group_state(&updater_group, &init_key, &mut rng).unwrap();
+        let add_proposal =
+            updater_group.create_add_proposal(init_key.clone(), &welcome_info_hash).unwrap();
+        let new_group = GroupState::from_welcome(welcome, new_identity_key, init_key).unwrap();
+
+        // Commit everything
+        let proposals = vec![
+            first_update_proposal,
+            removed_update_proposal,
+            first_remove_proposal,
+            add_proposal,
+            second_remove_proposal,
+            second_update_proposal,
+        ];
+        let new_path_secret = PathSecret::new_from_random(committer_group.cs, &mut rng);
+        let (commit_handshake, committer_group, _) = committer_group
+            .create_and_apply_commit_handshake(proposals, new_path_secret, &mut rng)
+            .unwrap();
+
+        // The removed member should find out that they're removed
+        match removed_group.process_handshake(&commit_handshake) {
+            Ok(_) => panic!("Removed party didn't give an error"),
+            Err(Error::IAmRemoved) => (),
+            Err(e) => panic!("Removed party didn't give an Error::IAmRemoved, instead got {}", e),
+        }
+
+        // Everyone else should agree
+        let (updater_group, _) = updater_group.process_handshake(&commit_handshake).unwrap();
+        let (new_group, _) = new_group.process_handshake(&commit_handshake).unwrap();
+        assert_serialized_eq!(committer_group, updater_group, "GroupStates disagree after Commit");
+        assert_serialized_eq!(committer_group, new_group, "GroupStates disagree after Commit");
+
+        // The removed member's slot was the only empty one, so that's where the new member goes
+        assert_eq!(new_group.roster_index, Some(removed_idx));
+
+        // Now run an update from the new member just to make sure everything is working
+        let new_path_secret = PathSecret::new_from_random(new_group.cs, &mut rng);
+        let (update_handshake, new_group, _) =
+            new_group.create_and_apply_update_handshake(new_path_secret, &mut rng).unwrap();
+        let (committer_group, _) = committer_group.process_handshake(&update_handshake).unwrap();
+        let (updater_group, _) = updater_group.process_handshake(&update_handshake).unwrap();
+        assert_serialized_eq!(
+            committer_group,
+            new_group,
+            "GroupStates disagree after post-Commit Update"
+        );
+        assert_serialized_eq!(
+            committer_group,
+            updater_group,
+            "GroupStates disagree after post-Commit Update"
+        );
+    }
+
+    // Checks that a member who announces that they're leaving ends up in the Left state, rather
+    // than getting Error::IAmRemoved, once their leave is honored by either a Commit or a Remove
+    #[quickcheck]
+    fn leave_correctness(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        // Make a starting group of at least 3 members
+        let (committer_group, identity_keys) = test_utils::random_full_group_state(3, &mut rng);
+
+        // Pick someone to leave and someone to watch
+        let committer_idx = committer_group.roster_index.unwrap();
+        let leaver_idx = test_utils::random_roster_index_with_exceptions(
+            committer_group.roster.len(),
+            &[committer_idx as usize],
+            &mut rng,
+        );
+        let other_idx = test_utils::random_roster_index_with_exceptions(
+            committer_group.roster.len(),
+            &[committer_idx as usize, leaver_idx as usize],
+            &mut rng,
+        );
+        let leaver_group =
+            test_utils::change_self_index(&committer_group, &identity_keys, leaver_idx);
+        let other_group =
+            test_utils::change_self_index(&committer_group, &identity_keys, other_idx);
+
+        // Announce the departure
+        let (leave_proposal, leaver_group) = leaver_group.create_leave_proposal().unwrap();
+        assert_eq!(leaver_group.get_membership_status(), MembershipStatus::Leaving);
+
+        // Honor it with a Remove. The leaver should be told they've left.
+        let new_path_secret = PathSecret::new_from_random(committer_group.cs, &mut rng);
+        let (remove_handshake, _, _) = committer_group
+            .create_and_apply_remove_handshake(leaver_idx, new_path_secret, &mut rng)
+            .unwrap();
+        let (left_group, _) = leaver_group.process_handshake(&remove_handshake).unwrap();
+        assert_eq!(left_group.get_membership_status(), MembershipStatus::Left);
+
+        // Same thing if the Remove arrives before the Update it follows. The leaver keeps what the
+        // Update got them.
+        let new_path_secret = PathSecret::new_from_random(committer_group.cs, &mut rng);
+        let (update_handshake, updated_group, _) =
+            committer_group.create_and_apply_update_handshake(new_path_secret, &mut rng).unwrap();
+        let new_path_secret = PathSecret::new_from_random(committer_group.cs, &mut rng);
+        let (later_remove_handshake, _, _) = updated_group
+            .create_and_apply_remove_handshake(leaver_idx, new_path_secret, &mut rng)
+            .unwrap();
+        let (buffering_group, _, _) =
+            leaver_group.process_or_buffer_handshake(later_remove_handshake).unwrap();
+        let (left_group, app_key_chains, failed) =
+            buffering_group.process_or_buffer_handshake(update_handshake).unwrap();
+        assert_eq!(left_group.get_membership_status(), MembershipStatus::Left);
+        assert_eq!(left_group.get_epoch(), updated_group.get_epoch());
+        assert_eq!(app_key_chains.len(), 1);
+        assert!(failed.is_empty());
+
+        // Now honor it with a Commit instead. Same thing should happen.
+        let new_path_secret = PathSecret::new_from_random(committer_group.cs, &mut rng);
+        let (commit_handshake, committer_group, _) = committer_group
+            .create_and_apply_commit_handshake(vec![leave_proposal], new_path_secret, &mut rng)
+            .unwrap();
+        let (left_group, mut left_app_key_chain) =
+            leaver_group.process_handshake(&commit_handshake).unwrap();
+        assert_eq!(left_group.get_membership_status(), MembershipStatus::Left);
+        // There's nothing to send or read with
+        assert!(encrypt_application_message(
+            b"hello".to_vec(),
+            &left_group,
+            &mut left_app_key_chain
+        )
+        .is_err());
+
+        // Everyone else carries on without the leaver
+        let (other_group, _) = other_group.process_handshake(&commit_handshake).unwrap();
+        assert_serialized_eq!(committer_group, other_group, "GroupStates disagree after leave");
+        assert!(!matches!(committer_group.roster.0.get(leaver_idx as usize), Some(Some(_))));
+
+        // The Left state is terminal
+        let new_path_secret = PathSecret::new_from_random(committer_group.cs, &mut rng);
+        let (update_handshake, _, _) =
+            committer_group.create_and_apply_update_handshake(new_path_secret, &mut rng).unwrap();
+        assert!(left_group.process_handshake(&update_handshake).is_err());
+        assert!(left_group.create_leave_proposal().is_err());
+    }
+
+    // Checks that a Commit that removes the committer or contains a stale proposal is rejected
+    #[quickcheck]
+    fn commit_invalid_proposals(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        // Make a starting group of at least 2 members, and the same group from another member's
+        // perspective
+        let (group_state1, identity_keys) = test_utils::random_full_group_state(2, &mut rng);
+        let committer_idx = group_state1.roster_index.unwrap();
+        let other_idx = test_utils::random_roster_index_with_exceptions(
+            group_state1.roster.len(),
+            &[committer_idx as usize],
+            &mut rng,
+        );
+        let group_state2 = test_utils::change_self_index(&group_state1, &identity_keys, other_idx);
+
+        // Someone else proposes to remove the committer. The committer can't commit that.
+        let remove_proposal = group_state2.create_remove_proposal(committer_idx).unwrap();
+        let new_path_secret = PathSecret::new_from_random(group_state1.cs, &mut rng);
+        let res = group_state1.create_and_apply_commit_handshake(
+            vec![remove_proposal],
+            new_path_secret,
+            &mut rng,
+        );
+        assert!(res.is_err(), "Commit removing the committer was accepted");
+
+        // Make an Update proposal, then move to the next epoch. The proposal is stale now.
+        let (update_proposal, _) = group_state2.create_update_proposal(&mut rng).unwrap();
+        let new_path_secret = PathSecret::new_from_random(group_state1.cs, &mut rng);
+        let (_, group_state1, _) =
+            group_state1.create_and_apply_update_handshake(new_path_secret, &mut rng).unwrap();
+        let new_path_secret = PathSecret::new_from_random(group_state1.cs, &mut rng);
+        let res = group_state1.create_and_apply_commit_handshake(
+            vec![update_proposal],
+            new_path_secret,
+            &mut rng,
+        );
+        assert!(res.is_err(), "Commit with a stale proposal was accepted");
+    }
+
+    // Checks that PSKs referenced by a Commit are required by everyone who processes it
+    #[quickcheck]
+    fn psk_correctness(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        // Make a starting group of at least 2 members, and the same group from another member's
+        // perspective
+        let (group_state1, identity_keys) = test_utils::random_full_group_state(2, &mut rng);
+        let committer_idx = group_state1.roster_index.unwrap();
+        let other_idx = test_utils::random_roster_index_with_exceptions(
+            group_state1.roster.len(),
+            &[committer_idx as usize],
+            &mut rng,
+        );
+        let group_state2 = test_utils::change_self_index(&group_state1, &identity_keys, other_idx);
+
+        // Both members hold the same PSK. Someone else holds a different PSK with the same ID.
+        let psk_id = b"out-of-band psk".to_vec();
+        let mut psk = [0u8; 32];
+        rng.fill_bytes(&mut psk);
+        let mut wrong_psk = [0u8; 32];
+        rng.fill_bytes(&mut wrong_psk);
+        let psks: HashMap<Vec<u8>, Vec<u8>> =
+            vec![(psk_id.clone(), psk.to_vec())].into_iter().collect();
+        let wrong_psks: HashMap<Vec<u8>, Vec<u8>> =
+            vec![(psk_id.clone(), wrong_psk.to_vec())].into_iter().collect();
+
+        // The non-committer proposes the PSK, twice. The duplicate should be ignored.
+        let psk_proposal = group_state2.create_psk_proposal(&psk_id).unwrap();
+        let proposals = vec![psk_proposal.clone(), psk_proposal];
+
+        // The committer can't commit without having the PSK
+        let new_path_secret = PathSecret::new_from_random(group_state1.cs, &mut rng);
+        let res = group_state1.create_and_apply_commit_handshake(
+            proposals.clone(),
+            new_path_secret.clone(),
+            &mut rng,
+        );
+        assert!(res.is_err(), "Commit was made without the PSK");
+
+        let (commit_handshake, new_group_state1, mut app_key_chain) = group_state1
+            .create_and_apply_commit_handshake_with_psks(
+                proposals,
+                &psks,
+                new_path_secret,
+                &mut rng,
+            )
+            .unwrap();
+
+        // A member with no PSK or the wrong PSK rejects the commit
+        let no_psks_ctx = ProcessingContext::new();
+        let psks_ctx = ProcessingContext::new().with_psks(&psks);
+        let wrong_psks_ctx = ProcessingContext::new().with_psks(&wrong_psks);
+        assert!(group_state2.process_handshake(&commit_handshake).is_err());
+        assert!(group_state2
+            .process_handshake_with_context(&commit_handshake, &wrong_psks_ctx)
+            .is_err());
+
+        // The PSKs are needed however the commit arrives. The key chain is only used to decrypt
+        // ciphertexts, so any will do.
+        let res = group_state2
+            .process_or_buffer_handshake_with_context(commit_handshake.clone(), &no_psks_ctx);
+        assert!(res.is_err(), "Buffering path accepted a Commit without the PSK");
+        let (buffered_group_state2, _, _) = group_state2
+            .process_or_buffer_handshake_with_context(commit_handshake.clone(), &psks_ctx)
+            .unwrap();
+        let message =
+            frame_plaintext(MlsContent::Handshake(commit_handshake.clone()), &group_state1)
+                .unwrap();
+        let res = group_state2.process_message_with_context(
+            message.clone(),
+            &mut app_key_chain,
+            &no_psks_ctx,
+        );
+        assert!(res.is_err(), "Framing path accepted a Commit without the PSK");
+        let res = group_state2.process_message_with_context(message, &mut app_key_chain, &psks_ctx);
+        let framed_group_state2 = match res {
+            Ok(ProcessedMessage::Handshake(group_state, _, _)) => group_state,
+            _ => panic!("Framing path rejected a Commit with the PSK"),
+        };
+        let group_state1 = new_group_state1;
+        assert_serialized_eq!(group_state1, buffered_group_state2, "GroupStates disagree");
+        assert_serialized_eq!(group_state1, framed_group_state2, "GroupStates disagree");
+
+        // A member with the right PSK agrees with the committer
+        let (group_state2, _) =
+            group_state2.process_handshake_with_context(&commit_handshake, &psks_ctx).unwrap();
+        assert_serialized_eq!(group_state1, group_state2, "GroupStates disagree after PSK Commit");
+        assert_eq!(
+            group_state1.export_secret(b"label", b"", 32).unwrap(),
+            group_state2.export_secret(b"label", b"", 32).unwrap()
+        );
+    }
+
+    // Checks that extensions set by a handshake are agreed on by existing members, delivered to
+    // new members, and bound into the key schedule
+    #[quickcheck]
+    fn extensions_correctness(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        // Make a starting group of at least 2 members, and the same group from another member's
+        // perspective
+        let (group_state1, identity_keys) = test_utils::random_full_group_state(2, &mut rng);
+        let other_idx = test_utils::random_roster_index_with_exceptions(
+            group_state1.roster.len(),
+            &[group_state1.roster_index.unwrap() as usize],
+            &mut rng,
+        );
+        let group_state2 = test_utils::change_self_index(&group_state1, &identity_keys, other_idx);
+
+        // Two extensions of the same type aren't allowed
+        let name = Extension::new(ExtensionType(1), b"book club".to_vec());
+        let other_name = Extension::new(ExtensionType(1), b"knitting circle".to_vec());
+        let admins = Extension::new(ExtensionType(2), other_idx.to_be_bytes().to_vec());
+        let res =
+            group_state1.create_and_apply_extensions_handshake(vec![name.clone(), other_name]);
+        assert!(res.is_err(), "Duplicate extension types were accepted");
+
+        // Set the extensions and make sure the other member agrees
+        let extensions = vec![name, admins];
+        let (extensions_handshake, group_state1, _) =
+            group_state1.create_and_apply_extensions_handshake(extensions.clone()).unwrap();
+        let (group_state2, _) = group_state2.process_handshake(&extensions_handshake).unwrap();
+        assert_eq!(group_state1.get_extensions(), extensions.as_slice());
+        assert_serialized_eq!(group_state1, group_state2, "GroupStates disagree after Extensions");
+
+        // A new member gets the extensions in their WelcomeInfo
+        let new_roster_index = group_state1.roster.len() as u32;
+        let (init_key, new_identity_key) =
+            test_utils::random_user_init_key(&mut rng, group_state1.cs);
+        let (welcome, welcome_info_hash) =
+            Welcome::from_group_state(&group_state1, &init_key, &mut rng).unwrap();
+        let new_group =
+            GroupState::from_welcome(welcome, new_identity_key, init_key.clone()).unwrap();
+        assert_eq!(new_group.get_extensions(), extensions.as_slice());
+        let (add_handshake, group_state1, _) = group_state1
+            .create_and_apply_add_handshake(new_roster_index, init_key, &welcome_info_hash)
+            .unwrap();
+        let (new_group, _) = new_group.process_handshake(&add_handshake).unwrap();
+        let (group_state2, _) = group_state2.process_handshake(&add_handshake).unwrap();
+        assert_serialized_eq!(group_state1, new_group, "GroupStates disagree after Add");
+
+        // A member who disagrees about the extensions can't follow the group
+        let mut confused_group = group_state2.clone();
+        confused_group.extensions.pop();
+        let new_path_secret = PathSecret::new_from_random(group_state1.cs, &mut rng);
+        let (update_handshake, _, _) =
+            group_state1.create_and_apply_update_handshake(new_path_secret, &mut rng).unwrap();
+        assert!(group_state2.process_handshake(&update_handshake).is_ok());
+        assert!(confused_group.process_handshake(&update_handshake).is_err());
+    }
+
     // File: messages.bin
     //
     // struct {
@@ -763,39 +2692,27 @@ mod test {
     // * The test cases for any supported ciphersuites should parse successfully
     // * All of the above parsed values should survive a marshal / unmarshal round-trip
 
+    // Everything we add to a message goes at the end and is left out when absent, so the messages
+    // parse as our types unchanged. We read each one out of its length-prefixed field, since
+    // that's where the message ends.
     #[derive(Debug, Deserialize, Serialize)]
     struct MessagesCase {
         cipher_suite: &'static CipherSuite,
         signature_scheme: &'static SignatureScheme,
-        _user_init_key_len: u32,
+        #[serde(rename = "user_init_key__bound_u32")]
         user_init_key: UserInitKey,
-        _welcome_info_len: u32,
+        #[serde(rename = "welcome_info__bound_u32")]
         welcome_info: WelcomeInfo,
         _welcome_len: u32,
         welcome: Welcome,
-        _add_len: u32,
+        #[serde(rename = "add__bound_u32")]
         add: Handshake,
-        _update_len: u32,
+        #[serde(rename = "update__bound_u32")]
         update: Handshake,
-        _remove_len: u32,
+        #[serde(rename = "remove__bound_u32")]
         remove: Handshake,
     }
 
-    impl CryptoUpcast for MessagesCase {
-        fn upcast_crypto_values(&mut self, ctx: &CryptoCtx) -> Result<CryptoCtx, Error> {
-            let new_ctx =
-                ctx.set_cipher_suite(self.cipher_suite).set_signature_scheme(self.signature_scheme);
-            self.user_init_key.upcast_crypto_values(&new_ctx)?;
-            self.welcome_info.upcast_crypto_values(&new_ctx)?;
-            self.welcome.upcast_crypto_values(&new_ctx)?;
-            self.add.upcast_crypto_values(&new_ctx)?;
-            self.update.upcast_crypto_values(&new_ctx)?;
-            self.remove.upcast_crypto_values(&new_ctx)?;
-
-            Ok(*ctx)
-        }
-    }
-
     #[derive(Debug, Deserialize, Serialize)]
     struct MessagesTestVectors {
         epoch: u32,
@@ -814,31 +2731,16 @@ mod test {
         #[serde(rename = "random__bound_u8")]
         random: Vec<u8>,
         uik_all_scheme: &'static SignatureScheme,
-        _user_init_key_all_len: u32,
+        #[serde(rename = "user_init_key_all__bound_u32")]
         user_init_key_all: UserInitKey,
 
         case_p256_p256: MessagesCase,
         case_x25519_ed25519: MessagesCase,
     }
 
-    impl CryptoUpcast for MessagesTestVectors {
-        fn upcast_crypto_values(&mut self, ctx: &CryptoCtx) -> Result<CryptoCtx, Error> {
-            let new_ctx = ctx.set_signature_scheme(self.uik_all_scheme);
-            self.user_init_key_all.upcast_crypto_values(&new_ctx)?;
-
-            let new_ctx = ctx.set_cipher_suite(&P256_SHA256_AES128GCM);
-            self.case_p256_p256.upcast_crypto_values(&new_ctx)?;
-
-            let new_ctx = ctx.set_cipher_suite(&X25519_SHA256_AES128GCM);
-            self.case_x25519_ed25519.upcast_crypto_values(&new_ctx)?;
-
-            Ok(*ctx)
-        }
-    }
-
-    // Tests our code against the official key schedule test vector. All this has to do is make
-    // sure that the given test vector parses without error, and that the bytes are the same after
-    // being reserialized
+    // Tests our code against the official message test vectors. All this has to do is make sure
+    // that the given test vectors parse without error, and that the bytes are the same after being
+    // reserialized
     #[test]
     fn official_message_parsing_kat() {
         // Read in the file. We'll use these bytes at the end to compare to the reserialization of
@@ -847,16 +2749,13 @@ mod test {
         let mut f = std::fs::File::open("test_vectors/messages.bin").unwrap();
         f.read_to_end(&mut original_bytes).unwrap();
 
-        // Deserialize the file's contents
+        // Deserialize the file's contents. We can't do the upcasting here. The documentation lied
+        // when it said that UserInitKeys are validly signed. They are [0xd6; 32], which is not a
+        // valid Ed25519 signature.
         let test_vec = {
             let mut cursor = original_bytes.as_slice();
             let mut deserializer = TlsDeserializer::from_reader(&mut cursor);
-            let raw = MessagesTestVectors::deserialize(&mut deserializer).unwrap();
-            // We can't do the upcasting here. The documentation lied when it said that
-            // UserInitKeys are validly signed. They are [0xd6; 32], which is not a valid Ed25519
-            // signature. So skip this step and call it a mission success.
-            //raw.upcast_crypto_values(&CryptoCtx::new()).unwrap();
-            raw
+            MessagesTestVectors::deserialize(&mut deserializer).unwrap()
         };
 
         // Reserialized the deserialized input and make sure it's the same as the original
@@ -0,0 +1,510 @@
+//! Defines `UserInitKeyStore`, which keeps the private halves of a client's `UserInitKey`s until
+//! they're used to join a group. Section 6 of the spec says that a client MUST make sure that a
+//! `UserInitKey`'s ID uniquely identifies it among all the `UserInitKey`s the client has made. A
+//! store makes sure of this by picking the IDs itself. It also makes sure that each `UserInitKey`
+//! is only ever used to join one group, by deleting it once it has been. The exception is the
+//! store's last-resort `UserInitKey`, which is kept so that the client can still be added to groups
+//! once all of its single-use `UserInitKey`s have been used up.
+
+use crate::{
+    clock::{Clock, SystemClock},
+    credential::Credential,
+    crypto::{ciphersuite::CipherSuite, rng::CryptoRng, sig::SigSecretKey},
+    error::Error,
+    group_state::{GroupState, MultiWelcome, Welcome},
+    handshake::{Lifetime, ProtocolVersion, UserInitKey, UserInitKeyBuilder},
+};
+
+use std::collections::{HashMap, HashSet};
+
+/// The length of the IDs of the `UserInitKey`s made by a `UserInitKeyStore`, in bytes
+const USER_INIT_KEY_ID_SIZE: usize = 16;
+
+/// A client's collection of unused `UserInitKey`s, along with their private keys
+pub struct UserInitKeyStore {
+    /// The client's identity key, which every `UserInitKey` is signed with
+    identity_key: SigSecretKey,
+
+    /// The client's credential, which goes in every `UserInitKey`
+    credential: Credential,
+
+    /// The `UserInitKey`s that haven't been used yet, indexed by ID. These all have their private
+    /// keys. This includes the last-resort `UserInitKey`, if there is one.
+    unused_init_keys: HashMap<Vec<u8>, UserInitKey>,
+
+    /// The ID of the current last-resort `UserInitKey`, if one has been generated. This key is in
+    /// `unused_init_keys`, and stays there no matter how many times it's used.
+    last_resort_id: Option<Vec<u8>>,
+
+    /// The ID of every `UserInitKey` this store has ever made, used or not. An ID is never issued
+    /// twice.
+    issued_ids: HashSet<Vec<u8>>,
+}
+
+impl UserInitKeyStore {
+    /// Makes an empty store for the client with the given identity key and credential
+    ///
+    /// Requires: `identity_key` is the signing key of `credential`
+    pub fn new(identity_key: SigSecretKey, credential: Credential) -> UserInitKeyStore {
+        UserInitKeyStore {
+            identity_key,
+            credential,
+            unused_init_keys: HashMap::new(),
+            last_resort_id: None,
+            issued_ids: HashSet::new(),
+        }
+    }
+
+    /// Generates a new `UserInitKey` with the given ciphersuites, supported versions, and
+    /// lifetime, and a fresh ID that this store has never issued before. The store keeps the
+    /// private keys.
+    ///
+    /// Returns: `Ok(init_key)` on success, where `init_key` is the public part of the new
+    /// `UserInitKey`, which can be published. Otherwise, returns the same errors as
+    /// `UserInitKeyBuilder::build`.
+    pub fn generate<R>(
+        &mut self,
+        cipher_suites: Vec<&'static CipherSuite>,
+        supported_versions: Vec<ProtocolVersion>,
+        lifetime: Lifetime,
+        csprng: &mut R,
+    ) -> Result<UserInitKey, Error>
+    where
+        R: CryptoRng,
+    {
+        let user_init_key_id = self.fresh_id(csprng)?;
+        let init_key = UserInitKeyBuilder::new(
+            user_init_key_id,
+            self.credential.clone(),
+            cipher_suites,
+            supported_versions,
+        )
+        .with_lifetime(lifetime)
+        .build(&self.identity_key, csprng)?;
+
+        Ok(self.insert(init_key))
+    }
+
+    /// Generates a new last-resort `UserInitKey` with the given ciphersuites, supported versions,
+    /// and lifetime, and a fresh ID. This replaces the store's current last-resort `UserInitKey`,
+    /// if there is one, so `Welcome`s encrypted to the old one can't be opened anymore. Clients
+    /// should call this often enough that there's always a last-resort `UserInitKey` within its
+    /// lifetime.
+    ///
+    /// Returns: `Ok(init_key)` on success, where `init_key` is the public part of the new
+    /// `UserInitKey`, which can be published. Otherwise, returns the same errors as
+    /// `UserInitKey::new_last_resort_from_random`.
+    pub fn generate_last_resort<R>(
+        &mut self,
+        cipher_suites: Vec<&'static CipherSuite>,
+        supported_versions: Vec<ProtocolVersion>,
+        lifetime: Lifetime,
+        csprng: &mut R,
+    ) -> Result<UserInitKey, Error>
+    where
+        R: CryptoRng,
+    {
+        let user_init_key_id = self.fresh_id(csprng)?;
+        let init_key = UserInitKey::new_last_resort_from_random(
+            &self.identity_key,
+            user_init_key_id.clone(),
+            self.credential.clone(),
+            cipher_suites,
+            supported_versions,
+            lifetime,
+            csprng,
+        )?;
+
+        // TODO: Make this erasure secure
+        if let Some(old_id) = self.last_resort_id.replace(user_init_key_id) {
+            self.unused_init_keys.remove(&old_id);
+        }
+        Ok(self.insert(init_key))
+    }
+
+    /// Returns the public part of the store's current last-resort `UserInitKey`, if there is one
+    pub fn get_last_resort(&self) -> Option<UserInitKey> {
+        self.last_resort_id.as_ref().map(|id| {
+            let mut public_init_key = self.unused_init_keys[id].clone();
+            public_init_key.private_keys = None;
+            public_init_key
+        })
+    }
+
+    /// Picks an ID that this store has never issued before
+    ///
+    /// Returns: `Ok(id)` on success. If the RNG fails, returns an `Error::OutOfEntropy`.
+    fn fresh_id<R: CryptoRng>(&self, csprng: &mut R) -> Result<Vec<u8>, Error> {
+        // Pick random IDs until we find one that's new. A collision is astronomically unlikely,
+        // but uniqueness is a MUST, so we check anyway.
+        loop {
+            let mut id = vec![0u8; USER_INIT_KEY_ID_SIZE];
+            csprng.try_fill_bytes(&mut id).map_err(|_| Error::OutOfEntropy)?;
+            if !self.issued_ids.contains(&id) {
+                return Ok(id);
+            }
+        }
+    }
+
+    /// Records the given freshly generated `UserInitKey` as issued and unused
+    ///
+    /// Returns: The public part of `init_key`
+    fn insert(&mut self, init_key: UserInitKey) -> UserInitKey {
+        let mut public_init_key = init_key.clone();
+        public_init_key.private_keys = None;
+
+        let user_init_key_id = init_key.user_init_key_id.clone();
+        self.issued_ids.insert(user_init_key_id.clone());
+        self.unused_init_keys.insert(user_init_key_id, init_key);
+
+        public_init_key
+    }
+
+    /// Returns whether this store holds an unused `UserInitKey` with the given ID
+    pub fn contains(&self, user_init_key_id: &[u8]) -> bool {
+        self.unused_init_keys.contains_key(user_init_key_id)
+    }
+
+    /// Returns the number of unused `UserInitKey`s in this store, counting the last-resort one
+    pub fn len(&self) -> usize {
+        self.unused_init_keys.len()
+    }
+
+    /// Returns whether this store has no unused `UserInitKey`s
+    pub fn is_empty(&self) -> bool {
+        self.unused_init_keys.is_empty()
+    }
+
+    /// Deletes the unused single-use `UserInitKey` with the given ID, e.g., because it was
+    /// revoked. Its ID is still never issued again. The last-resort `UserInitKey` can't be
+    /// discarded, only replaced with `generate_last_resort`.
+    ///
+    /// Returns: `true` iff there was such a `UserInitKey`
+    pub fn discard(&mut self, user_init_key_id: &[u8]) -> bool {
+        if self.is_last_resort_id(user_init_key_id) {
+            return false;
+        }
+        self.unused_init_keys.remove(user_init_key_id).is_some()
+    }
+
+    /// Returns whether the given ID is that of the store's current last-resort `UserInitKey`
+    fn is_last_resort_id(&self, user_init_key_id: &[u8]) -> bool {
+        self.last_resort_id.as_deref() == Some(user_init_key_id)
+    }
+
+    /// Deletes the `UserInitKey` with the given ID now that it has been used to join a group,
+    /// unless it's the last-resort `UserInitKey`
+    fn consume(&mut self, user_init_key_id: &[u8]) {
+        if !self.is_last_resort_id(user_init_key_id) {
+            // TODO: Make this erasure secure
+            self.unused_init_keys.remove(user_init_key_id);
+        }
+    }
+
+    /// Creates a new `GroupState` from a `Welcome` message that was encrypted to one of the
+    /// `UserInitKey`s in this store. If this succeeds, that `UserInitKey` is deleted, so it can't
+    /// be used again, unless it's the last-resort `UserInitKey`. If this fails, the store is left
+    /// unchanged. A group joined with the last-resort `UserInitKey` reports that an `Update` is
+    /// due, via `GroupState::update_due`.
+    ///
+    /// Returns: `Ok(group_state)` on success, where `group_state` is the same as in
+    /// `GroupState::from_welcome`. Returns an `Error::ValidationError` if the `Welcome` isn't for
+    /// an unused `UserInitKey` in this store. Otherwise, returns the same errors as
+    /// `GroupState::from_welcome`.
+    pub fn group_from_welcome(&mut self, welcome: Welcome) -> Result<GroupState, Error> {
+        self.group_from_welcome_with_clock(welcome, &SystemClock)
+    }
+
+    /// Like `group_from_welcome`, but checks the `UserInitKey`'s lifetime against the given
+    /// `Clock`
+    ///
+    /// Returns: The same as `group_from_welcome`. If the current time is outside of the
+    /// `UserInitKey`'s lifetime, returns an `Error::ValidationError`.
+    pub fn group_from_welcome_with_clock<C: Clock>(
+        &mut self,
+        welcome: Welcome,
+        clock: &C,
+    ) -> Result<GroupState, Error> {
+        let user_init_key_id = welcome.get_user_init_key_id().to_vec();
+        let init_key = self
+            .unused_init_keys
+            .get(&user_init_key_id)
+            .ok_or(Error::ValidationError("Welcome is not for an unused UserInitKey in the store"))?
+            .clone();
+
+        let group_state = GroupState::from_welcome_with_clock(
+            welcome,
+            self.identity_key.clone(),
+            init_key,
+            clock,
+        )?;
+        self.consume(&user_init_key_id);
+
+        Ok(group_state)
+    }
+
+    /// Creates a new `GroupState` from a `MultiWelcome` message that was encrypted to one of the
+    /// `UserInitKey`s in this store. Like in `group_from_welcome`, that `UserInitKey` is deleted
+    /// iff this succeeds and it's not the last-resort `UserInitKey`.
+    ///
+    /// Returns: `Ok(group_state)` on success, where `group_state` is the same as in
+    /// `GroupState::from_multi_welcome`. Returns an `Error::ValidationError` if none of the
+    /// `MultiWelcome`'s recipients is an unused `UserInitKey` in this store. Otherwise, returns
+    /// the same errors as `GroupState::from_multi_welcome`.
+    pub fn group_from_multi_welcome(
+        &mut self,
+        multi_welcome: MultiWelcome,
+    ) -> Result<GroupState, Error> {
+        self.group_from_multi_welcome_with_clock(multi_welcome, &SystemClock)
+    }
+
+    /// Like `group_from_multi_welcome`, but checks the `UserInitKey`'s lifetime against the given
+    /// `Clock`
+    ///
+    /// Returns: The same as `group_from_multi_welcome`. If the current time is outside of the
+    /// `UserInitKey`'s lifetime, returns an `Error::ValidationError`.
+    pub fn group_from_multi_welcome_with_clock<C: Clock>(
+        &mut self,
+        multi_welcome: MultiWelcome,
+        clock: &C,
+    ) -> Result<GroupState, Error> {
+        let user_init_key_id = multi_welcome
+            .get_user_init_key_ids()
+            .into_iter()
+            .find(|id| self.unused_init_keys.contains_key(*id))
+            .ok_or(Error::ValidationError(
+                "MultiWelcome is not for an unused UserInitKey in the store",
+            ))?
+            .to_vec();
+        let init_key = self.unused_init_keys[&user_init_key_id].clone();
+
+        let group_state = GroupState::from_multi_welcome_with_clock(
+            multi_welcome,
+            self.identity_key.clone(),
+            init_key,
+            clock,
+        )?;
+        self.consume(&user_init_key_id);
+
+        Ok(group_state)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::UserInitKeyStore;
+    use crate::{
+        clock::FixedClock,
+        error::Error,
+        group_state::{MultiWelcome, Welcome},
+        handshake::{Lifetime, UserInitKey, MLS_DUMMY_VERSION},
+        ratchet_tree::PathSecret,
+        test_utils,
+        tls_de::TlsDeserializer,
+        tls_ser,
+        upcast::{CryptoCtx, CryptoUpcast},
+    };
+
+    use std::collections::HashSet;
+
+    use quickcheck_macros::quickcheck;
+    use rand::SeedableRng;
+    use serde::de::Deserialize;
+
+    // Checks that a store issues unique IDs, and that each of its UserInitKeys can be used to join
+    // exactly one group
+    #[quickcheck]
+    fn init_key_store_correctness(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let (group_state, _) = test_utils::random_full_group_state(3, &mut rng);
+        let cs = group_state.cs;
+
+        let (credential, identity_key) = test_utils::random_basic_credential(&mut rng);
+        let mut store = UserInitKeyStore::new(identity_key, credential);
+        let init_keys: Vec<_> = (0..5)
+            .map(|_| {
+                store
+                    .generate(
+                        vec![cs],
+                        vec![MLS_DUMMY_VERSION],
+                        Lifetime::new(0, u64::MAX),
+                        &mut rng,
+                    )
+                    .unwrap()
+            })
+            .collect();
+        assert_eq!(store.len(), 5);
+
+        // Every ID is distinct, and none of the private keys leave the store
+        let ids: HashSet<&[u8]> = init_keys.iter().map(|uik| uik.get_user_init_key_id()).collect();
+        assert_eq!(ids.len(), init_keys.len());
+        assert!(init_keys.iter().all(|uik| uik.private_keys.is_none()));
+
+        // A Welcome consumes its UserInitKey, so it can only be used once. We serialize the
+        // Welcome so that we can deliver it twice.
+        let (welcome, _) =
+            Welcome::from_group_state(&group_state, &init_keys[0], &mut rng).unwrap();
+        let welcome_bytes = tls_ser::serialize_to_bytes(&welcome).unwrap();
+        store.group_from_welcome(welcome).unwrap();
+        assert!(!store.contains(init_keys[0].get_user_init_key_id()));
+        assert_eq!(store.len(), 4);
+        let replayed_welcome = {
+            let mut cursor = welcome_bytes.as_slice();
+            let mut deserializer = TlsDeserializer::from_reader(&mut cursor);
+            let mut w = Welcome::deserialize(&mut deserializer).unwrap();
+            w.upcast_crypto_values(&CryptoCtx::new()).unwrap();
+            w
+        };
+        match store.group_from_welcome(replayed_welcome) {
+            Err(Error::ValidationError(_)) => (),
+            Err(e) => panic!("Replayed Welcome gave the wrong error: {}", e),
+            Ok(_) => panic!("UserInitKey was consumed twice"),
+        }
+
+        // A Welcome that fails to open doesn't consume its UserInitKey. This one is encrypted to
+        // someone else's UserInitKey with the same ID as one of ours.
+        let (other_credential, other_identity_key) = test_utils::random_basic_credential(&mut rng);
+        let impostor_key = UserInitKey::new_from_random(
+            &other_identity_key,
+            init_keys[2].get_user_init_key_id().to_vec(),
+            other_credential,
+            vec![cs],
+            vec![MLS_DUMMY_VERSION],
+            &mut rng,
+        )
+        .unwrap();
+        let (bad_welcome, _) =
+            Welcome::from_group_state(&group_state, &impostor_key, &mut rng).unwrap();
+        assert!(store.group_from_welcome(bad_welcome).is_err());
+        assert!(store.contains(init_keys[2].get_user_init_key_id()));
+
+        // A discarded UserInitKey can't be used either
+        assert!(store.discard(init_keys[1].get_user_init_key_id()));
+        let (welcome, _) =
+            Welcome::from_group_state(&group_state, &init_keys[1], &mut rng).unwrap();
+        assert!(store.group_from_welcome(welcome).is_err());
+
+        // A MultiWelcome consumes whichever of its recipients is in the store
+        let (other_key, _) = test_utils::random_user_init_key(&mut rng, cs);
+        let (multi_welcome, _) = MultiWelcome::from_group_state(
+            &group_state,
+            &[other_key, init_keys[3].clone()],
+            &mut rng,
+        )
+        .unwrap();
+        store.group_from_multi_welcome(multi_welcome).unwrap();
+        assert!(!store.contains(init_keys[3].get_user_init_key_id()));
+        assert_eq!(store.len(), 2);
+
+        // A UserInitKey is only accepted within its lifetime, according to the caller's clock.
+        // Being rejected doesn't consume it.
+        let short_lived_key = store
+            .generate(vec![cs], vec![MLS_DUMMY_VERSION], Lifetime::new(1000, 2000), &mut rng)
+            .unwrap();
+        let (welcome, _) =
+            Welcome::from_group_state(&group_state, &short_lived_key, &mut rng).unwrap();
+        let welcome_bytes = tls_ser::serialize_to_bytes(&welcome).unwrap();
+        match store.group_from_welcome_with_clock(welcome, &FixedClock(2001)) {
+            Err(Error::ValidationError(_)) => (),
+            Err(e) => panic!("Expired UserInitKey gave the wrong error: {}", e),
+            Ok(_) => panic!("Expired UserInitKey was accepted"),
+        }
+        assert!(store.contains(short_lived_key.get_user_init_key_id()));
+        let welcome = {
+            let mut cursor = welcome_bytes.as_slice();
+            let mut deserializer = TlsDeserializer::from_reader(&mut cursor);
+            let mut w = Welcome::deserialize(&mut deserializer).unwrap();
+            w.upcast_crypto_values(&CryptoCtx::new()).unwrap();
+            w
+        };
+        store.group_from_welcome_with_clock(welcome, &FixedClock(1500)).unwrap();
+        assert!(!store.contains(short_lived_key.get_user_init_key_id()));
+    }
+
+    // Checks that the last-resort UserInitKey survives being used to join several groups, that
+    // those groups ask for an Update until one is made, and that it can be replaced but not
+    // discarded
+    #[quickcheck]
+    fn last_resort_correctness(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let (group_state, _) = test_utils::random_full_group_state(3, &mut rng);
+        let cs = group_state.cs;
+
+        let (credential, identity_key) = test_utils::random_basic_credential(&mut rng);
+        let mut store = UserInitKeyStore::new(identity_key, credential);
+        let single_use_key = store
+            .generate(vec![cs], vec![MLS_DUMMY_VERSION], Lifetime::new(0, u64::MAX), &mut rng)
+            .unwrap();
+        let last_resort_key = store
+            .generate_last_resort(
+                vec![cs],
+                vec![MLS_DUMMY_VERSION],
+                Lifetime::new(0, u64::MAX),
+                &mut rng,
+            )
+            .unwrap();
+        assert!(last_resort_key.is_last_resort());
+        assert!(!single_use_key.is_last_resort());
+        assert_eq!(store.len(), 2);
+
+        // Use the last-resort key to join the same group twice. Both times, the key stays in the
+        // store, and the new member is told to update.
+        for _ in 0..2 {
+            let (welcome, welcome_info_hash) =
+                Welcome::from_group_state(&group_state, &last_resort_key, &mut rng).unwrap();
+            let new_roster_index = group_state.roster.len() as u32;
+            let (add, adder_group, _) = group_state
+                .create_and_apply_add_handshake(
+                    new_roster_index,
+                    last_resort_key.clone(),
+                    &welcome_info_hash,
+                )
+                .unwrap();
+            let new_group = store.group_from_welcome(welcome).unwrap();
+            let (new_group, _) = new_group.process_handshake(&add).unwrap();
+
+            assert!(store.contains(last_resort_key.get_user_init_key_id()));
+            assert!(new_group.update_due());
+            assert!(!adder_group.update_due());
+
+            // Once the new member updates, they're fine
+            let new_path_secret = PathSecret::new_from_random(cs, &mut rng);
+            let (_, new_group, _) =
+                new_group.create_and_apply_update_handshake(new_path_secret, &mut rng).unwrap();
+            assert!(!new_group.update_due());
+        }
+        assert_eq!(store.len(), 2);
+
+        // Joining with a single-use key doesn't call for an Update
+        let (welcome, welcome_info_hash) =
+            Welcome::from_group_state(&group_state, &single_use_key, &mut rng).unwrap();
+        let (add, _, _) = group_state
+            .create_and_apply_add_handshake(
+                group_state.roster.len() as u32,
+                single_use_key.clone(),
+                &welcome_info_hash,
+            )
+            .unwrap();
+        let new_group = store.group_from_welcome(welcome).unwrap();
+        let (new_group, _) = new_group.process_handshake(&add).unwrap();
+        assert!(!new_group.update_due());
+        assert!(!store.contains(single_use_key.get_user_init_key_id()));
+
+        // The last-resort key can't be discarded, but it can be replaced
+        assert!(!store.discard(last_resort_key.get_user_init_key_id()));
+        let new_last_resort_key = store
+            .generate_last_resort(
+                vec![cs],
+                vec![MLS_DUMMY_VERSION],
+                Lifetime::new(0, u64::MAX),
+                &mut rng,
+            )
+            .unwrap();
+        assert!(!store.contains(last_resort_key.get_user_init_key_id()));
+        assert_eq!(
+            store.get_last_resort().unwrap().get_user_init_key_id(),
+            new_last_resort_key.get_user_init_key_id()
+        );
+        assert_eq!(store.len(), 1);
+    }
+}
@@ -0,0 +1,443 @@
+//! Defines `KeyChainManager`, which holds a group's `ApplicationKeyChain`s across epochs. Every
+//! `Handshake` gives the group a new key chain, but application messages sent under the old one may
+//! still be in flight. The manager keeps the key chains of a few past epochs around for those
+//! messages, and throws them away once they've been retired for long enough or have decrypted
+//! enough messages.
+
+use crate::{
+    application::{
+        decrypt_application_message_with_roster, encrypt_application_message, ApplicationKeyChain,
+        ApplicationMessage, PrivateApplicationMessage,
+    },
+    clock::{Clock, SystemClock},
+    credential::Roster,
+    error::Error,
+    group_state::GroupState,
+};
+
+/// By default, how many past epochs' key chains a `KeyChainManager` keeps
+pub const DEFAULT_MAX_OLD_EPOCHS: usize = 2;
+
+/// By default, how long a past epoch's key chain is kept after being replaced, in seconds
+pub const DEFAULT_MAX_OLD_AGE: u64 = 60 * 60;
+
+/// By default, how many messages a past epoch's key chain may decrypt after being replaced
+pub const DEFAULT_MAX_OLD_MESSAGES: u32 = 1000;
+
+/// An `ApplicationKeyChain` along with what's needed to know when to get rid of it
+struct ManagedKeyChain {
+    key_chain: ApplicationKeyChain,
+
+    /// The roster of the key chain's epoch. Messages are verified against this rather than the
+    /// current roster, since their sender may have been removed in the meantime.
+    roster: Roster,
+
+    /// When this key chain was replaced by a newer one, in seconds since the Unix epoch. This is
+    /// `None` iff this is the current key chain.
+    retired_at: Option<u64>,
+
+    /// How many messages this key chain has decrypted since it was replaced
+    messages_since_retired: u32,
+}
+
+/// Holds the `ApplicationKeyChain` of a group's current epoch, as well as those of a few past
+/// epochs, and picks the right one for each incoming `ApplicationMessage`. A past epoch's key
+/// chain is deleted when there are too many newer ones, when it's been too long since it was
+/// replaced, or when it's decrypted too many messages since it was replaced, whichever comes first.
+pub struct KeyChainManager<C: Clock = SystemClock> {
+    /// Where the manager gets the time from
+    clock: C,
+
+    /// The key chains, oldest first. The last one is the current one.
+    key_chains: Vec<ManagedKeyChain>,
+
+    /// The most past epochs' key chains to keep
+    max_old_epochs: usize,
+
+    /// The most seconds a past epoch's key chain is kept after being replaced
+    max_old_age: u64,
+
+    /// The most messages a past epoch's key chain may decrypt after being replaced
+    max_old_messages: u32,
+}
+
+impl KeyChainManager<SystemClock> {
+    /// Makes an empty `KeyChainManager` that uses the system clock and the default limits
+    pub fn new() -> KeyChainManager<SystemClock> {
+        KeyChainManager::with_clock(SystemClock)
+    }
+}
+
+impl Default for KeyChainManager<SystemClock> {
+    fn default() -> KeyChainManager<SystemClock> {
+        KeyChainManager::new()
+    }
+}
+
+impl<C: Clock> KeyChainManager<C> {
+    /// Makes an empty `KeyChainManager` that uses the given clock and the default limits
+    pub fn with_clock(clock: C) -> KeyChainManager<C> {
+        KeyChainManager {
+            clock,
+            key_chains: Vec::new(),
+            max_old_epochs: DEFAULT_MAX_OLD_EPOCHS,
+            max_old_age: DEFAULT_MAX_OLD_AGE,
+            max_old_messages: DEFAULT_MAX_OLD_MESSAGES,
+        }
+    }
+
+    /// Sets how many past epochs' key chains are kept, how many seconds each is kept for after
+    /// being replaced, and how many messages each may decrypt after being replaced. Key chains
+    /// that are already past these limits are deleted right away.
+    pub fn set_limits(&mut self, max_old_epochs: usize, max_old_age: u64, max_old_messages: u32) {
+        self.max_old_epochs = max_old_epochs;
+        self.max_old_age = max_old_age;
+        self.max_old_messages = max_old_messages;
+        self.expire();
+    }
+
+    /// Makes the given key chain the current one. This is what to do with the key chain that comes
+    /// out of creating or processing a `Handshake`, along with the `GroupState` that came out with
+    /// it. That state's roster is kept with the key chain, for verifying messages sent in its
+    /// epoch. The previous current key chain is kept around for late messages, unless it's from
+    /// the same epoch, as happens after `GroupState::resolve_fork`, in which case it's replaced.
+    ///
+    /// Returns: `Ok(())` on success. Returns an `Error::ValidationError` if `key_chain` is from an
+    /// earlier epoch than the current key chain, or from a different group or epoch than
+    /// `group_state`.
+    pub fn insert(
+        &mut self,
+        key_chain: ApplicationKeyChain,
+        group_state: &GroupState,
+    ) -> Result<(), Error> {
+        key_chain.validate_against_group_state(group_state)?;
+        if key_chain.get_epoch() != group_state.get_epoch() {
+            return Err(Error::ValidationError("Key chain is not from the group's epoch"));
+        }
+        if let Some(current) = self.key_chains.last() {
+            let current = &current.key_chain;
+            if key_chain.get_group_id() != current.get_group_id() {
+                return Err(Error::ValidationError("Key chain belongs to a different group"));
+            }
+            if key_chain.get_epoch() < current.get_epoch() {
+                return Err(Error::ValidationError("Key chain is older than the current one"));
+            }
+            if key_chain.get_epoch() == current.get_epoch() {
+                // TODO: Make this erasure secure
+                self.key_chains.pop();
+            }
+        }
+
+        // The current key chain, if there still is one, is now a past epoch's
+        let now = self.clock.now();
+        if let Some(previous) = self.key_chains.last_mut() {
+            previous.retired_at = Some(now);
+        }
+        self.key_chains.push(ManagedKeyChain {
+            key_chain,
+            roster: group_state.roster.clone(),
+            retired_at: None,
+            messages_since_retired: 0,
+        });
+        self.expire();
+
+        Ok(())
+    }
+
+    /// Returns the current key chain, if there is one
+    pub fn get_current(&mut self) -> Option<&mut ApplicationKeyChain> {
+        self.key_chains.last_mut().map(|managed| &mut managed.key_chain)
+    }
+
+    /// Returns the epochs of all the key chains this manager holds, oldest first
+    pub fn get_epochs(&self) -> Vec<u32> {
+        self.key_chains.iter().map(|managed| managed.key_chain.get_epoch()).collect()
+    }
+
+    /// Encrypts the given plaintext with the current key chain. This is the same as
+    /// `encrypt_application_message`, except that it makes sure the key chain is from the
+    /// group's current epoch.
+    ///
+    /// Returns: `Ok(app_message)` on success. Returns an `Error::ValidationError` if there is no
+    /// current key chain or it's not from `group_state`'s epoch. Otherwise, returns the same
+    /// errors as `encrypt_application_message`.
+    pub fn encrypt(
+        &mut self,
+        plaintext: Vec<u8>,
+        group_state: &GroupState,
+    ) -> Result<ApplicationMessage, Error> {
+        let current = self
+            .key_chains
+            .last_mut()
+            .ok_or(Error::ValidationError("Key chain manager has no key chains"))?;
+        if current.key_chain.get_epoch() != group_state.get_epoch() {
+            return Err(Error::ValidationError(
+                "Current key chain is not from the group's current epoch",
+            ));
+        }
+
+        encrypt_application_message(plaintext, group_state, &mut current.key_chain)
+    }
+
+    /// Decrypts the given application message with the key chain of the epoch it was sent in. This
+    /// is the same as `decrypt_application_message`, except that the key chain is picked for you,
+    /// and the message is verified against the roster of the epoch it was sent in. Key chains that
+    /// have expired are deleted first.
+    ///
+    /// Returns: `Ok(plaintext)` on success. Returns an `Error::ValidationError` if there is no key
+    /// chain for the message's epoch, e.g., because it expired. Otherwise, returns the same errors
+    /// as `decrypt_application_message`.
+    pub fn decrypt(
+        &mut self,
+        app_message: ApplicationMessage,
+        group_state: &GroupState,
+    ) -> Result<Vec<u8>, Error> {
+        self.expire();
+
+        let epoch = app_message.get_epoch();
+        let managed = self
+            .key_chains
+            .iter_mut()
+            .find(|managed| managed.key_chain.get_epoch() == epoch)
+            .ok_or(Error::ValidationError("No key chain for the application message's epoch"))?;
+        let plaintext = decrypt_application_message_with_roster(
+            app_message,
+            group_state,
+            &managed.roster,
+            &mut managed.key_chain,
+        )?;
+
+        // Count this against the key chain's budget if it's a past epoch's
+        if managed.retired_at.is_some() {
+            managed.messages_since_retired = managed.messages_since_retired.saturating_add(1);
+            self.expire();
+        }
+
+        Ok(plaintext)
+    }
+
+    /// Encrypts the given plaintext with the current key chain, and then encrypts its sender data,
+    /// so that only members of the group can tell who sent it
+    ///
+    /// Returns: `Ok(private_app_message)` on success. Otherwise, returns the same errors as
+    /// `encrypt` and `ApplicationMessage::encrypt_sender_data`.
+    pub fn encrypt_private(
+        &mut self,
+        plaintext: Vec<u8>,
+        group_state: &GroupState,
+    ) -> Result<PrivateApplicationMessage, Error> {
+        let app_message = self.encrypt(plaintext, group_state)?;
+        // encrypt() makes sure there's a current key chain
+        let current = &self.key_chains.last().unwrap().key_chain;
+        app_message.encrypt_sender_data(current)
+    }
+
+    /// Decrypts the sender data of the given message with the key chain of the epoch it was sent
+    /// in, and then decrypts the message itself like `decrypt` does
+    ///
+    /// Returns: `Ok(plaintext)` on success. Otherwise, returns the same errors as `decrypt` and
+    /// `PrivateApplicationMessage::decrypt_sender_data`.
+    pub fn decrypt_private(
+        &mut self,
+        private_app_message: PrivateApplicationMessage,
+        group_state: &GroupState,
+    ) -> Result<Vec<u8>, Error> {
+        self.expire();
+
+        let epoch = private_app_message.get_epoch();
+        let managed =
+            self.key_chains.iter().find(|managed| managed.key_chain.get_epoch() == epoch).ok_or(
+                Error::ValidationError("No key chain for the application message's epoch"),
+            )?;
+        let app_message = private_app_message.decrypt_sender_data(&managed.key_chain)?;
+
+        self.decrypt(app_message, group_state)
+    }
+
+    /// Deletes every past epoch's key chain that's over one of this manager's limits. The current
+    /// key chain is never deleted.
+    fn expire(&mut self) {
+        let now = self.clock.now();
+        let max_old_age = self.max_old_age;
+        let max_old_messages = self.max_old_messages;
+
+        // TODO: Make this erasure secure
+        self.key_chains.retain(|managed| match managed.retired_at {
+            None => true,
+            Some(retired_at) => {
+                now.saturating_sub(retired_at) <= max_old_age
+                    && managed.messages_since_retired < max_old_messages
+            }
+        });
+
+        // There's at most one current key chain, and it's at the end. Everything before it is old.
+        let num_old = self.key_chains.len().saturating_sub(1);
+        if num_old > self.max_old_epochs {
+            self.key_chains.drain(..num_old - self.max_old_epochs);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::KeyChainManager;
+    use crate::{
+        clock::Clock, error::Error, group_state::GroupState, ratchet_tree::PathSecret, test_utils,
+    };
+
+    use std::cell::Cell;
+
+    use quickcheck_macros::quickcheck;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    // A clock whose time the test can move forward
+    struct TestClock<'a>(&'a Cell<u64>);
+
+    impl Clock for TestClock<'_> {
+        fn now(&self) -> u64 {
+            self.0.get()
+        }
+    }
+
+    // Runs an Update from group1, applies it to group2, and gives each manager its new key chain
+    fn do_update(
+        group1: &mut GroupState,
+        group2: &mut GroupState,
+        manager1: &mut KeyChainManager<TestClock>,
+        manager2: &mut KeyChainManager<TestClock>,
+        rng: &mut StdRng,
+    ) {
+        let new_path_secret = PathSecret::new_from_random(group1.cs, rng);
+        let (handshake, new_group1, key_chain1) =
+            group1.create_and_apply_update_handshake(new_path_secret, rng).unwrap();
+        let (new_group2, key_chain2) = group2.process_handshake(&handshake).unwrap();
+        *group1 = new_group1;
+        *group2 = new_group2;
+        manager1.insert(key_chain1, group1).unwrap();
+        manager2.insert(key_chain2, group2).unwrap();
+    }
+
+    // Checks that late messages from past epochs are decrypted, and that past epochs' key chains
+    // expire after too many epochs, too much time, or too many messages
+    #[quickcheck]
+    fn key_chain_manager_correctness(rng_seed: u64) {
+        let mut rng = StdRng::seed_from_u64(rng_seed);
+
+        // Make two perspectives of the same group
+        let (mut group1, identity_keys) = test_utils::random_full_group_state(2, &mut rng);
+        let idx2 = test_utils::random_roster_index_with_exceptions(
+            group1.roster.len(),
+            &[group1.roster_index.unwrap() as usize],
+            &mut rng,
+        );
+        let mut group2 = test_utils::change_self_index(&group1, &identity_keys, idx2);
+
+        // Past epochs' key chains are kept for 2 epochs, 100 seconds, or 2 messages
+        let time = Cell::new(0);
+        let mut manager1 = KeyChainManager::with_clock(TestClock(&time));
+        let mut manager2 = KeyChainManager::with_clock(TestClock(&time));
+        manager1.set_limits(2, 100, 2);
+        manager2.set_limits(2, 100, 2);
+
+        // Group 1 sends three messages that aren't delivered until the next epoch
+        do_update(&mut group1, &mut group2, &mut manager1, &mut manager2, &mut rng);
+        let late_epoch = group1.get_epoch();
+        let mut late_messages: Vec<_> = (0..3)
+            .map(|_| manager1.encrypt(b"sorry I'm late".to_vec(), &group1).unwrap())
+            .collect();
+        do_update(&mut group1, &mut group2, &mut manager1, &mut manager2, &mut rng);
+
+        // The late messages are still decryptable, up to the message budget
+        let last_late_message = late_messages.pop().unwrap();
+        for app_message in late_messages {
+            let plaintext = manager2.decrypt(app_message, &group2).unwrap();
+            assert_eq!(plaintext, b"sorry I'm late");
+        }
+        assert!(!manager2.get_epochs().contains(&late_epoch));
+        match manager2.decrypt(last_late_message, &group2) {
+            Err(Error::ValidationError(_)) => (),
+            _ => panic!("Expired key chain was used"),
+        }
+
+        // A key chain can't be used to encrypt for a different epoch
+        let stale_group1 = group1.clone();
+        let message1 = manager1.encrypt(b"on time".to_vec(), &group1).unwrap();
+        let message2 = manager1.encrypt(b"on time".to_vec(), &group1).unwrap();
+        do_update(&mut group1, &mut group2, &mut manager1, &mut manager2, &mut rng);
+        assert!(manager1.encrypt(b"too late".to_vec(), &stale_group1).is_err());
+
+        // Only the last 2 past epochs are kept
+        do_update(&mut group1, &mut group2, &mut manager1, &mut manager2, &mut rng);
+        let epochs = manager2.get_epochs();
+        assert_eq!(epochs.len(), 3);
+        assert!(epochs.contains(&stale_group1.get_epoch()));
+        assert_eq!(*epochs.last().unwrap(), group2.get_epoch());
+
+        // Messages from 2 epochs ago can be decrypted until their key chain gets too old
+        assert_eq!(manager2.decrypt(message1, &group2).unwrap(), b"on time");
+        time.set(101);
+        assert!(manager2.decrypt(message2, &group2).is_err());
+        assert_eq!(manager2.get_epochs(), vec![group2.get_epoch()]);
+
+        // The current key chain never expires
+        let app_message = manager1.encrypt(b"hello".to_vec(), &group1).unwrap();
+        assert_eq!(manager2.decrypt(app_message, &group2).unwrap(), b"hello");
+
+        // So do messages with encrypted sender data
+        let private_app_message = manager1.encrypt_private(b"psst".to_vec(), &group1).unwrap();
+        assert_eq!(manager2.decrypt_private(private_app_message, &group2).unwrap(), b"psst");
+    }
+
+    // Checks that a late message from someone who has since been removed is still verified
+    // against the roster of the epoch it was sent in
+    #[quickcheck]
+    fn removed_sender_correctness(rng_seed: u64) {
+        let mut rng = StdRng::seed_from_u64(rng_seed);
+
+        // Make a group of at least 3 members: a remover, a receiver, and a sender who gets removed
+        let (group1, identity_keys) = test_utils::random_full_group_state(3, &mut rng);
+        let idx1 = group1.roster_index.unwrap();
+        let idx2 = test_utils::random_roster_index_with_exceptions(
+            group1.roster.len(),
+            &[idx1 as usize],
+            &mut rng,
+        );
+        let idx3 = test_utils::random_roster_index_with_exceptions(
+            group1.roster.len(),
+            &[idx1 as usize, idx2 as usize],
+            &mut rng,
+        );
+        let group2 = test_utils::change_self_index(&group1, &identity_keys, idx2);
+        let group3 = test_utils::change_self_index(&group1, &identity_keys, idx3);
+
+        // Do an Update so that everyone has a key chain
+        let new_path_secret = PathSecret::new_from_random(group1.cs, &mut rng);
+        let (handshake, group1, _) =
+            group1.create_and_apply_update_handshake(new_path_secret, &mut rng).unwrap();
+        let (group2, key_chain2) = group2.process_handshake(&handshake).unwrap();
+        let (group3, key_chain3) = group3.process_handshake(&handshake).unwrap();
+        let mut manager2 = KeyChainManager::new();
+        let mut manager3 = KeyChainManager::new();
+        manager2.insert(key_chain2, &group2).unwrap();
+        manager3.insert(key_chain3, &group3).unwrap();
+
+        // The sender sends a message, and is removed before it's delivered
+        let late_message = manager3.encrypt(b"goodbye".to_vec(), &group3).unwrap();
+        let new_path_secret = PathSecret::new_from_random(group1.cs, &mut rng);
+        let (remove_handshake, _, _) =
+            group1.create_and_apply_remove_handshake(idx3, new_path_secret, &mut rng).unwrap();
+        let (group2, key_chain2) = group2.process_handshake(&remove_handshake).unwrap();
+        manager2.insert(key_chain2, &group2).unwrap();
+        assert!(!matches!(group2.roster.0.get(idx3 as usize), Some(Some(_))));
+
+        // The message still decrypts, since its sender was in the roster when they sent it
+        assert_eq!(manager2.decrypt(late_message, &group2).unwrap(), b"goodbye");
+
+        // A key chain has to be inserted along with the state of its epoch
+        let stale_group2 = group2.clone();
+        let new_path_secret = PathSecret::new_from_random(group2.cs, &mut rng);
+        let (_, _, key_chain2) =
+            group2.create_and_apply_update_handshake(new_path_secret, &mut rng).unwrap();
+        assert!(manager2.insert(key_chain2, &stale_group2).is_err());
+    }
+}
@@ -19,13 +19,23 @@ mod utils;
 mod test_utils;
 
 pub mod application;
+pub mod clock;
 mod codec;
 pub mod credential;
 pub mod crypto;
 pub mod error;
+pub mod extension;
+pub mod framing;
 pub mod group_state;
 pub mod handshake;
+pub mod init_key_store;
+pub mod key_chain_manager;
+pub mod observer;
+pub mod policy;
+pub mod psk;
 pub mod ratchet_tree;
+mod snapshot;
+pub mod storage;
 pub mod tls_de;
 pub mod tls_ser;
 mod tree_math;
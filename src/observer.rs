@@ -0,0 +1,441 @@
+//! Defines `GroupObserver`, a view of a group that only holds public information. This is meant
+//! for parties that see a group's `Handshake`s without being a member, such as a delivery service
+//! that has to know who is in which group in order to fan out messages. An observer tracks the
+//! group's roster, the public keys of its ratchet tree, its epoch, and its transcript hash, and it
+//! checks the signature on every `Handshake`. It never knows any of the group's secrets.
+
+use crate::{
+    clock::{Clock, SystemClock},
+    credential::Roster,
+    crypto::{ciphersuite::CipherSuite, hash::Digest},
+    error::Error,
+    extension::Extension,
+    group_state::{self, GroupState, OperationTarget, PublicGroupInfo},
+    handshake::{
+        DirectPathMessage, GroupOperation, GroupReInit, Handshake, ProtocolVersion, UpdateProposal,
+        UserInitKey,
+    },
+    ratchet_tree::{RatchetTree, RatchetTreeNode},
+};
+
+/// The public state of a group, as seen by someone who isn't in it
+#[derive(Clone)]
+pub struct GroupObserver {
+    /// The ciphersuite of the group
+    cs: &'static CipherSuite,
+
+    /// The protocol version of the group
+    protocol_version: ProtocolVersion,
+
+    /// An application-defined identifier for the group
+    group_id: Vec<u8>,
+
+    /// Represents the current version of the group key
+    epoch: u32,
+
+    /// Contains credentials for the occupied slots in the tree, including the identity and
+    /// signature public key for the holder of the slot
+    roster: Roster,
+
+    /// The ratchet tree of the group. None of its nodes has a private key.
+    tree: RatchetTree,
+
+    /// The tree hash of `tree`
+    tree_hash: Digest,
+
+    /// A running hash of all the `GroupOperation`s that led to this state
+    transcript_hash: Digest,
+
+    /// The group's extensions
+    extensions: Vec<Extension>,
+
+    /// The successor group, if this group has been reinitialized. A reinitialized group cannot
+    /// process any more handshakes.
+    reinit: Option<GroupReInit>,
+}
+
+impl GroupObserver {
+    /// Starts observing the group described by `group_info`. As with
+    /// `GroupState::create_external_join_handshake`, the caller is responsible for deciding
+    /// whether the signer of `group_info` is trustworthy.
+    ///
+    /// Returns: `Ok(observer)` on success. Returns an `Error::SignatureError` if the signature on
+    /// `group_info` or any of its tree's leaves doesn't verify, and an `Error::ValidationError` if
+    /// its tree is otherwise malformed.
+    pub fn from_public_group_info(group_info: PublicGroupInfo) -> Result<GroupObserver, Error> {
+        group_info.verify_sig()?;
+
+        // Like with a Welcome, don't trust the tree until its keys are all vouched for
+        let cs = group_info.cipher_suite;
+        let mut tree = group_info.tree;
+        tree.set_parent_hashes(group_info.tree_parent_hashes)?;
+        tree.verify_parent_hashes(cs, &group_info.roster)?;
+        let tree_hash = tree.tree_hash(cs)?;
+
+        Ok(GroupObserver {
+            cs,
+            protocol_version: group_info.protocol_version,
+            group_id: group_info.group_id,
+            epoch: group_info.epoch,
+            roster: group_info.roster,
+            tree,
+            tree_hash,
+            transcript_hash: group_info.transcript_hash,
+            extensions: group_info.extensions,
+            reinit: None,
+        })
+    }
+
+    /// Returns the ID of the observed group
+    pub fn get_group_id(&self) -> &[u8] {
+        self.group_id.as_slice()
+    }
+
+    /// Returns the protocol version of the observed group
+    pub fn get_protocol_version(&self) -> ProtocolVersion {
+        self.protocol_version
+    }
+
+    /// Returns the current epoch of the observed group
+    pub fn get_epoch(&self) -> u32 {
+        self.epoch
+    }
+
+    /// Returns the roster of the observed group
+    pub fn get_roster(&self) -> &Roster {
+        &self.roster
+    }
+
+    /// Returns the tree hash of the observed group's ratchet tree
+    pub fn get_tree_hash(&self) -> &[u8] {
+        self.tree_hash.as_bytes()
+    }
+
+    /// Returns the current transcript hash of the observed group
+    pub fn get_transcript_hash(&self) -> &[u8] {
+        self.transcript_hash.as_bytes()
+    }
+
+    /// Returns the extensions of the observed group
+    pub fn get_extensions(&self) -> &[Extension] {
+        self.extensions.as_slice()
+    }
+
+    /// Returns the group ID of the observed group's successor, if it has been reinitialized
+    pub fn get_successor_group_id(&self) -> Option<&[u8]> {
+        self.reinit.as_ref().map(|reinit| reinit.group_id.as_slice())
+    }
+
+    /// Processes the given `Handshake` and, if successful, produces the `GroupObserver` of the
+    /// next epoch. This does not mutate the current `GroupObserver`. The lifetimes of new members'
+    /// `UserInitKey`s are checked against the system clock.
+    ///
+    /// This checks everything that `GroupState::process_handshake` checks except for what needs
+    /// the group's secrets. Namely, the confirmation MAC isn't checked, nor are the `WelcomeInfo`
+    /// hashes in `Add`, `MultiAdd`, and `Init` operations and `Add` proposals, nor is it checked
+    /// that a direct path's public keys are derived from the path secrets sent to the group. So a
+    /// member can make a `Handshake` that this accepts and the rest of the group rejects. A sealed
+    /// `Handshake` (see `GroupState::seal_handshake`) can't be processed at all.
+    ///
+    /// Returns: `Ok(observer)` on success. Returns an `Error::SignatureError` if the `Handshake`'s
+    /// signature doesn't verify, an `Error::ValidationError` if it's sealed, and some other sort
+    /// of `Error` if the operation is invalid.
+    pub fn process_handshake(&self, handshake: &Handshake) -> Result<GroupObserver, Error> {
+        self.process_handshake_with_clock(handshake, &SystemClock)
+    }
+
+    /// Like `process_handshake`, but checks the lifetimes of new members' `UserInitKey`s against
+    /// the given `Clock`
+    ///
+    /// Returns: The same as `process_handshake`
+    pub fn process_handshake_with_clock<C: Clock>(
+        &self,
+        handshake: &Handshake,
+        clock: &C,
+    ) -> Result<GroupObserver, Error> {
+        if self.reinit.is_some() {
+            return Err(Error::ValidationError(
+                "Cannot process a Handshake after the group has been reinitialized",
+            ));
+        }
+        if handshake.prior_epoch != self.epoch {
+            return Err(Error::ValidationError("Handshake's prior epoch isn't the current epoch"));
+        }
+        // That's the point of sealing
+        if let GroupOperation::Sealed(_) = handshake.operation {
+            return Err(Error::ValidationError("Cannot observe a sealed Handshake"));
+        }
+
+        // Someone who is joining externally isn't in the group yet. Their position is checked when
+        // the operation is processed.
+        let is_external_join = matches!(handshake.operation, GroupOperation::ExternalJoin(_));
+        let sender_tree_idx = GroupState::roster_index_to_tree_index(handshake.signer_index)?;
+        if sender_tree_idx >= self.tree.size() && !is_external_join {
+            return Err(Error::ValidationError("Handshake sender tree index is out of range"));
+        }
+
+        // Same as in GroupState, an external joiner brings their own credential
+        let sender_credential = match handshake.operation {
+            GroupOperation::ExternalJoin(ref join) => &join.init_key.credential,
+            _ => self
+                .roster
+                .0
+                .get(handshake.signer_index as usize)
+                .ok_or(Error::ValidationError("Handshake's signer index is out of bounds"))?
+                .as_ref()
+                .ok_or(Error::ValidationError("Handshake's signer credential is empty"))?,
+        };
+
+        let mut new_observer = self.clone();
+        new_observer.update_transcript_hash(&handshake.operation)?;
+        new_observer.epoch = self
+            .epoch
+            .checked_add(1)
+            .ok_or(Error::ValidationError("Cannot increment epoch past its maximum"))?;
+
+        // The signature is over the new transcript hash, which doesn't depend on how the operation
+        // is applied. So we can check it before doing any work.
+        let sig_data = new_observer.transcript_hash.as_bytes();
+        sender_credential.get_signature_scheme().verify(
+            sender_credential.get_public_key(),
+            sig_data,
+            &handshake.signature,
+        )?;
+
+        new_observer.apply_operation(handshake, sender_tree_idx, self.epoch, clock.now())?;
+        new_observer.tree_hash = new_observer.tree.tree_hash(self.cs)?;
+
+        Ok(new_observer)
+    }
+
+    /// Computes and updates the transcript hash, given a new `Handshake` message. This is the same
+    /// as in `GroupState`.
+    ///
+    /// Returns: An `Error::SerdeError` if there was an issue during serialization
+    fn update_transcript_hash(&mut self, operation: &GroupOperation) -> Result<(), Error> {
+        self.transcript_hash =
+            group_state::next_transcript_hash(self.cs, &self.transcript_hash, operation)?;
+        Ok(())
+    }
+
+    /// Applies the operation in the given `Handshake` to the roster and tree, where
+    /// `sender_tree_idx` is the tree index of the `Handshake`'s signer, `prior_epoch` is the epoch
+    /// the `Handshake` was made in, and `now` is the current time, in seconds since the Unix
+    /// epoch. This does no signature verification on the `Handshake` itself.
+    ///
+    /// Returns: `Ok(())` on success. Otherwise, some sort of `Error`.
+    fn apply_operation(
+        &mut self,
+        handshake: &Handshake,
+        sender_tree_idx: usize,
+        prior_epoch: u32,
+        now: u64,
+    ) -> Result<(), Error> {
+        // Everything but the direct path is done the same way GroupState does it. We can't check
+        // WelcomeInfo hashes, since we don't know the group's secrets.
+        group_state::apply_operation(
+            self,
+            &handshake.operation,
+            handshake.signer_index,
+            prior_epoch,
+            None,
+            now,
+        )?;
+
+        // We can't decrypt the path, but we can still take its public keys
+        let path = match handshake.operation {
+            GroupOperation::Update(ref update) => &update.path,
+            GroupOperation::Init(ref init) => &init.path,
+            GroupOperation::Commit(ref commit) => &commit.path,
+            GroupOperation::ExternalJoin(ref join) => &join.path,
+            // A Remove's path is blanked right after it's applied, so it's skipped entirely
+            _ => return Ok(()),
+        };
+        self.apply_direct_path(path, sender_tree_idx)
+    }
+
+    /// Sets the public keys of the direct path of the leaf at `sender_tree_idx` to the ones in
+    /// `path`, and checks that the member at that leaf signed the path
+    ///
+    /// Returns: `Ok(())` on success. Returns an `Error::SignatureError` if the signature doesn't
+    /// verify, and an `Error::ValidationError` if the path doesn't fit in the tree or its sender
+    /// isn't in the roster.
+    fn apply_direct_path(
+        &mut self,
+        path: &DirectPathMessage,
+        sender_tree_idx: usize,
+    ) -> Result<(), Error> {
+        // We don't know any secrets, so every key on the path is one we have to take on faith.
+        // There's no stopping node, so the whole path is overwritten.
+        let direct_path_public_keys =
+            path.node_messages.iter().map(|node_msg| &node_msg.public_key);
+        self.tree.set_public_keys_with_bound(
+            sender_tree_idx,
+            usize::MAX,
+            direct_path_public_keys.clone(),
+        )?;
+        // This can only fail if the path is the wrong length
+        self.tree.validate_direct_path_public_keys(sender_tree_idx, direct_path_public_keys)?;
+
+        group_state::set_direct_path_parent_hashes(
+            self.cs,
+            &self.roster,
+            &mut self.tree,
+            path,
+            sender_tree_idx,
+        )
+    }
+}
+
+impl OperationTarget for GroupObserver {
+    fn group_id(&self) -> &[u8] {
+        &self.group_id
+    }
+
+    fn roster(&self) -> &Roster {
+        &self.roster
+    }
+
+    fn insert_new_member(
+        &mut self,
+        init_key: &UserInitKey,
+        new_roster_index: u32,
+    ) -> Result<(), Error> {
+        let public_key = init_key.get_public_key(self.cs)?.ok_or(Error::ValidationError(
+            "UserInitKey has no public keys for group's ciphersuite",
+        ))?;
+        let new_node = RatchetTreeNode::new_from_public_key(public_key.clone(), None)
+            .with_user_init_key(init_key);
+        group_state::insert_member(
+            &mut self.roster,
+            &mut self.tree,
+            init_key.credential.clone(),
+            new_node,
+            new_roster_index,
+        )
+    }
+
+    fn replace_leaf(&mut self, roster_index: u32, update: &UpdateProposal) -> Result<(), Error> {
+        let new_leaf = RatchetTreeNode::new_from_public_key(update.public_key.clone(), None)
+            .with_leaf_signature(update.signature.clone());
+        group_state::replace_leaf(&mut self.tree, roster_index, new_leaf)
+    }
+
+    fn remove_member(&mut self, removed_roster_index: u32) -> Result<(), Error> {
+        group_state::remove_member(&mut self.roster, &mut self.tree, removed_roster_index)
+    }
+
+    fn truncate_to_last_nonblank(&mut self) -> Result<(), Error> {
+        self.roster.truncate_to_last_nonblank()?;
+        self.tree.truncate_to_last_nonblank();
+        Ok(())
+    }
+
+    fn reinit_mut(&mut self) -> &mut Option<GroupReInit> {
+        &mut self.reinit
+    }
+
+    fn extensions_mut(&mut self) -> &mut Vec<Extension> {
+        &mut self.extensions
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::GroupObserver;
+    use crate::{
+        error::Error,
+        group_state::{GroupState, Welcome},
+        ratchet_tree::PathSecret,
+        test_utils,
+    };
+
+    use core::convert::TryFrom;
+
+    use quickcheck_macros::quickcheck;
+    use rand::SeedableRng;
+
+    // Checks that the observer's view of the group matches a member's
+    fn assert_observer_agrees(observer: &GroupObserver, group_state: &GroupState) {
+        assert_eq!(observer.get_epoch(), group_state.epoch);
+        assert_eq!(observer.get_roster(), group_state.get_roster());
+        assert_eq!(observer.get_transcript_hash(), group_state.transcript_hash.as_bytes());
+        let tree_hash = group_state.tree.tree_hash(group_state.cs).unwrap();
+        assert_eq!(observer.get_tree_hash(), tree_hash.as_bytes());
+    }
+
+    // Checks that an observer follows a group through Updates, Adds, Removes, and Commits without
+    // knowing any of its secrets, and that it rejects forged handshakes
+    #[quickcheck]
+    fn observer_correctness(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let (group_state, _) = test_utils::random_full_group_state(4, &mut rng);
+        let group_info = group_state.create_public_group_info().unwrap();
+        let observer = GroupObserver::from_public_group_info(group_info).unwrap();
+        assert_observer_agrees(&observer, &group_state);
+
+        // Update
+        let new_path_secret = PathSecret::new_from_random(group_state.cs, &mut rng);
+        let (handshake, group_state, _) =
+            group_state.create_and_apply_update_handshake(new_path_secret, &mut rng).unwrap();
+        let observer = observer.process_handshake(&handshake).unwrap();
+        assert_observer_agrees(&observer, &group_state);
+
+        // Add someone to the end of the roster
+        let (init_key, _) = test_utils::random_user_init_key(&mut rng, group_state.cs);
+        let (_, welcome_info_hash) =
+            Welcome::from_group_state(&group_state, &init_key, &mut rng).unwrap();
+        let new_roster_idx = u32::try_from(group_state.get_roster().len()).unwrap();
+        let (handshake, group_state, _) = group_state
+            .create_and_apply_add_handshake(new_roster_idx, init_key, &welcome_info_hash)
+            .unwrap();
+        let observer = observer.process_handshake(&handshake).unwrap();
+        assert_observer_agrees(&observer, &group_state);
+
+        // Remove someone other than ourselves
+        let my_roster_idx = group_state.roster_index.unwrap();
+        let removed_idx = test_utils::random_roster_index_with_exceptions(
+            group_state.get_roster().len(),
+            &[my_roster_idx as usize],
+            &mut rng,
+        );
+        let new_path_secret = PathSecret::new_from_random(group_state.cs, &mut rng);
+        let (handshake, group_state, _) = group_state
+            .create_and_apply_remove_handshake(removed_idx, new_path_secret, &mut rng)
+            .unwrap();
+        let observer = observer.process_handshake(&handshake).unwrap();
+        assert_observer_agrees(&observer, &group_state);
+
+        // Commit an Update and an Add. This fills the empty slot left by the Remove.
+        let (update_proposal, group_state) = group_state.create_update_proposal(&mut rng).unwrap();
+        let (init_key, _) = test_utils::random_user_init_key(&mut rng, group_state.cs);
+        let (_, welcome_info_hash) =
+            Welcome::from_group_state(&group_state, &init_key, &mut rng).unwrap();
+        let add_proposal = group_state.create_add_proposal(init_key, &welcome_info_hash).unwrap();
+        let new_path_secret = PathSecret::new_from_random(group_state.cs, &mut rng);
+        let (handshake, group_state, _) = group_state
+            .create_and_apply_commit_handshake(
+                vec![update_proposal, add_proposal],
+                new_path_secret,
+                &mut rng,
+            )
+            .unwrap();
+        let observer = observer.process_handshake(&handshake).unwrap();
+        assert_observer_agrees(&observer, &group_state);
+
+        // A handshake from the wrong epoch is rejected
+        assert!(observer.process_handshake(&handshake).is_err());
+        let other_handshake = handshake;
+
+        // A handshake with someone else's signature is rejected
+        let new_path_secret = PathSecret::new_from_random(group_state.cs, &mut rng);
+        let (mut handshake, _, _) =
+            group_state.create_and_apply_update_handshake(new_path_secret, &mut rng).unwrap();
+        handshake.signature = other_handshake.signature.clone();
+        match observer.process_handshake(&handshake) {
+            Err(Error::SignatureError(_)) => (),
+            Err(e) => panic!("Forged handshake gave the wrong error: {}", e),
+            Ok(_) => panic!("Forged handshake was accepted"),
+        }
+    }
+}
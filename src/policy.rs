@@ -0,0 +1,370 @@
+//! Defines membership policies, which let an application decide who may change a group and how.
+//! The protocol itself accepts any operation from any member whose signature verifies. A
+//! `MembershipPolicy` is consulted while a `Handshake` is processed, before the new `GroupState`
+//! is accepted, and can veto the operation.
+//!
+//! External joins are the one exception to the protocol accepting everything. Anyone who holds a
+//! group's `PublicGroupInfo` can make an `ExternalJoin`, so these are rejected unless the policy
+//! opts in via `MembershipPolicy::allows_external_joins`.
+
+use crate::{
+    credential::{Credential, Identity},
+    error::Error,
+    group_state::GroupState,
+    handshake::Handshake,
+};
+
+/// A caller-provided rule for which group operations are allowed
+pub trait MembershipPolicy {
+    /// Decides whether the member with credential `sender` may perform the operation in
+    /// `handshake` on the group, where `state` is the group before the operation is applied. For
+    /// an `ExternalJoin`, `sender` is the joiner's credential.
+    ///
+    /// Returns: `Ok(())` if the operation is allowed. Otherwise, an `Error::AuthorizationError`.
+    fn authorize(
+        &self,
+        sender: &Credential,
+        handshake: &Handshake,
+        state: &GroupState,
+    ) -> Result<(), Error>;
+
+    /// Returns whether this policy lets non-members join via an `ExternalJoin`. Defaults to
+    /// `false`. A policy that returns `true` still has `authorize` called on every `ExternalJoin`,
+    /// and can reject individual joiners there.
+    fn allows_external_joins(&self) -> bool {
+        false
+    }
+}
+
+/// A policy that is only satisfied if both of its policies are. It allows external joins if either
+/// of its policies does.
+impl<A: MembershipPolicy, B: MembershipPolicy> MembershipPolicy for (A, B) {
+    fn authorize(
+        &self,
+        sender: &Credential,
+        handshake: &Handshake,
+        state: &GroupState,
+    ) -> Result<(), Error> {
+        self.0.authorize(sender, handshake, state)?;
+        self.1.authorize(sender, handshake, state)
+    }
+
+    // Opting in is not itself a restriction, so one half opting in is enough. Both halves still
+    // get to veto each joiner in `authorize`.
+    fn allows_external_joins(&self) -> bool {
+        self.0.allows_external_joins() || self.1.allows_external_joins()
+    }
+}
+
+/// The policy used when the caller doesn't provide one. It allows everything but external joins.
+pub(crate) struct AllowAll;
+
+impl MembershipPolicy for AllowAll {
+    fn authorize(
+        &self,
+        _sender: &Credential,
+        _handshake: &Handshake,
+        _state: &GroupState,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// A policy that lets anyone join via an `ExternalJoin`, and otherwise allows everything. Pair it
+/// with another policy to restrict the rest, e.g., `(AllowExternalJoins, MaxGroupSize::new(10))`.
+pub struct AllowExternalJoins;
+
+impl MembershipPolicy for AllowExternalJoins {
+    fn authorize(
+        &self,
+        _sender: &Credential,
+        _handshake: &Handshake,
+        _state: &GroupState,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn allows_external_joins(&self) -> bool {
+        true
+    }
+}
+
+/// A policy that only lets admins remove members. A `Commit` that removes anyone must be sent by
+/// an admin, even if the `Remove` proposals in it were made by someone else. This includes a
+/// member's own request to leave.
+pub struct OnlyAdminsCanRemove {
+    admins: Vec<Identity>,
+}
+
+impl OnlyAdminsCanRemove {
+    /// Makes a policy where the members with the given identities are the admins
+    pub fn new(admins: Vec<Identity>) -> OnlyAdminsCanRemove {
+        OnlyAdminsCanRemove {
+            admins,
+        }
+    }
+}
+
+impl MembershipPolicy for OnlyAdminsCanRemove {
+    fn authorize(
+        &self,
+        sender: &Credential,
+        handshake: &Handshake,
+        _state: &GroupState,
+    ) -> Result<(), Error> {
+        let removes_someone = !handshake.get_removed_roster_indices().is_empty();
+        if removes_someone && !self.admins.contains(sender.get_identity()) {
+            Err(Error::AuthorizationError("Only admins can remove members"))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// A policy that caps the number of members in a group
+pub struct MaxGroupSize {
+    max_size: usize,
+}
+
+impl MaxGroupSize {
+    /// Makes a policy that rejects any operation that would leave the group with more than
+    /// `max_size` members
+    pub fn new(max_size: usize) -> MaxGroupSize {
+        MaxGroupSize {
+            max_size,
+        }
+    }
+}
+
+impl MembershipPolicy for MaxGroupSize {
+    fn authorize(
+        &self,
+        _sender: &Credential,
+        handshake: &Handshake,
+        state: &GroupState,
+    ) -> Result<(), Error> {
+        let roster = &state.roster.0;
+        let num_members = roster.iter().filter(|entry| entry.is_some()).count();
+        // Only count the removal of members who are actually there
+        let num_removed = handshake
+            .get_removed_roster_indices()
+            .into_iter()
+            .filter(|&idx| matches!(roster.get(idx as usize), Some(Some(_))))
+            .count();
+        let num_added = handshake.get_added_credentials().len();
+
+        if num_members.saturating_sub(num_removed) + num_added > self.max_size {
+            Err(Error::AuthorizationError("Operation would exceed the maximum group size"))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{MaxGroupSize, MembershipPolicy, OnlyAdminsCanRemove};
+    use crate::{
+        credential::Identity,
+        error::Error,
+        framing::{frame_plaintext, MlsContent},
+        group_state::{ProcessingContext, Welcome},
+        ratchet_tree::PathSecret,
+        test_utils,
+    };
+
+    use core::convert::TryFrom;
+
+    use quickcheck_macros::quickcheck;
+    use rand::SeedableRng;
+
+    // Check that the example policies veto exactly what they should
+    #[quickcheck]
+    fn policy_correctness(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let (group_state, identity_keys) = test_utils::random_full_group_state(3, &mut rng);
+        let my_roster_idx = group_state.roster_index.unwrap();
+        let group_size = group_state.roster.len();
+
+        // Pick someone to remove and someone else to process our handshakes
+        let removed_idx = test_utils::random_roster_index_with_exceptions(
+            group_size,
+            &[my_roster_idx as usize],
+            &mut rng,
+        );
+        let other_idx = test_utils::random_roster_index_with_exceptions(
+            group_size,
+            &[my_roster_idx as usize, removed_idx as usize],
+            &mut rng,
+        );
+        let other_group = test_utils::change_self_index(&group_state, &identity_keys, other_idx);
+
+        let identity_of = |idx: u32| -> Identity {
+            group_state.roster.0[idx as usize].as_ref().unwrap().get_identity().clone()
+        };
+
+        //
+        // Only admins can remove
+        //
+
+        let new_path_secret = PathSecret::new_from_random(group_state.cs, &mut rng);
+        let (remove_handshake, _, _) = group_state
+            .create_and_apply_remove_handshake(removed_idx, new_path_secret, &mut rng)
+            .unwrap();
+
+        // We aren't an admin, so the other member must reject the Remove
+        let not_us = OnlyAdminsCanRemove::new(vec![identity_of(other_idx)]);
+        let not_us_ctx = ProcessingContext::new().with_policy(&not_us);
+        match other_group.process_handshake_with_context(&remove_handshake, &not_us_ctx) {
+            Err(Error::AuthorizationError(_)) => (),
+            Err(e) => panic!("Remove by a non-admin gave the wrong error: {}", e),
+            Ok(_) => panic!("Remove by a non-admin was accepted"),
+        }
+        // The removed member must not think they've been removed either
+        let removed_group =
+            test_utils::change_self_index(&group_state, &identity_keys, removed_idx);
+        match removed_group.process_handshake_with_context(&remove_handshake, &not_us_ctx) {
+            Err(Error::AuthorizationError(_)) => (),
+            _ => panic!("Removed member didn't reject a Remove by a non-admin"),
+        }
+
+        // We get told up front, rather than applying a Remove that everyone else vetoes
+        let new_path_secret = PathSecret::new_from_random(group_state.cs, &mut rng);
+        match group_state.create_and_apply_remove_handshake_with_context(
+            removed_idx,
+            new_path_secret,
+            &not_us_ctx,
+            &mut rng,
+        ) {
+            Err(Error::AuthorizationError(_)) => (),
+            Err(e) => panic!("Making a Remove as a non-admin gave the wrong error: {}", e),
+            Ok(_) => panic!("Made a Remove as a non-admin"),
+        }
+
+        // If we're an admin, it goes through
+        let us = OnlyAdminsCanRemove::new(vec![identity_of(my_roster_idx)]);
+        let new_path_secret = PathSecret::new_from_random(group_state.cs, &mut rng);
+        group_state
+            .create_and_apply_remove_handshake_with_context(
+                removed_idx,
+                new_path_secret,
+                &ProcessingContext::new().with_policy(&us),
+                &mut rng,
+            )
+            .unwrap();
+        other_group
+            .process_handshake_with_context(
+                &remove_handshake,
+                &ProcessingContext::new().with_policy(&us),
+            )
+            .unwrap();
+
+        // Updates don't remove anyone, so admins don't matter
+        let new_path_secret = PathSecret::new_from_random(group_state.cs, &mut rng);
+        let (update_handshake, _, _) =
+            group_state.create_and_apply_update_handshake(new_path_secret, &mut rng).unwrap();
+        other_group.process_handshake_with_context(&update_handshake, &not_us_ctx).unwrap();
+
+        // The policy applies however the Remove arrives. Make it follow an Update, and deliver it
+        // first. It gets buffered, and then vetoed once the Update makes it ready.
+        let new_path_secret = PathSecret::new_from_random(group_state.cs, &mut rng);
+        let (first_handshake, updated_group, _) =
+            group_state.create_and_apply_update_handshake(new_path_secret, &mut rng).unwrap();
+        let new_path_secret = PathSecret::new_from_random(group_state.cs, &mut rng);
+        let (second_handshake, _, _) = updated_group
+            .create_and_apply_remove_handshake(removed_idx, new_path_secret, &mut rng)
+            .unwrap();
+        let (buffering_group, _, _) = other_group
+            .process_or_buffer_handshake_with_context(second_handshake.clone(), &not_us_ctx)
+            .unwrap();
+        let (caught_up_group, mut app_key_chains, failed) = buffering_group
+            .process_or_buffer_handshake_with_context(first_handshake, &not_us_ctx)
+            .unwrap();
+        assert_eq!(app_key_chains.len(), 1);
+        assert_eq!(caught_up_group.epoch, updated_group.epoch);
+        assert!(caught_up_group.get_pending_handshakes().is_empty());
+
+        // The vetoed Remove is handed back, not lost
+        assert_eq!(failed.len(), 1);
+        assert!(matches!(failed[0].1, Error::AuthorizationError(_)));
+        assert_serialized_eq!(failed[0].0, second_handshake, "Wrong Handshake failed");
+
+        // Framed, it's vetoed too
+        let message =
+            frame_plaintext(MlsContent::Handshake(second_handshake), &updated_group).unwrap();
+        let mut app_key_chain = app_key_chains.pop().unwrap();
+        let res =
+            caught_up_group.process_message_with_context(message, &mut app_key_chain, &not_us_ctx);
+        match res {
+            Err(Error::AuthorizationError(_)) => (),
+            Err(e) => panic!("Framed Remove by a non-admin gave the wrong error: {}", e),
+            Ok(_) => panic!("Framed Remove by a non-admin was accepted"),
+        }
+
+        //
+        // Max group size
+        //
+
+        // Add someone to the end of the full roster
+        let (init_key, _) = test_utils::random_user_init_key(&mut rng, group_state.cs);
+        let (_, welcome_info_hash) =
+            Welcome::from_group_state(&group_state, &init_key, &mut rng).unwrap();
+        let (add_handshake, _, _) = group_state
+            .create_and_apply_add_handshake(
+                u32::try_from(group_size).unwrap(),
+                init_key,
+                &welcome_info_hash,
+            )
+            .unwrap();
+
+        // The group is full, so one more member is too many
+        let at_capacity = MaxGroupSize::new(group_size);
+        match other_group.process_handshake_with_context(
+            &add_handshake,
+            &ProcessingContext::new().with_policy(&at_capacity),
+        ) {
+            Err(Error::AuthorizationError(_)) => (),
+            Err(e) => panic!("Add past the maximum group size gave the wrong error: {}", e),
+            Ok(_) => panic!("Add past the maximum group size was accepted"),
+        }
+        let (init_key, _) = test_utils::random_user_init_key(&mut rng, group_state.cs);
+        let res = group_state.create_and_apply_add_handshake_with_context(
+            u32::try_from(group_size).unwrap(),
+            init_key,
+            &welcome_info_hash,
+            &ProcessingContext::new().with_policy(&at_capacity),
+        );
+        assert!(matches!(res, Err(Error::AuthorizationError(_))));
+        let with_room = MaxGroupSize::new(group_size + 1);
+        other_group
+            .process_handshake_with_context(
+                &add_handshake,
+                &ProcessingContext::new().with_policy(&with_room),
+            )
+            .unwrap();
+
+        // Removing someone never grows the group
+        other_group
+            .process_handshake_with_context(
+                &remove_handshake,
+                &ProcessingContext::new().with_policy(&at_capacity),
+            )
+            .unwrap();
+
+        // A pair of policies needs both to be satisfied
+        let both = (us, MaxGroupSize::new(group_size));
+        other_group
+            .process_handshake_with_context(
+                &remove_handshake,
+                &ProcessingContext::new().with_policy(&both),
+            )
+            .unwrap();
+        assert!(both
+            .authorize(
+                group_state.roster.0[my_roster_idx as usize].as_ref().unwrap(),
+                &add_handshake,
+                &group_state
+            )
+            .is_err());
+    }
+}
@@ -0,0 +1,59 @@
+//! Defines pre-shared keys (PSKs), which let a group mix secrets from outside the group into its
+//! key schedule. A PSK is referenced in a `Commit` by a PSK proposal carrying its ID. Every member
+//! processing that `Commit` looks the ID up in a `PskStore` of their own, so a member who doesn't
+//! have the PSK can't derive the new epoch's secrets.
+
+use crate::{
+    crypto::{ciphersuite::CipherSuite, hkdf, hmac::HmacKey},
+    error::Error,
+};
+
+use std::collections::HashMap;
+
+/// A caller-provided collection of pre-shared keys, indexed by PSK ID
+pub trait PskStore {
+    /// Returns: `Some(psk)` if this store holds a PSK with the given ID. Otherwise, `None`.
+    fn get_psk(&self, psk_id: &[u8]) -> Option<&[u8]>;
+}
+
+impl PskStore for HashMap<Vec<u8>, Vec<u8>> {
+    fn get_psk(&self, psk_id: &[u8]) -> Option<&[u8]> {
+        self.get(psk_id).map(|psk| psk.as_slice())
+    }
+}
+
+/// The store used when the caller doesn't provide one. It holds no PSKs.
+pub(crate) struct NoPsks;
+
+impl PskStore for NoPsks {
+    fn get_psk(&self, _psk_id: &[u8]) -> Option<&[u8]> {
+        None
+    }
+}
+
+/// Combines the PSKs with the given IDs into a single secret, in the given order, in the style of
+/// the key schedule.
+///
+/// Returns: `Ok(Some(psk_secret))` on success, or `Ok(None)` if `psk_ids` is empty. If one of the
+/// PSKs isn't in `psks`, returns an `Error::ValidationError`.
+pub(crate) fn derive_psk_secret<P: PskStore + ?Sized>(
+    cs: &'static CipherSuite,
+    psk_ids: &[&[u8]],
+    psks: &P,
+) -> Result<Option<HmacKey>, Error> {
+    if psk_ids.is_empty() {
+        return Ok(None);
+    }
+
+    // psk_secret_[0] = 0
+    // psk_secret_[i] = HKDF-Extract(salt=psk_secret_[i-1], ikm=psk_[i])
+    let mut psk_secret = HmacKey::new_from_zeros(cs.hash_impl);
+    for psk_id in psk_ids {
+        let psk = psks
+            .get_psk(psk_id)
+            .ok_or(Error::ValidationError("Commit references a PSK that isn't in the PSK store"))?;
+        psk_secret = hkdf::extract(cs.hash_impl, &psk_secret, psk);
+    }
+
+    Ok(Some(psk_secret))
+}
@@ -1,16 +1,19 @@
 //! Defines `RatchetTree` and all its functionality. Not much public API here.
 
 use crate::{
+    credential::Roster,
     crypto::{
         ciphersuite::CipherSuite,
         dh::{DhPrivateKey, DhPublicKey},
         ecies,
+        hash::Digest,
         hmac::HmacKey,
         rng::CryptoRng,
+        sig::{SigSecretKey, Signature, SignatureScheme},
     },
     error::Error,
-    handshake::{DirectPathMessage, DirectPathNodeMessage},
-    tree_math, utils,
+    handshake::{DirectPathMessage, DirectPathNodeMessage, UserInitKey},
+    tls_ser, tree_math, utils,
 };
 
 use subtle::ConstantTimeEq;
@@ -62,7 +65,8 @@ impl From<PathSecret> for HmacKey {
 // Ratchet trees are serialized in DirectPath messages as optional<PublicKey> tree<1..2^32-1> So we
 // encode RatchetTree as a Vec<RatchetTreeNode> with length bound u32, and we encode
 // RatchetTreeNode as enum { Blank, Filled { DhPublicKey } }, which is encoded in the same way as
-// an Option<DhPublicKey> would be.
+// an Option<DhPublicKey> would be. Parent hashes and leaf signatures aren't in the spec, so they
+// are left out of this encoding and carried separately as NodeParentHashes.
 
 /// A node in a `RatchetTree`. Every node must have a DH pubkey. It may also optionally contain the
 /// corresponding private key.
@@ -75,6 +79,19 @@ pub(crate) enum RatchetTreeNode {
         public_key: DhPublicKey,
         #[serde(skip)]
         private_key: Option<DhPrivateKey>,
+        /// The hash of this node's parent at the time this node was set by a direct path. This is
+        /// empty for the root of that path, and for nodes that weren't set by a direct path.
+        #[serde(skip)]
+        parent_hash: Vec<u8>,
+        /// For a leaf node, the signature of the leaf's owner over its public key and parent hash,
+        /// if they've signed it. This is `None` for every other node.
+        #[serde(skip)]
+        signature: Option<Signature>,
+        /// For a leaf node that was added and hasn't been touched since, the `UserInitKey` it was
+        /// added with, minus its private keys. This vouches for the leaf's public key in place of
+        /// a signature. This is `None` for every other node.
+        #[serde(skip)]
+        user_init_key: Option<Box<UserInitKey>>,
     },
 }
 
@@ -89,6 +106,24 @@ impl RatchetTreeNode {
         RatchetTreeNode::Filled {
             public_key: pubkey,
             private_key: Some(private_key),
+            parent_hash: Vec::new(),
+            signature: None,
+            user_init_key: None,
+        }
+    }
+
+    /// Makes a new node with the given public key and, optionally, its private key. The node has
+    /// no parent hash.
+    pub(crate) fn new_from_public_key(
+        public_key: DhPublicKey,
+        private_key: Option<DhPrivateKey>,
+    ) -> RatchetTreeNode {
+        RatchetTreeNode::Filled {
+            public_key,
+            private_key,
+            parent_hash: Vec::new(),
+            signature: None,
+            user_init_key: None,
         }
     }
 
@@ -102,20 +137,54 @@ impl RatchetTreeNode {
         }
     }
 
+    /// Returns this node with `signature` as its leaf signature. `signature` must be over the
+    /// node's public key and parent hash. This does nothing to a `Blank` node.
+    pub(crate) fn with_leaf_signature(mut self, new_signature: Signature) -> RatchetTreeNode {
+        if let RatchetTreeNode::Filled {
+            ref mut signature,
+            ..
+        } = self
+        {
+            *signature = Some(new_signature);
+        }
+        self
+    }
+
+    /// Returns this node with `init_key` as the `UserInitKey` it was added with. Only the public
+    /// part of `init_key` is kept. This does nothing to a `Blank` node.
+    pub(crate) fn with_user_init_key(mut self, init_key: &UserInitKey) -> RatchetTreeNode {
+        if let RatchetTreeNode::Filled {
+            ref mut user_init_key,
+            ..
+        } = self
+        {
+            let mut public_init_key = init_key.clone();
+            public_init_key.private_keys = None;
+            *user_init_key = Some(Box::new(public_init_key));
+        }
+        self
+    }
+
     /// Updates the node's public key to the given one. This is the only way to convert a `Blank`
-    /// node into a `Filled` one.
+    /// node into a `Filled` one. The node's parent hash, signature, and `UserInitKey` are cleared,
+    /// since they were about the old public key.
     pub(crate) fn update_public_key(&mut self, new_public_key: DhPublicKey) {
         match self {
             RatchetTreeNode::Blank => {
-                *self = RatchetTreeNode::Filled {
-                    public_key: new_public_key,
-                    private_key: None,
-                };
+                *self = RatchetTreeNode::new_from_public_key(new_public_key, None);
             }
             RatchetTreeNode::Filled {
                 ref mut public_key,
+                ref mut parent_hash,
+                ref mut signature,
+                ref mut user_init_key,
                 ..
-            } => *public_key = new_public_key,
+            } => {
+                *public_key = new_public_key;
+                parent_hash.clear();
+                *signature = None;
+                *user_init_key = None;
+            }
         }
     }
 
@@ -155,6 +224,145 @@ impl RatchetTreeNode {
             } => private_key.as_ref(),
         }
     }
+
+    /// Returns a node's parent hash. If the node is `Blank`, returns `None`.
+    pub(crate) fn get_parent_hash(&self) -> Option<&[u8]> {
+        match self {
+            RatchetTreeNode::Blank => None,
+            RatchetTreeNode::Filled {
+                ref parent_hash,
+                ..
+            } => Some(parent_hash.as_slice()),
+        }
+    }
+}
+
+/// The parts of a node that the serialized form of a `RatchetTree` leaves out: its parent hash
+/// and, for a leaf, its owner's signature over it or the `UserInitKey` it was added with. These are
+/// carried alongside the tree wherever a new member receives one, so that they can check where the
+/// tree's public keys came from.
+#[derive(Clone, Deserialize, Serialize)]
+#[cfg_attr(test, derive(Debug))]
+pub(crate) struct NodeParentHash {
+    // opaque parent_hash<0..255>;
+    /// The node's parent hash. This is empty for `Blank` nodes.
+    #[serde(rename = "parent_hash__bound_u8")]
+    pub(crate) parent_hash: Vec<u8>,
+
+    // optional<Signature> signature;
+    /// The signature of a leaf's owner over the leaf's public key and parent hash
+    pub(crate) signature: Option<Signature>,
+
+    // opaque user_init_key<0..2^32-1>; // An optional<UserInitKey>
+    /// The `UserInitKey` an unsigned leaf was added with. This is length-prefixed, since a
+    /// `UserInitKey` may end in extensions the spec has no room for.
+    #[serde(rename = "user_init_key__bound_u32")]
+    pub(crate) user_init_key: Option<UserInitKey>,
+}
+
+// struct {
+//     PublicKey public_key;
+//     opaque parent_hash<0..255>;
+// } NodeInfo;
+/// The contents of a node that parent hashes, leaf signatures, and tree hashes commit to
+#[derive(Serialize)]
+struct NodeInfo<'a> {
+    public_key: &'a DhPublicKey,
+    #[serde(rename = "parent_hash__bound_u8")]
+    parent_hash: &'a [u8],
+}
+
+// struct {
+//     uint8 hash_type = 0;
+//     optional<NodeInfo> info;
+// } LeafNodeHashInput;
+/// The thing that is hashed to get the tree hash of a leaf node
+#[derive(Serialize)]
+struct LeafNodeHashInput<'a> {
+    hash_type: u8,
+    info: Option<NodeInfo<'a>>,
+}
+
+// struct {
+//     uint8 hash_type = 1;
+//     optional<NodeInfo> info;
+//     opaque left_hash<0..255>;
+//     opaque right_hash<0..255>;
+// } ParentNodeHashInput;
+/// The thing that is hashed to get the tree hash of a non-leaf node
+#[derive(Serialize)]
+struct ParentNodeHashInput<'a> {
+    hash_type: u8,
+    info: Option<NodeInfo<'a>>,
+    left_hash: &'a Digest,
+    right_hash: &'a Digest,
+}
+
+impl<'a> NodeInfo<'a> {
+    /// Returns the `NodeInfo` of the given node, or `None` if it's `Blank`
+    fn from_node(node: &'a RatchetTreeNode) -> Option<NodeInfo<'a>> {
+        match node {
+            RatchetTreeNode::Blank => None,
+            RatchetTreeNode::Filled {
+                ref public_key,
+                ref parent_hash,
+                ..
+            } => Some(NodeInfo {
+                public_key,
+                parent_hash,
+            }),
+        }
+    }
+}
+
+/// Computes the hash of a node with the given public key and parent hash. This is the parent hash
+/// that each child of the node gets when the node is set by a direct path.
+pub(crate) fn node_hash(
+    cs: &'static CipherSuite,
+    public_key: &DhPublicKey,
+    parent_hash: &[u8],
+) -> Result<Digest, Error> {
+    cs.hash_impl.hash_serializable(&NodeInfo {
+        public_key,
+        parent_hash,
+    })
+}
+
+/// Returns the bytes that a leaf's owner signs to vouch for the leaf's public key and parent hash
+pub(crate) fn leaf_signature_content(
+    public_key: &DhPublicKey,
+    parent_hash: &[u8],
+) -> Result<Vec<u8>, Error> {
+    tls_ser::serialize_to_bytes(&NodeInfo {
+        public_key,
+        parent_hash,
+    })
+}
+
+/// Computes the parent hash of every node on a direct path, given the public keys of the path from
+/// the leaf up to and including the root
+///
+/// Returns: `Ok(parent_hashes)` on success, where `parent_hashes` is in the same order as
+/// `public_keys`. The last of these, the root's, is empty.
+pub(crate) fn compute_parent_hashes<'a, I>(
+    cs: &'static CipherSuite,
+    public_keys: I,
+) -> Result<Vec<Vec<u8>>, Error>
+where
+    I: DoubleEndedIterator<Item = &'a DhPublicKey>,
+{
+    // parent_hash[root] = ""
+    // parent_hash[n] = Hash(NodeInfo { public_key[n+1], parent_hash[n+1] })
+    let mut parent_hashes = Vec::new();
+    let mut parent_hash = Vec::new();
+    for public_key in public_keys.rev() {
+        let next_parent_hash = node_hash(cs, public_key, &parent_hash)?.as_bytes().to_vec();
+        parent_hashes.push(parent_hash);
+        parent_hash = next_parent_hash;
+    }
+
+    parent_hashes.reverse();
+    Ok(parent_hashes)
 }
 
 /// A left-balanced binary tree of `RatchetTreeNode`s
@@ -221,6 +429,9 @@ impl RatchetTree {
     // there are >1 non-blank leaf nodes gives you a vector of odd length. All vectors of odd
     // length have a unique interpretation as a binary left-balanced tree. And if there are no
     // non-blank leaf nodes, you get an empty tree.
+    // Shrinking the tree can change the right child of the nodes on the direct path of the new
+    // last leaf, and nothing else. Those nodes' parent hashes were computed for their old
+    // children, so we blank them.
     /// Truncates the tree down to the first non-blank leaf node. If there is all blank, this will
     /// clear the tree.
     pub(crate) fn truncate_to_last_nonblank(&mut self) {
@@ -241,7 +452,16 @@ impl RatchetTree {
             Some(i) => {
                 // This can't fail, because i is an index
                 let num_elements_to_retain = i + 1;
-                self.nodes.truncate(num_elements_to_retain)
+                if num_elements_to_retain < self.size() {
+                    self.nodes.truncate(num_elements_to_retain);
+                    let new_num_leaves = tree_math::num_leaves_in_tree(self.size());
+                    // Skip the leaf itself. Its children haven't changed, since it has none.
+                    for path_node_idx in
+                        tree_math::node_extended_direct_path(i, new_num_leaves).skip(1)
+                    {
+                        self.nodes[path_node_idx] = RatchetTreeNode::Blank;
+                    }
+                }
             }
         }
     }
@@ -368,13 +588,16 @@ impl RatchetTree {
 
     /// Given a path secret, constructs a `DirectPathMessage` containing encrypted copies of the
     /// appropriately ratcheted path secret for the rest of the ratchet tree. See section
-    /// 5.2 in the spec for details.
+    /// 5.2 in the spec for details. The path's leaf and parent hash are signed with
+    /// `identity_key`.
     ///
     /// Requires: `starting_tree_idx` to be a leaf node. Otherwise, any child of ours would be
     /// unable to decrypt this message.
     pub(crate) fn encrypt_direct_path_secrets<R>(
         &self,
         cs: &'static CipherSuite,
+        ss: &SignatureScheme,
+        identity_key: &SigSecretKey,
         starting_tree_idx: usize,
         starting_path_secret: PathSecret,
         csprng: &mut R,
@@ -436,8 +659,16 @@ impl RatchetTree {
             parent_path_secret = grandparent_path_secret;
         }
 
+        // Vouch for the new leaf and, through its parent hash, the rest of the path
+        let parent_hashes =
+            compute_parent_hashes(cs, node_messages.iter().map(|msg| &msg.public_key))?;
+        let signature_content =
+            leaf_signature_content(&starting_node_public_key, &parent_hashes[0])?;
+        let signature = ss.sign(identity_key, &signature_content);
+
         Ok(DirectPathMessage {
             node_messages,
+            signature: Some(signature),
         })
     }
 
@@ -583,6 +814,242 @@ impl RatchetTree {
 
         Ok(root_node_secret)
     }
+
+    /// Computes the hash of the whole tree. This commits to every node's public key and parent
+    /// hash, as well as the shape of the tree.
+    pub(crate) fn tree_hash(&self, cs: &'static CipherSuite) -> Result<Digest, Error> {
+        // Helper function that hashes the subtree rooted at the given node
+        fn helper(
+            tree: &RatchetTree,
+            cs: &'static CipherSuite,
+            idx: usize,
+            num_leaves: usize,
+        ) -> Result<Digest, Error> {
+            let info = NodeInfo::from_node(&tree.nodes[idx]);
+            if tree_math::node_level(idx) == 0 {
+                cs.hash_impl.hash_serializable(&LeafNodeHashInput {
+                    hash_type: 0,
+                    info,
+                })
+            } else {
+                let left_hash = helper(tree, cs, tree_math::node_left_child(idx), num_leaves)?;
+                let right_hash =
+                    helper(tree, cs, tree_math::node_right_child(idx, num_leaves), num_leaves)?;
+                cs.hash_impl.hash_serializable(&ParentNodeHashInput {
+                    hash_type: 1,
+                    info,
+                    left_hash: &left_hash,
+                    right_hash: &right_hash,
+                })
+            }
+        }
+
+        if self.nodes.is_empty() {
+            // The empty tree has no root to hash
+            Ok(cs.hash_impl.hash_bytes(&[]))
+        } else {
+            let num_leaves = tree_math::num_leaves_in_tree(self.size());
+            helper(self, cs, tree_math::root_idx(num_leaves), num_leaves)
+        }
+    }
+
+    /// Returns the parent hash, signature, and `UserInitKey` of every node in the tree, in order
+    pub(crate) fn get_parent_hashes(&self) -> Vec<NodeParentHash> {
+        self.nodes
+            .iter()
+            .map(|node| match node {
+                RatchetTreeNode::Blank => NodeParentHash {
+                    parent_hash: Vec::new(),
+                    signature: None,
+                    user_init_key: None,
+                },
+                RatchetTreeNode::Filled {
+                    ref parent_hash,
+                    ref signature,
+                    ref user_init_key,
+                    ..
+                } => NodeParentHash {
+                    parent_hash: parent_hash.clone(),
+                    signature: signature.clone(),
+                    user_init_key: user_init_key.as_ref().map(|init_key| (**init_key).clone()),
+                },
+            })
+            .collect()
+    }
+
+    /// Sets the parent hash, signature, and `UserInitKey` of every node in the tree. This is the
+    /// inverse of `get_parent_hashes`. No verification is done here; for that, see
+    /// `verify_parent_hashes`.
+    ///
+    /// Returns: `Ok(())` on success. Returns an `Error::ValidationError` if there isn't exactly
+    /// one entry per node, or if a `Blank` node is given a parent hash, signature, or
+    /// `UserInitKey`.
+    pub(crate) fn set_parent_hashes(
+        &mut self,
+        parent_hashes: Vec<NodeParentHash>,
+    ) -> Result<(), Error> {
+        if parent_hashes.len() != self.size() {
+            return Err(Error::ValidationError("Number of parent hashes doesn't match tree size"));
+        }
+
+        for (node, entry) in self.nodes.iter_mut().zip(parent_hashes) {
+            match node {
+                RatchetTreeNode::Blank => {
+                    if !entry.parent_hash.is_empty()
+                        || entry.signature.is_some()
+                        || entry.user_init_key.is_some()
+                    {
+                        return Err(Error::ValidationError("Blank node has a parent hash"));
+                    }
+                }
+                RatchetTreeNode::Filled {
+                    ref mut parent_hash,
+                    ref mut signature,
+                    ref mut user_init_key,
+                    ..
+                } => {
+                    *parent_hash = entry.parent_hash;
+                    *signature = entry.signature;
+                    *user_init_key = entry.user_init_key.map(Box::new);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sets the parent hashes of the extended direct path of `start_idx` to `parent_hashes`, and
+    /// sets the signature of the leaf at `start_idx` to `signature`. This is meant to be called
+    /// right after the public keys of that path have been set.
+    ///
+    /// Returns: `Ok(())` on success. Returns an `Error::ValidationError` if the number of parent
+    /// hashes doesn't match the length of the path, or if a node on the path is `Blank`.
+    pub(crate) fn set_path_parent_hashes(
+        &mut self,
+        start_idx: usize,
+        parent_hashes: Vec<Vec<u8>>,
+        signature: Signature,
+    ) -> Result<(), Error> {
+        let num_leaves = tree_math::num_leaves_in_tree(self.size());
+        let ext_direct_path: Vec<usize> =
+            tree_math::node_extended_direct_path(start_idx, num_leaves).collect();
+        if ext_direct_path.len() != parent_hashes.len() {
+            return Err(Error::ValidationError("Parent hashes don't match the direct path"));
+        }
+
+        let mut signature = Some(signature);
+        for (path_node_idx, new_parent_hash) in ext_direct_path.into_iter().zip(parent_hashes) {
+            match self.get_mut(path_node_idx) {
+                Some(RatchetTreeNode::Filled {
+                    ref mut parent_hash,
+                    signature: ref mut node_signature,
+                    ref mut user_init_key,
+                    ..
+                }) => {
+                    *parent_hash = new_parent_hash;
+                    // Only the first node, the leaf, is signed. Once it is, it no longer needs its
+                    // UserInitKey to vouch for it.
+                    *node_signature = signature.take();
+                    *user_init_key = None;
+                }
+                _ => return Err(Error::ValidationError("Direct path node is blank")),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks that every public key in the tree is vouched for by some member. Concretely, every
+    /// filled leaf must either be signed by the member at that leaf, or come from a `UserInitKey`
+    /// that member signed, and every filled non-leaf node must have a child whose parent hash is
+    /// that node's hash.
+    ///
+    /// Returns: `Ok(())` if the tree checks out. Returns an `Error::ValidationError` if a node's
+    /// parent hash doesn't match or a leaf is vouched for by nobody, and an
+    /// `Error::SignatureError` if a leaf's signature doesn't verify.
+    pub(crate) fn verify_parent_hashes(
+        &self,
+        cs: &'static CipherSuite,
+        roster: &Roster,
+    ) -> Result<(), Error> {
+        let num_leaves = tree_math::num_leaves_in_tree(self.size());
+
+        for (idx, node) in self.nodes.iter().enumerate() {
+            let (public_key, parent_hash, signature, user_init_key) = match node {
+                RatchetTreeNode::Blank => continue,
+                RatchetTreeNode::Filled {
+                    ref public_key,
+                    ref parent_hash,
+                    ref signature,
+                    ref user_init_key,
+                    ..
+                } => (public_key, parent_hash, signature, user_init_key),
+            };
+
+            if tree_math::node_level(idx) == 0 {
+                let credential = roster
+                    .0
+                    .get(idx / 2)
+                    .and_then(|entry| entry.as_ref())
+                    .ok_or(Error::ValidationError("Filled leaf has no roster entry"))?;
+                match (signature, user_init_key) {
+                    (Some(signature), _) => {
+                        let signature_content = leaf_signature_content(public_key, parent_hash)?;
+                        credential.get_signature_scheme().verify(
+                            credential.get_public_key(),
+                            &signature_content,
+                            signature,
+                        )?;
+                    }
+                    // A leaf that was added and hasn't been touched since has the member's signed
+                    // UserInitKey to vouch for it instead
+                    (None, Some(init_key)) => {
+                        if !parent_hash.is_empty() {
+                            return Err(Error::ValidationError(
+                                "Leaf with a parent hash is unsigned",
+                            ));
+                        }
+                        if init_key.credential != *credential {
+                            return Err(Error::ValidationError(
+                                "Leaf's UserInitKey doesn't belong to the member there",
+                            ));
+                        }
+                        init_key.verify_sig()?;
+                        let init_public_key = init_key.get_public_key(cs)?.ok_or(
+                            Error::ValidationError("Leaf's UserInitKey has no key for this group"),
+                        )?;
+                        let keys_match: bool = init_public_key.ct_eq(public_key).into();
+                        if !keys_match {
+                            return Err(Error::ValidationError(
+                                "Leaf's public key doesn't match its UserInitKey",
+                            ));
+                        }
+                    }
+                    (None, None) => {
+                        return Err(Error::ValidationError("Leaf is vouched for by nobody"))
+                    }
+                }
+            } else {
+                // Some child on the direct path that set this node must point back to it
+                let expected_parent_hash = node_hash(cs, public_key, parent_hash)?;
+                let left_idx = tree_math::node_left_child(idx);
+                let right_idx = tree_math::node_right_child(idx, num_leaves);
+                let has_matching_child = [left_idx, right_idx].iter().any(|&child_idx| {
+                    self.nodes[child_idx]
+                        .get_parent_hash()
+                        .map(|child_parent_hash| {
+                            child_parent_hash == expected_parent_hash.as_bytes()
+                        })
+                        .unwrap_or(false)
+                });
+                if !has_matching_child {
+                    return Err(Error::ValidationError("Node is not the parent of its children"));
+                }
+            }
+        }
+
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -592,6 +1059,7 @@ mod test {
         crypto::{
             ciphersuite::X25519_SHA256_AES128GCM,
             dh::{DhPublicKey, DhPublicKeyRaw},
+            sig::ED25519_IMPL,
         },
         tls_de::TlsDeserializer,
     };
@@ -691,8 +1159,17 @@ mod test {
             rng.fill_bytes(&mut buf);
             PathSecret::new_from_bytes(&buf)
         };
+        let ss: &'static SignatureScheme = &ED25519_IMPL;
+        let identity_key = SigSecretKey::new_from_random(ss, &mut rng).unwrap();
         let direct_path_msg = tree
-            .encrypt_direct_path_secrets(cs, sender_tree_idx, sender_path_secret.clone(), &mut rng)
+            .encrypt_direct_path_secrets(
+                cs,
+                ss,
+                &identity_key,
+                sender_tree_idx,
+                sender_path_secret.clone(),
+                &mut rng,
+            )
             .expect("failed to encrypt direct path secrets");
         // Decrypt the path secret closest to the receiver
         let (derived_path_secret, common_ancestor_idx) = tree
@@ -751,10 +1228,10 @@ mod test {
                     nodes.push(RatchetTreeNode::Blank);
                 } else {
                     // TODO: Make a better way to put dummy values in the tree than invalid DH pubkeys
-                    nodes.push(RatchetTreeNode::Filled {
-                        public_key: DhPublicKey::Raw(DhPublicKeyRaw(Vec::new())),
-                        private_key: None,
-                    });
+                    nodes.push(RatchetTreeNode::new_from_public_key(
+                        DhPublicKey::Raw(DhPublicKeyRaw(Vec::new())),
+                        None,
+                    ));
                 }
                 bit_mask <<= 1;
             }
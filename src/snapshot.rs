@@ -0,0 +1,593 @@
+//! Defines the snapshot formats used to persist a `GroupState` and `ApplicationKeyChain`, secrets
+//! and all. Snapshots are TLS-serialized like everything else in this crate, but unlike the
+//! serialized form of `GroupState`, they are not meant to be sent over the wire.
+
+use crate::{
+    application::{ApplicationKeyChain, SkippedKey, WriteSecret},
+    credential::Roster,
+    crypto::{
+        ciphersuite::CipherSuite,
+        dh::{DhPrivateKey, DhPublicKey},
+        hash::Digest,
+        hmac::HmacKey,
+        sig::{SigSecretKey, SignatureScheme},
+    },
+    error::Error,
+    extension::Extension,
+    group_state::{ForkPoint, GroupState, MembershipStatus, SealSecret},
+    handshake::{GroupReInit, Handshake, ProtocolVersion, UserInitKey},
+    ratchet_tree::{NodeParentHash, RatchetTree},
+    tls_de::TlsDeserializer,
+    tls_ser, tree_math,
+    upcast::{CryptoCtx, CryptoUpcast},
+};
+
+use clear_on_drop::clear::Clear;
+use serde::de::Deserialize;
+
+/// The version of the snapshot formats produced by `GroupState::to_snapshot` and
+/// `ApplicationKeyChain::to_snapshot`. This MUST be incremented every time the layout of
+/// `GroupStateSnapshot` or `ApplicationKeyChainSnapshot` changes.
+const SNAPSHOT_FORMAT_VERSION: u8 = 1;
+
+// opaque secret<1..2^16-1>;
+/// The raw bytes of a secret key. These only ever appear in snapshots, and are cleared once the
+/// snapshot is done with them.
+#[derive(Deserialize, Serialize)]
+#[serde(rename = "SecretBytes__bound_u16")]
+struct SecretBytes(Vec<u8>);
+
+impl Drop for SecretBytes {
+    fn drop(&mut self) {
+        Clear::clear(self.0.as_mut_slice());
+    }
+}
+
+// opaque pending_handshake<0..2^32-1>;
+/// A buffered `Handshake`. It's length-prefixed since a `Handshake` may end in data the spec has no
+/// room for. See `WireHandshake`.
+#[derive(Deserialize, Serialize)]
+#[serde(rename = "PendingHandshake__bound_u32")]
+pub(crate) struct PendingHandshake(pub(crate) Handshake);
+
+/// Contains all group state, including all the fields of `GroupState` that are skipped when
+/// serializing it normally
+#[derive(Deserialize, Serialize)]
+pub(crate) struct GroupStateSnapshot {
+    // uint8 format_version;
+    /// The version of the snapshot format. This is the first thing in the snapshot, so that
+    /// future versions can tell what they're looking at.
+    format_version: u8,
+
+    /// The ciphersuite of the group. Every other crypto value in the snapshot is interpreted with
+    /// respect to this.
+    pub(crate) cipher_suite: &'static CipherSuite,
+
+    /// Version info
+    protocol_version: ProtocolVersion,
+
+    /// The signature scheme of `identity_key`
+    pub(crate) identity_key_scheme: &'static SignatureScheme,
+
+    /// This member's long-lived signing key
+    identity_key: SecretBytes,
+
+    // opaque group_id<0..255>;
+    #[serde(rename = "group_id__bound_u8")]
+    group_id: Vec<u8>,
+
+    epoch: u32,
+
+    // optional<Credential> roster<1..2^32-1>;
+    #[serde(rename = "roster__bound_u32")]
+    pub(crate) roster: Roster,
+
+    /// The public part of the ratchet tree
+    pub(crate) tree: RatchetTree,
+
+    // optional<SecretBytes> tree_private_keys<0..2^32-1>;
+    /// The private keys of the ratchet tree. The `i`th entry is the private key of the `i`th node
+    /// in `tree`, if it's known. This MUST have the same length as `tree.nodes`.
+    #[serde(rename = "tree_private_keys__bound_u32")]
+    tree_private_keys: Vec<Option<SecretBytes>>,
+
+    // NodeParentHash tree_parent_hashes<0..2^32-1>;
+    /// The parent hashes and leaf signatures of the ratchet tree, in the same order as
+    /// `tree.nodes`
+    #[serde(rename = "tree_parent_hashes__bound_u32")]
+    pub(crate) tree_parent_hashes: Vec<NodeParentHash>,
+
+    /// The hash of the ratchet tree
+    tree_hash: Digest,
+
+    transcript_hash: Digest,
+
+    // Extension extensions<0..2^16-1>;
+    #[serde(rename = "extensions__bound_u16")]
+    extensions: Vec<Extension>,
+
+    /// This member's position in the roster. This is `None` iff the group is in a preliminary
+    /// state.
+    roster_index: Option<u32>,
+
+    // opaque initializing_user_init_key<0..2^32-1>; // An optional<UserInitKey>
+    /// The `UserInitKey` this group was created from. This is `Some` iff the group is in a
+    /// preliminary state. This is length-prefixed, since a `UserInitKey` may end in extensions.
+    #[serde(rename = "initializing_user_init_key__bound_u32")]
+    pub(crate) initializing_user_init_key: Option<UserInitKey>,
+
+    // SecretBytes initializing_user_init_key_private_keys<0..2^16-1>;
+    /// The private keys of `initializing_user_init_key`. The `i`th entry is the private key
+    /// corresponding to the `i`th ciphersuite in the `UserInitKey`. This is empty if
+    /// `initializing_user_init_key` is `None` or has no private keys.
+    #[serde(rename = "initializing_user_init_key_private_keys__bound_u16")]
+    initializing_user_init_key_private_keys: Vec<SecretBytes>,
+
+    /// The public key of the last-resort `UserInitKey` this member joined with, if any
+    pub(crate) last_resort_public_key: Option<DhPublicKey>,
+
+    /// The private key of this member's pending `Update` proposal, if any
+    pending_update_private_key: Option<SecretBytes>,
+
+    /// Whether this member is in the group, leaving it, or has left it
+    membership_status: MembershipStatus,
+
+    // PendingHandshake pending_handshakes<0..2^32-1>;
+    /// Handshakes for future epochs that haven't been applied yet
+    #[serde(rename = "pending_handshakes__bound_u32")]
+    pub(crate) pending_handshakes: Vec<PendingHandshake>,
+
+    // struct {
+    //     uint32 prior_epoch;
+    //     optional<Credential> prior_roster<1..2^32-1>;
+    //     opaque prior_transcript_hash<0..255>;
+    //     uint32 signer_index;
+    // } ForkPoint;
+    // optional<ForkPoint> fork_point;
+    /// The public part of the previous epoch and who moved the group on from it, if the
+    /// `Handshake` that did so can still be rolled back. This contains no secrets.
+    pub(crate) fork_point: Option<ForkPoint>,
+
+    /// The `ReInit` operation that ended the group, if any
+    reinit: Option<GroupReInit>,
+
+    /// The secret that exported secrets are derived from, if it's known
+    exporter_secret: Option<HmacKey>,
+
+    /// The secret that successor groups are linked to, if it's known
+    resumption_secret: Option<HmacKey>,
+
+    /// The secret that sender data keys are derived from, if it's known
+    sender_data_secret: Option<HmacKey>,
+
+    /// The secret that the handshake keys are derived from, if it's known
+    handshake_secret: Option<HmacKey>,
+
+    /// The seal secret this member was given in their `Welcome`, if they haven't seen an epoch
+    /// change since
+    welcome_seal_secret: Option<SealSecret>,
+
+    // opaque init_secret<0..255>;
+    init_secret: HmacKey,
+}
+
+impl GroupStateSnapshot {
+    /// Makes a snapshot of the given `GroupState`
+    ///
+    /// Returns: `Ok(snapshot)` on success. Otherwise, returns an `Error::SerdeError`.
+    fn from_group_state(group_state: &GroupState) -> Result<GroupStateSnapshot, Error> {
+        let tree_private_keys = group_state
+            .tree
+            .nodes
+            .iter()
+            .map(|node| node.get_private_key().map(|k| SecretBytes(k.as_bytes())))
+            .collect();
+
+        // Pull the private keys out of the initializing UserInitKey, if there are any
+        let initializing_user_init_key_private_keys = group_state
+            .initializing_user_init_key
+            .as_ref()
+            .and_then(|uik| uik.private_keys.as_ref())
+            .map(|keys| keys.iter().map(|k| SecretBytes(k.as_bytes())).collect())
+            .unwrap_or_default();
+
+        Ok(GroupStateSnapshot {
+            format_version: SNAPSHOT_FORMAT_VERSION,
+            cipher_suite: group_state.cs,
+            protocol_version: group_state.protocol_version,
+            identity_key_scheme: group_state.get_signature_scheme(),
+            identity_key: SecretBytes(group_state.identity_key.as_bytes().to_vec()),
+            group_id: group_state.group_id.clone(),
+            epoch: group_state.epoch,
+            roster: group_state.roster.clone(),
+            tree: group_state.tree.clone(),
+            tree_private_keys,
+            tree_parent_hashes: group_state.tree.get_parent_hashes(),
+            tree_hash: group_state.tree_hash.clone(),
+            transcript_hash: group_state.transcript_hash.clone(),
+            extensions: group_state.extensions.clone(),
+            roster_index: group_state.roster_index,
+            initializing_user_init_key: group_state.initializing_user_init_key.clone(),
+            initializing_user_init_key_private_keys,
+            last_resort_public_key: group_state.last_resort_public_key.clone(),
+            pending_update_private_key: group_state
+                .pending_update_private_key
+                .as_ref()
+                .map(|k| SecretBytes(k.as_bytes())),
+            membership_status: group_state.membership_status,
+            pending_handshakes: group_state
+                .pending_handshakes
+                .iter()
+                .cloned()
+                .map(PendingHandshake)
+                .collect(),
+            fork_point: group_state.fork_point.clone(),
+            reinit: group_state.reinit.clone(),
+            exporter_secret: group_state.exporter_secret.clone(),
+            resumption_secret: group_state.resumption_secret.clone(),
+            sender_data_secret: group_state.sender_data_secret.clone(),
+            handshake_secret: group_state.handshake_secret.clone(),
+            welcome_seal_secret: group_state.welcome_seal_secret.clone(),
+            init_secret: group_state.init_secret.clone(),
+        })
+    }
+
+    /// Reassembles a `GroupState` from this snapshot. This assumes that the snapshot has already
+    /// been upcast.
+    ///
+    /// Returns: `Ok(group_state)` on success. If the snapshot is malformed, returns an
+    /// `Error::ValidationError`. If any of the secret keys are malformed, returns some other
+    /// sort of `Error`.
+    fn into_group_state(self) -> Result<GroupState, Error> {
+        let cs = self.cipher_suite;
+
+        // A valid GroupState is either preliminary or not
+        if self.roster_index.is_some() == self.initializing_user_init_key.is_some() {
+            return Err(Error::ValidationError(
+                "Snapshot must have precisely one of roster_index and initializing_user_init_key",
+            ));
+        }
+
+        // A member's own leaf and roster entry are looked up all the time, and it's assumed that
+        // they're there
+        if let Some(roster_index) = self.roster_index {
+            let idx = roster_index as usize;
+            let tree_size = self.tree.size();
+            if tree_size % 2 == 0 || idx >= tree_math::num_leaves_in_tree(tree_size) {
+                return Err(Error::ValidationError("Snapshot's roster_index is outside the tree"));
+            }
+            match self.roster.0.get(idx) {
+                Some(Some(_)) => (),
+                _ => {
+                    return Err(Error::ValidationError(
+                        "Snapshot's roster_index has no credential in the roster",
+                    ))
+                }
+            }
+        }
+
+        let identity_key =
+            SigSecretKey::new_from_bytes(self.identity_key_scheme, &self.identity_key.0)?;
+
+        // Put the private keys back in the tree
+        let mut tree = self.tree;
+        if tree.size() != self.tree_private_keys.len() {
+            return Err(Error::ValidationError(
+                "Snapshot's tree and tree private keys differ in length",
+            ));
+        }
+        for (node, private_key) in tree.nodes.iter_mut().zip(self.tree_private_keys) {
+            if let Some(bytes) = private_key {
+                if !node.is_filled() {
+                    return Err(Error::ValidationError(
+                        "Snapshot has a private key for a blank node",
+                    ));
+                }
+                node.update_private_key(DhPrivateKey::new_from_bytes(cs.dh_impl, &bytes.0)?);
+            }
+        }
+        // And the parent hashes. These were checked when they first came in, so we don't check
+        // them again.
+        tree.set_parent_hashes(self.tree_parent_hashes)?;
+
+        // Put the private keys back in the initializing UserInitKey. These are with respect to the
+        // UserInitKey's ciphersuites, not the group's.
+        let mut initializing_user_init_key = self.initializing_user_init_key;
+        if !self.initializing_user_init_key_private_keys.is_empty() {
+            let uik = initializing_user_init_key.as_mut().ok_or(Error::ValidationError(
+                "Snapshot has UserInitKey private keys but no UserInitKey",
+            ))?;
+            if uik.cipher_suites.len() != self.initializing_user_init_key_private_keys.len() {
+                return Err(Error::ValidationError(
+                    "Snapshot's UserInitKey ciphersuites and private keys differ in length",
+                ));
+            }
+
+            let mut private_keys = Vec::new();
+            for (uik_cs, bytes) in
+                uik.cipher_suites.iter().zip(self.initializing_user_init_key_private_keys.iter())
+            {
+                private_keys.push(DhPrivateKey::new_from_bytes(uik_cs.dh_impl, &bytes.0)?);
+            }
+            uik.private_keys = Some(private_keys);
+        }
+
+        let pending_update_private_key = self
+            .pending_update_private_key
+            .map(|bytes| DhPrivateKey::new_from_bytes(cs.dh_impl, &bytes.0))
+            .transpose()?;
+
+        Ok(GroupState {
+            cs,
+            protocol_version: self.protocol_version,
+            identity_key,
+            group_id: self.group_id,
+            epoch: self.epoch,
+            roster: self.roster,
+            tree,
+            tree_hash: self.tree_hash,
+            transcript_hash: self.transcript_hash,
+            extensions: self.extensions,
+            roster_index: self.roster_index,
+            initializing_user_init_key,
+            last_resort_public_key: self.last_resort_public_key,
+            pending_update_private_key,
+            membership_status: self.membership_status,
+            pending_handshakes: self.pending_handshakes.into_iter().map(|p| p.0).collect(),
+            fork_point: self.fork_point,
+            reinit: self.reinit,
+            exporter_secret: self.exporter_secret,
+            resumption_secret: self.resumption_secret,
+            sender_data_secret: self.sender_data_secret,
+            handshake_secret: self.handshake_secret,
+            welcome_seal_secret: self.welcome_seal_secret,
+            init_secret: self.init_secret,
+        })
+    }
+}
+
+/// Contains an entire `ApplicationKeyChain`. Unlike `GroupState`, an `ApplicationKeyChain` has no
+/// serialized form of its own, since all of it is secret.
+#[derive(Deserialize, Serialize)]
+struct ApplicationKeyChainSnapshot {
+    // uint8 format_version;
+    /// The version of the snapshot format
+    format_version: u8,
+
+    // (WriteSecret, uint32) write_secrets_and_gens<0..2^32-1>;
+    #[serde(rename = "write_secrets_and_gens__bound_u32")]
+    write_secrets_and_gens: Vec<(WriteSecret, u32)>,
+
+    group_cs: &'static CipherSuite,
+
+    // opaque group_id<0..255>;
+    #[serde(rename = "group_id__bound_u8")]
+    group_id: Vec<u8>,
+
+    group_epoch_at_creation: u32,
+
+    sender_data_secret: Option<HmacKey>,
+
+    // SkippedKey skipped_keys<0..2^32-1>;
+    #[serde(rename = "skipped_keys__bound_u32")]
+    skipped_keys: Vec<SkippedKey>,
+
+    max_forward_skip: u32,
+
+    max_skipped_keys: u32,
+}
+
+impl GroupState {
+    /// Serializes the entire `GroupState`, including all of its secrets, into a versioned snapshot
+    /// that can later be restored with `GroupState::from_snapshot`. The output of this function is
+    /// extremely sensitive. Store it accordingly.
+    ///
+    /// Returns: `Ok(snapshot_bytes)` on success. Otherwise, returns an `Error::SerdeError`.
+    pub fn to_snapshot(&self) -> Result<Vec<u8>, Error> {
+        let snapshot = GroupStateSnapshot::from_group_state(self)?;
+        tls_ser::serialize_to_bytes(&snapshot)
+    }
+
+    /// Restores a `GroupState` from the output of `GroupState::to_snapshot`
+    ///
+    /// Returns: `Ok(group_state)` on success. If the snapshot was made by an unsupported version
+    /// of the snapshot format, or is otherwise malformed, returns some sort of `Error`.
+    pub fn from_snapshot(bytes: &[u8]) -> Result<GroupState, Error> {
+        let mut cursor = bytes;
+        let mut deserializer = TlsDeserializer::from_reader(&mut cursor);
+        let mut snapshot = GroupStateSnapshot::deserialize(&mut deserializer)?;
+
+        if snapshot.format_version != SNAPSHOT_FORMAT_VERSION {
+            return Err(Error::ValidationError("Unsupported snapshot format version"));
+        }
+
+        // Once it's deserialized, make it nice and typesafe. The snapshot knows its own
+        // ciphersuite, so we don't need to supply one here.
+        snapshot.upcast_crypto_values(&CryptoCtx::new())?;
+        snapshot.into_group_state()
+    }
+}
+
+impl ApplicationKeyChain {
+    /// Serializes the entire `ApplicationKeyChain`, including all of its secrets, into a versioned
+    /// snapshot that can later be restored with `ApplicationKeyChain::from_snapshot`. The output
+    /// of this function is extremely sensitive. Store it accordingly.
+    ///
+    /// Returns: `Ok(snapshot_bytes)` on success. Otherwise, returns an `Error::SerdeError`.
+    pub fn to_snapshot(&self) -> Result<Vec<u8>, Error> {
+        let key_chain = self.clone();
+        let snapshot = ApplicationKeyChainSnapshot {
+            format_version: SNAPSHOT_FORMAT_VERSION,
+            write_secrets_and_gens: key_chain.write_secrets_and_gens,
+            group_cs: key_chain.group_cs,
+            group_id: key_chain.group_id,
+            group_epoch_at_creation: key_chain.group_epoch_at_creation,
+            sender_data_secret: key_chain.sender_data_secret,
+            skipped_keys: key_chain.skipped_keys,
+            max_forward_skip: key_chain.max_forward_skip,
+            max_skipped_keys: key_chain.max_skipped_keys,
+        };
+        tls_ser::serialize_to_bytes(&snapshot)
+    }
+
+    /// Restores an `ApplicationKeyChain` from the output of `ApplicationKeyChain::to_snapshot`
+    ///
+    /// Returns: `Ok(key_chain)` on success. If the snapshot was made by an unsupported version of
+    /// the snapshot format, or is otherwise malformed, returns some sort of `Error`.
+    pub fn from_snapshot(bytes: &[u8]) -> Result<ApplicationKeyChain, Error> {
+        let mut cursor = bytes;
+        let mut deserializer = TlsDeserializer::from_reader(&mut cursor);
+        let snapshot = ApplicationKeyChainSnapshot::deserialize(&mut deserializer)?;
+
+        if snapshot.format_version != SNAPSHOT_FORMAT_VERSION {
+            return Err(Error::ValidationError("Unsupported snapshot format version"));
+        }
+
+        // There's nothing to upcast here. The key chain consists only of symmetric secrets.
+        Ok(ApplicationKeyChain {
+            write_secrets_and_gens: snapshot.write_secrets_and_gens,
+            group_cs: snapshot.group_cs,
+            group_id: snapshot.group_id,
+            group_epoch_at_creation: snapshot.group_epoch_at_creation,
+            sender_data_secret: snapshot.sender_data_secret,
+            skipped_keys: snapshot.skipped_keys,
+            max_forward_skip: snapshot.max_forward_skip,
+            max_skipped_keys: snapshot.max_skipped_keys,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::GroupStateSnapshot;
+    use crate::{
+        crypto::ciphersuite::X25519_SHA256_AES128GCM,
+        error::Error,
+        group_state::{GroupState, Welcome},
+        handshake::{ProtocolVersion, UserInitKey, MLS_DUMMY_VERSION},
+        ratchet_tree::PathSecret,
+        test_utils, tls_ser,
+    };
+
+    use quickcheck_macros::quickcheck;
+    use rand::{RngCore, SeedableRng};
+
+    // Checks that GroupState::from_snapshot(group.to_snapshot()) == group. Since snapshots contain
+    // every field of a GroupState, it suffices to check that the restored group has the same
+    // snapshot as the original.
+    #[quickcheck]
+    fn snapshot_correctness(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+
+        // Start with an ordinary group whose tree secrets are all known
+        let (group_state, _) = test_utils::random_full_group_state(1, &mut rng);
+        let snapshot = group_state.to_snapshot().unwrap();
+        let restored_group_state = GroupState::from_snapshot(&snapshot).unwrap();
+
+        assert_serialized_eq!(group_state, restored_group_state, "GroupStates disagree");
+        assert_eq!(snapshot, restored_group_state.to_snapshot().unwrap(), "Snapshots disagree");
+
+        // Same for a group with a pending Update proposal
+        let (_, pending_group_state) = group_state.create_update_proposal(&mut rng).unwrap();
+        let snapshot = pending_group_state.to_snapshot().unwrap();
+        let restored_group_state = GroupState::from_snapshot(&snapshot).unwrap();
+        assert!(restored_group_state.pending_update_private_key.is_some());
+        assert_eq!(snapshot, restored_group_state.to_snapshot().unwrap(), "Snapshots disagree");
+
+        // Same for a group that just made a Handshake, and so remembers the state it came from
+        let new_path_secret = PathSecret::new_from_random(group_state.cs, &mut rng);
+        let (_, forkable_group_state, _) =
+            group_state.create_and_apply_update_handshake(new_path_secret, &mut rng).unwrap();
+        let snapshot = forkable_group_state.to_snapshot().unwrap();
+        let restored_group_state = GroupState::from_snapshot(&snapshot).unwrap();
+        assert!(restored_group_state.fork_point.is_some());
+        assert_eq!(snapshot, restored_group_state.to_snapshot().unwrap(), "Snapshots disagree");
+
+        // Same for a group that has been reinitialized. This also knows its resumption secret.
+        let (_, ended_group_state, _) = group_state
+            .create_and_apply_reinit_handshake(
+                b"successor".to_vec(),
+                &X25519_SHA256_AES128GCM,
+                MLS_DUMMY_VERSION,
+            )
+            .unwrap();
+        let snapshot = ended_group_state.to_snapshot().unwrap();
+        let restored_group_state = GroupState::from_snapshot(&snapshot).unwrap();
+        assert!(restored_group_state.reinit.is_some());
+        assert!(restored_group_state.resumption_secret.is_some());
+        assert_eq!(snapshot, restored_group_state.to_snapshot().unwrap(), "Snapshots disagree");
+
+        // Now do the same for a preliminary group, i.e., one that was just created from a Welcome.
+        // This has an initializing UserInitKey with private keys in it.
+        let cipher_suites = vec![&X25519_SHA256_AES128GCM];
+        let supported_versions: Vec<ProtocolVersion> = vec![MLS_DUMMY_VERSION; cipher_suites.len()];
+        let (new_credential, new_identity_key) = test_utils::random_basic_credential(&mut rng);
+        let user_init_key_id = {
+            let mut buf = [0u8; 16];
+            rng.fill_bytes(&mut buf);
+            buf.to_vec()
+        };
+        let init_key = UserInitKey::new_from_random(
+            &new_identity_key,
+            user_init_key_id,
+            new_credential,
+            cipher_suites,
+            supported_versions,
+            &mut rng,
+        )
+        .unwrap();
+        let (welcome, _) = Welcome::from_group_state(&group_state, &init_key, &mut rng).unwrap();
+        let preliminary_group_state =
+            GroupState::from_welcome(welcome, new_identity_key, init_key).unwrap();
+
+        let snapshot = preliminary_group_state.to_snapshot().unwrap();
+        let restored_group_state = GroupState::from_snapshot(&snapshot).unwrap();
+
+        assert_serialized_eq!(
+            preliminary_group_state,
+            restored_group_state,
+            "GroupStates disagree"
+        );
+        assert_eq!(snapshot, restored_group_state.to_snapshot().unwrap(), "Snapshots disagree");
+    }
+
+    // Checks that snapshots of an unknown format version are rejected
+    #[quickcheck]
+    fn snapshot_version_check(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let (group_state, _) = test_utils::random_full_group_state(1, &mut rng);
+
+        // The format version is the first byte of the snapshot
+        let mut snapshot = group_state.to_snapshot().unwrap();
+        snapshot[0] = snapshot[0].wrapping_add(1);
+
+        assert!(GroupState::from_snapshot(&snapshot).is_err());
+    }
+
+    // Checks that snapshots whose roster_index doesn't point at a member are rejected, rather than
+    // making a GroupState that panics later
+    #[quickcheck]
+    fn snapshot_roster_index_check(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let (group_state, _) = test_utils::random_full_group_state(2, &mut rng);
+        let my_idx = group_state.roster_index.unwrap();
+
+        // Past the end of the roster and the tree
+        let mut snapshot = GroupStateSnapshot::from_group_state(&group_state).unwrap();
+        snapshot.roster_index = Some(u32::MAX);
+        let bytes = tls_ser::serialize_to_bytes(&snapshot).unwrap();
+        match GroupState::from_snapshot(&bytes) {
+            Err(Error::ValidationError(_)) => (),
+            _ => panic!("Out-of-range roster_index was accepted"),
+        }
+
+        // Inside the tree, but nobody's there
+        let mut snapshot = GroupStateSnapshot::from_group_state(&group_state).unwrap();
+        snapshot.roster.0[my_idx as usize] = None;
+        let bytes = tls_ser::serialize_to_bytes(&snapshot).unwrap();
+        match GroupState::from_snapshot(&bytes) {
+            Err(Error::ValidationError(_)) => (),
+            _ => panic!("roster_index with no credential was accepted"),
+        }
+    }
+}
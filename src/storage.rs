@@ -0,0 +1,389 @@
+//! Defines encrypted-at-rest storage for group state. A `GroupState` and its current
+//! `ApplicationKeyChain` are snapshotted together and sealed under a caller-supplied
+//! `StorageKey`. The group ID, epoch, and a caller-maintained write counter are bound to the
+//! ciphertext as associated data, so that a sealed snapshot can't be passed off as belonging to a
+//! different group, nor as being newer than it is.
+
+use crate::{
+    application::ApplicationKeyChain,
+    crypto::{
+        aead::{AeadKey, AeadNonce},
+        ciphersuite::CipherSuite,
+        hkdf,
+        hmac::HmacKey,
+        rng::CryptoRng,
+    },
+    error::Error,
+    group_state::GroupState,
+    tls_de::TlsDeserializer,
+    tls_ser,
+};
+
+use serde::de::Deserialize;
+
+/// The version of the sealed snapshot format. This MUST be incremented every time the layout of
+/// `SealedGroupSnapshot` or `SealedGroupSnapshotContents` changes.
+const SEALED_SNAPSHOT_FORMAT_VERSION: u8 = 1;
+
+/// The minimum length of a `StorageKey`, in bytes
+const MIN_STORAGE_KEY_SIZE: usize = 16;
+
+/// A long-lived symmetric key used to seal group snapshots at rest. This is supplied by the
+/// caller, and is typically held in some platform keystore.
+#[derive(Clone)]
+pub struct StorageKey(HmacKey);
+
+impl StorageKey {
+    /// Makes a `StorageKey` from the given bytes. The bytes MUST be uniformly random.
+    ///
+    /// Returns: `Ok(storage_key)` on success. If there are fewer than 16 bytes, returns an
+    /// `Error::EncryptionError`.
+    pub fn new_from_bytes(bytes: &[u8]) -> Result<StorageKey, Error> {
+        if bytes.len() < MIN_STORAGE_KEY_SIZE {
+            return Err(Error::EncryptionError("Storage keys must be at least 128 bits long"));
+        }
+        Ok(StorageKey(HmacKey::new_from_bytes(bytes)))
+    }
+
+    /// Derives the AEAD key used to seal snapshots for the given ciphersuite
+    ///
+    /// Returns: `Ok(key)` on success. Otherwise, returns an `Error::EncryptionError`.
+    fn derive_aead_key(&self, cs: &'static CipherSuite) -> Result<AeadKey, Error> {
+        // key = HKDF-Expand-Label(storage_key, "snapshot key", "", key_length)
+        let mut key_buf = vec![0u8; cs.aead_impl.key_size()];
+        hkdf::expand_label(cs.hash_impl, &self.0, b"snapshot key", b"", key_buf.as_mut_slice());
+        AeadKey::new_from_bytes(cs.aead_impl, &key_buf)
+    }
+}
+
+impl core::fmt::Debug for StorageKey {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        // Ensure that the secret value isn't accidentally logged
+        f.write_str("StorageKey: CONTENTS OMITTED")
+    }
+}
+
+/// This is the associated data of a sealed snapshot. It's everything in `SealedGroupSnapshot`
+/// except for the ciphertext.
+#[derive(Serialize)]
+struct SealedGroupSnapshotHeader<'a> {
+    format_version: u8,
+    cipher_suite: &'static CipherSuite,
+    #[serde(rename = "group_id__bound_u8")]
+    group_id: &'a [u8],
+    epoch: u32,
+    write_counter: u64,
+    #[serde(rename = "nonce__bound_u8")]
+    nonce: &'a [u8],
+}
+
+/// This is the plaintext of a sealed snapshot
+#[derive(Deserialize, Serialize)]
+struct SealedGroupSnapshotContents {
+    // opaque group_state<0..2^32-1>;
+    /// The output of `GroupState::to_snapshot`
+    #[serde(rename = "group_state__bound_u32")]
+    group_state: Vec<u8>,
+
+    // opaque app_key_chain<0..2^32-1>;
+    /// The output of `ApplicationKeyChain::to_snapshot`
+    #[serde(rename = "app_key_chain__bound_u32")]
+    app_key_chain: Vec<u8>,
+}
+
+/// A `GroupState` and its `ApplicationKeyChain`, encrypted under a `StorageKey`. This is safe to
+/// write to disk.
+#[derive(Deserialize, Serialize)]
+#[cfg_attr(test, derive(Debug))]
+pub struct SealedGroupSnapshot {
+    // uint8 format_version;
+    /// The version of the sealed snapshot format
+    format_version: u8,
+
+    /// The ciphersuite of the sealed group. This determines how the ciphertext is encrypted.
+    cipher_suite: &'static CipherSuite,
+
+    // opaque group_id<0..255>;
+    /// The ID of the sealed group
+    #[serde(rename = "group_id__bound_u8")]
+    group_id: Vec<u8>,
+
+    /// The epoch of the sealed group
+    epoch: u32,
+
+    /// The caller's count of how many times this group has been sealed. This is what rollback is
+    /// checked against, since the `ApplicationKeyChain` advances without the epoch changing.
+    write_counter: u64,
+
+    // opaque nonce<0..255>;
+    /// The nonce that the contents were sealed with
+    #[serde(rename = "nonce__bound_u8")]
+    nonce: Vec<u8>,
+
+    // opaque ciphertext<0..2^32-1>;
+    /// The sealed `SealedGroupSnapshotContents`, with the tag at the end
+    #[serde(rename = "ciphertext__bound_u32")]
+    ciphertext: Vec<u8>,
+}
+
+impl SealedGroupSnapshot {
+    /// Snapshots the given `GroupState` and `ApplicationKeyChain` and seals them under the given
+    /// `StorageKey`. The key chain MUST be the one that was created alongside the `GroupState`.
+    /// `write_counter` MUST be greater than that of every snapshot of this group sealed before,
+    /// and the caller MUST persist it somewhere that can't be rolled back along with the sealed
+    /// snapshot, to later give to `open`.
+    ///
+    /// Returns: `Ok(sealed_snapshot)` on success. If the key chain does not belong to the
+    /// `GroupState`, returns an `Error::ValidationError`. Otherwise, if something goes wrong with
+    /// serialization or encryption, returns some other sort of `Error`.
+    pub fn seal<R>(
+        storage_key: &StorageKey,
+        group_state: &GroupState,
+        app_key_chain: &ApplicationKeyChain,
+        write_counter: u64,
+        csprng: &mut R,
+    ) -> Result<SealedGroupSnapshot, Error>
+    where
+        R: CryptoRng,
+    {
+        let cs = group_state.cs;
+
+        // We don't want to seal a key chain with a group that it doesn't belong to
+        app_key_chain.validate_against_group_state(group_state)?;
+        if app_key_chain.get_epoch() != group_state.epoch {
+            return Err(Error::ValidationError("Key chain is not from the group's current epoch"));
+        }
+
+        // Pick a random nonce
+        let mut nonce = vec![0u8; cs.aead_impl.nonce_size()];
+        csprng.try_fill_bytes(&mut nonce).map_err(|_| Error::OutOfEntropy)?;
+
+        // Make the associated data
+        let header = SealedGroupSnapshotHeader {
+            format_version: SEALED_SNAPSHOT_FORMAT_VERSION,
+            cipher_suite: cs,
+            group_id: &group_state.group_id,
+            epoch: group_state.epoch,
+            write_counter,
+            nonce: &nonce,
+        };
+        let associated_data = tls_ser::serialize_to_bytes(&header)?;
+
+        // Serialize the contents and make room for the tag
+        let contents = SealedGroupSnapshotContents {
+            group_state: group_state.to_snapshot()?,
+            app_key_chain: app_key_chain.to_snapshot()?,
+        };
+        let mut ciphertext = tls_ser::serialize_to_bytes(&contents)?;
+        ciphertext.extend(vec![0u8; cs.aead_impl.tag_size()]);
+
+        // Encrypt it
+        let key = storage_key.derive_aead_key(cs)?;
+        let aead_nonce = AeadNonce::new_from_bytes(cs.aead_impl, &nonce)?;
+        cs.aead_impl.seal_with_ad(&key, aead_nonce, &associated_data, &mut ciphertext)?;
+
+        Ok(SealedGroupSnapshot {
+            format_version: SEALED_SNAPSHOT_FORMAT_VERSION,
+            cipher_suite: cs,
+            group_id: group_state.group_id.clone(),
+            epoch: group_state.epoch,
+            write_counter,
+            nonce,
+            ciphertext,
+        })
+    }
+
+    /// Decrypts this snapshot with the given `StorageKey` and restores the `GroupState` and
+    /// `ApplicationKeyChain` inside it. `min_write_counter` is the write counter of the most recent
+    /// snapshot of this group that the caller has sealed. Any snapshot sealed before that one is
+    /// rejected, even one from the same epoch, since loading it would rewind the key chain and
+    /// make the next encryption reuse a key and nonce.
+    ///
+    /// Returns: `Ok((group_state, app_key_chain))` on success. If the snapshot's write counter is
+    /// less than `min_write_counter`, or its contents are inconsistent with its header, returns an
+    /// `Error::ValidationError`. If the snapshot was tampered with or the storage key is wrong,
+    /// returns an `Error::EncryptionError`. Otherwise, if the contents are malformed, returns some
+    /// other sort of `Error`.
+    pub fn open(
+        self,
+        storage_key: &StorageKey,
+        min_write_counter: u64,
+    ) -> Result<(GroupState, ApplicationKeyChain), Error> {
+        if self.format_version != SEALED_SNAPSHOT_FORMAT_VERSION {
+            return Err(Error::ValidationError("Unsupported sealed snapshot format version"));
+        }
+        // Check for rollback before we bother decrypting anything. The write counter is
+        // authenticated below, so this can't be faked.
+        if self.write_counter < min_write_counter {
+            return Err(Error::ValidationError(
+                "Sealed snapshot is older than the last one written",
+            ));
+        }
+
+        let cs = self.cipher_suite;
+
+        // Reconstruct the associated data
+        let header = SealedGroupSnapshotHeader {
+            format_version: self.format_version,
+            cipher_suite: cs,
+            group_id: &self.group_id,
+            epoch: self.epoch,
+            write_counter: self.write_counter,
+            nonce: &self.nonce,
+        };
+        let associated_data = tls_ser::serialize_to_bytes(&header)?;
+
+        // Decrypt and deserialize the contents
+        let key = storage_key.derive_aead_key(cs)?;
+        let nonce = AeadNonce::new_from_bytes(cs.aead_impl, &self.nonce)?;
+        let mut ciphertext = self.ciphertext;
+        let plaintext =
+            cs.aead_impl.open_with_ad(&key, nonce, &associated_data, &mut ciphertext)?;
+        let contents = {
+            let mut cursor: &[u8] = plaintext;
+            let mut deserializer = TlsDeserializer::from_reader(&mut cursor);
+            SealedGroupSnapshotContents::deserialize(&mut deserializer)?
+        };
+
+        let group_state = GroupState::from_snapshot(&contents.group_state)?;
+        let app_key_chain = ApplicationKeyChain::from_snapshot(&contents.app_key_chain)?;
+
+        // Make sure the contents are what the header says they are
+        if group_state.group_id != self.group_id
+            || group_state.epoch != self.epoch
+            || group_state.cs != cs
+        {
+            return Err(Error::ValidationError(
+                "Sealed snapshot's contents differ from its header",
+            ));
+        }
+        app_key_chain.validate_against_group_state(&group_state)?;
+        if app_key_chain.get_epoch() != group_state.epoch {
+            return Err(Error::ValidationError("Key chain is not from the group's current epoch"));
+        }
+
+        Ok((group_state, app_key_chain))
+    }
+
+    /// Returns the ID of the group in this sealed snapshot. This is not authenticated until the
+    /// snapshot is opened.
+    pub fn get_group_id(&self) -> &[u8] {
+        self.group_id.as_slice()
+    }
+
+    /// Returns the epoch of the group in this sealed snapshot. This is not authenticated until the
+    /// snapshot is opened.
+    pub fn get_epoch(&self) -> u32 {
+        self.epoch
+    }
+
+    /// Returns the write counter this snapshot was sealed with. This is not authenticated until
+    /// the snapshot is opened.
+    pub fn get_write_counter(&self) -> u64 {
+        self.write_counter
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        application::ApplicationKeyChain,
+        crypto::rng::CryptoRng,
+        group_state::GroupState,
+        ratchet_tree::PathSecret,
+        storage::{SealedGroupSnapshot, StorageKey},
+        test_utils,
+    };
+
+    use quickcheck_macros::quickcheck;
+    use rand::{RngCore, SeedableRng};
+
+    // Returns a random StorageKey
+    fn random_storage_key<R: CryptoRng>(rng: &mut R) -> StorageKey {
+        let mut buf = [0u8; 32];
+        rng.fill_bytes(&mut buf);
+        StorageKey::new_from_bytes(&buf).unwrap()
+    }
+
+    // Makes a random group and does an Update on it so that we get a key chain that goes with it
+    fn random_group_and_key_chain<R: CryptoRng>(rng: &mut R) -> (GroupState, ApplicationKeyChain) {
+        let (group_state, _) = test_utils::random_full_group_state(1, rng);
+        let new_path_secret = PathSecret::new_from_random(group_state.cs, rng);
+        let (_, new_group_state, app_key_chain) =
+            group_state.create_and_apply_update_handshake(new_path_secret, rng).unwrap();
+
+        (new_group_state, app_key_chain)
+    }
+
+    // Checks that opening a sealed snapshot gives back what was sealed
+    #[quickcheck]
+    fn sealed_snapshot_correctness(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let storage_key = random_storage_key(&mut rng);
+        let (group_state, app_key_chain) = random_group_and_key_chain(&mut rng);
+
+        let write_counter = rng.next_u64();
+        let sealed = SealedGroupSnapshot::seal(
+            &storage_key,
+            &group_state,
+            &app_key_chain,
+            write_counter,
+            &mut rng,
+        )
+        .unwrap();
+        assert_eq!(sealed.get_write_counter(), write_counter);
+        let (restored_group_state, restored_app_key_chain) =
+            sealed.open(&storage_key, write_counter).unwrap();
+
+        assert_eq!(group_state.to_snapshot().unwrap(), restored_group_state.to_snapshot().unwrap());
+        assert_eq!(
+            app_key_chain.to_snapshot().unwrap(),
+            restored_app_key_chain.to_snapshot().unwrap()
+        );
+    }
+
+    // Checks that sealed snapshots fail to open if they're tampered with, rolled back, or opened
+    // with the wrong key
+    #[quickcheck]
+    fn sealed_snapshot_soundness(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let storage_key = random_storage_key(&mut rng);
+        let (group_state, app_key_chain) = random_group_and_key_chain(&mut rng);
+
+        let seal = |rng: &mut rand::rngs::StdRng| {
+            SealedGroupSnapshot::seal(&storage_key, &group_state, &app_key_chain, 1, rng).unwrap()
+        };
+
+        // Flip a random bit in the ciphertext
+        let mut sealed = seal(&mut rng);
+        let idx = rng.next_u32() as usize % sealed.ciphertext.len();
+        sealed.ciphertext[idx] ^= 1 << (rng.next_u32() % 8);
+        assert!(sealed.open(&storage_key, 0).is_err());
+
+        // Claim that the snapshot is from a later epoch than it really is. This should fail
+        // authentication.
+        let mut sealed = seal(&mut rng);
+        sealed.epoch = sealed.epoch.wrapping_add(1);
+        assert!(sealed.open(&storage_key, 0).is_err());
+
+        // Claim that the snapshot is from a different group
+        let mut sealed = seal(&mut rng);
+        sealed.group_id.push(0xff);
+        assert!(sealed.open(&storage_key, 0).is_err());
+
+        // Claim that the snapshot was written later than it really was
+        let mut sealed = seal(&mut rng);
+        sealed.write_counter += 1;
+        assert!(sealed.open(&storage_key, 0).is_err());
+
+        // Try to open a snapshot from before the last one written. This is from the same epoch,
+        // so only the write counter can catch it.
+        let sealed = seal(&mut rng);
+        assert!(sealed.open(&storage_key, 2).is_err());
+
+        // Try to open a snapshot with the wrong key
+        let sealed = seal(&mut rng);
+        let wrong_storage_key = random_storage_key(&mut rng);
+        assert!(sealed.open(&wrong_storage_key, 0).is_err());
+    }
+}
@@ -7,9 +7,9 @@ use crate::{
         rng::CryptoRng,
         sig::{SigPublicKey, SigSecretKey, SignatureScheme, ED25519_IMPL},
     },
-    group_state::GroupState,
-    handshake::MLS_DUMMY_VERSION,
-    ratchet_tree::{PathSecret, RatchetTree, RatchetTreeNode},
+    group_state::{GroupState, MembershipStatus},
+    handshake::{UserInitKey, MLS_DUMMY_VERSION},
+    ratchet_tree::{self, NodeParentHash, PathSecret, RatchetTree, RatchetTreeNode},
     tree_math,
 };
 
@@ -97,6 +97,54 @@ fn random_tree<R: rand::Rng + CryptoRng>(
     tree
 }
 
+// Gives every node of a full tree the parent hash it would have if its parent were set by a
+// direct path through it, and signs every leaf with the identity key of the member there
+fn sign_full_tree(
+    tree: &mut RatchetTree,
+    cs: &'static CipherSuite,
+    ss: &SignatureScheme,
+    identity_keys: &[SigSecretKey],
+) {
+    let num_leaves = tree_math::num_leaves_in_tree(tree.size());
+    let root_idx = tree_math::root_idx(num_leaves);
+
+    // Go top-down, so that every node's parent has its parent hash by the time we get to it
+    let mut indices: Vec<usize> = (0..tree.size()).collect();
+    indices.sort_by_key(|&idx| core::cmp::Reverse(tree_math::node_level(idx)));
+    let mut parent_hashes = vec![Vec::new(); tree.size()];
+    for idx in indices {
+        if idx != root_idx {
+            let parent_idx = tree_math::node_parent(idx, num_leaves);
+            let parent_public_key = tree.get(parent_idx).unwrap().get_public_key().unwrap();
+            parent_hashes[idx] =
+                ratchet_tree::node_hash(cs, parent_public_key, &parent_hashes[parent_idx])
+                    .unwrap()
+                    .as_bytes()
+                    .to_vec();
+        }
+    }
+
+    let entries = parent_hashes
+        .into_iter()
+        .enumerate()
+        .map(|(idx, parent_hash)| {
+            let signature = if idx % 2 == 0 {
+                let public_key = tree.get(idx).unwrap().get_public_key().unwrap();
+                let content = ratchet_tree::leaf_signature_content(public_key, &parent_hash);
+                Some(ss.sign(&identity_keys[idx / 2], &content.unwrap()))
+            } else {
+                None
+            };
+            NodeParentHash {
+                parent_hash,
+                signature,
+                user_init_key: None,
+            }
+        })
+        .collect();
+    tree.set_parent_hashes(entries).unwrap();
+}
+
 // Generates a random GroupState object (of at least min_size many members) and all the identity
 // keys associated with the credentials in the roster. The group state generated has all roster
 // entries non-null and all tree nodes Filled with known secrets.
@@ -125,8 +173,10 @@ pub(crate) fn random_full_group_state<R: rand::Rng + CryptoRng>(
     }
     let my_identity_key = identity_keys[my_roster_idx as usize].clone();
 
-    // Make a full tree with all secrets known
-    let tree = random_tree(rng, cs, group_size as usize);
+    // Make a full tree with all secrets known, whose parent hashes all check out
+    let mut tree = random_tree(rng, cs, group_size as usize);
+    sign_full_tree(&mut tree, cs, ss, &identity_keys);
+    let tree_hash = tree.tree_hash(cs).unwrap();
 
     // Make a random 16 byte group ID
     let group_id = {
@@ -147,9 +197,22 @@ pub(crate) fn random_full_group_state<R: rand::Rng + CryptoRng>(
         epoch: rng.gen(),
         roster: roster,
         tree: tree,
+        tree_hash,
         transcript_hash: transcript_hash,
+        extensions: Vec::new(),
         roster_index: Some(my_roster_idx),
         initializing_user_init_key: None,
+        last_resort_public_key: None,
+        pending_update_private_key: None,
+        membership_status: MembershipStatus::Member,
+        pending_handshakes: Vec::new(),
+        fork_point: None,
+        reinit: None,
+        exporter_secret: None,
+        resumption_secret: None,
+        sender_data_secret: None,
+        handshake_secret: None,
+        welcome_seal_secret: None,
         init_secret: init_secret,
     };
 
@@ -184,6 +247,32 @@ pub(crate) fn random_basic_credential<R: rand::Rng + CryptoRng>(
     (cred, identity_key)
 }
 
+// Returns a randomly-generated UserInitKey for the given cipher suite with a random credential,
+// along with the identity key corresponding to that credential
+pub(crate) fn random_user_init_key<R: rand::Rng + CryptoRng>(
+    rng: &mut R,
+    cs: &'static CipherSuite,
+) -> (UserInitKey, SigSecretKey) {
+    let (credential, identity_key) = random_basic_credential(rng);
+    // Key ID is random
+    let user_init_key_id = {
+        let mut buf = [0u8; 16];
+        rng.fill_bytes(&mut buf);
+        buf.to_vec()
+    };
+    let init_key = UserInitKey::new_from_random(
+        &identity_key,
+        user_init_key_id,
+        credential,
+        vec![cs],
+        vec![MLS_DUMMY_VERSION],
+        rng,
+    )
+    .unwrap();
+
+    (init_key, identity_key)
+}
+
 // Returns a new GroupState where the roster index is changed to the given `new_index` and the
 // identity key is changed to correspond to that roster index. Requires that the secret keys in
 // `identity_keys` correspond to the public keys in the given group's roster
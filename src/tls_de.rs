@@ -64,13 +64,40 @@ where
 /// prefix".
 pub struct TlsDeserializer<'a, R: std::io::Read> {
     reader: &'a mut R,
+    /// Whether the value being deserialized runs to the end of `reader`, and `reader` ends where
+    /// some length prefix said it does. Only such a value can end in `__trailing` fields, since
+    /// that's the only way to tell they're absent.
+    at_bounded_end: bool,
 }
 
 impl<'a, R: std::io::Read> TlsDeserializer<'a, R> {
-    /// Makes a new `TlsDeserializer` from the given byte reader
+    /// Makes a new `TlsDeserializer` from the given byte reader. Nothing is known about where the
+    /// reader ends, so a value that ends in `__trailing` fields can't be read from it. Use
+    /// `from_bytes` for those.
     pub fn from_reader(reader: &'a mut R) -> TlsDeserializer<R> {
         TlsDeserializer {
             reader,
+            at_bounded_end: false,
+        }
+    }
+
+    /// Makes a `TlsDeserializer` for the next part of the value this one is deserializing. If
+    /// `is_last`, nothing in this value follows the part.
+    fn part(&mut self, is_last: bool) -> TlsDeserializer<R> {
+        TlsDeserializer {
+            reader: &mut *self.reader,
+            at_bounded_end: is_last && self.at_bounded_end,
+        }
+    }
+}
+
+impl<'a, 'b> TlsDeserializer<'a, &'b [u8]> {
+    /// Makes a new `TlsDeserializer` that reads a single value out of the given bytes. The value
+    /// is taken to end where the bytes do, so it may end in `__trailing` fields.
+    pub fn from_bytes(bytes: &'a mut &'b [u8]) -> TlsDeserializer<'a, &'b [u8]> {
+        TlsDeserializer {
+            reader: bytes,
+            at_bounded_end: true,
         }
     }
 }
@@ -144,9 +171,12 @@ impl<'de, 'a, 'b, R: std::io::Read> Deserializer<'de> for &'b mut TlsDeserialize
         // deserialize the contents normally. It will finish when it runs out of things to read.
         // This is guaranteed by the logic in TlsVecSeq.
         if let Some(len) = field_len {
-            // Make a new deserializer with a sub-buffer
+            // Make a new deserializer with a sub-buffer. The contents run to the end of it.
             let mut sub_reader = self.reader.take(len);
-            let mut sub_deserializer = TlsDeserializer::from_reader(&mut sub_reader);
+            let mut sub_deserializer = TlsDeserializer {
+                reader: &mut sub_reader,
+                at_bounded_end: true,
+            };
 
             // Deserialize the contents normally
             visitor.visit_newtype_struct(&mut sub_deserializer)
@@ -305,7 +335,13 @@ impl<'de, 'a, 'b, R: std::io::Read> Deserializer<'de> for &'b mut TlsDeserialize
 }
 
 /// This deals with the logic of deserializing structs. This is just a sequence of fields. If the
-/// field name has a length tag size, this is handled appropriately.
+/// field name has a length tag size, this is handled appropriately. If the field name contains
+/// `__trailing`, the field may be left off the end of the input entirely. This is how we carry
+/// things the spec has no room for, without breaking messages that follow the spec. Such fields
+/// must come last, be `#[serde(default)]`, and be skipped when serializing their default value.
+/// A struct with such fields must end a length-bounded field, or the bytes given to
+/// `TlsDeserializer::from_bytes`. Otherwise, whatever follows it could be mistaken for them, so
+/// deserializing it fails.
 struct TlsStructSeq<'a, 'b, R: std::io::Read> {
     /// A reference to the deserializer that called us
     de: &'a mut TlsDeserializer<'b, R>,
@@ -366,7 +402,8 @@ impl<'de, 'a, 'b, R: std::io::Read> serde::de::SeqAccess<'de> for TlsTupleSeq<'a
             Ok(None)
         } else {
             self.idx += 1;
-            seed.deserialize(&mut *self.de).map(Some)
+            let is_last = self.idx == self.len;
+            seed.deserialize(&mut self.de.part(is_last)).map(Some)
         }
     }
 }
@@ -384,26 +421,33 @@ impl<'de, 'a, 'b, R: std::io::Read> serde::de::SeqAccess<'de> for TlsStructSeq<'
         let field =
             self.fields.get(self.field_idx).expect("in unknown field while deserializing a struct");
         self.field_idx += 1;
-
-        // If this is a variable-length field, read off the length
-        let field_len = get_field_len(field, &mut self.de.reader)?;
-
-        // As in TlsDeserializer::deserialize_newtype_struct, make a sub-reader that only reads the
-        // number of bytes specified by the length tag. Then deserialize the contents normally. It
-        // will finish when it runs out of things to read. This is guaranteed by the logic in
-        // TlsVecSeq.
-        if let Some(len) = field_len {
-            // Make a sub-buffer to read from
-            let mut sub_reader = self.de.reader.take(len);
-            let mut sub_deserializer = TlsDeserializer::from_reader(&mut sub_reader);
-
-            // Deserialize from it normally
-            seed.deserialize(&mut sub_deserializer).map(Some)
-        } else {
-            // If no length is specified, do the natural thing
-            seed.deserialize(&mut *self.de).map(Some)
+        let is_last = self.field_idx == self.fields.len();
+
+        // A trailing field is absent iff there's nothing left to read. Returning None makes serde
+        // fill in the default. Otherwise, put back the byte we peeked at and read the field from
+        // there. If this struct doesn't run to the end of a length-bounded reader, running out of
+        // things to read says nothing, so we refuse.
+        if field.contains("__trailing") {
+            if !self.de.at_bounded_end {
+                return Err(make_custom_error(format_args!(
+                    "trailing field {} is not at the end of a length-bounded field",
+                    field
+                )));
+            }
+            let mut first_byte = [0u8; 1];
+            if self.de.reader.read(&mut first_byte)? == 0 {
+                return Ok(None);
+            }
+            let mut rest = (&first_byte[..]).chain(&mut *self.de.reader);
+            let mut sub_deserializer = TlsDeserializer {
+                reader: &mut rest,
+                at_bounded_end: is_last,
+            };
+            return deserialize_field(field, &mut sub_deserializer, seed).map(Some);
         }
 
+        deserialize_field(field, &mut self.de.part(is_last), seed).map(Some)
+
         // We can't wrap errors like below. Recall that the sequence deserializer will stop
         // deserializing once it hits an io::ErrorKind::UnexpectedEof. If `res` above is one of
         // those, then changing it to an ErrorKind::Custom will make the sequence deserializer
@@ -414,6 +458,38 @@ impl<'de, 'a, 'b, R: std::io::Read> serde::de::SeqAccess<'de> for TlsStructSeq<'
     }
 }
 
+/// Deserializes the struct field named `field` from `de`. If this is a variable-length field, its
+/// length is read off first.
+fn deserialize_field<'de, R, T>(
+    field: &'static str,
+    de: &mut TlsDeserializer<R>,
+    seed: T,
+) -> Result<T::Value, Error>
+where
+    R: std::io::Read,
+    T: serde::de::DeserializeSeed<'de>,
+{
+    let field_len = get_field_len(field, &mut de.reader)?;
+
+    // As in TlsDeserializer::deserialize_newtype_struct, make a sub-reader that only reads the
+    // number of bytes specified by the length tag. Then deserialize the contents normally. It will
+    // finish when it runs out of things to read. This is guaranteed by the logic in TlsVecSeq.
+    if let Some(len) = field_len {
+        // Make a sub-buffer to read from. The field runs to the end of it.
+        let mut sub_reader = de.reader.take(len);
+        let mut sub_deserializer = TlsDeserializer {
+            reader: &mut sub_reader,
+            at_bounded_end: true,
+        };
+
+        // Deserialize from it normally
+        seed.deserialize(&mut sub_deserializer)
+    } else {
+        // If no length is specified, do the natural thing
+        seed.deserialize(de)
+    }
+}
+
 /// This deals with the logic of deserializing sequences (mostly `Vec`s). The logic is simple: keep
 /// deserializing items until you run out of buffer space. The reader that this is given is limited
 /// to the total number of bytes we're supposed to read, so there's no fear of overrun.
@@ -438,8 +514,9 @@ impl<'de, 'a, 'b, R: std::io::Read> serde::de::SeqAccess<'de> for TlsVecSeq<'a,
     where
         T: serde::de::DeserializeSeed<'de>,
     {
-        // Try to deserialize the next item
-        match seed.deserialize(&mut *self.de) {
+        // Try to deserialize the next item. We don't know whether it's the last one, so it can't
+        // end in trailing fields.
+        match seed.deserialize(&mut self.de.part(false)) {
             // If it's all good, return it
             Ok(a) => Ok(Some(a)),
             Err(Error::SerdeError(io_err)) => {
@@ -549,4 +626,85 @@ mod test {
 
         assert_eq!(deserialized_biff, expected_biff);
     }
+
+    #[derive(Debug, Deserialize, Eq, PartialEq, Serialize)]
+    struct Tail {
+        a: u8,
+        #[serde(rename = "b__trailing__bound_u8", default, skip_serializing_if = "Vec::is_empty")]
+        b: Vec<u8>,
+    }
+
+    // Make sure that a trailing field round-trips when it's there, and gets its default when it's
+    // left off
+    #[test]
+    fn trailing_field_kat() {
+        let cases: &[(&[u8], Tail)] = &[
+            (
+                &[0x01],
+                Tail {
+                    a: 1,
+                    b: Vec::new(),
+                },
+            ),
+            (
+                &[0x01, 0x02, 0x03, 0x04],
+                Tail {
+                    a: 1,
+                    b: vec![3, 4],
+                },
+            ),
+        ];
+        for (bytes, expected_tail) in cases {
+            let mut buf = *bytes;
+            let mut deserializer = TlsDeserializer::from_bytes(&mut buf);
+            let deserialized_tail = Tail::deserialize(&mut deserializer).unwrap();
+            assert_eq!(&deserialized_tail, expected_tail);
+            assert_eq!(crate::tls_ser::serialize_to_bytes(expected_tail).unwrap(), *bytes);
+        }
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct TailThenByte {
+        tail: Tail,
+        c: u8,
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct BoundedTailThenByte {
+        #[serde(rename = "tail__bound_u8")]
+        tail: Tail,
+        c: u8,
+    }
+
+    // Make sure that a trailing field is only read where the end of the input says whether it's
+    // there. Anywhere else, whatever follows its struct would be mistaken for it.
+    #[test]
+    fn unbounded_trailing_field() {
+        // A reader whose end is unknown
+        let mut buf: &[u8] = &[0x01, 0x02, 0x03, 0x04];
+        let mut deserializer = TlsDeserializer::from_reader(&mut buf);
+        assert!(Tail::deserialize(&mut deserializer).is_err());
+
+        // A struct that's followed by another field
+        let mut buf: &[u8] = &[0x01, 0x05];
+        let mut deserializer = TlsDeserializer::from_bytes(&mut buf);
+        assert!(TailThenByte::deserialize(&mut deserializer).is_err());
+
+        // A struct that's in a list
+        let mut buf: &[u8] = &[0x01, 0x01];
+        let mut deserializer = TlsDeserializer::from_bytes(&mut buf);
+        assert!(Vec::<Tail>::deserialize(&mut deserializer).is_err());
+
+        // Length-prefixing the struct fixes all that
+        let cases: &[(&[u8], Vec<u8>)] =
+            &[(&[0x01, 0x01, 0x05], Vec::new()), (&[0x03, 0x01, 0x01, 0x04, 0x05], vec![4])];
+        for (bytes, expected_b) in cases {
+            let mut buf = *bytes;
+            let mut deserializer = TlsDeserializer::from_bytes(&mut buf);
+            let deserialized = BoundedTailThenByte::deserialize(&mut deserializer).unwrap();
+            assert_eq!(deserialized.tail.a, 1);
+            assert_eq!(&deserialized.tail.b, expected_b);
+            assert_eq!(deserialized.c, 5);
+        }
+    }
 }
@@ -155,6 +155,7 @@ impl CryptoUpcast for ratchet_tree::RatchetTreeNode {
         if let ratchet_tree::RatchetTreeNode::Filled {
             ref mut public_key,
             ref mut private_key,
+            ..
         } = self
         {
             public_key.upcast_crypto_values(ctx)?;
@@ -171,6 +172,31 @@ impl CryptoUpcast for ratchet_tree::RatchetTree {
     }
 }
 
+/// Upcasts the leaf signatures and `UserInitKey`s in `parent_hashes`. Each signature is under the
+/// signature scheme of the member at that leaf, which we find in `roster`.
+fn upcast_parent_hashes(
+    parent_hashes: &mut [ratchet_tree::NodeParentHash],
+    roster: &credential::Roster,
+    ctx: &CryptoCtx,
+) -> Result<(), Error> {
+    for (idx, entry) in parent_hashes.iter_mut().enumerate() {
+        if let Some(ref mut signature) = entry.signature {
+            let signer_ss = roster
+                .0
+                .get(idx / 2)
+                .and_then(|entry| entry.as_ref())
+                .map(|credential| credential.get_signature_scheme())
+                .ok_or(Error::UpcastError("Signed tree node has no roster entry"))?;
+            signature.upcast_crypto_values(&ctx.set_signature_scheme(signer_ss))?;
+        }
+        if let Some(ref mut init_key) = entry.user_init_key {
+            init_key.upcast_crypto_values(ctx)?;
+        }
+    }
+
+    Ok(())
+}
+
 impl CryptoUpcast for crate::credential::BasicCredential {
     fn upcast_crypto_values(&mut self, ctx: &CryptoCtx) -> Result<CryptoCtx, Error> {
         let new_ctx = ctx.set_signature_scheme(self.signature_scheme);
@@ -191,11 +217,72 @@ impl CryptoUpcast for crate::group_state::WelcomeInfo {
     fn upcast_crypto_values(&mut self, ctx: &CryptoCtx) -> Result<CryptoCtx, Error> {
         self.roster.upcast_crypto_values(ctx)?;
         self.tree.upcast_crypto_values(ctx)?;
+        upcast_parent_hashes(&mut self.extras.tree_parent_hashes, &self.roster, ctx)?;
         // No change in context
         Ok(*ctx)
     }
 }
 
+impl CryptoUpcast for crate::snapshot::GroupStateSnapshot {
+    fn upcast_crypto_values(&mut self, ctx: &CryptoCtx) -> Result<CryptoCtx, Error> {
+        // Snapshots carry their own ciphersuite
+        let new_ctx = ctx.set_cipher_suite(self.cipher_suite);
+        self.roster.upcast_crypto_values(&new_ctx)?;
+        self.tree.upcast_crypto_values(&new_ctx)?;
+        upcast_parent_hashes(&mut self.tree_parent_hashes, &self.roster, &new_ctx)?;
+        self.initializing_user_init_key.upcast_crypto_values(&new_ctx)?;
+        self.last_resort_public_key.upcast_crypto_values(&new_ctx)?;
+        // Like everywhere else a Handshake is upcast, this assumes the whole group uses one
+        // signature scheme, so our own is as good as anyone's
+        let handshake_ctx = new_ctx.set_signature_scheme(self.identity_key_scheme);
+        self.pending_handshakes.upcast_crypto_values(&handshake_ctx)?;
+        self.fork_point.upcast_crypto_values(&new_ctx)?;
+        Ok(new_ctx)
+    }
+}
+
+impl CryptoUpcast for crate::snapshot::PendingHandshake {
+    fn upcast_crypto_values(&mut self, ctx: &CryptoCtx) -> Result<CryptoCtx, Error> {
+        self.0.upcast_crypto_values(ctx)
+    }
+}
+
+impl CryptoUpcast for crate::group_state::ForkPoint {
+    fn upcast_crypto_values(&mut self, ctx: &CryptoCtx) -> Result<CryptoCtx, Error> {
+        self.prior_roster.upcast_crypto_values(ctx)?;
+        // No change in context
+        Ok(*ctx)
+    }
+}
+
+impl CryptoUpcast for crate::group_state::PublicGroupInfo {
+    fn upcast_crypto_values(&mut self, ctx: &CryptoCtx) -> Result<CryptoCtx, Error> {
+        let new_ctx = ctx.set_cipher_suite(self.cipher_suite);
+        self.roster.upcast_crypto_values(&new_ctx)?;
+        self.tree.upcast_crypto_values(&new_ctx)?;
+        upcast_parent_hashes(&mut self.tree_parent_hashes, &self.roster, &new_ctx)?;
+        self.external_public_key.upcast_crypto_values(&new_ctx)?;
+
+        // The signature is under the signer's signature scheme, which we can find in the roster
+        let signer_ss = self
+            .roster
+            .0
+            .get(self.signer_index as usize)
+            .and_then(|entry| entry.as_ref())
+            .map(|credential| credential.get_signature_scheme())
+            .ok_or(Error::UpcastError("PublicGroupInfo's signer is not in the roster"))?;
+        self.signature.upcast_crypto_values(&new_ctx.set_signature_scheme(signer_ss))?;
+        Ok(new_ctx)
+    }
+}
+
+impl CryptoUpcast for crate::group_state::Branch {
+    fn upcast_crypto_values(&mut self, ctx: &CryptoCtx) -> Result<CryptoCtx, Error> {
+        // The branch has the parent group's ciphersuite
+        self.handshake.upcast_crypto_values(ctx)
+    }
+}
+
 impl CryptoUpcast for crate::group_state::Welcome {
     fn upcast_crypto_values(&mut self, ctx: &CryptoCtx) -> Result<CryptoCtx, Error> {
         let new_ctx = ctx.set_cipher_suite(self.cipher_suite);
@@ -203,6 +290,19 @@ impl CryptoUpcast for crate::group_state::Welcome {
     }
 }
 
+impl CryptoUpcast for crate::group_state::EncryptedWelcomeKey {
+    fn upcast_crypto_values(&mut self, ctx: &CryptoCtx) -> Result<CryptoCtx, Error> {
+        self.encrypted_key.upcast_crypto_values(ctx)
+    }
+}
+
+impl CryptoUpcast for crate::group_state::MultiWelcome {
+    fn upcast_crypto_values(&mut self, ctx: &CryptoCtx) -> Result<CryptoCtx, Error> {
+        let new_ctx = ctx.set_cipher_suite(self.cipher_suite);
+        self.recipients.upcast_crypto_values(&new_ctx)
+    }
+}
+
 impl CryptoUpcast for crate::handshake::UserInitKey {
     fn upcast_crypto_values(&mut self, ctx: &CryptoCtx) -> Result<CryptoCtx, Error> {
         // Try to upcast the private keys if they're around
@@ -252,6 +352,9 @@ impl CryptoUpcast for crate::handshake::DirectPathMessage {
         for node_msg in self.node_messages.iter_mut() {
             node_msg.upcast_crypto_values(ctx)?;
         }
+        if let Some(ref mut signature) = self.signature {
+            signature.upcast_crypto_values(ctx)?;
+        }
         // No change to context
         Ok(*ctx)
     }
@@ -259,8 +362,8 @@ impl CryptoUpcast for crate::handshake::DirectPathMessage {
 
 impl CryptoUpcast for crate::handshake::GroupInit {
     fn upcast_crypto_values(&mut self, ctx: &CryptoCtx) -> Result<CryptoCtx, Error> {
-        // GroupInit is empty; this is a no-op
-        Ok(*ctx)
+        self.init_keys.upcast_crypto_values(ctx)?;
+        self.path.upcast_crypto_values(ctx)
     }
 }
 
@@ -270,6 +373,20 @@ impl CryptoUpcast for crate::handshake::GroupAdd {
     }
 }
 
+impl CryptoUpcast for crate::handshake::GroupMultiAdd {
+    fn upcast_crypto_values(&mut self, ctx: &CryptoCtx) -> Result<CryptoCtx, Error> {
+        self.init_keys.upcast_crypto_values(ctx)
+    }
+}
+
+impl CryptoUpcast for crate::handshake::GroupExternalJoin {
+    fn upcast_crypto_values(&mut self, ctx: &CryptoCtx) -> Result<CryptoCtx, Error> {
+        self.init_key.upcast_crypto_values(ctx)?;
+        self.encrypted_init_secret.upcast_crypto_values(ctx)?;
+        self.path.upcast_crypto_values(ctx)
+    }
+}
+
 impl CryptoUpcast for crate::handshake::GroupUpdate {
     fn upcast_crypto_values(&mut self, ctx: &CryptoCtx) -> Result<CryptoCtx, Error> {
         self.path.upcast_crypto_values(ctx)
@@ -282,6 +399,39 @@ impl CryptoUpcast for crate::handshake::GroupRemove {
     }
 }
 
+impl CryptoUpcast for crate::handshake::ProposalBody {
+    fn upcast_crypto_values(&mut self, ctx: &CryptoCtx) -> Result<CryptoCtx, Error> {
+        use crate::handshake::ProposalBody::*;
+        match self {
+            Add(add) => {
+                add.init_key.upcast_crypto_values(ctx)?;
+                Ok(*ctx)
+            }
+            Update(update) => {
+                update.public_key.upcast_crypto_values(ctx)?;
+                update.signature.upcast_crypto_values(ctx)
+            }
+            Remove(_) | Psk(_) => Ok(*ctx),
+        }
+    }
+}
+
+impl CryptoUpcast for crate::handshake::Proposal {
+    fn upcast_crypto_values(&mut self, ctx: &CryptoCtx) -> Result<CryptoCtx, Error> {
+        self.body.upcast_crypto_values(ctx)?;
+        self.signature.upcast_crypto_values(ctx)?;
+        // No change to context
+        Ok(*ctx)
+    }
+}
+
+impl CryptoUpcast for crate::handshake::GroupCommit {
+    fn upcast_crypto_values(&mut self, ctx: &CryptoCtx) -> Result<CryptoCtx, Error> {
+        self.proposals.upcast_crypto_values(ctx)?;
+        self.path.upcast_crypto_values(ctx)
+    }
+}
+
 impl CryptoUpcast for crate::handshake::GroupOperation {
     fn upcast_crypto_values(&mut self, ctx: &CryptoCtx) -> Result<CryptoCtx, Error> {
         use crate::handshake::GroupOperation::*;
@@ -290,6 +440,15 @@ impl CryptoUpcast for crate::handshake::GroupOperation {
             Add(add) => add.upcast_crypto_values(ctx),
             Update(update) => update.upcast_crypto_values(ctx),
             Remove(remove) => remove.upcast_crypto_values(ctx),
+            Commit(commit) => commit.upcast_crypto_values(ctx),
+            MultiAdd(multi_add) => multi_add.upcast_crypto_values(ctx),
+            ExternalJoin(join) => join.upcast_crypto_values(ctx),
+            // The successor's parameters have nothing to upcast
+            ReInit(_) => Ok(*ctx),
+            // Extensions are opaque to us
+            Extensions(_) => Ok(*ctx),
+            // The operation is upcast once it's unsealed
+            Sealed(_) => Ok(*ctx),
         }
     }
 }
@@ -309,3 +468,42 @@ impl CryptoUpcast for crate::application::ApplicationMessage {
         Ok(*ctx)
     }
 }
+
+impl CryptoUpcast for crate::application::PrivateApplicationMessage {
+    fn upcast_crypto_values(&mut self, ctx: &CryptoCtx) -> Result<CryptoCtx, Error> {
+        // No-op
+        Ok(*ctx)
+    }
+}
+
+impl CryptoUpcast for crate::framing::MlsContent {
+    fn upcast_crypto_values(&mut self, ctx: &CryptoCtx) -> Result<CryptoCtx, Error> {
+        use crate::framing::MlsContent::*;
+        match self {
+            Handshake(handshake) => handshake.upcast_crypto_values(ctx),
+            // Application data is opaque to us
+            Application(_) => Ok(*ctx),
+            Proposal(proposal) => proposal.upcast_crypto_values(ctx),
+        }
+    }
+}
+
+impl CryptoUpcast for crate::framing::MlsPlaintext {
+    fn upcast_crypto_values(&mut self, ctx: &CryptoCtx) -> Result<CryptoCtx, Error> {
+        self.content.upcast_crypto_values(ctx)?;
+        self.signature.upcast_crypto_values(ctx)?;
+        // No change to context
+        Ok(*ctx)
+    }
+}
+
+impl CryptoUpcast for crate::framing::MlsMessage {
+    fn upcast_crypto_values(&mut self, ctx: &CryptoCtx) -> Result<CryptoCtx, Error> {
+        use crate::framing::MlsMessage::*;
+        match self {
+            Plaintext(plaintext) => plaintext.upcast_crypto_values(ctx),
+            // The content is upcast once it's decrypted
+            Ciphertext(ciphertext) => ciphertext.upcast_crypto_values(ctx),
+        }
+    }
+}